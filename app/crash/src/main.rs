@@ -0,0 +1,45 @@
+#![no_std]
+#![cfg_attr(not(target_os = "linux"), no_main)]
+
+use core::arch::asm;
+use noli::args;
+use noli::prelude::*;
+
+fn main() -> Result<()> {
+    let args = args::from_env();
+    let kind = args.get(1).copied().unwrap_or("pf");
+    println!("crash: triggering {kind}...");
+    // SAFETY: Not safe at all. This app is intended to be killed by the OS.
+    unsafe {
+        match kind {
+            "pf" => {
+                // Write to the address that is not mapped for the user mode
+                (0x10 as *mut u64).write_volatile(0xDEADBEEF);
+            }
+            "gp" => {
+                // Non-canonical address
+                (0x8000_0000_0000_0000u64 as *mut u64).write_volatile(0xDEADBEEF);
+            }
+            "ud" => {
+                asm!("ud2");
+            }
+            "de" => {
+                asm!(
+                    "xor edx, edx",
+                    "div ecx",
+                    inout("eax") 1 => _,
+                    in("ecx") 0,
+                    out("edx") _,
+                );
+            }
+            _ => {
+                println!("Usage: crash [pf|gp|ud|de]");
+                return Ok(());
+            }
+        }
+    }
+    println!("crash: survived?!");
+    Ok(())
+}
+
+entry_point!(main);
@@ -0,0 +1,92 @@
+#![no_std]
+#![cfg_attr(not(target_os = "linux"), no_main)]
+
+extern crate alloc;
+
+use alloc::string::ToString;
+use noli::args;
+use noli::bitmap::Bitmap;
+use noli::bitmap::Color;
+use noli::entry_point;
+use noli::image::fit_size;
+use noli::image::load_image;
+use noli::prelude::*;
+use noli::rect::Rect;
+use noli::sys::api::Event;
+use noli::window::Window;
+use noli::window::TITLE_BAR_HEIGHT;
+
+// Larger images are shrunk to fit in this size
+const MAX_WIDTH: i64 = 640;
+const MAX_HEIGHT: i64 = 400;
+// Keeps the title and the close button visible for tiny images
+const MIN_WIDTH: i64 = 160;
+// Transparent parts of images are shown over a checkerboard
+const CHECKER_SIZE: i64 = 8;
+const CHECKER_COLORS: [Color; 2] = [Color::from_rgb(0xffffff), Color::from_rgb(0xcccccc)];
+
+fn main() -> Result<()> {
+    let args = args::from_env();
+    if args.len() != 2 {
+        println!("Usage: imgview <file>");
+        println!("Shows a BMP, PNG or GIF image.");
+        return Ok(());
+    }
+    let path = &args[1];
+    let image = match load_image(path) {
+        Ok(image) => image,
+        Err(e) => {
+            println!("{path}: {e:?}");
+            return Err(e);
+        }
+    };
+    let (w, h) = fit_size(image.width(), image.height(), MAX_WIDTH, MAX_HEIGHT);
+    println!(
+        "{path}: {}x{} (shown in {w}x{h})",
+        image.width(),
+        image.height()
+    );
+
+    let window_width = w.max(MIN_WIDTH);
+    let mut window = Window::new(
+        path.to_string(),
+        0xffffff,
+        40,
+        40,
+        window_width,
+        h + TITLE_BAR_HEIGHT,
+    )?;
+    let mut canvas = window.canvas();
+    let x = (window_width - w) / 2;
+    for cy in (0..h).step_by(CHECKER_SIZE as usize) {
+        for cx in (0..w).step_by(CHECKER_SIZE as usize) {
+            let color = CHECKER_COLORS[((cx + cy) / CHECKER_SIZE % 2) as usize];
+            let cell = Rect::new(
+                x + cx,
+                cy,
+                CHECKER_SIZE.min(w - cx),
+                CHECKER_SIZE.min(h - cy),
+            );
+            if let Some(cell) = cell {
+                canvas.fill_rect(cell, color);
+            }
+        }
+    }
+    if let Some(rect) = Rect::new(x, 0, w, h) {
+        canvas.draw_image(&image, rect);
+    }
+    window.flush();
+
+    println!("Type q or click the close button to exit.");
+    loop {
+        if Api::read_key() == Some('q') {
+            break;
+        }
+        if let Some(Event::WindowClose) = window.next_event() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+entry_point!(main);
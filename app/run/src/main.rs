@@ -0,0 +1,21 @@
+#![no_std]
+#![cfg_attr(not(target_os = "linux"), no_main)]
+
+use noli::args;
+use noli::prelude::*;
+use noli::process::Command;
+
+fn main() -> Result<()> {
+    let args = args::from_env();
+    if args.len() <= 1 {
+        println!("Usage: run <app> [args...]");
+        return Ok(());
+    }
+    let mut child = Command::new(args[1]).args(&args[2..]).spawn()?;
+    println!("run: spawned {} as pid {}", args[1], child.id());
+    let status = child.wait()?;
+    println!("run: {} exited with code {}", args[1], status.code());
+    Ok(())
+}
+
+entry_point!(main);
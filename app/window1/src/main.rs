@@ -4,14 +4,18 @@
 extern crate alloc;
 
 use alloc::string::ToString;
+use noli::bitmap::Color;
 use noli::entry_point;
-use noli::println;
+use noli::prelude::*;
+use noli::rasterizer::Point;
+use noli::rect::Rect;
+use noli::sys::api::Event;
 use noli::window;
 
 fn main() {
     println!("window 1");
 
-    let window1 =
+    let mut window1 =
         window::Window::new("first window!".to_string(), 0xffffff, 30, 30, 200, 100).unwrap();
     let mut window2 =
         window::Window::new("second window!!".to_string(), 0xff00ff, 60, 100, 200, 100).unwrap();
@@ -44,10 +48,43 @@ fn main() {
     // try to fill a bigger rect than the size of the window.
     assert!(window3.fill_rect(0xff0000, 100, 50, 50, 27).is_err());
 
+    // Translucent shapes with anti-aliased edges
+    let mut canvas = window1.canvas();
+    canvas.fill_rounded_rect(
+        Rect::new(10, 10, 80, 50).unwrap(),
+        8.0,
+        Color::from_rgb(0x00aa00).with_alpha(0xc0),
+    );
+    canvas.fill_circle(
+        Point::new(90.0, 40.0),
+        25.0,
+        Color::from_rgb(0x0000ff).with_alpha(0x80),
+    );
+    canvas.draw_line(
+        Point::new(10.0, 70.0),
+        Point::new(190.0, 15.0),
+        2.0,
+        Color::BLACK,
+    );
+
     window1.flush();
     window2.flush();
     window3.flush();
     window1.flush();
+
+    // The windows are closed when the app exits.
+    println!("Type q or click a close button to exit.");
+    loop {
+        if Api::read_key() == Some('q') {
+            break;
+        }
+        let closed = [&mut window1, &mut window2, &mut window3]
+            .into_iter()
+            .any(|w| matches!(w.next_event(), Some(Event::WindowClose)));
+        if closed {
+            break;
+        }
+    }
 }
 
 entry_point!(main);
@@ -9,6 +9,7 @@ use noli::entry_point;
 use noli::prelude::*;
 use noli::rect::Rect;
 use noli::sheet::Sheet;
+use noli::sys::api::Event;
 use noli::sys::api::MouseEvent;
 use noli::window;
 
@@ -23,7 +24,13 @@ fn main() {
     let _ = bitmap_draw_rect(bitmap, 0xff0000, 0, 0, 20, 20);
 
     loop {
-        if let Some(MouseEvent { button, position }) = Api::get_mouse_cursor_info() {
+        if let Some(Event::WindowClose) = window.next_event() {
+            break;
+        }
+        if let Some(MouseEvent {
+            button, position, ..
+        }) = Api::get_mouse_cursor_info()
+        {
             window.flush_area(cursor.rect());
             cursor.set_position(position.x, position.y);
             window.flush_area(cursor.rect());
@@ -10,6 +10,7 @@ fn main() -> Result<()> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use anyhow::Context;
     use e2etest::devenv::DevEnv;
     use e2etest::qemu::Qemu;
     use e2etest::run_shell_cmd;
@@ -176,6 +177,63 @@ mod test {
         Ok(())
     }
     #[tokio::test]
+    async fn app_fault_does_not_kill_os() -> Result<()> {
+        let dev_env = DevEnv::new()?;
+        let crash_bin_path = dev_env.build_builtin_app("crash")?;
+        let hello_bin_path = dev_env.build_builtin_app("hello1")?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os_and_files(
+            dev_env.wasabi_efi_path(),
+            &[crash_bin_path.as_str(), hello_bin_path.as_str()],
+        )?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.send_key_inputs_from_str("crash pf\n").await?;
+        qemu.wait_until_serial_output_contains(r#"App "crash" is killed by exception 0x0E"#)?;
+        qemu.wait_until_serial_output_contains("Ok(-270)")?;
+        // The OS and the shell should be still alive
+        qemu.send_key_inputs_from_str("hello1\n").await?;
+        qemu.wait_until_serial_output_contains("hello from sys_print!")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn app_can_spawn_and_wait_for_another_app() -> Result<()> {
+        let dev_env = DevEnv::new()?;
+        let run_bin_path = dev_env.build_builtin_app("run")?;
+        let hello_bin_path = dev_env.build_builtin_app("hello0")?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os_and_files(
+            dev_env.wasabi_efi_path(),
+            &[run_bin_path.as_str(), hello_bin_path.as_str()],
+        )?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.send_key_inputs_from_str("run hello0\n").await?;
+        qemu.wait_until_serial_output_contains("**** Hello from an app!")?;
+        qemu.wait_until_serial_output_contains("run: hello0 exited with code 42")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn all_cpus_are_online() -> Result<()> {
+        // QEMU is launched with -smp 4
+        let dev_env = DevEnv::new()?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os(dev_env.wasabi_efi_path())?;
+        qemu.wait_until_serial_output_contains("4 processors are online")?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.send_key_inputs_from_str("cpus\n").await?;
+        // Each AP runs a job that reports its APIC ID
+        qemu.wait_until_serial_output_contains("CPU 3: APIC ID 3, a job reported APIC ID 3")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
     async fn network_is_working() -> Result<()> {
         // cargo test -p e2etest -- network
         let dev_env = DevEnv::new()?;
@@ -190,6 +248,156 @@ mod test {
         qemu.kill().await?;
         Ok(())
     }
+    #[tokio::test]
+    async fn usb_mouse_and_tablet_are_detected() -> Result<()> {
+        // cargo test -p e2etest -- usb_mouse
+        let dev_env = DevEnv::new()?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os(dev_env.wasabi_efi_path())?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        qemu.send_monitor_cmd("device_add usb-mouse,id=mouse0")
+            .await?;
+        qemu.wait_until_serial_output_contains("usb_hid_mouse is ready")?;
+        // The absolute axes in the report descriptor tell that it is a tablet
+        qemu.send_monitor_cmd("device_add usb-tablet,id=tablet0")
+            .await?;
+        qemu.wait_until_serial_output_contains("usb_hid_tablet is ready")?;
+        qemu.send_monitor_cmd("mouse_move 10 10").await?;
+        qemu.send_monitor_cmd("mouse_button 1").await?;
+        qemu.send_monitor_cmd("mouse_button 0").await?;
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn usb_keyboard_can_be_reattached() -> Result<()> {
+        // cargo test -p e2etest -- usb_keyboard_can_be_reattached
+        let dev_env = DevEnv::new()?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os(dev_env.wasabi_efi_path())?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        for i in 1..=2 {
+            qemu.send_monitor_cmd("device_del kbd0").await?;
+            qemu.wait_until_serial_output_contains_n_times(": disabled", i)?;
+            qemu.send_monitor_cmd("device_add usb-kbd,id=kbd0").await?;
+            qemu.wait_until_serial_output_contains_n_times("usb_hid_keyboard is ready", i + 1)?;
+        }
+        // The re-attached keyboard should work as before
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.send_key_inputs_from_str("hello1\n").await?;
+        qemu.wait_until_serial_output_contains("hello from sys_print!")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn lspci_and_lsusb_show_devices() -> Result<()> {
+        // cargo test -p e2etest -- lspci_and_lsusb
+        let dev_env = DevEnv::new()?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os(dev_env.wasabi_efi_path())?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.send_key_inputs_from_str("lspci\n").await?;
+        qemu.wait_until_serial_output_contains("USB xHCI controller")?;
+        qemu.wait_until_serial_output_contains("driver: XhciDriver")?;
+        qemu.wait_until_serial_output_contains("driver: Rtl8139Driver")?;
+        qemu.send_key_inputs_from_str("lsusb\n").await?;
+        qemu.wait_until_serial_output_contains(r#"Product: "QEMU USB Keyboard""#)?;
+        qemu.wait_until_serial_output_contains("Endpoint 0x81 IN Interrupt")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn ps2_keyboard_works_without_usb_keyboard() -> Result<()> {
+        // cargo test -p e2etest -- ps2_keyboard
+        let dev_env = DevEnv::new()?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os(dev_env.wasabi_efi_path())?;
+        qemu.wait_until_serial_output_contains("ps2_keyboard is ready")?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        // Key inputs go to the PS/2 keyboard once the USB keyboard is removed
+        qemu.send_monitor_cmd("device_del kbd0").await?;
+        qemu.wait_until_serial_output_contains(": disabled")?;
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.send_key_inputs_from_str("hello1\n").await?;
+        qemu.wait_until_serial_output_contains("hello from sys_print!")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn shell_can_be_used_over_serial() -> Result<()> {
+        // cargo test -p e2etest -- shell_can_be_used_over_serial
+        let dev_env = DevEnv::new()?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os(dev_env.wasabi_efi_path())?;
+        qemu.wait_until_serial_output_contains("console_task has started")?;
+        // Backspace, cursor keys and CR LF are handled by the line discipline
+        qemu.send_serial_input("abc\x7f\x7f\x7fhelo\x1b[D\x1b[Dl\x1b[F1\r\n")
+            .await?;
+        qemu.wait_until_serial_output_contains("hello from sys_print!")?;
+        // Ctrl-C discards the line
+        qemu.send_serial_input("hello0\x03hello1\r").await?;
+        qemu.wait_until_serial_output_contains_n_times("hello from sys_print!", 2)?;
+        // Apps can read the input from the serial, and are terminated by Ctrl-C
+        qemu.send_serial_input("rev\r").await?;
+        qemu.wait_until_serial_output_contains("Type q and hit Enter to exit.")?;
+        qemu.send_serial_input("wasabi\r").await?;
+        qemu.wait_until_serial_output_contains("ibasaw")?;
+        qemu.send_serial_input("\x03").await?;
+        qemu.wait_until_serial_output_contains("Ok(-2)")?;
+        // Apps that never make syscalls are also terminated by Ctrl-C
+        qemu.send_serial_input("loop\r").await?;
+        qemu.wait_until_serial_output_contains("Executing cmd: [\"loop\"]")?;
+        qemu.send_serial_input("\x03").await?;
+        qemu.wait_until_serial_output_contains_n_times("Ok(-2)", 2)?;
+        qemu.send_serial_input("hello1\r").await?;
+        qemu.wait_until_serial_output_contains_n_times("hello from sys_print!", 3)?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn usb_mass_storage_is_working() -> Result<()> {
+        // cargo test -p e2etest -- usb_mass_storage
+        const MARKER: &[u8] = b"WASABIUSBSTICK";
+        const TEXT: &str = "hellofromwasabi";
+        let disk_dir = tempfile::TempDir::new()?;
+        let disk_path = disk_dir.path().join("stick.img");
+        let mut image = vec![0u8; 1024 * 1024];
+        image[..MARKER.len()].copy_from_slice(MARKER);
+        std::fs::write(&disk_path, &image)?;
+        let disk_path = disk_path.to_str().context("disk path is not UTF-8")?;
+
+        let dev_env = DevEnv::new()?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu.launch_with_wasabi_os(dev_env.wasabi_efi_path())?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        qemu.send_key_inputs_from_str("\n").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.send_monitor_cmd(&format!(
+            "drive_add 0 if=none,id=stick,format=raw,file={disk_path}"
+        ))
+        .await?;
+        qemu.send_monitor_cmd("device_add usb-storage,drive=stick,id=stick0")
+            .await?;
+        qemu.wait_until_serial_output_contains("usb_mass_storage is ready")?;
+        qemu.send_key_inputs_from_str("lsblk\n").await?;
+        qemu.wait_until_serial_output_contains("0: usb")?;
+        qemu.send_key_inputs_from_str("blkread 0 0\n").await?;
+        qemu.wait_until_serial_output_contains("|WASABIUSBSTICK")?;
+        qemu.send_key_inputs_from_str(&format!("blkwrite 0 1 {TEXT}\n"))
+            .await?;
+        qemu.send_key_inputs_from_str("blkread 0 1\n").await?;
+        qemu.wait_until_serial_output_contains(&format!("|{TEXT}"))?;
+        qemu.kill().await?;
+        // The data should be written back to the disk image
+        let image = std::fs::read(disk_path)?;
+        assert_eq!(&image[512..512 + TEXT.len()], TEXT.as_bytes());
+        Ok(())
+    }
     fn assert_tcp_echo_client_is_working(test_string: &str) -> Result<()> {
         eprintln!("sending {test_string}...");
         let cmd = format!("sleep 1 ; echo {test_string} | nc -w 3 localhost 18080");
@@ -14,6 +14,7 @@ use std::process::ExitStatus;
 use std::thread::sleep;
 use std::time::Duration;
 use tokio::io::AsyncWriteExt;
+use tokio::net::unix::OwnedWriteHalf;
 use tokio::net::UnixStream;
 
 pub struct RootFs {
@@ -43,27 +44,33 @@ impl RootFs {
     }
 }
 
+/// Connects to a UNIX domain socket that QEMU creates (e.g. for the monitor),
+/// retrying until QEMU is ready.
+async fn connect_to_qemu_socket(sock_path: &str) -> Result<UnixStream> {
+    let mut retry_count = 0;
+    loop {
+        let sock = UnixStream::connect(sock_path)
+            .await
+            .context(anyhow!("Failed to open a UNIX domain socket {sock_path}"));
+        if let Ok(sock) = sock {
+            return Ok(sock);
+        } else if retry_count < 100 {
+            eprintln!("{sock:?}");
+            std::thread::sleep(Duration::from_millis(500));
+            retry_count += 1;
+            continue;
+        } else {
+            bail!("{sock:?}")
+        }
+    }
+}
+
 pub struct QemuMonitor {
     stream: UnixStream,
 }
 impl QemuMonitor {
     pub async fn new(monitor_sock_path: &str) -> Result<Self> {
-        let mut retry_count = 0;
-        let stream = loop {
-            let sock = UnixStream::connect(monitor_sock_path)
-                .await
-                .context("Failed to open a UNIX domain socket for QEMU monitor");
-            if let Ok(sock) = sock {
-                break sock;
-            } else if retry_count < 100 {
-                eprintln!("{sock:?}");
-                std::thread::sleep(Duration::from_millis(500));
-                retry_count += 1;
-                continue;
-            } else {
-                bail!("{sock:?}")
-            }
-        };
+        let stream = connect_to_qemu_socket(monitor_sock_path).await?;
         // wait for the first prompt on connection
         let mut monitor = Self { stream };
         monitor.wait_until_prompt().await?;
@@ -146,15 +153,19 @@ pub struct Qemu {
     proc: Option<process::Child>,
     work_dir: tempfile::TempDir,
     path_to_ovmf: String,
+    // Connection to COM2, to send inputs to the serial console
+    serial_input: Option<OwnedWriteHalf>,
 }
 
 impl Qemu {
     const MONITOR_SOCKET_NAME: &'static str = "monitor.sock";
+    const COM2_SOCKET_NAME: &'static str = "com2.sock";
     pub fn new(path_to_ovmf: &str) -> Result<Self> {
         Ok(Self {
             proc: None,
             path_to_ovmf: path_to_ovmf.to_string(),
             work_dir: tempfile::TempDir::new()?,
+            serial_input: None,
         })
     }
     fn work_dir_path(&self) -> Result<&str> {
@@ -168,10 +179,16 @@ impl Qemu {
         let monitor_socket_name = Self::MONITOR_SOCKET_NAME;
         Ok(format!("{work_dir}/{monitor_socket_name}"))
     }
+    fn com2_sock_path(&self) -> Result<String> {
+        let work_dir = self.work_dir_path()?;
+        let com2_socket_name = Self::COM2_SOCKET_NAME;
+        Ok(format!("{work_dir}/{com2_socket_name}"))
+    }
     fn gen_base_args(&self) -> Result<String> {
         let work_dir = self.work_dir_path()?;
         let path_to_ovmf = self.path_to_ovmf.as_str();
         let monitor_sock_path = self.monitor_sock_path()?;
+        let com2_sock_path = self.com2_sock_path()?;
         Ok(format!(
             "qemu-system-x86_64 \
                 -machine q35 \
@@ -186,11 +203,11 @@ impl Qemu {
                 -object filter-dump,id=f2,netdev=net1,file={work_dir}/net1.pcap \
                 -m 1024M \
                 -chardev file,id=char_com1,mux=on,path={work_dir}/com1.txt \
-                -chardev file,id=char_com2,mux=on,path={work_dir}/com2.txt \
+                -chardev socket,id=char_com2,path={com2_sock_path},server=on,wait=off,logfile={work_dir}/com2.txt \
                 -serial chardev:char_com1 \
                 -serial chardev:char_com2 \
                 -display none \
-                -device usb-kbd \
+                -device usb-kbd,id=kbd0 \
                 -bios {path_to_ovmf}",
         ))
     }
@@ -199,9 +216,14 @@ impl Qemu {
         fs::read_to_string(format!("{work_dir}/com2.txt")).context("Failed to read com2 output")
     }
     pub fn wait_until_serial_output_contains(&mut self, s: &str) -> Result<()> {
+        self.wait_until_serial_output_contains_n_times(s, 1)
+    }
+    /// Waits until the string appears n times in the serial output since the launch.
+    /// Useful for messages which are printed again (e.g. on re-attaching devices).
+    pub fn wait_until_serial_output_contains_n_times(&mut self, s: &str, n: usize) -> Result<()> {
         const INTERVAL_MS: u64 = 500;
         const TIMEOUT_MS: u64 = 60 * 1000;
-        eprintln!("\n>>>>> Waiting serial output `{s}`...");
+        eprintln!("\n>>>>> Waiting serial output `{s}` ({n} times)...");
         let mut duration = 0;
         let mut output = self.read_serial_output().unwrap_or_else(|_| {
             eprint!("serial output was empty: using empty string for now");
@@ -216,7 +238,7 @@ impl Qemu {
                     String::from_utf8_lossy(&strip_ansi_escapes::strip(diff.replace('\r', "")))
                 );
                 output += diff;
-                if output.contains(s) {
+                if output.matches(s).count() >= n {
                     eprintln!("OK");
                     return Ok(());
                 }
@@ -225,7 +247,7 @@ impl Qemu {
             eprint!(".");
             duration += INTERVAL_MS;
         }
-        bail!("Expected a string `{s}` in the serial output within {TIMEOUT_MS} ms but not found. Output:\n{output}");
+        bail!("Expected a string `{s}` {n} times in the serial output within {TIMEOUT_MS} ms but not found. Output:\n{output}");
     }
     pub fn launch_without_os(&mut self) -> Result<()> {
         if self.proc.is_some() {
@@ -300,6 +322,26 @@ impl Qemu {
         }
         Ok(())
     }
+    /// Sends the string to COM2, as if it is typed on a serial terminal.
+    /// Use '\r' for the Enter key.
+    pub async fn send_serial_input(&mut self, s: &str) -> Result<()> {
+        if self.serial_input.is_none() {
+            let stream = connect_to_qemu_socket(&self.com2_sock_path()?).await?;
+            let (mut reader, writer) = stream.into_split();
+            // The output is read from com2.txt, so just drain the socket
+            // to prevent the serial port from being blocked.
+            tokio::spawn(async move { tokio::io::copy(&mut reader, &mut tokio::io::sink()).await });
+            self.serial_input = Some(writer);
+        }
+        let serial_input = self
+            .serial_input
+            .as_mut()
+            .context("COM2 should be connected")?;
+        serial_input.write_all(s.as_bytes()).await?;
+        serial_input.flush().await?;
+        eprintln!("Sent serial input: {s:?}");
+        Ok(())
+    }
     pub async fn kill(&mut self) -> Result<()> {
         if let Err(e) = self.send_monitor_cmd("quit\n").await {
             eprintln!("Qemu::kill : send_monitor_cmd returned an error but it is expected since the connection is lost: {e:?}")
@@ -1,9 +1,22 @@
 #![feature(proc_macro_span)]
 extern crate proc_macro;
 use proc_macro::TokenStream;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 
+// font.txt consists of glyphs in the following format:
+//
+// 0x41     <- the code point of the glyph in hex
+// ...**... <- 16 rows of '.' (off) and '*' (on)
+// ...
+//
+// Each row of a glyph has 8 columns (narrow) or 16 columns (wide).
+// Glyphs are separated by blank lines.
+const GLYPH_HEIGHT: usize = 16;
+const NARROW_GLYPH_WIDTH: usize = 8;
+const WIDE_GLYPH_WIDTH: usize = 16;
+
 #[proc_macro]
 pub fn gen_embedded_font(_input: TokenStream) -> TokenStream {
     let mut path = std::env::current_dir().expect("failed to get current dir");
@@ -18,68 +31,128 @@ pub fn gen_embedded_font(_input: TokenStream) -> TokenStream {
     gen_font_decl(path)
 }
 
+struct Glyph {
+    width: usize,
+    rows: Vec<u16>,
+}
+
 fn gen_font_decl(file_name: &str) -> TokenStream {
-    let mut fonts: [[u8; 16]; 256] = [[0; 16]; 256];
+    let mut fonts: BTreeMap<u32, Glyph> = BTreeMap::new();
     let mut file = File::open(file_name).expect("failed to open the font file");
     let mut input = String::new();
     file.read_to_string(&mut input)
         .expect("failed to read the file into string");
-    let mut font_index = 0;
-    let mut row_index = 16;
+    let mut code_point = None;
     let mut line = 0;
     for row in input.split('\n') {
         line += 1;
         if row.starts_with("0x") {
-            assert!(
-                row_index == 16,
-                "line {}: fonts[0x{:02X}] has {} rows but expected 16",
-                line,
-                font_index,
-                row_index
-            );
+            if let Some(c) = code_point {
+                let rows = fonts[&c].rows.len();
+                assert!(
+                    rows == GLYPH_HEIGHT,
+                    "line {}: fonts[0x{:02X}] has {} rows but expected {}",
+                    line,
+                    c,
+                    rows,
+                    GLYPH_HEIGHT
+                );
+            }
             let row_trimmed = row.trim_start_matches("0x");
-            font_index = match usize::from_str_radix(row_trimmed, 16) {
-                Ok(i) => i,
+            let c = match u32::from_str_radix(row_trimmed, 16) {
+                Ok(c) => c,
                 Err(_) => panic!("Failed to parse index line at line {}", line),
             };
-            row_index = 0;
+            assert!(
+                char::from_u32(c).is_some(),
+                "line {}: 0x{:02X} is not a valid code point",
+                line,
+                c
+            );
+            assert!(
+                !fonts.contains_key(&c),
+                "line {}: fonts[0x{:02X}] is defined twice",
+                line,
+                c
+            );
+            fonts.insert(
+                c,
+                Glyph {
+                    width: 0,
+                    rows: Vec::new(),
+                },
+            );
+            code_point = Some(c);
             continue;
         }
         if !row.starts_with('.') && !row.starts_with('*') {
             // skip blank lines
             continue;
         }
+        let c = code_point.unwrap_or_else(|| panic!("line {}: a row without a code point", line));
+        let glyph = fonts.get_mut(&c).unwrap();
         assert!(
-            row_index < 16,
+            glyph.rows.len() < GLYPH_HEIGHT,
             "line {}: fonts[0x{:02X}] has extra rows",
             line,
-            font_index
+            c
+        );
+        let width = row.chars().count();
+        assert!(
+            width == NARROW_GLYPH_WIDTH || width == WIDE_GLYPH_WIDTH,
+            "line {}: fonts[0x{:02X}] has a row with {} columns",
+            line,
+            c,
+            width
+        );
+        if glyph.rows.is_empty() {
+            glyph.width = width;
+        }
+        assert!(
+            glyph.width == width,
+            "line {}: fonts[0x{:02X}] has rows with different widths",
+            line,
+            c
         );
-        let mut row_bits = 0;
-        for i in 0..8 {
-            match row.chars().nth(i) {
-                Some('.') => (),
-                Some('*') => row_bits |= 1 << i,
-                c => panic!(
+        let mut row_bits: u16 = 0;
+        for (i, ch) in row.chars().enumerate() {
+            match ch {
+                '.' => (),
+                '*' => row_bits |= 1 << i,
+                ch => panic!(
                     "line {}: fonts[0x{:02X}] has an unexpected character {:?}",
-                    line, font_index, c
+                    line, c, ch
                 ),
             }
         }
-        fonts[font_index][row_index] = row_bits;
-        row_index += 1;
+        glyph.rows.push(row_bits);
+    }
+    if let Some(c) = code_point {
+        assert!(
+            fonts[&c].rows.len() == GLYPH_HEIGHT,
+            "fonts[0x{:02X}] has {} rows but expected {}",
+            c,
+            fonts[&c].rows.len(),
+            GLYPH_HEIGHT
+        );
     }
 
-    let mut src = "pub static BITMAP_FONT: [[u8; 16]; 256] = [".to_string();
-    for f in fonts {
-        src += "[";
-        for (i, bits) in f.iter().enumerate() {
+    // BitmapGlyph should be defined at the call site. The glyphs are sorted by the code points
+    // since BTreeMap iterates over the keys in order.
+    let mut src = format!("pub static BITMAP_FONT: [BitmapGlyph; {}] = [", fonts.len());
+    for (c, glyph) in fonts {
+        src += &format!(
+            "BitmapGlyph {{ code_point: 0x{:X}, wide: {}, rows: [",
+            c,
+            glyph.width == WIDE_GLYPH_WIDTH
+        );
+        for (i, bits) in glyph.rows.iter().enumerate() {
             src += &format!("{}", bits);
-            if i != 15 {
+            if i != GLYPH_HEIGHT - 1 {
                 src += ", ";
             }
         }
-        src += "],";
+        src += "] },";
     }
     src += "];";
     src.as_str().parse().unwrap()
@@ -2,7 +2,12 @@ extern crate alloc;
 
 use crate::error::Error;
 use crate::error::Result;
-use crate::font::BITMAP_FONT;
+use crate::font::char_width;
+use crate::font::glyph_for_char;
+use crate::rasterizer::sqrt;
+use crate::rasterizer::FillRule;
+use crate::rasterizer::Path;
+use crate::rasterizer::Point;
 use crate::rect::Rect;
 use alloc::vec::Vec;
 use core::cmp::min;
@@ -111,20 +116,18 @@ pub fn bitmap_draw_char_3x<T: Bitmap>(
     py: i64,
     c: char,
 ) -> Result<()> {
+    let glyph = glyph_for_char(c);
     if !buf.is_in_x_range(px)
         || !buf.is_in_y_range(py)
-        || !buf.is_in_x_range(px + 8 - 1)
-        || !buf.is_in_y_range(py + 16 - 1)
+        || !buf.is_in_x_range(px + glyph.width() - 1)
+        || !buf.is_in_y_range(py + glyph.height() - 1)
     {
         return Err(Error::GraphicsOutOfRange);
     }
 
-    let idx = c as usize;
-    for y in 0..48_i64 {
-        for x in 0..24_i64 {
-            let original_x = x / 3;
-            let original_y = y / 3;
-            if idx >= 256 || ((BITMAP_FONT[idx][original_y as usize] >> original_x) & 1) == 1 {
+    for y in 0..glyph.height() * 3 {
+        for x in 0..glyph.width() * 3 {
+            if glyph.is_set(x / 3, y / 3) {
                 bitmap_draw_point(buf, fg_color, px + x, py + y)?;
             } else if let Some(bg_color) = bg_color {
                 bitmap_draw_point(buf, bg_color, px + x, py + y)?;
@@ -143,18 +146,18 @@ pub fn bitmap_draw_char_2x<T: Bitmap>(
     py: i64,
     c: char,
 ) -> Result<()> {
+    let glyph = glyph_for_char(c);
     if !buf.is_in_x_range(px)
         || !buf.is_in_y_range(py)
-        || !buf.is_in_x_range(px + 8 - 1)
-        || !buf.is_in_y_range(py + 16 - 1)
+        || !buf.is_in_x_range(px + glyph.width() - 1)
+        || !buf.is_in_y_range(py + glyph.height() - 1)
     {
         return Err(Error::GraphicsOutOfRange);
     }
 
-    let idx = c as usize;
-    for y in 0..16_i64 {
-        for x in 0..8_i64 {
-            if idx >= 256 || ((BITMAP_FONT[idx][y as usize] >> x) & 1) == 1 {
+    for y in 0..glyph.height() {
+        for x in 0..glyph.width() {
+            if glyph.is_set(x, y) {
                 bitmap_draw_point(buf, fg_color, px + x * 2, py + y * 2)?;
                 bitmap_draw_point(buf, fg_color, px + x * 2 + 1, py + y * 2)?;
                 bitmap_draw_point(buf, fg_color, px + x * 2, py + y * 2 + 1)?;
@@ -177,18 +180,18 @@ pub fn bitmap_draw_char<T: Bitmap>(
     py: i64,
     c: char,
 ) -> Result<()> {
+    let glyph = glyph_for_char(c);
     if !buf.is_in_x_range(px)
         || !buf.is_in_y_range(py)
-        || !buf.is_in_x_range(px + 8 - 1)
-        || !buf.is_in_y_range(py + 16 - 1)
+        || !buf.is_in_x_range(px + glyph.width() - 1)
+        || !buf.is_in_y_range(py + glyph.height() - 1)
     {
         return Err(Error::GraphicsOutOfRange);
     }
 
-    let idx = c as usize;
-    for y in 0..16_i64 {
-        for x in 0..8_i64 {
-            if idx >= 256 || ((BITMAP_FONT[idx][y as usize] >> x) & 1) == 1 {
+    for y in 0..glyph.height() {
+        for x in 0..glyph.width() {
+            if glyph.is_set(x, y) {
                 bitmap_draw_point(buf, fg_color, px + x, py + y)?;
             } else if let Some(bg_color) = bg_color {
                 bitmap_draw_point(buf, bg_color, px + x, py + y)?;
@@ -210,7 +213,7 @@ pub fn bitmap_draw_string_3x<T: Bitmap>(
     let mut pos = 0;
     for c in s.chars() {
         bitmap_draw_char_3x(buf, fg_color, bg_color, px + pos, py, c)?;
-        pos += 24;
+        pos += char_width(c) * 3;
     }
     Ok(())
 }
@@ -226,7 +229,7 @@ pub fn bitmap_draw_string_2x<T: Bitmap>(
     let mut pos = 0;
     for c in s.chars() {
         bitmap_draw_char_2x(buf, fg_color, bg_color, px + pos, py, c)?;
-        pos += 16;
+        pos += char_width(c) * 2;
     }
     Ok(())
 }
@@ -242,7 +245,7 @@ pub fn bitmap_draw_string<T: Bitmap>(
     let mut pos = 0;
     for c in s.chars() {
         bitmap_draw_char(buf, fg_color, bg_color, px + pos, py, c)?;
-        pos += 8;
+        pos += char_width(c);
     }
     Ok(())
 }
@@ -258,7 +261,7 @@ pub fn bitmap_draw_string_3x_with_underline<T: Bitmap>(
     let mut pos = 0;
     for c in s.chars() {
         bitmap_draw_char_3x(buf, fg_color, bg_color, px + pos, py, c)?;
-        pos += 24;
+        pos += char_width(c) * 3;
     }
     bitmap_draw_line(buf, fg_color, px, py + 16, px + pos, py + 16)?;
     Ok(())
@@ -275,7 +278,7 @@ pub fn bitmap_draw_string_2x_with_underline<T: Bitmap>(
     let mut pos = 0;
     for c in s.chars() {
         bitmap_draw_char_2x(buf, fg_color, bg_color, px + pos, py, c)?;
-        pos += 16;
+        pos += char_width(c) * 2;
     }
     bitmap_draw_line(buf, fg_color, px, py + 16, px + pos, py + 16)?;
     Ok(())
@@ -292,7 +295,7 @@ pub fn bitmap_draw_string_with_underline<T: Bitmap>(
     let mut pos = 0;
     for c in s.chars() {
         bitmap_draw_char(buf, fg_color, bg_color, px + pos, py, c)?;
-        pos += 8;
+        pos += char_width(c);
     }
     bitmap_draw_line(buf, fg_color, px, py + 16, px + pos, py + 16)?;
     Ok(())
@@ -615,3 +618,626 @@ pub fn draw_bmp_clipped<DstBitmap: Bitmap, SrcBitmap: Bitmap>(
     }
     Some(())
 }
+
+/// Copies the pixels in `src_rect` of the src bitmap to (dx, dy) of the dst bitmap
+/// line by line. The rect is clipped to both of the bitmaps.
+/// Returns the rect in the dst bitmap that is actually updated.
+pub fn bitmap_blit<DstBitmap: Bitmap, SrcBitmap: Bitmap>(
+    dst: &mut DstBitmap,
+    src: &SrcBitmap,
+    src_rect: &Rect,
+    dx: i64,
+    dy: i64,
+) -> Option<Rect> {
+    let src_bounds = Rect::new(0, 0, min(src.width(), src.pixels_per_line()), src.height())?;
+    let src_clipped = src_rect.intersection(&src_bounds)?;
+    let dst_rect = Rect::new(
+        dx + src_clipped.x() - src_rect.x(),
+        dy + src_clipped.y() - src_rect.y(),
+        src_clipped.w(),
+        src_clipped.h(),
+    )?;
+    let dst_bounds = Rect::new(0, 0, min(dst.width(), dst.pixels_per_line()), dst.height())?;
+    let copy_rect = dst_rect.intersection(&dst_bounds)?;
+    if copy_rect.is_empty() {
+        return None;
+    }
+    let sx = src_clipped.x() + copy_rect.x() - dst_rect.x();
+    let sy = src_clipped.y() + copy_rect.y() - dst_rect.y();
+    for y in 0..copy_rect.h() {
+        // SAFETY: both of the lines are in the bitmaps since the rects are clipped above.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                src.unchecked_pixel_at(sx, sy + y),
+                dst.unchecked_pixel_at_mut(copy_rect.x(), copy_rect.y() + y),
+                copy_rect.w() as usize,
+            );
+        }
+    }
+    Some(copy_rect)
+}
+
+#[cfg(test)]
+mod bitmap_blit_tests {
+    use super::bitmap_blit;
+    use super::bitmap_draw_rect;
+    use super::Bitmap;
+    use super::BitmapBuffer;
+    use crate::rect::Rect;
+
+    #[test]
+    fn blit_with_stride() {
+        let mut src = BitmapBuffer::new(2, 2, 4);
+        let mut dst = BitmapBuffer::new(4, 4, 4);
+        bitmap_draw_rect(&mut src, 1, 0, 0, 2, 2).unwrap();
+        let updated = bitmap_blit(&mut dst, &src, &Rect::new(0, 0, 2, 2).unwrap(), 1, 1);
+        assert_eq!(updated, Rect::new(1, 1, 2, 2));
+        assert_eq!(*dst.pixel_at(0, 0).unwrap(), 0);
+        assert_eq!(*dst.pixel_at(1, 1).unwrap(), 1);
+        assert_eq!(*dst.pixel_at(2, 2).unwrap(), 1);
+        assert_eq!(*dst.pixel_at(3, 3).unwrap(), 0);
+    }
+    #[test]
+    fn blit_is_clipped() {
+        let mut src = BitmapBuffer::new(4, 4, 4);
+        let mut dst = BitmapBuffer::new(4, 4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                *src.pixel_at_mut(x, y).unwrap() = (y * 10 + x) as u32;
+            }
+        }
+        // Out of the dst on the top-left
+        let updated = bitmap_blit(&mut dst, &src, &Rect::new(0, 0, 4, 4).unwrap(), -1, -2);
+        assert_eq!(updated, Rect::new(0, 0, 3, 2));
+        assert_eq!(*dst.pixel_at(0, 0).unwrap(), 21);
+        assert_eq!(*dst.pixel_at(2, 1).unwrap(), 33);
+        assert_eq!(*dst.pixel_at(3, 0).unwrap(), 0);
+        // Out of the src
+        let updated = bitmap_blit(&mut dst, &src, &Rect::new(3, 3, 4, 4).unwrap(), 3, 3);
+        assert_eq!(updated, Rect::new(3, 3, 1, 1));
+        assert_eq!(*dst.pixel_at(3, 3).unwrap(), 33);
+        // Completely out of the dst
+        assert!(bitmap_blit(&mut dst, &src, &Rect::new(0, 0, 4, 4).unwrap(), 4, 0).is_none());
+    }
+}
+
+/// A color in the ARGB format (0xAARRGGBB) with straight (not premultiplied) alpha.
+/// The other functions in this module take opaque colors in the 0xRRGGBB format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color(u32);
+impl Color {
+    pub const TRANSPARENT: Color = Color(0);
+    pub const BLACK: Color = Color::from_rgb(0x000000);
+    pub const WHITE: Color = Color::from_rgb(0xffffff);
+
+    pub const fn from_argb(argb: u32) -> Self {
+        Self(argb)
+    }
+    /// Makes an opaque color from 0xRRGGBB.
+    pub const fn from_rgb(rgb: u32) -> Self {
+        Self(0xff00_0000 | (rgb & 0xff_ffff))
+    }
+    pub const fn argb(self) -> u32 {
+        self.0
+    }
+    pub const fn rgb(self) -> u32 {
+        self.0 & 0xff_ffff
+    }
+    pub const fn alpha(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+    pub const fn with_alpha(self, alpha: u8) -> Self {
+        Self(((alpha as u32) << 24) | self.rgb())
+    }
+    // Multiplies the alpha by the coverage (0-255) of the pixel.
+    fn with_coverage(self, coverage: u8) -> Self {
+        let a = (self.alpha() as u32 * coverage as u32 + 127) / 255;
+        self.with_alpha(a as u8)
+    }
+}
+
+fn channel(c: u32, shift: u32) -> u32 {
+    (c >> shift) & 0xff
+}
+
+/// Blends `src` over the opaque pixel `dst` (0xRRGGBB). The upper 8 bits of dst are kept.
+pub fn blend_src_over(dst: u32, src: Color) -> u32 {
+    let sa = src.alpha() as u32;
+    match sa {
+        0 => return dst,
+        255 => return (dst & 0xff00_0000) | src.rgb(),
+        _ => {}
+    }
+    let mut out = dst & 0xff00_0000;
+    for shift in [0, 8, 16] {
+        let c = (channel(src.0, shift) * sa + channel(dst, shift) * (255 - sa) + 127) / 255;
+        out |= c << shift;
+    }
+    out
+}
+
+/// Blends `src` over `dst` in the ARGB format with straight alpha.
+pub fn blend_src_over_argb(dst: u32, src: Color) -> u32 {
+    let sa = src.alpha() as u32;
+    let da = channel(dst, 24);
+    match (sa, da) {
+        (0, _) => return dst,
+        (255, _) | (_, 0) => return src.0,
+        _ => {}
+    }
+    // The weight of dst is da * (1 - sa), scaled by 255 * 255
+    let dw = da * (255 - sa);
+    let a = sa * 255 + dw;
+    let mut out = ((a + 127) / 255) << 24;
+    for shift in [0, 8, 16] {
+        let c = (channel(src.0, shift) * sa * 255 + channel(dst, shift) * dw + a / 2) / a;
+        out |= c << shift;
+    }
+    out
+}
+
+// Maps the center of the pixel `d` of `dst_len` pixels onto the source of
+// `src_len` pixels. Returns the left (or upper) source pixel, and the weight
+// of the next one in 1/256 units.
+fn sample_position(d: i64, dst_len: i64, src_len: i64) -> (i64, u32) {
+    // In 1/256 pixels: (d + 0.5) * src_len / dst_len - 0.5
+    let s = ((2 * d + 1) * src_len * 256 / (2 * dst_len) - 128).clamp(0, (src_len - 1) * 256);
+    (s >> 8, (s & 0xff) as u32)
+}
+
+// Interpolates the four ARGB pixels around (sx + fx / 256, sy + fy / 256).
+// The colors are weighted by their alpha so transparent pixels don't bleed.
+fn sample_bilinear<S: Bitmap>(src: &S, sx: i64, sy: i64, fx: u32, fy: u32) -> u32 {
+    let mut alpha = 0;
+    let mut sums = [0; 3];
+    for (dy, wy) in [(0, 256 - fy), (1, fy)] {
+        for (dx, wx) in [(0, 256 - fx), (1, fx)] {
+            let weight = wx * wy;
+            if weight == 0 {
+                continue;
+            }
+            let c = match src.pixel_at(sx + dx, sy + dy) {
+                Some(c) => *c,
+                None => continue,
+            };
+            let a = channel(c, 24) * weight;
+            alpha += a;
+            for (sum, shift) in sums.iter_mut().zip([0, 8, 16]) {
+                *sum += channel(c, shift) as u64 * a as u64;
+            }
+        }
+    }
+    if alpha == 0 {
+        return 0;
+    }
+    let mut out = ((alpha + (1 << 15)) >> 16) << 24;
+    for (sum, shift) in sums.iter().zip([0, 8, 16]) {
+        out |= (((sum + alpha as u64 / 2) / alpha as u64) as u32) << shift;
+    }
+    out
+}
+
+/// Draws shapes into a bitmap with alpha blending and anti-aliasing.
+///
+/// The coordinates are relative to the origin, which is the top-left corner
+/// of the bitmap by default. Nothing is drawn out of the clip rect.
+pub struct Canvas<'a, T: Bitmap> {
+    bitmap: &'a mut T,
+    origin: (i64, i64),
+    // In the bitmap coordinates. None if nothing can be drawn.
+    clip: Option<Rect>,
+    has_alpha: bool,
+}
+impl<'a, T: Bitmap> Canvas<'a, T> {
+    /// Makes a canvas for a bitmap of opaque pixels (0xRRGGBB) like the framebuffer.
+    pub fn new(bitmap: &'a mut T) -> Self {
+        let clip = Self::bounds(bitmap);
+        Self {
+            bitmap,
+            origin: (0, 0),
+            clip,
+            has_alpha: false,
+        }
+    }
+    /// Makes a canvas for a bitmap of ARGB pixels. The alpha channel is blended as well,
+    /// so the bitmap can be drawn onto another canvas with draw_bitmap() later.
+    pub fn with_alpha_channel(bitmap: &'a mut T) -> Self {
+        Self {
+            has_alpha: true,
+            ..Self::new(bitmap)
+        }
+    }
+    fn bounds(bitmap: &T) -> Option<Rect> {
+        Rect::new(
+            0,
+            0,
+            min(bitmap.width(), bitmap.pixels_per_line()),
+            bitmap.height(),
+        )
+    }
+    /// Moves the origin to (x, y) in the bitmap. The clip rect stays the same place.
+    pub fn set_origin(&mut self, x: i64, y: i64) {
+        self.origin = (x, y);
+    }
+    /// Limits drawing to `rect` (relative to the origin) in the bitmap.
+    pub fn set_clip_rect(&mut self, rect: Rect) {
+        let rect = Rect::new(
+            rect.x() + self.origin.0,
+            rect.y() + self.origin.1,
+            rect.w(),
+            rect.h(),
+        );
+        self.clip = rect.and_then(|r| Self::bounds(self.bitmap)?.intersection(&r));
+    }
+    /// Allows drawing to the whole bitmap.
+    pub fn reset_clip_rect(&mut self) {
+        self.clip = Self::bounds(self.bitmap);
+    }
+    /// Returns the clip rect relative to the origin.
+    pub fn clip_rect(&self) -> Option<Rect> {
+        let c = self.clip?;
+        Rect::new(c.x() - self.origin.0, c.y() - self.origin.1, c.w(), c.h())
+    }
+    // (x, y) is in the bitmap coordinates and should be in the clip rect.
+    fn blend_at(&mut self, x: i64, y: i64, color: Color) {
+        if let Some(p) = self.bitmap.pixel_at_mut(x, y) {
+            *p = if self.has_alpha {
+                blend_src_over_argb(*p, color)
+            } else {
+                blend_src_over(*p, color)
+            };
+        }
+    }
+    fn is_in_clip(&self, x: i64, y: i64) -> bool {
+        match &self.clip {
+            Some(c) => c.x() <= x && x < c.x() + c.w() && c.y() <= y && y < c.y() + c.h(),
+            None => false,
+        }
+    }
+    pub fn blend_pixel(&mut self, x: i64, y: i64, color: Color) {
+        let (x, y) = (x + self.origin.0, y + self.origin.1);
+        if self.is_in_clip(x, y) {
+            self.blend_at(x, y, color);
+        }
+    }
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let rect = match Rect::new(
+            rect.x() + self.origin.0,
+            rect.y() + self.origin.1,
+            rect.w(),
+            rect.h(),
+        )
+        .zip(self.clip)
+        .and_then(|(r, c)| r.intersection(&c))
+        {
+            Some(r) => r,
+            None => return,
+        };
+        for y in rect.y()..rect.y() + rect.h() {
+            for x in rect.x()..rect.x() + rect.w() {
+                self.blend_at(x, y, color);
+            }
+        }
+    }
+    /// Fills the area inside of the path, with anti-aliasing.
+    pub fn fill_path(&mut self, path: &Path, rule: FillRule, color: Color) {
+        let clip = match self.clip {
+            Some(c) => c,
+            None => return,
+        };
+        let (ox, oy) = self.origin;
+        path.fill(
+            rule,
+            clip.x() - ox,
+            clip.y() - oy,
+            clip.w(),
+            clip.h(),
+            |x, y, coverage| self.blend_at(x + ox, y + oy, color.with_coverage(coverage)),
+        );
+    }
+    pub fn fill_polygon(&mut self, points: &[Point], rule: FillRule, color: Color) {
+        let mut path = Path::new();
+        path.add_polygon(points);
+        self.fill_path(&path, rule, color);
+    }
+    /// Draws an anti-aliased line with the width in pixels. The ends are cut square.
+    pub fn draw_line(&mut self, p0: Point, p1: Point, width: f32, color: Color) {
+        let (dx, dy) = (p1.x - p0.x, p1.y - p0.y);
+        let len = sqrt(dx * dx + dy * dy);
+        if len <= f32::EPSILON {
+            return;
+        }
+        // Half of the width in the direction perpendicular to the line
+        let (nx, ny) = (-dy / len * width * 0.5, dx / len * width * 0.5);
+        self.fill_polygon(
+            &[
+                Point::new(p0.x + nx, p0.y + ny),
+                Point::new(p1.x + nx, p1.y + ny),
+                Point::new(p1.x - nx, p1.y - ny),
+                Point::new(p0.x - nx, p0.y - ny),
+            ],
+            FillRule::NonZero,
+            color,
+        );
+    }
+    pub fn fill_circle(&mut self, center: Point, radius: f32, color: Color) {
+        let mut path = Path::new();
+        path.add_circle(center, radius);
+        self.fill_path(&path, FillRule::NonZero, color);
+    }
+    /// Draws the outline of a circle. The line is centered on the circle.
+    pub fn stroke_circle(&mut self, center: Point, radius: f32, width: f32, color: Color) {
+        let mut path = Path::new();
+        path.add_circle(center, radius + width * 0.5);
+        if radius > width * 0.5 {
+            path.add_circle(center, radius - width * 0.5);
+        }
+        self.fill_path(&path, FillRule::EvenOdd, color);
+    }
+    pub fn fill_rounded_rect(&mut self, rect: Rect, radius: f32, color: Color) {
+        let mut path = Path::new();
+        path.add_rounded_rect(
+            rect.x() as f32,
+            rect.y() as f32,
+            rect.w() as f32,
+            rect.h() as f32,
+            radius,
+        );
+        self.fill_path(&path, FillRule::NonZero, color);
+    }
+    /// Draws the outline of a rounded rect inside of the rect.
+    pub fn stroke_rounded_rect(&mut self, rect: Rect, radius: f32, width: f32, color: Color) {
+        let (x, y, w, h) = (
+            rect.x() as f32,
+            rect.y() as f32,
+            rect.w() as f32,
+            rect.h() as f32,
+        );
+        let mut path = Path::new();
+        path.add_rounded_rect(x, y, w, h, radius);
+        if w > width * 2.0 && h > width * 2.0 {
+            path.add_rounded_rect(
+                x + width,
+                y + width,
+                w - width * 2.0,
+                h - width * 2.0,
+                (radius - width).max(0.0),
+            );
+        }
+        self.fill_path(&path, FillRule::EvenOdd, color);
+    }
+    /// Blends the ARGB pixels of `src` at (x, y).
+    pub fn draw_bitmap<S: Bitmap>(&mut self, src: &S, x: i64, y: i64) {
+        let (x, y) = (x + self.origin.0, y + self.origin.1);
+        let rect = match Rect::new(x, y, min(src.width(), src.pixels_per_line()), src.height())
+            .zip(self.clip)
+            .and_then(|(r, c)| r.intersection(&c))
+        {
+            Some(r) => r,
+            None => return,
+        };
+        for py in rect.y()..rect.y() + rect.h() {
+            for px in rect.x()..rect.x() + rect.w() {
+                if let Some(c) = src.pixel_at(px - x, py - y) {
+                    self.blend_at(px, py, Color::from_argb(*c));
+                }
+            }
+        }
+    }
+    /// Blends the ARGB pixels of `src` scaled to fill `rect`, with bilinear filtering.
+    pub fn draw_image<S: Bitmap>(&mut self, src: &S, rect: Rect) {
+        let (sw, sh) = (min(src.width(), src.pixels_per_line()), src.height());
+        if sw == 0 || sh == 0 {
+            return;
+        }
+        let (x, y) = (rect.x() + self.origin.0, rect.y() + self.origin.1);
+        let (w, h) = (rect.w(), rect.h());
+        let dst = match Rect::new(x, y, w, h)
+            .zip(self.clip)
+            .and_then(|(r, c)| r.intersection(&c))
+        {
+            Some(r) => r,
+            None => return,
+        };
+        for py in dst.y()..dst.y() + dst.h() {
+            let (sy, fy) = sample_position(py - y, h, sh);
+            for px in dst.x()..dst.x() + dst.w() {
+                let (sx, fx) = sample_position(px - x, w, sw);
+                let c = sample_bilinear(src, sx, sy, fx, fy);
+                self.blend_at(px, py, Color::from_argb(c));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod canvas_tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_blended() {
+        let half_red = Color::from_rgb(0xff0000).with_alpha(0x80);
+        assert_eq!(blend_src_over(0x0000ff, half_red), 0x80007f);
+        assert_eq!(blend_src_over(0x0000ff, Color::TRANSPARENT), 0x0000ff);
+        assert_eq!(blend_src_over(0x0000ff, Color::WHITE), 0xffffff);
+        // The alpha of the destination is taken into account
+        assert_eq!(blend_src_over_argb(0x00123456, half_red), 0x80ff0000);
+        assert_eq!(blend_src_over_argb(0xff0000ff, half_red), 0xff80007f);
+        assert_eq!(blend_src_over_argb(0x800000ff, half_red), 0xc0aa0055);
+    }
+
+    #[test]
+    fn rects_are_clipped() {
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        let mut canvas = Canvas::new(&mut buf);
+        canvas.set_origin(2, 2);
+        canvas.set_clip_rect(Rect::new(0, 0, 4, 4).unwrap());
+        assert_eq!(canvas.clip_rect(), Rect::new(0, 0, 4, 4));
+        canvas.fill_rect(Rect::new(-2, -2, 10, 10).unwrap(), Color::WHITE);
+        canvas.blend_pixel(5, 5, Color::WHITE);
+        assert_eq!(*buf.pixel_at(1, 1).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(2, 2).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(5, 5).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(6, 6).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(7, 7).unwrap(), 0);
+    }
+
+    #[test]
+    fn lines_are_antialiased() {
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        let mut canvas = Canvas::new(&mut buf);
+        // Covers the half of the rows 1 and 2
+        canvas.draw_line(
+            Point::new(0.0, 2.0),
+            Point::new(8.0, 2.0),
+            1.0,
+            Color::WHITE,
+        );
+        assert_eq!(*buf.pixel_at(3, 0).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(3, 1).unwrap(), 0x808080);
+        assert_eq!(*buf.pixel_at(3, 2).unwrap(), 0x808080);
+        assert_eq!(*buf.pixel_at(3, 3).unwrap(), 0);
+    }
+
+    #[test]
+    fn circles_are_filled() {
+        let mut buf = BitmapBuffer::new(16, 16, 16);
+        let mut canvas = Canvas::new(&mut buf);
+        canvas.fill_circle(Point::new(8.0, 8.0), 6.0, Color::from_rgb(0x00ff00));
+        assert_eq!(*buf.pixel_at(8, 8).unwrap(), 0x00ff00);
+        assert_eq!(*buf.pixel_at(3, 8).unwrap(), 0x00ff00);
+        assert_eq!(*buf.pixel_at(1, 1).unwrap(), 0);
+        // On the edge
+        let edge = *buf.pixel_at(3, 3).unwrap();
+        assert!(0 < edge && edge < 0x00ff00, "{edge:#x}");
+
+        let mut buf = BitmapBuffer::new(16, 16, 16);
+        let mut canvas = Canvas::new(&mut buf);
+        canvas.stroke_circle(Point::new(8.5, 8.5), 5.0, 2.0, Color::WHITE);
+        assert_eq!(*buf.pixel_at(8, 8).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(13, 8).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(15, 8).unwrap(), 0);
+    }
+
+    #[test]
+    fn rounded_rects_have_round_corners() {
+        let mut buf = BitmapBuffer::new(16, 16, 16);
+        let mut canvas = Canvas::new(&mut buf);
+        canvas.fill_rounded_rect(Rect::new(0, 0, 16, 16).unwrap(), 6.0, Color::WHITE);
+        assert_eq!(*buf.pixel_at(0, 0).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(15, 15).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(8, 0).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(0, 8).unwrap(), 0xffffff);
+
+        let mut buf = BitmapBuffer::new(16, 16, 16);
+        let mut canvas = Canvas::new(&mut buf);
+        canvas.stroke_rounded_rect(Rect::new(0, 0, 16, 16).unwrap(), 4.0, 2.0, Color::WHITE);
+        assert_eq!(*buf.pixel_at(8, 1).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(8, 2).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(8, 8).unwrap(), 0);
+    }
+
+    #[test]
+    fn fill_rules_are_applied() {
+        // Two squares in the same direction. The inner one is a hole with the even-odd rule.
+        let outer = [
+            Point::new(0.0, 0.0),
+            Point::new(8.0, 0.0),
+            Point::new(8.0, 8.0),
+            Point::new(0.0, 8.0),
+        ];
+        let inner = [
+            Point::new(2.0, 2.0),
+            Point::new(6.0, 2.0),
+            Point::new(6.0, 6.0),
+            Point::new(2.0, 6.0),
+        ];
+        let mut path = Path::new();
+        path.add_polygon(&outer);
+        path.add_polygon(&inner);
+        for (rule, center) in [(FillRule::NonZero, 0xffffff), (FillRule::EvenOdd, 0)] {
+            let mut buf = BitmapBuffer::new(8, 8, 8);
+            let mut canvas = Canvas::new(&mut buf);
+            canvas.fill_path(&path, rule, Color::WHITE);
+            assert_eq!(*buf.pixel_at(1, 1).unwrap(), 0xffffff);
+            assert_eq!(*buf.pixel_at(4, 4).unwrap(), center);
+        }
+    }
+
+    #[test]
+    fn shapes_out_of_the_bitmap_are_clipped() {
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        let mut canvas = Canvas::new(&mut buf);
+        canvas.fill_polygon(
+            &[
+                Point::new(-100.0, -100.0),
+                Point::new(4.0, -100.0),
+                Point::new(4.0, 100.0),
+            ],
+            FillRule::NonZero,
+            Color::WHITE,
+        );
+        assert_eq!(*buf.pixel_at(0, 7).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(3, 0).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(4, 0).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(7, 7).unwrap(), 0);
+    }
+
+    #[test]
+    fn images_are_scaled() {
+        let mut src = BitmapBuffer::new(2, 2, 2);
+        *src.pixel_at_mut(0, 0).unwrap() = 0xffff0000;
+        *src.pixel_at_mut(1, 0).unwrap() = 0xff0000ff;
+        *src.pixel_at_mut(0, 1).unwrap() = 0xff00ff00;
+        *src.pixel_at_mut(1, 1).unwrap() = 0x00000000;
+        // Same size: copied as is
+        let mut dst = BitmapBuffer::new(4, 4, 4);
+        let mut canvas = Canvas::with_alpha_channel(&mut dst);
+        canvas.draw_image(&src, Rect::new(1, 1, 2, 2).unwrap());
+        assert_eq!(*dst.pixel_at(1, 1).unwrap(), 0xffff0000);
+        assert_eq!(*dst.pixel_at(2, 1).unwrap(), 0xff0000ff);
+        assert_eq!(*dst.pixel_at(1, 2).unwrap(), 0xff00ff00);
+        assert_eq!(*dst.pixel_at(2, 2).unwrap(), 0);
+        assert_eq!(*dst.pixel_at(0, 0).unwrap(), 0);
+
+        // Enlarged 4 times: the corners keep the colors, and the middle of the
+        // top edge is interpolated.
+        let mut dst = BitmapBuffer::new(8, 8, 8);
+        let mut canvas = Canvas::with_alpha_channel(&mut dst);
+        canvas.draw_image(&src, Rect::new(0, 0, 8, 8).unwrap());
+        assert_eq!(*dst.pixel_at(0, 0).unwrap(), 0xffff0000);
+        assert_eq!(*dst.pixel_at(7, 0).unwrap(), 0xff0000ff);
+        assert_eq!(*dst.pixel_at(7, 7).unwrap(), 0);
+        let mid = *dst.pixel_at(4, 0).unwrap();
+        assert_eq!(mid >> 24, 0xff);
+        assert!((0x50..0xb0).contains(&((mid >> 16) & 0xff)));
+        assert!((0x50..0xb0).contains(&(mid & 0xff)));
+        // The transparent pixel doesn't darken its neighbors
+        let c = *dst.pixel_at(0, 7).unwrap();
+        assert_eq!(c & 0xffffff, 0x00ff00);
+
+        // Shrunk to 1x1: averaged
+        let mut dst = BitmapBuffer::new(1, 1, 1);
+        let mut canvas = Canvas::with_alpha_channel(&mut dst);
+        canvas.draw_image(&src, Rect::new(0, 0, 1, 1).unwrap());
+        assert_eq!(*dst.pixel_at(0, 0).unwrap() >> 24, 0xbf);
+    }
+
+    #[test]
+    fn bitmaps_with_alpha_are_blended() {
+        let mut layer = BitmapBuffer::new(4, 4, 4);
+        let mut canvas = Canvas::with_alpha_channel(&mut layer);
+        canvas.fill_rect(
+            Rect::new(0, 0, 2, 4).unwrap(),
+            Color::from_rgb(0xff0000).with_alpha(0x80),
+        );
+        let mut buf = BitmapBuffer::new(4, 4, 4);
+        bitmap_draw_rect(&mut buf, 0x0000ff, 0, 0, 4, 4).unwrap();
+        let mut canvas = Canvas::new(&mut buf);
+        canvas.draw_bitmap(&layer, 1, 0);
+        assert_eq!(*buf.pixel_at(0, 0).unwrap(), 0x0000ff);
+        assert_eq!(*buf.pixel_at(1, 0).unwrap(), 0x80007f);
+        assert_eq!(*buf.pixel_at(2, 3).unwrap(), 0x80007f);
+        assert_eq!(*buf.pixel_at(3, 0).unwrap(), 0x0000ff);
+    }
+}
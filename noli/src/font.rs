@@ -1,3 +1,159 @@
-// This proc-macro call will generate an const variable that contains font bitmap.
+//! Embedded bitmap font
+//!
+//! Every glyph is 16 pixels tall, and is either narrow (8 pixels wide) or
+//! wide (16 pixels wide). Wide glyphs are used for CJK characters, which
+//! take two cells in a text console.
+
+pub const GLYPH_HEIGHT: i64 = 16;
+pub const NARROW_GLYPH_WIDTH: i64 = 8;
+pub const WIDE_GLYPH_WIDTH: i64 = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BitmapGlyph {
+    code_point: u32,
+    wide: bool,
+    // Bit i of each row is the pixel at the column i
+    rows: [u16; 16],
+}
+impl BitmapGlyph {
+    pub fn is_wide(&self) -> bool {
+        self.wide
+    }
+    pub fn width(&self) -> i64 {
+        if self.wide {
+            WIDE_GLYPH_WIDTH
+        } else {
+            NARROW_GLYPH_WIDTH
+        }
+    }
+    pub fn height(&self) -> i64 {
+        GLYPH_HEIGHT
+    }
+    /// Returns true if the pixel at (x, y) of the glyph should be drawn in the foreground color.
+    pub fn is_set(&self, x: i64, y: i64) -> bool {
+        if x < 0 || x >= self.width() || y < 0 || y >= self.height() {
+            return false;
+        }
+        (self.rows[y as usize] >> x) & 1 == 1
+    }
+}
+
+// This proc-macro call will generate a static array `BITMAP_FONT` that
+// contains the glyphs sorted by their code points.
 // Please check /wasabi/font for more info.
 font::gen_embedded_font!();
+
+// Boxes drawn for the characters that don't have glyphs in the font
+static REPLACEMENT_GLYPH: BitmapGlyph = BitmapGlyph {
+    code_point: 0xFFFD,
+    wide: false,
+    rows: [
+        0x00, 0x7e, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7e, 0x00,
+        0x00,
+    ],
+};
+static WIDE_REPLACEMENT_GLYPH: BitmapGlyph = BitmapGlyph {
+    code_point: 0xFFFD,
+    wide: true,
+    rows: [
+        0x0000, 0x7ffe, 0x4002, 0x4002, 0x4002, 0x4002, 0x4002, 0x4002, 0x4002, 0x4002, 0x4002,
+        0x4002, 0x4002, 0x4002, 0x7ffe, 0x0000,
+    ],
+};
+
+/// Returns true if the character takes two cells in a text console
+/// (East Asian Wide and Fullwidth characters).
+fn is_wide_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD
+    )
+}
+
+/// Returns the glyph of the character if the font has it.
+pub fn lookup_glyph(c: char) -> Option<&'static BitmapGlyph> {
+    BITMAP_FONT
+        .binary_search_by_key(&(c as u32), |g| g.code_point)
+        .ok()
+        .map(|i| &BITMAP_FONT[i])
+}
+
+/// Returns the glyph to draw the character. A box is returned for the
+/// characters without glyphs, with the width the character should have.
+pub fn glyph_for_char(c: char) -> &'static BitmapGlyph {
+    match lookup_glyph(c) {
+        Some(glyph) => glyph,
+        None if is_wide_char(c) => &WIDE_REPLACEMENT_GLYPH,
+        None => &REPLACEMENT_GLYPH,
+    }
+}
+
+/// Returns the width of the character in pixels when drawn with the bitmap font.
+pub fn char_width(c: char) -> i64 {
+    glyph_for_char(c).width()
+}
+
+/// Returns the width of the string in pixels when drawn with the bitmap font.
+pub fn string_width(s: &str) -> i64 {
+    s.chars().map(char_width).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs_are_sorted() {
+        for w in BITMAP_FONT.windows(2) {
+            assert!(w[0].code_point < w[1].code_point);
+        }
+    }
+
+    #[test]
+    fn ascii_and_latin1_are_narrow() {
+        for c in ['A', 'z', '~', 'é', 'Ö', 'ß', 'α', 'Ω', 'Ж', 'я'] {
+            let glyph = lookup_glyph(c).unwrap();
+            assert!(!glyph.is_wide(), "{c}");
+            assert_eq!(char_width(c), NARROW_GLYPH_WIDTH);
+        }
+        // The glyph of 'I' has the vertical bar in the column 3
+        assert!(glyph_for_char('I').is_set(3, 6));
+        assert!(!glyph_for_char('I').is_set(0, 6));
+        assert!(!glyph_for_char(' ').is_set(3, 6));
+    }
+
+    #[test]
+    fn kana_are_wide() {
+        for c in "わさびワサビ、。ー".chars() {
+            let glyph = lookup_glyph(c).unwrap();
+            assert!(glyph.is_wide(), "{c}");
+        }
+        assert_eq!(string_width("わさびOS"), 16 * 3 + 8 * 2);
+        // Half-width katakana stay narrow
+        assert_eq!(char_width('ｱ'), NARROW_GLYPH_WIDTH);
+    }
+
+    #[test]
+    fn missing_glyphs_are_replaced_with_boxes() {
+        assert!(lookup_glyph('山').is_none());
+        assert_eq!(glyph_for_char('山'), &WIDE_REPLACEMENT_GLYPH);
+        assert_eq!(char_width('山'), WIDE_GLYPH_WIDTH);
+        assert!(lookup_glyph('\u{0e01}').is_none());
+        assert_eq!(glyph_for_char('\u{0e01}'), &REPLACEMENT_GLYPH);
+        assert!(glyph_for_char('\u{0e01}').is_set(1, 1));
+        assert!(!glyph_for_char('\u{0e01}').is_set(8, 1));
+    }
+}
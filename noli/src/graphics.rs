@@ -2,7 +2,8 @@ extern crate alloc;
 
 use crate::error::Error;
 use crate::error::Result;
-use crate::font::BITMAP_FONT;
+use crate::font::char_width;
+use crate::font::glyph_for_char;
 use crate::prelude::*;
 use core::cmp::max;
 use core::cmp::min;
@@ -13,7 +14,7 @@ pub fn draw_string_3x(color: u32, x: i64, y: i64, s: &str) -> Result<()> {
     let mut pos = 0;
     for c in s.chars() {
         draw_char_3x(color, x + pos, y, c)?;
-        pos += 24;
+        pos += char_width(c) * 3;
     }
     Ok(())
 }
@@ -23,7 +24,7 @@ pub fn draw_string_2x(color: u32, x: i64, y: i64, s: &str) -> Result<()> {
     let mut pos = 0;
     for c in s.chars() {
         draw_char_2x(color, x + pos, y, c)?;
-        pos += 16;
+        pos += char_width(c) * 2;
     }
     Ok(())
 }
@@ -32,7 +33,7 @@ pub fn draw_string_1p5x(color: u32, x: i64, y: i64, s: &str) -> Result<()> {
     let mut pos = 0;
     for c in s.chars() {
         draw_char_1p5x(color, x + pos, y, c)?;
-        pos += 12;
+        pos += char_width(c) * 3 / 2;
     }
     Ok(())
 }
@@ -42,7 +43,7 @@ pub fn draw_string(color: u32, x: i64, y: i64, s: &str) -> Result<()> {
     let mut pos = 0;
     for c in s.chars() {
         draw_char(color, x + pos, y, c)?;
-        pos += 8;
+        pos += char_width(c);
     }
     Ok(())
 }
@@ -51,7 +52,7 @@ pub fn draw_string_with_underline(color: u32, x: i64, y: i64, s: &str) -> Result
     let mut pos = 0;
     for c in s.chars() {
         draw_char(color, x + pos, y, c)?;
-        pos += 8;
+        pos += char_width(c);
     }
     draw_line(color, x, y + 16, x + pos, y + 16)?;
     Ok(())
@@ -83,21 +84,21 @@ pub fn draw_char_1p5x(color: u32, px: i64, py: i64, c: char) -> Result<()> {
     // size (1.5x): 12 * 24
     // size (2x): 16 * 32
     // size (3x): 24 * 48
-    let font_data = BITMAP_FONT[c as usize];
-    let mut font_3x = [[false; 24]; 48];
+    let glyph = glyph_for_char(c);
+    let mut font_3x = [[false; 48]; 48];
 
     for (y, font_3x_row_bitmap) in font_3x.iter_mut().enumerate() {
         for (x, font_3x_pixel) in font_3x_row_bitmap.iter_mut().enumerate() {
             let original_x = x / 3;
             let original_y = y / 3;
-            if (font_data[original_y] >> original_x) & 0b1 == 0b1 {
+            if glyph.is_set(original_x as i64, original_y as i64) {
                 *font_3x_pixel = true;
             }
         }
     }
 
     for y in 0..24 {
-        for x in 0..12 {
+        for x in 0..(glyph.width() * 3 / 2) as usize {
             // oo
             // oo -> black 0x00000
             //
@@ -163,13 +164,13 @@ pub fn draw_char_1p5x(color: u32, px: i64, py: i64, c: char) -> Result<()> {
 // ooooooooo
 // ooooooooo
 pub fn draw_char_3x(color: u32, px: i64, py: i64, c: char) -> Result<()> {
-    let font_data = BITMAP_FONT[c as usize];
-    for y in 0..font_data.len() * 3 {
-        for x in 0..24 {
+    let glyph = glyph_for_char(c);
+    for y in 0..glyph.height() * 3 {
+        for x in 0..glyph.width() * 3 {
             let original_x = x / 3;
             let original_y = y / 3;
-            if (font_data[original_y] >> original_x) & 0b1 == 0b1 {
-                draw_point(color, px + x, py + y as i64)?;
+            if glyph.is_set(original_x, original_y) {
+                draw_point(color, px + x, py + y)?;
             }
         }
     }
@@ -199,11 +200,10 @@ pub fn draw_char_3x(color: u32, px: i64, py: i64, c: char) -> Result<()> {
 // oooo
 // oooo
 pub fn draw_char_2x(color: u32, px: i64, py: i64, c: char) -> Result<()> {
-    let font_data = BITMAP_FONT[c as usize];
-    for (y, row_bitmap) in font_data.iter().enumerate() {
-        for x in 0..8 {
-            if (row_bitmap >> x) & 0b1 == 0b1 {
-                let y = y as i64;
+    let glyph = glyph_for_char(c);
+    for y in 0..glyph.height() {
+        for x in 0..glyph.width() {
+            if glyph.is_set(x, y) {
                 draw_point(color, px + x * 2, py + y * 2)?;
                 draw_point(color, px + x * 2 + 1, py + y * 2)?;
                 draw_point(color, px + x * 2, py + y * 2 + 1)?;
@@ -242,14 +242,14 @@ pub fn draw_char_2x(color: u32, px: i64, py: i64, c: char) -> Result<()> {
 // oooooo
 // oooooo
 
-/// Draws a character to the position of `x` and `y`. Upper case characters, lower case characters
-/// and symbols are supported.
+/// Draws a character to the position of `x` and `y`. Wide characters (e.g. CJK) take 16 pixels
+/// horizontally, and the others take 8 pixels.
 pub fn draw_char(color: u32, px: i64, py: i64, c: char) -> Result<()> {
-    let font_data = BITMAP_FONT[c as usize];
-    for (y, row_bitmap) in font_data.iter().enumerate() {
-        for x in 0..8 {
-            if (row_bitmap >> x) & 0b1 == 0b1 {
-                draw_point(color, px + x, py + y as i64)?;
+    let glyph = glyph_for_char(c);
+    for y in 0..glyph.height() {
+        for x in 0..glyph.width() {
+            if glyph.is_set(x, y) {
+                draw_point(color, px + x, py + y)?;
             }
         }
     }
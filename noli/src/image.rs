@@ -0,0 +1,970 @@
+//! Image decoders for BMP, PNG and GIF
+//!
+//! Images are decoded into a BitmapBuffer of ARGB pixels (0xAARRGGBB, straight alpha)
+//! that can be drawn with Canvas::draw_bitmap() or Canvas::draw_image().
+//!
+//! - BMP: 24-bit and 32-bit uncompressed (BI_RGB) and BI_BITFIELDS images
+//! - PNG: all the color types and bit depths, with palettes, tRNS and Adam7 interlacing
+//! - GIF: the first frame, with transparency and interlacing
+//!
+//! c.f. https://www.w3.org/TR/png/
+//! c.f. https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+
+extern crate alloc;
+
+use crate::bitmap::Bitmap;
+use crate::bitmap::BitmapBuffer;
+use crate::bitmap::Canvas;
+use crate::error::Error;
+use crate::error::Result;
+use crate::inflate::zlib_decompress_into;
+use crate::prelude::*;
+use crate::rect::Rect;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Larger images are rejected before allocating buffers for them
+const MAX_IMAGE_WIDTH: usize = 8192;
+const MAX_IMAGE_HEIGHT: usize = 8192;
+
+fn read_bytes(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    data.get(offset..offset + len)
+        .ok_or(Error::Failed("image: out of range"))
+}
+fn read_u8(data: &[u8], offset: usize) -> Result<u8> {
+    Ok(read_bytes(data, offset, 1)?[0])
+}
+fn read_u16_le(data: &[u8], offset: usize) -> Result<u16> {
+    let b = read_bytes(data, offset, 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32> {
+    let b = read_bytes(data, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+fn read_u32_be(data: &[u8], offset: usize) -> Result<u32> {
+    let b = read_bytes(data, offset, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn argb(a: u8, r: u8, g: u8, b: u8) -> u32 {
+    (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+fn new_image(width: usize, height: usize) -> Result<BitmapBuffer> {
+    if width == 0 || height == 0 || width > MAX_IMAGE_WIDTH || height > MAX_IMAGE_HEIGHT {
+        return Err(Error::Failed("image: unsupported size"));
+    }
+    Ok(BitmapBuffer::new(width as i64, height as i64, width as i64))
+}
+
+fn set_pixel(image: &mut BitmapBuffer, x: usize, y: usize, c: u32) {
+    if let Some(p) = image.pixel_at_mut(x as i64, y as i64) {
+        *p = c;
+    }
+}
+
+// Extracts the bits of `mask` from `v` and scales them to 8 bits.
+fn extract_bits(v: u32, mask: u32) -> Option<u8> {
+    if mask == 0 {
+        return None;
+    }
+    let shift = mask.trailing_zeros();
+    let max = mask >> shift;
+    Some((((v & mask) >> shift) as u64 * 255 / max as u64) as u8)
+}
+
+/// Decodes a BMP image of 24 or 32 bits per pixel.
+/// 32-bit images without any non-zero alpha are treated as opaque,
+/// since many encoders leave the unused byte zero.
+pub fn decode_bmp(data: &[u8]) -> Result<BitmapBuffer> {
+    if read_bytes(data, 0, 2)? != b"BM" {
+        return Err(Error::Failed("BMP: invalid signature"));
+    }
+    let pixels_offset = read_u32_le(data, 10)? as usize;
+    let header_size = read_u32_le(data, 14)?;
+    if header_size < 40 {
+        return Err(Error::Failed("BMP: OS/2 bitmaps are not supported"));
+    }
+    let width = read_u32_le(data, 18)? as i32;
+    let height = read_u32_le(data, 22)? as i32;
+    let bpp = read_u16_le(data, 28)?;
+    let compression = read_u32_le(data, 30)?;
+    // Rows are stored from the bottom unless the height is negative
+    let bottom_up = height > 0;
+    let (width, height) = (
+        width.unsigned_abs() as usize,
+        height.unsigned_abs() as usize,
+    );
+    if bpp != 24 && bpp != 32 {
+        return Err(Error::Failed(
+            "BMP: only 24-bit and 32-bit images are supported",
+        ));
+    }
+    let masks = match compression {
+        // BI_RGB
+        0 => {
+            if bpp == 24 {
+                [0xff_0000, 0xff00, 0xff, 0]
+            } else {
+                [0xff_0000, 0xff00, 0xff, 0xff00_0000]
+            }
+        }
+        // BI_BITFIELDS and BI_ALPHABITFIELDS. The masks follow the 40-byte
+        // header, or are part of the V4/V5 headers at the same offset.
+        3 | 6 if bpp == 32 => {
+            let alpha = if compression == 6 || header_size >= 56 {
+                read_u32_le(data, 66)?
+            } else {
+                0
+            };
+            [
+                read_u32_le(data, 54)?,
+                read_u32_le(data, 58)?,
+                read_u32_le(data, 62)?,
+                alpha,
+            ]
+        }
+        _ => return Err(Error::Failed("BMP: unsupported compression")),
+    };
+    let mut image = new_image(width, height)?;
+    let bytes_per_pixel = bpp as usize / 8;
+    let stride = (width * bytes_per_pixel + 3) & !3;
+    let mut has_alpha = false;
+    for row in 0..height {
+        let line = read_bytes(data, pixels_offset + row * stride, width * bytes_per_pixel)?;
+        let y = if bottom_up { height - 1 - row } else { row };
+        for (x, p) in line.chunks_exact(bytes_per_pixel).enumerate() {
+            let v = if bpp == 24 {
+                u32::from_le_bytes([p[0], p[1], p[2], 0])
+            } else {
+                u32::from_le_bytes([p[0], p[1], p[2], p[3]])
+            };
+            let a = extract_bits(v, masks[3]).unwrap_or(0xff);
+            has_alpha |= a != 0;
+            let r = extract_bits(v, masks[0]).unwrap_or(0);
+            let g = extract_bits(v, masks[1]).unwrap_or(0);
+            let b = extract_bits(v, masks[2]).unwrap_or(0);
+            set_pixel(&mut image, x, y, argb(a, r, g, b));
+        }
+    }
+    if !has_alpha {
+        for y in 0..height {
+            for x in 0..width {
+                if let Some(p) = image.pixel_at_mut(x as i64, y as i64) {
+                    *p |= 0xff00_0000;
+                }
+            }
+        }
+    }
+    Ok(image)
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// (x, y) of the first pixel and the distance between pixels of each Adam7 pass
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for data in chunks {
+        for &b in data.iter() {
+            crc ^= b as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+    }
+    !crc
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PngColorType {
+    Gray,
+    Rgb,
+    Indexed,
+    GrayAlpha,
+    Rgba,
+}
+impl PngColorType {
+    fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            0 => Ok(Self::Gray),
+            2 => Ok(Self::Rgb),
+            3 => Ok(Self::Indexed),
+            4 => Ok(Self::GrayAlpha),
+            6 => Ok(Self::Rgba),
+            _ => Err(Error::Failed("PNG: invalid color type")),
+        }
+    }
+    fn channels(self) -> usize {
+        match self {
+            Self::Gray | Self::Indexed => 1,
+            Self::GrayAlpha => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+    fn is_valid_bit_depth(self, depth: u8) -> bool {
+        match self {
+            Self::Gray => matches!(depth, 1 | 2 | 4 | 8 | 16),
+            Self::Indexed => matches!(depth, 1 | 2 | 4 | 8),
+            _ => matches!(depth, 8 | 16),
+        }
+    }
+}
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: PngColorType,
+    interlaced: bool,
+}
+impl PngHeader {
+    fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+    // The number of bytes of a scanline of `width` pixels, without the filter type
+    fn stride(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel() + 7) / 8
+    }
+    // (x0, y0, dx, dy) of each pass with its size (width, height) in pixels.
+    // Non-interlaced images have one pass.
+    fn passes(&self) -> impl Iterator<Item = (usize, usize, usize, usize, usize, usize)> + '_ {
+        let passes: &[_] = if self.interlaced {
+            &ADAM7_PASSES
+        } else {
+            &[(0, 0, 1, 1)]
+        };
+        passes.iter().map(|&(x0, y0, dx, dy)| {
+            let w = (self.width + dx - 1 - x0) / dx;
+            let h = (self.height + dy - 1 - y0) / dy;
+            (x0, y0, dx, dy, w, h)
+        })
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Reverts the filters of the scanlines in place. Each scanline starts with its filter type.
+fn unfilter_png(data: &mut [u8], stride: usize, height: usize, bpp: usize) -> Result<()> {
+    for y in 0..height {
+        let (prev, cur) = data.split_at_mut(y * (stride + 1));
+        let prev = if y == 0 {
+            None
+        } else {
+            Some(&prev[prev.len() - stride..])
+        };
+        let (filter, line) = cur[..stride + 1].split_first_mut().unwrap();
+        let up = |i: usize| prev.map_or(0, |p| p[i]);
+        match *filter {
+            0 => {}
+            1 => {
+                for i in bpp..stride {
+                    line[i] = line[i].wrapping_add(line[i - bpp]);
+                }
+            }
+            2 => {
+                for (i, v) in line.iter_mut().enumerate() {
+                    *v = v.wrapping_add(up(i));
+                }
+            }
+            3 => {
+                for i in 0..stride {
+                    let left = if i >= bpp { line[i - bpp] as u16 } else { 0 };
+                    line[i] = line[i].wrapping_add(((left + up(i) as u16) / 2) as u8);
+                }
+            }
+            4 => {
+                for i in 0..stride {
+                    let (left, up_left) = if i >= bpp {
+                        (line[i - bpp], up(i - bpp))
+                    } else {
+                        (0, 0)
+                    };
+                    line[i] = line[i].wrapping_add(paeth(left, up(i), up_left));
+                }
+            }
+            _ => return Err(Error::Failed("PNG: invalid filter type")),
+        }
+    }
+    Ok(())
+}
+
+// Returns the `index`-th sample of `bit_depth` bits in the scanline.
+fn png_sample(line: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([line[index * 2], line[index * 2 + 1]]),
+        8 => line[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            ((line[bit / 8] >> shift) & ((1 << bit_depth) - 1)) as u16
+        }
+    }
+}
+
+/// Decodes a PNG image.
+pub fn decode_png(data: &[u8]) -> Result<BitmapBuffer> {
+    if read_bytes(data, 0, 8)? != PNG_SIGNATURE {
+        return Err(Error::Failed("PNG: invalid signature"));
+    }
+    let mut header = None;
+    let mut palette: Vec<u32> = Vec::new();
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut offset = 8;
+    loop {
+        let len = read_u32_be(data, offset)? as usize;
+        let chunk_type = read_bytes(data, offset + 4, 4)?;
+        let body = read_bytes(data, offset + 8, len)?;
+        let crc = read_u32_be(data, offset + 8 + len)?;
+        if crc != crc32(&[chunk_type, body]) {
+            return Err(Error::Failed("PNG: CRC mismatch"));
+        }
+        offset += len + 12;
+        match chunk_type {
+            b"IHDR" => {
+                let color_type = PngColorType::from_u8(read_u8(body, 9)?)?;
+                let bit_depth = read_u8(body, 8)?;
+                if !color_type.is_valid_bit_depth(bit_depth) {
+                    return Err(Error::Failed("PNG: invalid bit depth"));
+                }
+                if read_u8(body, 10)? != 0 || read_u8(body, 11)? != 0 {
+                    return Err(Error::Failed("PNG: unknown compression or filter method"));
+                }
+                header = Some(PngHeader {
+                    width: read_u32_be(body, 0)? as usize,
+                    height: read_u32_be(body, 4)? as usize,
+                    bit_depth,
+                    color_type,
+                    interlaced: read_u8(body, 12)? == 1,
+                });
+            }
+            b"PLTE" => {
+                palette = body
+                    .chunks_exact(3)
+                    .map(|c| argb(0xff, c[0], c[1], c[2]))
+                    .collect();
+            }
+            b"tRNS" => transparency = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {
+                // Ancillary chunks (with a lowercase first letter) can be ignored
+                if chunk_type[0] & 0x20 == 0 {
+                    return Err(Error::Failed("PNG: unknown critical chunk"));
+                }
+            }
+        }
+    }
+    let header = header.ok_or(Error::Failed("PNG: no IHDR"))?;
+    let mut image = new_image(header.width, header.height)?;
+    if header.color_type == PngColorType::Indexed {
+        if palette.is_empty() {
+            return Err(Error::Failed("PNG: no palette"));
+        }
+        for (c, &a) in palette.iter_mut().zip(transparency) {
+            *c = (*c & 0xff_ffff) | (a as u32) << 24;
+        }
+    }
+    // The color treated as transparent for Gray and Rgb images
+    let transparent_color: Option<[u16; 3]> = match (header.color_type, transparency.len()) {
+        (PngColorType::Gray, 2..) => {
+            let v = u16::from_be_bytes([transparency[0], transparency[1]]);
+            Some([v; 3])
+        }
+        (PngColorType::Rgb, 6..) => {
+            let v = |i: usize| u16::from_be_bytes([transparency[i], transparency[i + 1]]);
+            Some([v(0), v(2), v(4)])
+        }
+        _ => None,
+    };
+
+    let expected_size: usize = header
+        .passes()
+        .filter(|p| p.4 > 0)
+        .map(|p| (header.stride(p.4) + 1) * p.5)
+        .sum();
+    let mut raw = Vec::with_capacity(expected_size);
+    zlib_decompress_into(&compressed, &mut raw, expected_size)?;
+    drop(compressed);
+    if raw.len() < expected_size {
+        return Err(Error::Failed("PNG: not enough image data"));
+    }
+
+    let bit_depth = header.bit_depth;
+    // Scales a sample to 8 bits
+    let to_u8 = |v: u16| -> u8 {
+        match bit_depth {
+            16 => (v >> 8) as u8,
+            8 => v as u8,
+            _ => (v as u32 * 255 / ((1 << bit_depth) - 1)) as u8,
+        }
+    };
+    let bytes_per_pixel = (header.bits_per_pixel() + 7) / 8;
+    let mut pass_offset = 0;
+    for (x0, y0, dx, dy, w, h) in header.passes() {
+        if w == 0 || h == 0 {
+            continue;
+        }
+        let stride = header.stride(w);
+        let pass = &mut raw[pass_offset..pass_offset + (stride + 1) * h];
+        pass_offset += (stride + 1) * h;
+        unfilter_png(pass, stride, h, bytes_per_pixel)?;
+        for (py, line) in pass.chunks_exact(stride + 1).enumerate() {
+            let line = &line[1..];
+            for px in 0..w {
+                let sample =
+                    |i: usize| png_sample(line, px * header.color_type.channels() + i, bit_depth);
+                let c = match header.color_type {
+                    PngColorType::Indexed => *palette
+                        .get(sample(0) as usize)
+                        .ok_or(Error::Failed("PNG: palette index out of range"))?,
+                    PngColorType::Gray | PngColorType::Rgb => {
+                        let rgb = if header.color_type == PngColorType::Gray {
+                            [sample(0); 3]
+                        } else {
+                            [sample(0), sample(1), sample(2)]
+                        };
+                        let a = if Some(rgb) == transparent_color {
+                            0
+                        } else {
+                            0xff
+                        };
+                        argb(a, to_u8(rgb[0]), to_u8(rgb[1]), to_u8(rgb[2]))
+                    }
+                    PngColorType::GrayAlpha => {
+                        let v = to_u8(sample(0));
+                        argb(to_u8(sample(1)), v, v, v)
+                    }
+                    PngColorType::Rgba => argb(
+                        to_u8(sample(3)),
+                        to_u8(sample(0)),
+                        to_u8(sample(1)),
+                        to_u8(sample(2)),
+                    ),
+                };
+                set_pixel(&mut image, x0 + px * dx, y0 + py * dy, c);
+            }
+        }
+    }
+    Ok(image)
+}
+
+// Reads the data sub-blocks of GIF, each of which starts with its length.
+struct GifSubBlocks<'a> {
+    data: &'a [u8],
+    pos: usize,
+    remaining: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+impl<'a> GifSubBlocks<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self {
+            data,
+            pos,
+            remaining: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+    // Returns None at the block terminator
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        if self.remaining == 0 {
+            self.remaining = read_u8(self.data, self.pos)? as usize;
+            self.pos += 1;
+            if self.remaining == 0 {
+                return Ok(None);
+            }
+        }
+        let b = read_u8(self.data, self.pos)?;
+        self.pos += 1;
+        self.remaining -= 1;
+        Ok(Some(b))
+    }
+    // Reads a code of `n` bits, LSB first
+    fn bits(&mut self, n: u32) -> Result<Option<u16>> {
+        while self.bit_count < n {
+            match self.next_byte()? {
+                Some(b) => {
+                    self.bit_buf |= (b as u32) << self.bit_count;
+                    self.bit_count += 8;
+                }
+                None => return Ok(None),
+            }
+        }
+        let v = (self.bit_buf & ((1 << n) - 1)) as u16;
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(Some(v))
+    }
+    // Skips the rest of the sub-blocks and returns the position after the terminator
+    fn skip_to_end(mut self) -> Result<usize> {
+        while self.next_byte()?.is_some() {}
+        Ok(self.pos)
+    }
+}
+
+const GIF_MAX_CODES: usize = 4096;
+
+// Decodes the LZW-compressed color indexes into `out`. Extra data is ignored.
+fn decode_gif_lzw(blocks: &mut GifSubBlocks, min_code_size: u32, out: &mut [u8]) -> Result<()> {
+    if !(2..=8).contains(&min_code_size) {
+        return Err(Error::Failed("GIF: invalid LZW code size"));
+    }
+    let clear_code = 1u16 << min_code_size;
+    let end_code = clear_code + 1;
+    // Each code is a prefix code followed by a byte
+    let mut prefix = [0u16; GIF_MAX_CODES];
+    let mut suffix = [0u8; GIF_MAX_CODES];
+    let mut lengths = [0u16; GIF_MAX_CODES];
+    for i in 0..clear_code {
+        suffix[i as usize] = i as u8;
+        lengths[i as usize] = 1;
+    }
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end_code + 1;
+    let mut prev: Option<u16> = None;
+    let mut written = 0;
+    while written < out.len() {
+        let code = match blocks.bits(code_size)? {
+            Some(code) => code,
+            None => break,
+        };
+        if code == clear_code {
+            code_size = min_code_size + 1;
+            next_code = end_code + 1;
+            prev = None;
+            continue;
+        }
+        if code == end_code {
+            break;
+        }
+        let prev_code = match prev {
+            Some(p) => p,
+            None => {
+                if code >= clear_code {
+                    return Err(Error::Failed("GIF: invalid LZW code"));
+                }
+                out[written] = code as u8;
+                written += 1;
+                prev = Some(code);
+                continue;
+            }
+        };
+        // The first byte of the string of `code`. For the code not in the table
+        // yet (the KwKwK case), it is the first byte of the previous string.
+        let known = code < next_code;
+        if !known && code != next_code {
+            return Err(Error::Failed("GIF: invalid LZW code"));
+        }
+        let first_of = |mut c: u16| {
+            while lengths[c as usize] > 1 {
+                c = prefix[c as usize];
+            }
+            suffix[c as usize]
+        };
+        let first = first_of(if known { code } else { prev_code });
+        if (next_code as usize) < GIF_MAX_CODES {
+            prefix[next_code as usize] = prev_code;
+            suffix[next_code as usize] = first;
+            lengths[next_code as usize] = lengths[prev_code as usize] + 1;
+            next_code += 1;
+            if next_code as usize == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        // Write the string backwards from its last byte
+        let len = lengths[code as usize] as usize;
+        let end = written + len;
+        let mut c = code;
+        for i in (written..end).rev() {
+            if i < out.len() {
+                out[i] = suffix[c as usize];
+            }
+            c = prefix[c as usize];
+        }
+        written = end;
+        prev = Some(code);
+    }
+    Ok(())
+}
+
+fn read_gif_color_table(data: &[u8], offset: usize, flags: u8) -> Result<(Vec<u32>, usize)> {
+    if flags & 0x80 == 0 {
+        return Ok((Vec::new(), offset));
+    }
+    let len = 3 << ((flags & 7) + 1);
+    let table = read_bytes(data, offset, len)?
+        .chunks_exact(3)
+        .map(|c| argb(0xff, c[0], c[1], c[2]))
+        .collect();
+    Ok((table, offset + len))
+}
+
+/// Decodes the first frame of a GIF image. Pixels outside of the frame are transparent.
+pub fn decode_gif(data: &[u8]) -> Result<BitmapBuffer> {
+    let signature = read_bytes(data, 0, 6)?;
+    if signature != b"GIF87a" && signature != b"GIF89a" {
+        return Err(Error::Failed("GIF: invalid signature"));
+    }
+    let width = read_u16_le(data, 6)? as usize;
+    let height = read_u16_le(data, 8)? as usize;
+    let (global_colors, mut offset) = read_gif_color_table(data, 13, read_u8(data, 10)?)?;
+    let mut image = new_image(width, height)?;
+    let mut transparent_index = None;
+    loop {
+        match read_u8(data, offset)? {
+            // Extension
+            0x21 => {
+                let label = read_u8(data, offset + 1)?;
+                // Graphic Control Extension
+                if label == 0xf9 && read_u8(data, offset + 3)? & 1 != 0 {
+                    transparent_index = Some(read_u8(data, offset + 6)?);
+                }
+                offset = GifSubBlocks::new(data, offset + 2).skip_to_end()?;
+            }
+            // Image Descriptor
+            0x2c => {
+                let left = read_u16_le(data, offset + 1)? as usize;
+                let top = read_u16_le(data, offset + 3)? as usize;
+                let w = read_u16_le(data, offset + 5)? as usize;
+                let h = read_u16_le(data, offset + 7)? as usize;
+                let flags = read_u8(data, offset + 9)?;
+                let (local_colors, next) = read_gif_color_table(data, offset + 10, flags)?;
+                let colors = if local_colors.is_empty() {
+                    &global_colors
+                } else {
+                    &local_colors
+                };
+                if colors.is_empty() {
+                    return Err(Error::Failed("GIF: no color table"));
+                }
+                let min_code_size = read_u8(data, next)? as u32;
+                let mut indexes = vec![0u8; w * h];
+                let mut blocks = GifSubBlocks::new(data, next + 1);
+                decode_gif_lzw(&mut blocks, min_code_size, &mut indexes)?;
+                // Interlaced rows are stored in 4 passes
+                let rows: Vec<usize> = if flags & 0x40 != 0 {
+                    (0..h)
+                        .step_by(8)
+                        .chain((4..h).step_by(8))
+                        .chain((2..h).step_by(4))
+                        .chain((1..h).step_by(2))
+                        .collect()
+                } else {
+                    (0..h).collect()
+                };
+                for (line, &y) in indexes.chunks_exact(w.max(1)).zip(&rows) {
+                    for (x, &i) in line.iter().enumerate() {
+                        if Some(i) == transparent_index {
+                            continue;
+                        }
+                        let c = colors.get(i as usize).copied().unwrap_or(0xff00_0000);
+                        set_pixel(&mut image, left + x, top + y, c);
+                    }
+                }
+                return Ok(image);
+            }
+            // Trailer
+            0x3b => return Err(Error::Failed("GIF: no image")),
+            _ => return Err(Error::Failed("GIF: unknown block")),
+        }
+    }
+}
+
+/// Decodes an image in any of the supported formats, detected from its signature.
+pub fn decode_image(data: &[u8]) -> Result<BitmapBuffer> {
+    if data.starts_with(&PNG_SIGNATURE) {
+        decode_png(data)
+    } else if data.starts_with(b"GIF8") {
+        decode_gif(data)
+    } else if data.starts_with(b"BM") {
+        decode_bmp(data)
+    } else {
+        Err(Error::Failed("image: unknown format"))
+    }
+}
+
+/// Loads an image from the file at `path`.
+pub fn load_image(path: &str) -> Result<BitmapBuffer> {
+    let size = Api::read_file(path, &mut []);
+    if size < 0 {
+        return Err(Error::Failed("image: failed to open the file"));
+    }
+    let mut data = vec![0u8; size as usize];
+    if Api::read_file(path, &mut data) != size {
+        return Err(Error::Failed("image: failed to read the file"));
+    }
+    decode_image(&data)
+}
+
+/// Returns a copy of the image scaled to `width` x `height` with bilinear filtering.
+pub fn scale_bitmap<T: Bitmap>(src: &T, width: i64, height: i64) -> Result<BitmapBuffer> {
+    let mut scaled = new_image(width.max(0) as usize, height.max(0) as usize)?;
+    let rect = Rect::new(0, 0, width, height).ok_or(Error::Failed("image: invalid size"))?;
+    Canvas::with_alpha_channel(&mut scaled).draw_image(src, rect);
+    Ok(scaled)
+}
+
+/// Returns the largest size to show a `width` x `height` image in a
+/// `max_width` x `max_height` area, keeping the aspect ratio.
+/// Images smaller than the area are not enlarged.
+pub fn fit_size(width: i64, height: i64, max_width: i64, max_height: i64) -> (i64, i64) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+    if width * max_height > height * max_width {
+        (max_width, (height * max_width / width).max(1))
+    } else {
+        ((width * max_height / height).max(1), max_height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &BitmapBuffer, x: i64, y: i64) -> u32 {
+        *image.pixel_at(x, y).unwrap()
+    }
+
+    #[test]
+    fn bmp_24bit_is_decoded() {
+        // 2x2, bottom-up. Each row is padded to 8 bytes.
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&(54u32 + 16).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&54u32.to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&2i32.to_le_bytes());
+        data.extend_from_slice(&2i32.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&24u16.to_le_bytes());
+        data.extend_from_slice(&[0; 24]);
+        // Bottom row: blue, green. Top row: red, white. (BGR order)
+        data.extend_from_slice(&[0xff, 0, 0, 0, 0xff, 0, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0]);
+        let image = decode_image(&data).unwrap();
+        assert_eq!(image.width(), 2);
+        assert_eq!(image.height(), 2);
+        assert_eq!(pixel(&image, 0, 0), 0xffff0000);
+        assert_eq!(pixel(&image, 1, 0), 0xffffffff);
+        assert_eq!(pixel(&image, 0, 1), 0xff0000ff);
+        assert_eq!(pixel(&image, 1, 1), 0xff00ff00);
+        // Truncated data is an error
+        assert!(decode_bmp(&data[..60]).is_err());
+    }
+
+    #[test]
+    fn bmp_32bit_is_decoded() {
+        // 1x2, top-down (negative height), with alpha
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&(54u32 + 8).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&54u32.to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&1i32.to_le_bytes());
+        data.extend_from_slice(&(-2i32).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&[0; 24]);
+        data.extend_from_slice(&[0x30, 0x20, 0x10, 0x80, 0x01, 0x02, 0x03, 0x00]);
+        let image = decode_bmp(&data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), 0x80102030);
+        assert_eq!(pixel(&image, 0, 1), 0x00030201);
+        // Without any alpha, the pixels are opaque
+        let len = data.len();
+        data[len - 5] = 0;
+        let image = decode_bmp(&data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), 0xff102030);
+        assert_eq!(pixel(&image, 0, 1), 0xff030201);
+    }
+
+    // Builds a PNG file from the chunks
+    fn png(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        for &(chunk_type, body) in chunks {
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            data.extend_from_slice(chunk_type);
+            data.extend_from_slice(body);
+            data.extend_from_slice(&crc32(&[chunk_type, body]).to_be_bytes());
+        }
+        data
+    }
+    fn ihdr(w: u32, h: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&w.to_be_bytes());
+        body.extend_from_slice(&h.to_be_bytes());
+        body.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        body
+    }
+    // Wraps the data in a zlib stream of stored blocks
+    fn zlib_stored(raw: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x78, 0x01];
+        data.push(0x01);
+        data.extend_from_slice(&(raw.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(raw.len() as u16)).to_le_bytes());
+        data.extend_from_slice(raw);
+        let (mut a, mut b) = (1u32, 0u32);
+        for &x in raw {
+            a = (a + x as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        data.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn png_rgba_is_decoded() {
+        // 2x2 RGBA with the Sub filter on the first row and the Up filter on the second
+        let raw = [
+            1, 0xff, 0, 0, 0xff, 0x01, 0xff, 0x00, 0x00, // red, then +(1, ff, 0, 0)
+            2, 0, 0, 0xff, 0x80, 0, 0, 0, 0x80, // +red / +(0,0,0,80) from above
+        ];
+        let data = png(&[
+            (b"IHDR", &ihdr(2, 2, 8, 6, 0)),
+            (b"tEXt", b"Comment\0hello"),
+            (b"IDAT", &zlib_stored(&raw)),
+            (b"IEND", &[]),
+        ]);
+        let image = decode_image(&data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), 0xffff0000);
+        assert_eq!(pixel(&image, 1, 0), 0xff00ff00);
+        assert_eq!(pixel(&image, 0, 1), 0x7fff00ff);
+        assert_eq!(pixel(&image, 1, 1), 0x7f00ff00);
+        // A broken CRC is detected
+        let mut broken = data.clone();
+        broken[30] ^= 1;
+        assert!(decode_png(&broken).is_err());
+    }
+
+    #[test]
+    fn png_palette_and_gray_are_decoded() {
+        // 3x1, 2-bit palette with transparency for the index 1
+        let raw = [0, 0b00_01_10_00];
+        let data = png(&[
+            (b"IHDR", &ihdr(3, 1, 2, 3, 0)),
+            (b"PLTE", &[0xff, 0, 0, 0, 0xff, 0, 0, 0, 0xff]),
+            (b"tRNS", &[0xff, 0x00]),
+            (b"IDAT", &zlib_stored(&raw)),
+            (b"IEND", &[]),
+        ]);
+        let image = decode_png(&data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), 0xffff0000);
+        assert_eq!(pixel(&image, 1, 0), 0x0000ff00);
+        assert_eq!(pixel(&image, 2, 0), 0xff0000ff);
+
+        // 2x1, 16-bit gray with the Paeth filter
+        let raw = [4, 0x80, 0x00, 0xff, 0xff];
+        let data = png(&[
+            (b"IHDR", &ihdr(2, 1, 16, 0, 0)),
+            (b"IDAT", &zlib_stored(&raw)),
+            (b"IEND", &[]),
+        ]);
+        let image = decode_png(&data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), 0xff808080);
+        // 0xffff + 0x8000 (left) wraps to 0x7fff
+        assert_eq!(pixel(&image, 1, 0), 0xff7f7f7f);
+    }
+
+    #[test]
+    fn png_interlaced_is_decoded() {
+        // 3x3 1-bit gray, Adam7. Pass 1: (0,0), pass 4: (2,0), pass 5: (0,2) (2,2),
+        // pass 6: (1,0) / (1,2), pass 7: row 1. Passes 2 and 3 are empty.
+        let raw = [
+            0,
+            0b1000_0000, // pass 1
+            0,
+            0b0000_0000, // pass 4
+            0,
+            0b1000_0000, // pass 5
+            0,
+            0b0000_0000, // pass 6
+            0,
+            0b1000_0000,
+            0,
+            0b1010_0000, // pass 7
+        ];
+        let data = png(&[
+            (b"IHDR", &ihdr(3, 3, 1, 0, 1)),
+            (b"IDAT", &zlib_stored(&raw)),
+            (b"IEND", &[]),
+        ]);
+        let image = decode_png(&data).unwrap();
+        let expected = [[1, 0, 0], [1, 0, 1], [1, 1, 0]];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, &v) in row.iter().enumerate() {
+                let c = if v == 1 { 0xffffffff } else { 0xff000000 };
+                assert_eq!(pixel(&image, x as i64, y as i64), c, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn gif_is_decoded() {
+        // A 3x2 GIF89a with a 4-color global table, and the index 3 transparent.
+        // Indexes: 0 1 2 / 2 1 3, LZW-compressed with the minimum code size 2.
+        let data = [
+            b'G', b'I', b'F', b'8', b'9', b'a', 3, 0, 2, 0, 0x81, 0, 0, // header
+            0xff, 0, 0, 0, 0xff, 0, 0, 0, 0xff, 0, 0, 0, // colors
+            0x21, 0xf9, 4, 0x01, 0, 0, 3, 0, // graphic control, transparent: 3
+            0x2c, 0, 0, 0, 0, 3, 0, 2, 0, 0, // image descriptor
+            2, 4, 0x44, 0x24, 0x31, 0x05, 0, // LZW data
+            0x3b,
+        ];
+        let image = decode_image(&data).unwrap();
+        assert_eq!(pixel(&image, 0, 0), 0xffff0000);
+        assert_eq!(pixel(&image, 1, 0), 0xff00ff00);
+        assert_eq!(pixel(&image, 2, 0), 0xff0000ff);
+        assert_eq!(pixel(&image, 0, 1), 0xff0000ff);
+        assert_eq!(pixel(&image, 1, 1), 0xff00ff00);
+        assert_eq!(pixel(&image, 2, 1), 0);
+    }
+
+    #[test]
+    fn images_are_fit_into_areas() {
+        assert_eq!(fit_size(100, 50, 200, 200), (100, 50));
+        assert_eq!(fit_size(400, 200, 200, 200), (200, 100));
+        assert_eq!(fit_size(200, 400, 200, 200), (100, 200));
+        let image = decode_image(&png(&[
+            (b"IHDR", &ihdr(1, 1, 8, 2, 0)),
+            (b"IDAT", &zlib_stored(&[0, 1, 2, 3])),
+            (b"IEND", &[]),
+        ]))
+        .unwrap();
+        let scaled = scale_bitmap(&image, 3, 2).unwrap();
+        assert_eq!(scaled.width(), 3);
+        assert_eq!(pixel(&scaled, 2, 1), 0xff010203);
+        assert!(decode_image(b"not an image").is_err());
+    }
+}
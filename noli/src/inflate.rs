@@ -0,0 +1,421 @@
+//! Decompressor of DEFLATE (RFC 1951) and zlib (RFC 1950) streams
+//!
+//! All the block types (stored, fixed Huffman and dynamic Huffman) are
+//! supported. Huffman codes are decoded canonically bit by bit, which is
+//! slow but small enough for the images and files apps load.
+//! c.f. https://www.rfc-editor.org/rfc/rfc1951
+
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use alloc::vec::Vec;
+
+const MAX_CODE_LENGTH: usize = 15;
+const NUM_LITERAL_CODES: usize = 288;
+const NUM_DISTANCE_CODES: usize = 30;
+const END_OF_BLOCK: u16 = 256;
+const OUTPUT_TOO_LARGE: Error = Error::Failed("inflate: output is larger than the limit");
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order of the code lengths of the code length alphabet in dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+    /// Reads `n` (<= 16) bits, LSB first.
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.bit_count < n {
+            let b = *self
+                .data
+                .get(self.pos)
+                .ok_or(Error::Failed("inflate: unexpected end of data"))?;
+            self.pos += 1;
+            self.bit_buf |= (b as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let v = self.bit_buf & ((1u32 << n) - 1);
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(v)
+    }
+    /// Discards the bits up to the next byte boundary.
+    fn align_to_byte(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(Error::Failed("inflate: unexpected end of data"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+/// A canonical Huffman code
+struct Huffman {
+    // The number of codes for each length
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    // Symbols sorted by their codes
+    symbols: [u16; NUM_LITERAL_CODES],
+}
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        // Reject over-subscribed codes. Incomplete codes are allowed (e.g. a
+        // distance code with only one symbol).
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(Error::Failed("inflate: over-subscribed Huffman code"));
+            }
+        }
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 2];
+        for len in 1..=MAX_CODE_LENGTH {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = [0u16; NUM_LITERAL_CODES];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+    fn decode(&self, br: &mut BitReader) -> Result<u16> {
+        // The first code and the index of its symbol for the current length
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_CODE_LENGTH {
+            code |= br.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Failed("inflate: invalid Huffman code"))
+    }
+}
+
+fn fixed_huffman_codes() -> Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; NUM_LITERAL_CODES];
+    for (i, len) in lengths.iter_mut().enumerate() {
+        *len = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    let literal = Huffman::new(&lengths)?;
+    let distance = Huffman::new(&[5; NUM_DISTANCE_CODES])?;
+    Ok((literal, distance))
+}
+
+fn dynamic_huffman_codes(br: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let num_literals = br.bits(5)? as usize + 257;
+    let num_distances = br.bits(5)? as usize + 1;
+    let num_code_lengths = br.bits(4)? as usize + 4;
+    if num_literals > 286 || num_distances > NUM_DISTANCE_CODES {
+        return Err(Error::Failed("inflate: too many codes"));
+    }
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..num_code_lengths] {
+        code_lengths[i] = br.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // The code lengths of the literal/length and distance codes are
+    // encoded as one sequence, so repeats can cross the boundary.
+    let mut lengths = [0u8; 286 + NUM_DISTANCE_CODES];
+    let total = num_literals + num_distances;
+    let mut i = 0;
+    while i < total {
+        let symbol = code_length_code.decode(br)?;
+        let (len, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(Error::Failed("inflate: no code length to repeat"));
+                }
+                (lengths[i - 1], 3 + br.bits(2)? as usize)
+            }
+            17 => (0, 3 + br.bits(3)? as usize),
+            _ => (0, 11 + br.bits(7)? as usize),
+        };
+        if i + repeat > total {
+            return Err(Error::Failed("inflate: too many code lengths"));
+        }
+        lengths[i..i + repeat].fill(len);
+        i += repeat;
+    }
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(Error::Failed("inflate: no end of block code"));
+    }
+    let literal = Huffman::new(&lengths[..num_literals])?;
+    let distance = Huffman::new(&lengths[num_literals..total])?;
+    Ok((literal, distance))
+}
+
+fn inflate_block(
+    br: &mut BitReader,
+    literal: &Huffman,
+    distance: &Huffman,
+    out: &mut Vec<u8>,
+    max_len: usize,
+) -> Result<()> {
+    loop {
+        let symbol = literal.decode(br)?;
+        match symbol {
+            0..=255 => {
+                if out.len() >= max_len {
+                    return Err(OUTPUT_TOO_LARGE);
+                }
+                out.push(symbol as u8)
+            }
+            END_OF_BLOCK => return Ok(()),
+            _ => {
+                let i = (symbol - 257) as usize;
+                if i >= LENGTH_BASE.len() {
+                    return Err(Error::Failed("inflate: invalid length code"));
+                }
+                let len = LENGTH_BASE[i] as usize + br.bits(LENGTH_EXTRA_BITS[i] as u32)? as usize;
+                let i = distance.decode(br)? as usize;
+                if i >= DISTANCE_BASE.len() {
+                    return Err(Error::Failed("inflate: invalid distance code"));
+                }
+                let dist =
+                    DISTANCE_BASE[i] as usize + br.bits(DISTANCE_EXTRA_BITS[i] as u32)? as usize;
+                if dist > out.len() {
+                    return Err(Error::Failed("inflate: distance too far back"));
+                }
+                if len > max_len - out.len() {
+                    return Err(OUTPUT_TOO_LARGE);
+                }
+                // The source and the destination can overlap, so copy byte by byte.
+                let start = out.len() - dist;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+/// Decompresses a raw DEFLATE stream and appends the result to `out`.
+/// Fails if the result is longer than `limit` bytes.
+/// Reserve the expected size in `out` beforehand to avoid reallocations.
+/// Returns the number of bytes consumed from `data`.
+pub fn inflate_into(data: &[u8], out: &mut Vec<u8>, limit: usize) -> Result<usize> {
+    let max_len = out.len().saturating_add(limit);
+    let mut br = BitReader::new(data);
+    loop {
+        let is_final = br.bits(1)? == 1;
+        match br.bits(2)? {
+            0 => {
+                br.align_to_byte();
+                let header = br.bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(Error::Failed("inflate: corrupted stored block"));
+                }
+                if len as usize > max_len - out.len() {
+                    return Err(OUTPUT_TOO_LARGE);
+                }
+                out.extend_from_slice(br.bytes(len as usize)?);
+            }
+            1 => {
+                let (literal, distance) = fixed_huffman_codes()?;
+                inflate_block(&mut br, &literal, &distance, out, max_len)?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_huffman_codes(&mut br)?;
+                inflate_block(&mut br, &literal, &distance, out, max_len)?;
+            }
+            _ => return Err(Error::Failed("inflate: invalid block type")),
+        }
+        if is_final {
+            return Ok(br.pos);
+        }
+    }
+}
+
+/// Decompresses a raw DEFLATE stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    inflate_into(data, &mut out, usize::MAX)?;
+    Ok(out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest n such that the sums do not overflow u32
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses a zlib stream and appends the result to `out`.
+/// The header and the Adler-32 checksum are verified.
+/// Fails if the result is longer than `limit` bytes.
+pub fn zlib_decompress_into(data: &[u8], out: &mut Vec<u8>, limit: usize) -> Result<()> {
+    if data.len() < 6 {
+        return Err(Error::Failed("zlib: too short"));
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 {
+        return Err(Error::Failed("zlib: unsupported compression method"));
+    }
+    if ((cmf as u16) << 8 | flg as u16) % 31 != 0 {
+        return Err(Error::Failed("zlib: broken header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(Error::Failed("zlib: preset dictionaries are not supported"));
+    }
+    let start = out.len();
+    let consumed = inflate_into(&data[2..], out, limit)?;
+    let checksum = data
+        .get(2 + consumed..2 + consumed + 4)
+        .ok_or(Error::Failed("zlib: no checksum"))?;
+    let checksum = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if checksum != adler32(&out[start..]) {
+        return Err(Error::Failed("zlib: checksum mismatch"));
+    }
+    Ok(())
+}
+
+/// Decompresses a zlib stream.
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    zlib_decompress_into(data, &mut out, usize::MAX)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_blocks_are_copied() {
+        // Two stored blocks: "abc" and "de" (final)
+        let data = [
+            0x00, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x01, 0x02, 0x00, 0xfd, 0xff, b'd',
+            b'e',
+        ];
+        assert_eq!(inflate(&data).unwrap(), b"abcde");
+        // LEN and NLEN should match
+        let data = [0x01, 0x03, 0x00, 0xfc, 0xfe, b'a', b'b', b'c'];
+        assert!(inflate(&data).is_err());
+    }
+
+    #[test]
+    fn fixed_huffman_blocks_are_decoded() {
+        // zlib.compress(b"hello hello hello hello")
+        let data = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        assert_eq!(zlib_decompress(&data).unwrap(), b"hello hello hello hello");
+        // A broken checksum is detected
+        let mut broken = data;
+        broken[15] ^= 1;
+        assert!(zlib_decompress(&broken).is_err());
+        // Truncated data is an error, not a panic
+        assert!(zlib_decompress(&data[..8]).is_err());
+    }
+
+    #[test]
+    fn dynamic_huffman_blocks_are_decoded() {
+        // zlib.compress(b"".join(bytes([i % 7 + 97]) * (i % 5 + 1) for i in range(40)))
+        let expected: Vec<u8> = (0..40u8)
+            .flat_map(|i| core::iter::repeat(i % 7 + 97).take((i % 5 + 1) as usize))
+            .collect();
+        let data = [
+            0x78, 0xda, 0x55, 0x8d, 0xb1, 0x0d, 0x00, 0x30, 0x0c, 0xc2, 0x6e, 0x05, 0xd2, 0xe4,
+            0xff, 0x0f, 0x4a, 0x51, 0x96, 0x32, 0x5a, 0xb6, 0x00, 0x29, 0xa9, 0xbc, 0xf3, 0xd6,
+            0x33, 0x00, 0xc8, 0x50, 0x73, 0x93, 0x36, 0x0b, 0x35, 0x8f, 0x19, 0xef, 0x6d, 0x62,
+            0x6a, 0xfb, 0x3a, 0x31, 0xb1, 0x3d, 0x15, 0xb3, 0xb7, 0x1f, 0xfc, 0x47, 0x17, 0x0f,
+            0xac, 0x2e, 0xdc,
+        ];
+        // BTYPE of the first block is 2 (dynamic Huffman)
+        assert_eq!((data[2] >> 1) & 3, 2);
+        assert_eq!(zlib_decompress(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn output_is_limited() {
+        // A stored block "abc" and the zlib stream of "hello hello hello hello"
+        let stored = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        let zlib = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        let mut out = b"xy".to_vec();
+        assert!(inflate_into(&stored, &mut out, 2).is_err());
+        let mut out = b"xy".to_vec();
+        inflate_into(&stored, &mut out, 3).unwrap();
+        assert_eq!(out, b"xyabc");
+        let mut out = Vec::new();
+        assert!(zlib_decompress_into(&zlib, &mut out, 22).is_err());
+        // The limit is checked before copying a match, not after
+        assert!(out.len() <= 22);
+        let mut out = Vec::new();
+        zlib_decompress_into(&zlib, &mut out, 23).unwrap();
+        assert_eq!(out, b"hello hello hello hello");
+    }
+
+    #[test]
+    fn adler32_is_correct() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&[]), 1);
+    }
+}
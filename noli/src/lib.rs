@@ -7,12 +7,17 @@ pub mod bitmap;
 pub mod error;
 pub mod font;
 pub mod graphics;
+pub mod image;
+pub mod inflate;
 pub mod mem;
 pub mod net;
 pub mod prelude;
 pub mod print;
+pub mod process;
+pub mod rasterizer;
 pub mod rect;
 pub mod sheet;
 pub mod sys;
 pub mod text_area;
+pub mod truetype;
 pub mod window;
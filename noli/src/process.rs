@@ -0,0 +1,92 @@
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::*;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// A process builder, similar to std::process::Command.
+/// The program is looked up in the root directory of the OS.
+#[derive(Debug, Clone)]
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+}
+impl Command {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            args: Vec::new(),
+        }
+    }
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(arg.to_string());
+        self
+    }
+    pub fn args(&mut self, args: &[&str]) -> &mut Self {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+    /// Args passed to the new process. The first one is the program name.
+    fn argv(&self) -> Vec<&str> {
+        let mut argv = Vec::new();
+        argv.push(self.program.as_str());
+        argv.extend(self.args.iter().map(|s| s.as_str()));
+        argv
+    }
+    /// Starts the program as a child process and returns a handle to it.
+    pub fn spawn(&mut self) -> Result<Child> {
+        let pid = Api::spawn(&self.program, &self.argv());
+        if pid >= 0 {
+            Ok(Child { pid })
+        } else {
+            Err(Error::Failed("SPAWN_FAILED"))
+        }
+    }
+    /// Starts the program as a child process and waits for it to exit.
+    pub fn status(&mut self) -> Result<ExitStatus> {
+        self.spawn()?.wait()
+    }
+}
+
+#[derive(Debug)]
+pub struct Child {
+    pid: i64,
+}
+impl Child {
+    pub fn id(&self) -> i64 {
+        self.pid
+    }
+    /// Blocks the execution until the child process exits.
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        Api::wait(self.pid)
+            .map(ExitStatus)
+            .ok_or(Error::Failed("NO_SUCH_PROCESS"))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExitStatus(i64);
+impl ExitStatus {
+    pub fn code(&self) -> i64 {
+        self.0
+    }
+    pub fn success(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    #[test]
+    fn argv_starts_with_program_name() {
+        let mut cmd = Command::new("hello1");
+        cmd.arg("a").args(&["b", "c"]);
+        assert_eq!(cmd.argv(), ["hello1", "a", "b", "c"]);
+    }
+}
@@ -0,0 +1,563 @@
+//! Anti-aliased scanline rasterizer for outlines
+//!
+//! Each edge of the outline adds the signed area it covers to an accumulation
+//! buffer, and the coverage of a pixel is computed as the prefix sum of the
+//! buffer along the scanline (the same approach as font-rs and stb_truetype).
+//! Coordinates are in pixels with the origin at the top-left corner.
+//!
+//! Rasterizer keeps the whole bitmap, which suits small outlines like glyphs.
+//! Path is rasterized line by line instead, for shapes drawn on large bitmaps.
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::max;
+use core::cmp::min;
+
+// Curves are split until the segments deviate from the curve less than this (in pixels).
+const FLATTEN_TOLERANCE: f32 = 0.1;
+const MAX_CURVE_SEGMENTS: usize = 32;
+
+pub(crate) fn floor(x: f32) -> f32 {
+    let t = x as i64 as f32;
+    if t > x {
+        t - 1.0
+    } else {
+        t
+    }
+}
+pub(crate) fn ceil(x: f32) -> f32 {
+    let t = x as i64 as f32;
+    if t < x {
+        t + 1.0
+    } else {
+        t
+    }
+}
+pub(crate) fn abs(x: f32) -> f32 {
+    if x < 0.0 {
+        -x
+    } else {
+        x
+    }
+}
+pub(crate) fn sqrt(x: f32) -> f32 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    // Newton's method from an approximation made by halving the exponent
+    let mut y = f32::from_bits((x.to_bits() >> 1) + 0x1fbd_1df5);
+    for _ in 0..4 {
+        y = 0.5 * (y + x / y);
+    }
+    y
+}
+
+/// Decides which areas are inside of outlines that overlap each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillRule {
+    /// Inside if the outlines wind around the point any times
+    NonZero,
+    /// Inside if the outlines wind around the point odd times
+    EvenOdd,
+}
+impl FillRule {
+    /// Converts the accumulated signed area of a pixel to the coverage (0-255).
+    fn coverage(self, acc: f32) -> u8 {
+        let c = match self {
+            FillRule::NonZero => abs(acc),
+            FillRule::EvenOdd => {
+                let a = abs(acc);
+                let m = a - 2.0 * floor(a * 0.5);
+                if m > 1.0 {
+                    2.0 - m
+                } else {
+                    m
+                }
+            }
+        };
+        if c >= 1.0 {
+            255
+        } else {
+            (c * 255.0 + 0.5) as u8
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+impl Point {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+    fn lerp(&self, other: &Point, t: f32) -> Point {
+        Point::new(
+            self.x + (other.x - self.x) * t,
+            self.y + (other.y - self.y) * t,
+        )
+    }
+}
+
+pub struct Rasterizer {
+    width: usize,
+    height: usize,
+    // Signed areas of the edges. The area right of the last pixel of a line
+    // spills over to the next line, which is fine since the sum of each line is 0.
+    acc: Vec<f32>,
+}
+impl Rasterizer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            acc: vec![0.0; width * height + 4],
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    fn clamp(&self, p: Point) -> Point {
+        let x = if p.x < 0.0 {
+            0.0
+        } else if p.x > self.width as f32 {
+            self.width as f32
+        } else {
+            p.x
+        };
+        Point::new(x, p.y)
+    }
+    pub fn draw_line(&mut self, p0: Point, p1: Point) {
+        // Points out of the bitmap horizontally are clamped to keep the area
+        // in the same line. Vertically, the lines out of the bitmap are skipped.
+        let (p0, p1) = (self.clamp(p0), self.clamp(p1));
+        if abs(p0.y - p1.y) <= f32::EPSILON {
+            return;
+        }
+        let (dir, p0, p1) = if p0.y < p1.y {
+            (1.0, p0, p1)
+        } else {
+            (-1.0, p1, p0)
+        };
+        let dxdy = (p1.x - p0.x) / (p1.y - p0.y);
+        let mut x = p0.x;
+        if p0.y < 0.0 {
+            x -= p0.y * dxdy;
+        }
+        let y_start = if p0.y < 0.0 { 0 } else { p0.y as usize };
+        let y_end = core::cmp::min(self.height, ceil(p1.y) as usize);
+        for y in y_start..y_end {
+            let line_start = y * self.width;
+            let y_top = if (y as f32) > p0.y { y as f32 } else { p0.y };
+            let y_bottom = if ((y + 1) as f32) < p1.y {
+                (y + 1) as f32
+            } else {
+                p1.y
+            };
+            let dy = y_bottom - y_top;
+            let x_next = x + dxdy * dy;
+            accumulate_segment(&mut self.acc[line_start..], x, x_next, dy * dir);
+            x = x_next;
+        }
+    }
+    /// Draws a quadratic Bézier curve from p0 to p2 with the control point p1.
+    pub fn draw_quad(&mut self, p0: Point, p1: Point, p2: Point) {
+        flatten_quad(p0, p1, p2, |p, q| self.draw_line(p, q));
+    }
+    /// Returns the coverage of each pixel (0-255) with the non-zero winding rule.
+    pub fn coverage(&self) -> Vec<u8> {
+        self.coverage_with_rule(FillRule::NonZero)
+    }
+    /// Returns the coverage of each pixel (0-255) with the given rule.
+    pub fn coverage_with_rule(&self, rule: FillRule) -> Vec<u8> {
+        let mut acc = 0.0;
+        self.acc[..self.width * self.height]
+            .iter()
+            .map(|a| {
+                acc += a;
+                rule.coverage(acc)
+            })
+            .collect()
+    }
+}
+
+/// Adds the signed area of a segment in a line of pixels to `acc`, which
+/// starts at the left end of the line. The segment goes from x to x_next
+/// (0 <= x, x_next <= the width) while going down `d` pixels (negative if up).
+/// `acc` should have 2 more elements than the width.
+fn accumulate_segment(acc: &mut [f32], x: f32, x_next: f32, d: f32) {
+    let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+    let x0_floor = floor(x0);
+    let x0i = x0_floor as usize;
+    let x1_ceil = ceil(x1);
+    let x1i = x1_ceil as usize;
+    if x1i <= x0i + 1 {
+        // The segment is in a pixel
+        let xmf = 0.5 * (x + x_next) - x0_floor;
+        acc[x0i] += d - d * xmf;
+        acc[x0i + 1] += d * xmf;
+    } else {
+        let s = 1.0 / (x1 - x0);
+        let x0f = x0 - x0_floor;
+        let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+        let x1f = x1 - x1_ceil + 1.0;
+        let am = 0.5 * s * x1f * x1f;
+        acc[x0i] += d * a0;
+        if x1i == x0i + 2 {
+            acc[x0i + 1] += d * (1.0 - a0 - am);
+        } else {
+            let a1 = s * (1.5 - x0f);
+            acc[x0i + 1] += d * (a1 - a0);
+            for a in &mut acc[x0i + 2..x1i - 1] {
+                *a += d * s;
+            }
+            let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+            acc[x1i - 1] += d * (1.0 - a2 - am);
+        }
+        acc[x1i] += d * am;
+    }
+}
+
+/// Splits a quadratic Bézier curve from p0 to p2 with the control point p1
+/// into lines, and calls `f` with the both ends of each line.
+fn flatten_quad(p0: Point, p1: Point, p2: Point, mut f: impl FnMut(Point, Point)) {
+    let dev = abs(p0.x - 2.0 * p1.x + p2.x) + abs(p0.y - 2.0 * p1.y + p2.y);
+    // The deviation from the chord is dev / 4 and it becomes 1/n^2 by splitting into n.
+    let mut n = 1;
+    while n < MAX_CURVE_SEGMENTS && dev > FLATTEN_TOLERANCE * 4.0 * (n * n) as f32 {
+        n += 1;
+    }
+    let mut prev = p0;
+    for i in 1..=n {
+        let t = i as f32 / n as f32;
+        let p = p0.lerp(&p1, t).lerp(&p1.lerp(&p2, t), t);
+        f(prev, p);
+        prev = p;
+    }
+}
+
+// cos and sin of 0, 22.5, 45 and 67.5 degrees
+const UNIT_VECTORS: [(f32, f32); 4] = [
+    (1.0, 0.0),
+    (0.923_879_5, 0.382_683_43),
+    (0.707_106_77, 0.707_106_77),
+    (0.382_683_43, 0.923_879_5),
+];
+// A quadratic curve approximates an arc of 45 degrees when the control point
+// is at this distance (1 / cos(22.5 degrees)) in the middle of the arc.
+const ARC_CONTROL_DISTANCE: f32 = 1.082_392_2;
+
+/// Returns the unit vector in the direction of `k` * 22.5 degrees (clockwise on the screen).
+fn unit_vector(k: usize) -> Point {
+    let (x, y) = UNIT_VECTORS[k % 4];
+    match (k / 4) % 4 {
+        0 => Point::new(x, y),
+        1 => Point::new(-y, x),
+        2 => Point::new(-x, -y),
+        _ => Point::new(y, -x),
+    }
+}
+
+/// Outlines to be filled, made of lines and curves
+#[derive(Debug, Clone, Default)]
+pub struct Path {
+    // Curves are flattened into lines when they are added
+    lines: Vec<(Point, Point)>,
+    start: Option<Point>,
+    current: Option<Point>,
+}
+impl Path {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+    /// Starts a new outline at p. The previous outline is closed.
+    pub fn move_to(&mut self, p: Point) {
+        self.close();
+        self.start = Some(p);
+        self.current = Some(p);
+    }
+    pub fn line_to(&mut self, p: Point) {
+        match self.current {
+            Some(current) => self.lines.push((current, p)),
+            None => self.start = Some(p),
+        }
+        self.current = Some(p);
+    }
+    /// Adds a quadratic Bézier curve to p with the control point c.
+    pub fn quad_to(&mut self, c: Point, p: Point) {
+        match self.current {
+            Some(current) => flatten_quad(current, c, p, |p, q| self.lines.push((p, q))),
+            None => self.start = Some(p),
+        }
+        self.current = Some(p);
+    }
+    /// Closes the current outline with a line to the start point.
+    pub fn close(&mut self) {
+        if let (Some(start), Some(current)) = (self.start, self.current) {
+            if start != current {
+                self.lines.push((current, start));
+            }
+        }
+        self.current = self.start;
+    }
+    /// Adds a closed polygon.
+    pub fn add_polygon(&mut self, points: &[Point]) {
+        if let Some((first, rest)) = points.split_first() {
+            self.move_to(*first);
+            for p in rest {
+                self.line_to(*p);
+            }
+            self.close();
+        }
+    }
+    // Adds arcs of 45 degrees from the direction of `k` * 22.5 degrees, to the current outline.
+    fn arc_to(&mut self, center: Point, radius: f32, k: usize, n: usize) {
+        for i in 0..n {
+            let m = unit_vector(k + i * 2 + 1);
+            let e = unit_vector(k + i * 2 + 2);
+            let d = radius * ARC_CONTROL_DISTANCE;
+            self.quad_to(
+                Point::new(center.x + m.x * d, center.y + m.y * d),
+                Point::new(center.x + e.x * radius, center.y + e.y * radius),
+            );
+        }
+    }
+    /// Adds a circle as a closed outline.
+    pub fn add_circle(&mut self, center: Point, radius: f32) {
+        self.move_to(Point::new(center.x + radius, center.y));
+        self.arc_to(center, radius, 0, 8);
+        self.close();
+    }
+    /// Adds a rectangle with rounded corners as a closed outline.
+    pub fn add_rounded_rect(&mut self, x: f32, y: f32, w: f32, h: f32, radius: f32) {
+        let r = radius.min(w * 0.5).min(h * 0.5).max(0.0);
+        self.move_to(Point::new(x + r, y));
+        self.line_to(Point::new(x + w - r, y));
+        self.arc_to(Point::new(x + w - r, y + r), r, 12, 2);
+        self.line_to(Point::new(x + w, y + h - r));
+        self.arc_to(Point::new(x + w - r, y + h - r), r, 0, 2);
+        self.line_to(Point::new(x + r, y + h));
+        self.arc_to(Point::new(x + r, y + h - r), r, 4, 2);
+        self.line_to(Point::new(x, y + r));
+        self.arc_to(Point::new(x + r, y + r), r, 8, 2);
+        self.close();
+    }
+    /// Returns the bounding box as (min_x, min_y, max_x, max_y).
+    pub fn bounds(&self) -> Option<(f32, f32, f32, f32)> {
+        let mut points = self.lines.iter().flat_map(|(p, q)| [p, q]);
+        let first = points.next()?;
+        Some(points.fold(
+            (first.x, first.y, first.x, first.y),
+            |(x0, y0, x1, y1), p| (x0.min(p.x), y0.min(p.y), x1.max(p.x), y1.max(p.y)),
+        ))
+    }
+    /// Calls `f(x, y, coverage)` for each pixel covered by the path in the rect
+    /// at (x, y) sized (w, h). The outlines are closed implicitly.
+    pub fn fill(
+        &self,
+        rule: FillRule,
+        x: i64,
+        y: i64,
+        w: i64,
+        h: i64,
+        mut f: impl FnMut(i64, i64, u8),
+    ) {
+        let (bx0, by0, bx1, by1) = match self.bounds() {
+            Some(b) => b,
+            None => return,
+        };
+        let x0 = max(x, floor(bx0) as i64);
+        let x1 = min(x + w, ceil(bx1) as i64);
+        let y0 = max(y, floor(by0) as i64);
+        let y1 = min(y + h, ceil(by1) as i64);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        let closing = match (self.start, self.current) {
+            (Some(start), Some(current)) if start != current => Some((current, start)),
+            _ => None,
+        };
+        let width = (x1 - x0) as usize;
+        let mut acc = vec![0.0; width + 2];
+        for py in y0..y1 {
+            acc.iter_mut().for_each(|a| *a = 0.0);
+            for (p0, p1) in self.lines.iter().chain(closing.iter()) {
+                // Relative to the top-left of the line of pixels
+                let (dir, p0, p1) = if p0.y < p1.y {
+                    (1.0, p0, p1)
+                } else {
+                    (-1.0, p1, p0)
+                };
+                let (top, bottom) = (p0.y - py as f32, p1.y - py as f32);
+                if bottom <= 0.0 || top >= 1.0 || abs(bottom - top) <= f32::EPSILON {
+                    continue;
+                }
+                let dxdy = (p1.x - p0.x) / (bottom - top);
+                let y_top = top.max(0.0);
+                let y_bottom = bottom.min(1.0);
+                let xa = p0.x - x0 as f32 + (y_top - top) * dxdy;
+                let xb = xa + (y_bottom - y_top) * dxdy;
+                accumulate_clamped_segment(
+                    &mut acc,
+                    width as f32,
+                    xa,
+                    xb,
+                    (y_bottom - y_top) * dir,
+                );
+            }
+            let mut sum = 0.0;
+            for (i, a) in acc[..width].iter().enumerate() {
+                sum += a;
+                let c = rule.coverage(sum);
+                if c > 0 {
+                    f(x0 + i as i64, py, c);
+                }
+            }
+        }
+    }
+}
+
+/// Same as accumulate_segment(), but the segment can be out of the line horizontally.
+/// The parts on the left of the line go to the left end since they cover every pixel
+/// on their right, and the parts on the right are ignored.
+fn accumulate_clamped_segment(acc: &mut [f32], width: f32, x: f32, x_next: f32, d: f32) {
+    let clamp = |x: f32| x.max(0.0).min(width);
+    let mut ts = [0.0, 1.0, 1.0, 1.0];
+    if x != x_next {
+        for (i, edge) in [0.0, width].iter().enumerate() {
+            let t = (edge - x) / (x_next - x);
+            if 0.0 < t && t < 1.0 {
+                ts[i + 1] = t;
+            }
+        }
+    }
+    if ts[1] > ts[2] {
+        ts.swap(1, 2);
+    }
+    for w in ts.windows(2) {
+        let (ta, tb) = (w[0], w[1]);
+        if tb <= ta {
+            continue;
+        }
+        let xa = clamp(x + (x_next - x) * ta);
+        let xb = clamp(x + (x_next - x) * tb);
+        accumulate_segment(acc, xa, xb, d * (tb - ta));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw_polygon(r: &mut Rasterizer, points: &[(f32, f32)]) {
+        for i in 0..points.len() {
+            let (x0, y0) = points[i];
+            let (x1, y1) = points[(i + 1) % points.len()];
+            r.draw_line(Point::new(x0, y0), Point::new(x1, y1));
+        }
+    }
+
+    #[test]
+    fn square_is_filled() {
+        let mut r = Rasterizer::new(8, 8);
+        draw_polygon(&mut r, &[(2.0, 2.0), (6.0, 2.0), (6.0, 6.0), (2.0, 6.0)]);
+        let c = r.coverage();
+        assert_eq!(c[0], 0);
+        assert_eq!(c[2 * 8 + 2], 255);
+        assert_eq!(c[5 * 8 + 5], 255);
+        assert_eq!(c[5 * 8 + 6], 0);
+        assert_eq!(c[6 * 8 + 5], 0);
+    }
+
+    #[test]
+    fn edges_are_antialiased() {
+        let mut r = Rasterizer::new(4, 4);
+        // Counter-clockwise is also filled (non-zero)
+        draw_polygon(&mut r, &[(0.5, 0.0), (0.5, 4.0), (3.0, 4.0), (3.0, 0.0)]);
+        let c = r.coverage();
+        assert_eq!(c[0], 128);
+        assert_eq!(c[1], 255);
+        assert_eq!(c[3], 0);
+    }
+
+    #[test]
+    fn triangle_has_partial_coverage_on_the_diagonal() {
+        let mut r = Rasterizer::new(4, 4);
+        draw_polygon(&mut r, &[(0.0, 0.0), (4.0, 4.0), (0.0, 4.0)]);
+        let c = r.coverage();
+        // Half of the pixels on the diagonal are covered
+        for i in 0..4 {
+            assert_eq!(c[i * 4 + i], 128);
+        }
+        assert_eq!(c[3 * 4], 255);
+        assert_eq!(c[3], 0);
+    }
+
+    #[test]
+    fn even_odd_rule_makes_holes() {
+        let mut r = Rasterizer::new(8, 8);
+        draw_polygon(&mut r, &[(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)]);
+        draw_polygon(&mut r, &[(2.0, 2.0), (6.0, 2.0), (6.0, 6.0), (2.0, 6.0)]);
+        assert_eq!(r.coverage()[4 * 8 + 4], 255);
+        let c = r.coverage_with_rule(FillRule::EvenOdd);
+        assert_eq!(c[4 * 8 + 4], 0);
+        assert_eq!(c[8 + 1], 255);
+    }
+
+    #[test]
+    fn sqrt_is_accurate() {
+        for x in [0.25, 1.0, 2.0, 100.0, 12345.0] {
+            let y = sqrt(x);
+            assert!(abs(y * y - x) < x * 0.0001, "{x} {y}");
+        }
+        assert_eq!(sqrt(0.0), 0.0);
+        assert_eq!(sqrt(-1.0), 0.0);
+    }
+
+    #[test]
+    fn paths_are_filled_line_by_line() {
+        let mut path = Path::new();
+        path.add_circle(Point::new(8.0, 8.0), 8.0);
+        let mut covered = [[0u8; 16]; 16];
+        path.fill(FillRule::NonZero, 0, 0, 16, 16, |x, y, c| {
+            covered[y as usize][x as usize] = c
+        });
+        assert_eq!(covered[8][8], 255);
+        assert_eq!(covered[0][0], 0);
+        assert!(covered[0][8] > 0);
+        // The result is clipped to the given rect
+        let mut count = 0;
+        path.fill(FillRule::NonZero, 8, 8, 4, 4, |x, y, _| {
+            assert!((8..12).contains(&x) && (8..12).contains(&y));
+            count += 1;
+        });
+        assert_eq!(count, 16);
+    }
+
+    #[test]
+    fn curves_are_flattened() {
+        let mut r = Rasterizer::new(16, 16);
+        r.draw_quad(
+            Point::new(0.0, 16.0),
+            Point::new(8.0, -16.0),
+            Point::new(16.0, 16.0),
+        );
+        r.draw_line(Point::new(16.0, 16.0), Point::new(0.0, 16.0));
+        let c = r.coverage();
+        // The peak of the curve is at (8, 0)
+        assert!(c[8] > 200);
+        assert_eq!(c[8 * 16 + 8], 255);
+        assert!(c[15 * 16] > 0);
+        assert_eq!(c[0], 0);
+        assert_eq!(c[15], 0);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::graphics::ScalarRange;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct Rect {
     x: i64,
     y: i64,
@@ -44,6 +44,20 @@ impl Rect {
         let h = ry.end() - ry.start();
         Some(Self { x, y, w, h })
     }
+    /// Returns the smallest rect that contains both of the rects.
+    pub fn union(&self, another: &Self) -> Rect {
+        let x = core::cmp::min(self.x, another.x);
+        let y = core::cmp::min(self.y, another.y);
+        let w = core::cmp::max(self.x + self.w, another.x + another.w) - x;
+        let h = core::cmp::max(self.y + self.h, another.y + another.h) - y;
+        Self { x, y, w, h }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+    pub fn contains_point(&self, x: i64, y: i64) -> bool {
+        self.x <= x && x < self.x + self.w && self.y <= y && y < self.y + self.h
+    }
 }
 
 #[cfg(test)]
@@ -82,4 +96,20 @@ mod rect_tests {
         let self_intersect = r1.intersection(&r1).unwrap();
         assert_eq!(self_intersect, r1);
     }
+    #[test]
+    fn calc_union() {
+        let r1 = Rect::new(0, 0, 2, 2).unwrap();
+        let r2 = Rect::new(1, -1, 3, 2).unwrap();
+        assert_eq!(r1.union(&r2), Rect::new(0, -1, 4, 3).unwrap());
+        assert_eq!(r1.union(&r1), r1);
+    }
+    #[test]
+    fn contains_point() {
+        let r = Rect::new(1, 2, 3, 4).unwrap();
+        assert!(r.contains_point(1, 2));
+        assert!(r.contains_point(3, 5));
+        assert!(!r.contains_point(4, 5));
+        assert!(!r.contains_point(3, 6));
+        assert!(!r.contains_point(0, 2));
+    }
 }
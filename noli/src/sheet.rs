@@ -4,8 +4,9 @@ use crate::bitmap::Bitmap;
 use crate::bitmap::BitmapBuffer;
 use crate::error::Result;
 use crate::graphics::draw_line;
-use crate::graphics::draw_point;
+use crate::prelude::*;
 use crate::rect::Rect;
+use sabi::BLIT_TARGET_SCREEN;
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct Sheet {
@@ -52,6 +53,10 @@ impl Sheet {
         &mut self.bitmap
     }
 
+    pub fn buffer(&self) -> &BitmapBuffer {
+        &self.bitmap
+    }
+
     // `rect` is absolute position.
     pub fn flush_area(&self, global_rect: Rect) {
         let global_intersection_rect = match self.rect().intersection(&global_rect) {
@@ -65,26 +70,18 @@ impl Sheet {
             global_intersection_rect.h(),
         )
         .unwrap();
-        let (x_range, y_range) = intersection_rect.frame_ranges();
-        for y in y_range.range {
-            for x in x_range.range.clone() {
-                let p = self.bitmap.pixel_at(x, y).cloned().unwrap_or_default();
-                let x = x + self.x;
-                let y = y + self.y;
-                let _ = draw_point(p, x, y);
-            }
-        }
+        Api::blit(
+            BLIT_TARGET_SCREEN,
+            &self.bitmap,
+            &intersection_rect,
+            global_intersection_rect.x(),
+            global_intersection_rect.y(),
+        );
     }
 
     pub fn flush(&self) {
-        for y in 0..self.bitmap.height() {
-            for x in 0..self.bitmap.width() {
-                let p = self.bitmap.pixel_at(x, y).cloned().unwrap_or_default();
-                let x = x + self.x;
-                let y = y + self.y;
-                let _ = draw_point(p, x, y);
-            }
-        }
+        let rect = Rect::new(0, 0, self.bitmap.width(), self.bitmap.height()).unwrap();
+        Api::blit(BLIT_TARGET_SCREEN, &self.bitmap, &rect, self.x, self.y);
     }
 
     pub fn draw_border(&mut self, color: u32) -> Result<()> {
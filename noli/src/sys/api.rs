@@ -1,3 +1,6 @@
+use crate::bitmap::BitmapBuffer;
+use crate::rect::Rect;
+pub use sabi::Event;
 pub use sabi::MouseEvent;
 pub use sabi::RawIpV4Addr;
 
@@ -67,4 +70,64 @@ pub trait SystemApi {
     fn read_from_tcp_socket(_handle: i64, _buf: &mut [u8]) -> i64 {
         unimplemented!()
     }
+    /// Starts a new process of the app `path` in the root directory.
+    /// `args` will be passed to the app as is (so args[0] should be the app name).
+    /// Returns a non-negative process id of the new process.
+    /// -1: SPAWN_FAILED
+    fn spawn(_path: &str, _args: &[&str]) -> i64 {
+        unimplemented!()
+    }
+    /// Waits for the process `pid`, spawned by the current process, to exit.
+    /// Returns Some(exit_code) or None if there is no such child process.
+    /// This will yield the execution to the OS until the process exits.
+    fn wait(_pid: i64) -> Option<i64> {
+        unimplemented!()
+    }
+    /// Creates a window at (x, y) sized (w, h) on the top of the other windows.
+    /// Returns the handle of the window and its surface, which has w * h pixels
+    /// without any padding between lines. Drawing into the surface will be shown
+    /// on the screen after calling damage_window_rect().
+    /// Returns None if the window can not be created.
+    fn create_window(_x: i64, _y: i64, _w: i64, _h: i64) -> Option<(i64, &'static mut [u32])> {
+        unimplemented!()
+    }
+    /// Destroys the window. The surface should not be used after this call.
+    /// -1: NO_SUCH_WINDOW
+    fn destroy_window(_handle: i64) -> i64 {
+        unimplemented!()
+    }
+    /// Requests the compositor to redraw the rect (in the window coordinates)
+    /// of the window surface.
+    /// -1: NO_SUCH_WINDOW
+    fn damage_window_rect(_handle: i64, _x: i64, _y: i64, _w: i64, _h: i64) -> i64 {
+        unimplemented!()
+    }
+    /// Copies `src_rect` of the bitmap to (dx, dy) of the window, or the screen
+    /// if `window` is sabi::BLIT_TARGET_SCREEN. The rect is clipped to the destination.
+    /// The updated region of the window is redrawn on the screen.
+    /// -1: INVALID_ARGS or NO_SUCH_WINDOW
+    fn blit(_window: i64, _src: &BitmapBuffer, _src_rect: &Rect, _dx: i64, _dy: i64) -> i64 {
+        unimplemented!()
+    }
+    /// Returns Some if there is an event for the window, or None.
+    fn next_event(_handle: i64) -> Option<Event> {
+        unimplemented!()
+    }
+    /// Moves the window to (x, y) on the screen.
+    /// -1: NO_SUCH_WINDOW
+    fn move_window(_handle: i64, _x: i64, _y: i64) -> i64 {
+        unimplemented!()
+    }
+    /// Delivers Event::Timer to the window every interval_ms.
+    /// The timer is stopped if interval_ms is 0.
+    /// -1: NO_SUCH_WINDOW
+    fn set_timer(_handle: i64, _interval_ms: u64) -> i64 {
+        unimplemented!()
+    }
+    /// Reads the file at `path` into `buf`.
+    /// Returns the size of the file, which can be larger than the buf.
+    /// -1: NO_SUCH_FILE
+    fn read_file(_path: &str, _buf: &mut [u8]) -> i64 {
+        unimplemented!()
+    }
 }
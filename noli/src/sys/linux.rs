@@ -1,5 +1,7 @@
 extern crate std;
 
+use crate::bitmap::BitmapBuffer;
+use crate::rect::Rect;
 use crate::sys::api::SystemApi;
 
 use std::print;
@@ -30,4 +32,20 @@ impl SystemApi for Api {
     fn draw_point(_x: i64, _y: i64, _c: u32) -> u64 {
         0
     }
+    fn blit(_window: i64, _src: &BitmapBuffer, _src_rect: &Rect, _dx: i64, _dy: i64) -> i64 {
+        0
+    }
+    fn create_window(_x: i64, _y: i64, _w: i64, _h: i64) -> Option<(i64, &'static mut [u32])> {
+        None
+    }
+    fn read_file(path: &str, buf: &mut [u8]) -> i64 {
+        match std::fs::read(path) {
+            Ok(data) => {
+                let len = core::cmp::min(data.len(), buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                data.len() as i64
+            }
+            Err(_) => -1,
+        }
+    }
 }
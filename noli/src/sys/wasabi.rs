@@ -1,9 +1,15 @@
 use crate::prelude::*;
 
+use crate::args::serialize_args;
+use crate::bitmap::Bitmap;
+use crate::bitmap::BitmapBuffer;
+use crate::rect::Rect;
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::ptr::null_mut;
 use core::slice;
+use sabi::BlitRequest;
+use sabi::Event;
 use sabi::MouseEvent;
 use sabi::RawIpV4Addr;
 
@@ -194,4 +200,85 @@ impl SystemApi for Api {
     fn read_from_tcp_socket(handle: i64, buf: &mut [u8]) -> i64 {
         syscall_3(10, handle as u64, buf.as_mut_ptr() as u64, buf.len() as u64) as i64
     }
+    fn spawn(path: &str, args: &[&str]) -> i64 {
+        let args = serialize_args(args);
+        syscall_4(
+            11,
+            path.as_ptr() as u64,
+            path.len() as u64,
+            args.as_ptr() as u64,
+            args.len() as u64,
+        ) as i64
+    }
+    fn wait(pid: i64) -> Option<i64> {
+        let mut exit_code = 0i64;
+        if syscall_2(12, pid as u64, &mut exit_code as *mut i64 as u64) == 0 {
+            Some(exit_code)
+        } else {
+            None
+        }
+    }
+    fn create_window(x: i64, y: i64, w: i64, h: i64) -> Option<(i64, &'static mut [u32])> {
+        let mut addr = 0u64;
+        let handle = syscall_5(
+            13,
+            x as u64,
+            y as u64,
+            w as u64,
+            h as u64,
+            &mut addr as *mut u64 as u64,
+        ) as i64;
+        if handle < 0 || addr == 0 {
+            None
+        } else {
+            Some((handle, unsafe {
+                slice::from_raw_parts_mut(addr as *mut u32, (w * h) as usize)
+            }))
+        }
+    }
+    fn destroy_window(handle: i64) -> i64 {
+        syscall_1(14, handle as u64) as i64
+    }
+    fn damage_window_rect(handle: i64, x: i64, y: i64, w: i64, h: i64) -> i64 {
+        syscall_5(15, handle as u64, x as u64, y as u64, w as u64, h as u64) as i64
+    }
+    fn blit(window: i64, src: &BitmapBuffer, src_rect: &Rect, dx: i64, dy: i64) -> i64 {
+        let req = BlitRequest {
+            window,
+            buf: src.buf() as u64,
+            width: src.width(),
+            height: src.height(),
+            pixels_per_line: src.pixels_per_line(),
+            sx: src_rect.x(),
+            sy: src_rect.y(),
+            w: src_rect.w(),
+            h: src_rect.h(),
+            dx,
+            dy,
+        };
+        syscall_1(16, &req as *const BlitRequest as u64) as i64
+    }
+    fn next_event(handle: i64) -> Option<Event> {
+        let mut e = Event::default();
+        if syscall_2(17, handle as u64, &mut e as *mut Event as u64) as i64 == 1 {
+            Some(e)
+        } else {
+            None
+        }
+    }
+    fn move_window(handle: i64, x: i64, y: i64) -> i64 {
+        syscall_3(18, handle as u64, x as u64, y as u64) as i64
+    }
+    fn set_timer(handle: i64, interval_ms: u64) -> i64 {
+        syscall_2(19, handle as u64, interval_ms) as i64
+    }
+    fn read_file(path: &str, buf: &mut [u8]) -> i64 {
+        syscall_4(
+            20,
+            path.as_ptr() as u64,
+            path.len() as u64,
+            buf.as_mut_ptr() as u64,
+            buf.len() as u64,
+        ) as i64
+    }
 }
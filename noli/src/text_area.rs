@@ -4,6 +4,9 @@ use crate::bitmap::bitmap_draw_rect;
 use crate::bitmap::transfer_rect;
 use crate::bitmap::Bitmap;
 use crate::error::Result;
+use crate::font::char_width;
+use crate::font::NARROW_GLYPH_WIDTH;
+use crate::rect::Rect;
 use core::cmp::max;
 use core::fmt;
 
@@ -40,6 +43,10 @@ impl<T: Bitmap> TextArea<T> {
         text_area.clear_screen().unwrap();
         text_area
     }
+    /// Returns the region of the bitmap used by this text area.
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.w, self.h).expect("Invalid text area size")
+    }
     fn clear_screen(&mut self) -> Result<()> {
         bitmap_draw_rect(&mut self.buf, 0x000000, self.x, self.y, self.w, self.h)
     }
@@ -99,8 +106,8 @@ impl<T: Bitmap> TextArea<T> {
         }
         Ok(())
     }
-    fn move_cursor_next(&mut self) -> Result<()> {
-        self.cx += 1;
+    fn move_cursor_next(&mut self, cells: i64) -> Result<()> {
+        self.cx += cells;
         if self.cx * 8 + 8 <= self.w {
             return Ok(());
         }
@@ -114,15 +121,23 @@ impl<T: Bitmap> TextArea<T> {
         match c {
             '\n' => self.new_line(),
             '\x08' | '\x7f' => self.move_cursor_prev(),
-            _ => bitmap_draw_char(
-                &mut self.buf,
-                fg,
-                Some(bg),
-                self.x + self.cx * 8,
-                self.y + self.cy * 16,
-                c,
-            )
-            .and_then(|_| -> Result<()> { self.move_cursor_next() }),
+            _ => {
+                // Wide characters (e.g. CJK) take two cells
+                let cells = char_width(c) / NARROW_GLYPH_WIDTH;
+                if self.cx > 0 && (self.cx + cells) * 8 > self.w {
+                    // Wrap instead of splitting the character at the end of the line
+                    self.new_line()?;
+                }
+                bitmap_draw_char(
+                    &mut self.buf,
+                    fg,
+                    Some(bg),
+                    self.x + self.cx * 8,
+                    self.y + self.cy * 16,
+                    c,
+                )?;
+                self.move_cursor_next(cells)
+            }
         }
     }
     pub fn print_char(&mut self, c: char) -> Result<()> {
@@ -145,3 +160,38 @@ impl<T: Bitmap> fmt::Write for TextArea<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::BitmapBuffer;
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        // 5 cells x 3 lines
+        let buf = BitmapBuffer::new(40, 48, 40);
+        let mut text_area = TextArea::new(buf, 0, 0, 40, 48);
+        text_area.print_string("abわ").unwrap();
+        assert_eq!((text_area.cx, text_area.cy), (4, 0));
+        // The second character doesn't fit in the last cell, so it goes to the next line
+        text_area.print_string("さび").unwrap();
+        assert_eq!((text_area.cx, text_area.cy), (4, 1));
+        let glyph = crate::font::glyph_for_char('さ');
+        for y in 0..16 {
+            for x in 0..16 {
+                let expected = if glyph.is_set(x, y) {
+                    0xFFFFFF
+                } else {
+                    0x000000
+                };
+                assert_eq!(*text_area.buf.pixel_at(x, 16 + y).unwrap(), expected);
+            }
+        }
+        // The last cell of the first line is left blank
+        for y in 0..16 {
+            for x in 32..40 {
+                assert_eq!(*text_area.buf.pixel_at(x, y).unwrap(), 0x000000);
+            }
+        }
+    }
+}
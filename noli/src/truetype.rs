@@ -0,0 +1,934 @@
+//! TrueType font parser and renderer
+//!
+//! The following tables are used: head, maxp, hhea, hmtx, cmap (format 4 and 12),
+//! loca, glyf (simple and composite glyphs) and kern (format 0).
+//! Fonts with CFF outlines (OpenType "OTTO") and hinting are not supported.
+//! Glyphs are rasterized with anti-aliasing at arbitrary pixel sizes (see rasterizer.rs)
+//! and cached by GlyphCache.
+//! c.f. https://learn.microsoft.com/en-us/typography/opentype/spec/
+
+extern crate alloc;
+
+use crate::bitmap::blend_src_over;
+use crate::bitmap::Bitmap;
+use crate::bitmap::Color;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::*;
+use crate::rasterizer::ceil;
+use crate::rasterizer::floor;
+use crate::rasterizer::Point;
+use crate::rasterizer::Rasterizer;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+// Composite glyphs referring each other too deeply are treated as broken.
+const MAX_COMPONENT_DEPTH: usize = 8;
+
+fn read_bytes(data: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    data.get(offset..offset + len)
+        .ok_or(Error::Failed("TrueType: out of range"))
+}
+fn read_u8(data: &[u8], offset: usize) -> Result<u8> {
+    Ok(read_bytes(data, offset, 1)?[0])
+}
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let b = read_bytes(data, offset, 2)?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+}
+fn read_i16(data: &[u8], offset: usize) -> Result<i16> {
+    Ok(read_u16(data, offset)? as i16)
+}
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let b = read_bytes(data, offset, 4)?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+// 2.14 fixed point number used in the transformation of components
+fn read_f2dot14(data: &[u8], offset: usize) -> Result<f32> {
+    Ok(read_i16(data, offset)? as f32 / 16384.0)
+}
+
+/// A point of a glyph outline in font units (y-up)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlinePoint {
+    pub x: f32,
+    pub y: f32,
+    pub on_curve: bool,
+}
+pub type Contour = Vec<OutlinePoint>;
+
+#[derive(Debug, Clone, Copy)]
+enum CmapFormat {
+    SegmentMapping,    // format 4
+    SegmentedCoverage, // format 12
+}
+
+pub struct TrueTypeFont {
+    data: Vec<u8>,
+    loca: Range<usize>,
+    glyf: Range<usize>,
+    hmtx: Range<usize>,
+    kern: Option<Range<usize>>,
+    cmap: Option<(CmapFormat, usize)>,
+    units_per_em: u16,
+    num_glyphs: u16,
+    num_h_metrics: u16,
+    is_long_loca: bool,
+    ascender: i16,
+    descender: i16,
+    line_gap: i16,
+}
+impl TrueTypeFont {
+    pub fn parse(data: Vec<u8>) -> Result<Self> {
+        let version = read_u32(&data, 0)?;
+        if version != 0x00010000 && &data[0..4] != b"true" {
+            return Err(Error::Failed("TrueType: unsupported font format"));
+        }
+        let num_tables = read_u16(&data, 4)? as usize;
+        let find_table = |tag: &[u8; 4]| -> Result<Option<Range<usize>>> {
+            for i in 0..num_tables {
+                let record = 12 + i * 16;
+                if read_bytes(&data, record, 4)? == tag {
+                    let offset = read_u32(&data, record + 8)? as usize;
+                    let len = read_u32(&data, record + 12)? as usize;
+                    read_bytes(&data, offset, len)?;
+                    return Ok(Some(offset..offset + len));
+                }
+            }
+            Ok(None)
+        };
+        let required = |table: Option<Range<usize>>| {
+            table.ok_or(Error::Failed("TrueType: a required table is missing"))
+        };
+        let head = required(find_table(b"head")?)?;
+        let maxp = required(find_table(b"maxp")?)?;
+        let hhea = required(find_table(b"hhea")?)?;
+        let hmtx = required(find_table(b"hmtx")?)?;
+        let loca = required(find_table(b"loca")?)?;
+        let glyf = required(find_table(b"glyf")?)?;
+        let cmap = find_table(b"cmap")?;
+        let kern = find_table(b"kern")?;
+
+        let units_per_em = read_u16(&data, head.start + 18)?;
+        if units_per_em == 0 {
+            return Err(Error::Failed("TrueType: invalid unitsPerEm"));
+        }
+        let is_long_loca = read_i16(&data, head.start + 50)? != 0;
+        let num_glyphs = read_u16(&data, maxp.start + 4)?;
+        let ascender = read_i16(&data, hhea.start + 4)?;
+        let descender = read_i16(&data, hhea.start + 6)?;
+        let line_gap = read_i16(&data, hhea.start + 8)?;
+        let num_h_metrics = read_u16(&data, hhea.start + 34)?;
+        if num_h_metrics == 0 {
+            return Err(Error::Failed("TrueType: no horizontal metrics"));
+        }
+        let cmap = match cmap {
+            Some(cmap) => Self::find_cmap_subtable(&data, cmap)?,
+            None => None,
+        };
+        Ok(Self {
+            data,
+            loca,
+            glyf,
+            hmtx,
+            kern,
+            cmap,
+            units_per_em,
+            num_glyphs,
+            num_h_metrics,
+            is_long_loca,
+            ascender,
+            descender,
+            line_gap,
+        })
+    }
+    /// Loads a font from the file at `path`.
+    pub fn load(path: &str) -> Result<Self> {
+        let size = Api::read_file(path, &mut []);
+        if size < 0 {
+            return Err(Error::Failed("TrueType: failed to open the font file"));
+        }
+        let mut data = vec![0u8; size as usize];
+        if Api::read_file(path, &mut data) != size {
+            return Err(Error::Failed("TrueType: failed to read the font file"));
+        }
+        Self::parse(data)
+    }
+    // Picks a Unicode subtable. Full repertoire (format 12) is preferred over BMP only.
+    fn find_cmap_subtable(data: &[u8], cmap: Range<usize>) -> Result<Option<(CmapFormat, usize)>> {
+        let num_subtables = read_u16(data, cmap.start + 2)? as usize;
+        let mut found = None;
+        for i in 0..num_subtables {
+            let record = cmap.start + 4 + i * 8;
+            let platform_id = read_u16(data, record)?;
+            let encoding_id = read_u16(data, record + 2)?;
+            let offset = cmap.start + read_u32(data, record + 4)? as usize;
+            let is_unicode =
+                platform_id == 0 || (platform_id == 3 && matches!(encoding_id, 1 | 10));
+            if !is_unicode {
+                continue;
+            }
+            match read_u16(data, offset)? {
+                12 => return Ok(Some((CmapFormat::SegmentedCoverage, offset))),
+                4 => found = Some((CmapFormat::SegmentMapping, offset)),
+                _ => {}
+            }
+        }
+        Ok(found)
+    }
+    pub fn units_per_em(&self) -> u16 {
+        self.units_per_em
+    }
+    pub fn num_glyphs(&self) -> u16 {
+        self.num_glyphs
+    }
+    /// Returns the scale to convert font units into pixels.
+    pub fn scale_for_pixel_size(&self, size_px: f32) -> f32 {
+        size_px / self.units_per_em as f32
+    }
+    /// Returns the distance from the baseline to the top of the line in pixels.
+    pub fn ascent(&self, size_px: f32) -> f32 {
+        self.ascender as f32 * self.scale_for_pixel_size(size_px)
+    }
+    /// Returns the distance between the baselines of two lines in pixels.
+    pub fn line_height(&self, size_px: f32) -> f32 {
+        (self.ascender as f32 - self.descender as f32 + self.line_gap as f32)
+            * self.scale_for_pixel_size(size_px)
+    }
+    /// Returns the glyph index for the character,
+    /// or None if the font does not have a glyph for it.
+    pub fn glyph_index(&self, c: char) -> Option<u16> {
+        let c = c as u32;
+        let index = match self.cmap? {
+            (CmapFormat::SegmentMapping, offset) => self.lookup_cmap_format4(offset, c),
+            (CmapFormat::SegmentedCoverage, offset) => self.lookup_cmap_format12(offset, c),
+        };
+        match index {
+            Ok(0) | Err(_) => None,
+            Ok(index) => Some(index),
+        }
+    }
+    fn lookup_cmap_format4(&self, offset: usize, c: u32) -> Result<u16> {
+        if c > 0xffff {
+            return Ok(0);
+        }
+        let c = c as u16;
+        let data = &self.data;
+        let seg_count = read_u16(data, offset + 6)? as usize / 2;
+        let end_codes = offset + 14;
+        let start_codes = end_codes + seg_count * 2 + 2;
+        let id_deltas = start_codes + seg_count * 2;
+        let id_range_offsets = id_deltas + seg_count * 2;
+        // endCode is sorted in the increasing order
+        let (mut lo, mut hi) = (0, seg_count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if read_u16(data, end_codes + mid * 2)? < c {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let i = lo;
+        if i >= seg_count || read_u16(data, start_codes + i * 2)? > c {
+            return Ok(0);
+        }
+        let start = read_u16(data, start_codes + i * 2)?;
+        let delta = read_u16(data, id_deltas + i * 2)?;
+        let range_offset = read_u16(data, id_range_offsets + i * 2)? as usize;
+        if range_offset == 0 {
+            return Ok(c.wrapping_add(delta));
+        }
+        // The offset is relative to the idRangeOffset entry itself
+        let glyph_addr = id_range_offsets + i * 2 + range_offset + (c - start) as usize * 2;
+        let index = read_u16(data, glyph_addr)?;
+        if index == 0 {
+            Ok(0)
+        } else {
+            Ok(index.wrapping_add(delta))
+        }
+    }
+    fn lookup_cmap_format12(&self, offset: usize, c: u32) -> Result<u16> {
+        let data = &self.data;
+        let num_groups = read_u32(data, offset + 12)? as usize;
+        let groups = offset + 16;
+        let (mut lo, mut hi) = (0, num_groups);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let group = groups + mid * 12;
+            let start = read_u32(data, group)?;
+            let end = read_u32(data, group + 4)?;
+            if c < start {
+                hi = mid;
+            } else if c > end {
+                lo = mid + 1;
+            } else {
+                let start_glyph = read_u32(data, group + 8)?;
+                return Ok((start_glyph + c - start) as u16);
+            }
+        }
+        Ok(0)
+    }
+    /// Returns the advance width of the glyph in font units.
+    pub fn advance_width(&self, glyph: u16) -> u16 {
+        let i = core::cmp::min(glyph, self.num_h_metrics - 1) as usize;
+        read_u16(&self.data, self.hmtx.start + i * 4).unwrap_or(0)
+    }
+    /// Returns the kerning adjustment between two glyphs in font units.
+    pub fn kerning(&self, left: u16, right: u16) -> i16 {
+        self.lookup_kern(left, right).unwrap_or(0)
+    }
+    fn lookup_kern(&self, left: u16, right: u16) -> Result<i16> {
+        let kern = match &self.kern {
+            Some(kern) => kern.clone(),
+            None => return Ok(0),
+        };
+        let data = &self.data;
+        let num_tables = read_u16(data, kern.start + 2)?;
+        let mut subtable = kern.start + 4;
+        let key = ((left as u32) << 16) | right as u32;
+        for _ in 0..num_tables {
+            let len = read_u16(data, subtable + 2)? as usize;
+            let coverage = read_u16(data, subtable + 4)?;
+            let is_horizontal = coverage & 1 != 0;
+            let format = coverage >> 8;
+            if is_horizontal && format == 0 {
+                let num_pairs = read_u16(data, subtable + 6)? as usize;
+                let pairs = subtable + 14;
+                let (mut lo, mut hi) = (0, num_pairs);
+                while lo < hi {
+                    let mid = (lo + hi) / 2;
+                    let pair_key = read_u32(data, pairs + mid * 6)?;
+                    match pair_key.cmp(&key) {
+                        core::cmp::Ordering::Less => lo = mid + 1,
+                        core::cmp::Ordering::Greater => hi = mid,
+                        core::cmp::Ordering::Equal => return read_i16(data, pairs + mid * 6 + 4),
+                    }
+                }
+            }
+            subtable += len;
+        }
+        Ok(0)
+    }
+    fn glyph_range(&self, glyph: u16) -> Result<Range<usize>> {
+        if glyph >= self.num_glyphs {
+            return Err(Error::Failed("TrueType: glyph index out of range"));
+        }
+        let i = glyph as usize;
+        let (start, end) = if self.is_long_loca {
+            (
+                read_u32(&self.data, self.loca.start + i * 4)? as usize,
+                read_u32(&self.data, self.loca.start + i * 4 + 4)? as usize,
+            )
+        } else {
+            (
+                read_u16(&self.data, self.loca.start + i * 2)? as usize * 2,
+                read_u16(&self.data, self.loca.start + i * 2 + 2)? as usize * 2,
+            )
+        };
+        if start > end || self.glyf.start + end > self.glyf.end {
+            return Err(Error::Failed("TrueType: invalid loca entry"));
+        }
+        Ok(self.glyf.start + start..self.glyf.start + end)
+    }
+    /// Returns the outline of the glyph in font units.
+    /// Glyphs without outlines (e.g. space) have no contours.
+    pub fn outline(&self, glyph: u16) -> Result<Vec<Contour>> {
+        let mut contours = Vec::new();
+        self.append_outline(glyph, &[1.0, 0.0, 0.0, 1.0, 0.0, 0.0], 0, &mut contours)?;
+        Ok(contours)
+    }
+    // transform: [a, b, c, d, e, f] maps (x, y) to (a*x + c*y + e, b*x + d*y + f)
+    fn append_outline(
+        &self,
+        glyph: u16,
+        transform: &[f32; 6],
+        depth: usize,
+        contours: &mut Vec<Contour>,
+    ) -> Result<()> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return Err(Error::Failed("TrueType: too deep composite glyph"));
+        }
+        let range = self.glyph_range(glyph)?;
+        if range.is_empty() {
+            return Ok(());
+        }
+        let data = &self.data[range];
+        let num_contours = read_i16(data, 0)?;
+        if num_contours >= 0 {
+            for mut contour in Self::parse_simple_glyph(data, num_contours as usize)? {
+                for p in contour.iter_mut() {
+                    let (x, y) = (p.x, p.y);
+                    p.x = transform[0] * x + transform[2] * y + transform[4];
+                    p.y = transform[1] * x + transform[3] * y + transform[5];
+                }
+                contours.push(contour);
+            }
+            Ok(())
+        } else {
+            self.append_composite_glyph(data, transform, depth, contours)
+        }
+    }
+    fn parse_simple_glyph(data: &[u8], num_contours: usize) -> Result<Vec<Contour>> {
+        const ON_CURVE_POINT: u8 = 0x01;
+        const X_SHORT_VECTOR: u8 = 0x02;
+        const Y_SHORT_VECTOR: u8 = 0x04;
+        const REPEAT_FLAG: u8 = 0x08;
+        const X_IS_SAME_OR_POSITIVE: u8 = 0x10;
+        const Y_IS_SAME_OR_POSITIVE: u8 = 0x20;
+
+        let mut end_points = Vec::with_capacity(num_contours);
+        for i in 0..num_contours {
+            end_points.push(read_u16(data, 10 + i * 2)? as usize);
+        }
+        let num_points = match end_points.last() {
+            Some(last) => last + 1,
+            None => return Ok(Vec::new()),
+        };
+        let instructions_len = read_u16(data, 10 + num_contours * 2)? as usize;
+        let mut offset = 12 + num_contours * 2 + instructions_len;
+        let mut flags = Vec::with_capacity(num_points);
+        while flags.len() < num_points {
+            let flag = read_u8(data, offset)?;
+            offset += 1;
+            flags.push(flag);
+            if flag & REPEAT_FLAG != 0 {
+                let count = read_u8(data, offset)?;
+                offset += 1;
+                for _ in 0..count {
+                    flags.push(flag);
+                }
+            }
+        }
+        flags.truncate(num_points);
+        let mut read_coords = |short: u8, same_or_positive: u8| -> Result<Vec<f32>> {
+            let mut v = 0i32;
+            let mut coords = Vec::with_capacity(num_points);
+            for flag in &flags {
+                if flag & short != 0 {
+                    let d = read_u8(data, offset)? as i32;
+                    offset += 1;
+                    v += if flag & same_or_positive != 0 { d } else { -d };
+                } else if flag & same_or_positive == 0 {
+                    v += read_i16(data, offset)? as i32;
+                    offset += 2;
+                }
+                coords.push(v as f32);
+            }
+            Ok(coords)
+        };
+        let xs = read_coords(X_SHORT_VECTOR, X_IS_SAME_OR_POSITIVE)?;
+        let ys = read_coords(Y_SHORT_VECTOR, Y_IS_SAME_OR_POSITIVE)?;
+        let mut contours = Vec::with_capacity(num_contours);
+        let mut start = 0;
+        for end in end_points {
+            if end < start || end >= num_points {
+                return Err(Error::Failed("TrueType: invalid contour"));
+            }
+            contours.push(
+                (start..=end)
+                    .map(|i| OutlinePoint {
+                        x: xs[i],
+                        y: ys[i],
+                        on_curve: flags[i] & ON_CURVE_POINT != 0,
+                    })
+                    .collect(),
+            );
+            start = end + 1;
+        }
+        Ok(contours)
+    }
+    fn append_composite_glyph(
+        &self,
+        data: &[u8],
+        transform: &[f32; 6],
+        depth: usize,
+        contours: &mut Vec<Contour>,
+    ) -> Result<()> {
+        const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+        const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+        const WE_HAVE_A_SCALE: u16 = 0x0008;
+        const MORE_COMPONENTS: u16 = 0x0020;
+        const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+        const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+        let mut offset = 10;
+        loop {
+            let flags = read_u16(data, offset)?;
+            let glyph = read_u16(data, offset + 2)?;
+            offset += 4;
+            let (arg1, arg2) = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                offset += 4;
+                (
+                    read_i16(data, offset - 4)? as f32,
+                    read_i16(data, offset - 2)? as f32,
+                )
+            } else {
+                offset += 2;
+                (
+                    read_u8(data, offset - 2)? as i8 as f32,
+                    read_u8(data, offset - 1)? as i8 as f32,
+                )
+            };
+            if flags & ARGS_ARE_XY_VALUES == 0 {
+                // Matching points are rarely used in practice
+                return Err(Error::Failed("TrueType: point matching is not supported"));
+            }
+            let (a, b, c, d) = if flags & WE_HAVE_A_SCALE != 0 {
+                offset += 2;
+                let s = read_f2dot14(data, offset - 2)?;
+                (s, 0.0, 0.0, s)
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                offset += 4;
+                (
+                    read_f2dot14(data, offset - 4)?,
+                    0.0,
+                    0.0,
+                    read_f2dot14(data, offset - 2)?,
+                )
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                offset += 8;
+                (
+                    read_f2dot14(data, offset - 8)?,
+                    read_f2dot14(data, offset - 6)?,
+                    read_f2dot14(data, offset - 4)?,
+                    read_f2dot14(data, offset - 2)?,
+                )
+            } else {
+                (1.0, 0.0, 0.0, 1.0)
+            };
+            // Apply the component transform first, then the parent transform
+            let t = transform;
+            let combined = [
+                t[0] * a + t[2] * b,
+                t[1] * a + t[3] * b,
+                t[0] * c + t[2] * d,
+                t[1] * c + t[3] * d,
+                t[0] * arg1 + t[2] * arg2 + t[4],
+                t[1] * arg1 + t[3] * arg2 + t[5],
+            ];
+            self.append_outline(glyph, &combined, depth + 1, contours)?;
+            if flags & MORE_COMPONENTS == 0 {
+                return Ok(());
+            }
+        }
+    }
+    /// Rasterizes the glyph at the given pixel size (the height of the em square).
+    pub fn rasterize(&self, glyph: u16, size_px: f32) -> Result<RasterizedGlyph> {
+        let scale = self.scale_for_pixel_size(size_px);
+        let advance = self.advance_width(glyph) as f32 * scale;
+        // Convert into pixels with y-down
+        let contours: Vec<Vec<(Point, bool)>> = self
+            .outline(glyph)?
+            .iter()
+            .map(|c| {
+                c.iter()
+                    .map(|p| (Point::new(p.x * scale, -p.y * scale), p.on_curve))
+                    .collect()
+            })
+            .collect();
+        let points = contours.iter().flatten().map(|(p, _)| p);
+        let (mut x_min, mut y_min, mut x_max, mut y_max) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for p in points {
+            x_min = x_min.min(p.x);
+            y_min = y_min.min(p.y);
+            x_max = x_max.max(p.x);
+            y_max = y_max.max(p.y);
+        }
+        if x_min > x_max {
+            return Ok(RasterizedGlyph {
+                width: 0,
+                height: 0,
+                left: 0,
+                top: 0,
+                advance,
+                coverage: Vec::new(),
+            });
+        }
+        let (left, top) = (floor(x_min), floor(y_min));
+        let width = (ceil(x_max) - left) as usize;
+        let height = (ceil(y_max) - top) as usize;
+        let mut r = Rasterizer::new(width, height);
+        let offset = |p: Point| Point::new(p.x - left, p.y - top);
+        for contour in &contours {
+            draw_contour(&mut r, contour, offset);
+        }
+        Ok(RasterizedGlyph {
+            width: width as i64,
+            height: height as i64,
+            left: left as i64,
+            top: top as i64,
+            advance,
+            coverage: r.coverage(),
+        })
+    }
+}
+
+// Draws a closed contour of quadratic B-spline.
+// Two consecutive off-curve points have an implicit on-curve point between them.
+fn draw_contour(r: &mut Rasterizer, contour: &[(Point, bool)], offset: impl Fn(Point) -> Point) {
+    let n = contour.len();
+    if n < 2 {
+        return;
+    }
+    let mid = |a: Point, b: Point| Point::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+    // Find an on-curve point to start with
+    let start = match contour.iter().position(|(_, on_curve)| *on_curve) {
+        Some(i) => contour[i].0,
+        None => mid(contour[0].0, contour[1].0),
+    };
+    let first = contour
+        .iter()
+        .position(|(_, on_curve)| *on_curve)
+        .unwrap_or(0);
+    let mut current = start;
+    let mut control: Option<Point> = None;
+    for i in 1..=n {
+        let (p, on_curve) = contour[(first + i) % n];
+        match (on_curve, control) {
+            (true, None) => {
+                r.draw_line(offset(current), offset(p));
+                current = p;
+            }
+            (true, Some(c)) => {
+                r.draw_quad(offset(current), offset(c), offset(p));
+                current = p;
+                control = None;
+            }
+            (false, None) => control = Some(p),
+            (false, Some(c)) => {
+                let m = mid(c, p);
+                r.draw_quad(offset(current), offset(c), offset(m));
+                current = m;
+                control = Some(p);
+            }
+        }
+    }
+    // Close the contour
+    match control {
+        Some(c) => r.draw_quad(offset(current), offset(c), offset(start)),
+        None => r.draw_line(offset(current), offset(start)),
+    }
+}
+
+/// Coverage map of a glyph. (left, top) is the offset of the top-left corner
+/// of the bitmap from the pen position on the baseline, in pixels (y-down).
+#[derive(Debug, Clone)]
+pub struct RasterizedGlyph {
+    pub width: i64,
+    pub height: i64,
+    pub left: i64,
+    pub top: i64,
+    pub advance: f32,
+    pub coverage: Vec<u8>,
+}
+
+/// Caches rasterized glyphs of a font for each pixel size.
+pub struct GlyphCache {
+    font: TrueTypeFont,
+    glyphs: BTreeMap<(u16, u32), RasterizedGlyph>,
+}
+impl GlyphCache {
+    pub fn new(font: TrueTypeFont) -> Self {
+        Self {
+            font,
+            glyphs: BTreeMap::new(),
+        }
+    }
+    pub fn font(&self) -> &TrueTypeFont {
+        &self.font
+    }
+    pub fn glyph(&mut self, glyph: u16, size_px: f32) -> Result<&RasterizedGlyph> {
+        let key = (glyph, size_px.to_bits());
+        match self.glyphs.entry(key) {
+            Entry::Occupied(e) => Ok(e.into_mut()),
+            Entry::Vacant(e) => Ok(e.insert(self.font.rasterize(glyph, size_px)?)),
+        }
+    }
+    /// Returns the width of the string in pixels, including the kerning.
+    pub fn measure_string(&self, size_px: f32, s: &str) -> f32 {
+        let font = &self.font;
+        let scale = font.scale_for_pixel_size(size_px);
+        let mut width = 0.0;
+        let mut prev = None;
+        for c in s.chars() {
+            let glyph = font.glyph_index(c).unwrap_or(0);
+            if let Some(prev) = prev {
+                width += font.kerning(prev, glyph) as f32 * scale;
+            }
+            width += font.advance_width(glyph) as f32 * scale;
+            prev = Some(glyph);
+        }
+        width
+    }
+    /// Draws the string with anti-aliasing. (x, y) is the left end of the baseline.
+    /// Returns the x coordinate after the string.
+    pub fn draw_string<T: Bitmap>(
+        &mut self,
+        buf: &mut T,
+        color: u32,
+        x: i64,
+        y: i64,
+        size_px: f32,
+        s: &str,
+    ) -> Result<i64> {
+        let color = Color::from_rgb(color);
+        let scale = self.font.scale_for_pixel_size(size_px);
+        let mut pen_x = x as f32;
+        let mut prev = None;
+        for c in s.chars() {
+            // Glyph 0 is .notdef, which is usually drawn as a box
+            let glyph_index = self.font.glyph_index(c).unwrap_or(0);
+            if let Some(prev) = prev {
+                pen_x += self.font.kerning(prev, glyph_index) as f32 * scale;
+            }
+            prev = Some(glyph_index);
+            let glyph = self.glyph(glyph_index, size_px)?;
+            let gx = floor(pen_x + 0.5) as i64 + glyph.left;
+            let gy = y + glyph.top;
+            for dy in 0..glyph.height {
+                for dx in 0..glyph.width {
+                    let coverage = glyph.coverage[(dy * glyph.width + dx) as usize];
+                    if coverage == 0 {
+                        continue;
+                    }
+                    if let Some(p) = buf.pixel_at_mut(gx + dx, gy + dy) {
+                        *p = blend_src_over(*p, color.with_alpha(coverage));
+                    }
+                }
+            }
+            pen_x += glyph.advance;
+        }
+        Ok(floor(pen_x + 0.5) as i64)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::bitmap::BitmapBuffer;
+    use crate::rasterizer::abs;
+
+    fn push_u16(v: &mut Vec<u8>, value: u16) {
+        v.extend_from_slice(&value.to_be_bytes());
+    }
+    fn push_u32(v: &mut Vec<u8>, value: u32) {
+        v.extend_from_slice(&value.to_be_bytes());
+    }
+
+    // Builds a font with glyph 0 (.notdef, empty), glyph 1 for 'A' (a square of 500 units),
+    // glyph 2 for 'B' (a triangle with an off-curve point)
+    // and glyph 3 for 'C' (a composite of glyph 1 shifted by 100 units).
+    // units_per_em is 1000 and the kerning between 'A' and 'B' is -100.
+    pub(crate) fn build_test_font() -> Vec<u8> {
+        let mut glyf = Vec::new();
+        let mut loca = vec![0u32];
+        // glyph 0: empty
+        loca.push(glyf.len() as u32);
+        // glyph 1: a square (0, 0)-(500, 500)
+        for v in [1i16, 0, 0, 500, 500] {
+            push_u16(&mut glyf, v as u16);
+        }
+        push_u16(&mut glyf, 3); // endPtsOfContours
+        push_u16(&mut glyf, 0); // instructionLength
+        glyf.extend_from_slice(&[0x01; 4]); // flags: on curve, long coords
+        for x in [0i16, 500, 0, -500] {
+            push_u16(&mut glyf, x as u16);
+        }
+        for y in [0i16, 0, 500, 0] {
+            push_u16(&mut glyf, y as u16);
+        }
+        loca.push(glyf.len() as u32);
+        // glyph 2: (0, 0) -> off (250, 500) -> (500, 0) with short coords
+        for v in [1i16, 0, 0, 500, 250] {
+            push_u16(&mut glyf, v as u16);
+        }
+        push_u16(&mut glyf, 2);
+        push_u16(&mut glyf, 0);
+        // x: 0 (same), +250, +250 / y: 0 (same), +250, -250 (all short except the first)
+        glyf.extend_from_slice(&[0x31, 0x36, 0x17]);
+        glyf.extend_from_slice(&[250, 250]);
+        glyf.extend_from_slice(&[250, 250]);
+        loca.push(glyf.len() as u32);
+        // glyph 3: composite of glyph 1 moved by (100, 0)
+        for v in [-1i16, 100, 0, 600, 500] {
+            push_u16(&mut glyf, v as u16);
+        }
+        push_u16(&mut glyf, 0x0003); // ARG_1_AND_2_ARE_WORDS | ARGS_ARE_XY_VALUES
+        push_u16(&mut glyf, 1);
+        push_u16(&mut glyf, 100);
+        push_u16(&mut glyf, 0);
+        loca.push(glyf.len() as u32);
+        let num_glyphs = loca.len() as u16 - 1;
+
+        let mut head = vec![0u8; 54];
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        head[50..52].copy_from_slice(&1u16.to_be_bytes()); // long loca
+        let mut maxp = vec![0u8; 6];
+        maxp[4..6].copy_from_slice(&num_glyphs.to_be_bytes());
+        let mut hhea = vec![0u8; 36];
+        hhea[4..6].copy_from_slice(&800u16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&2u16.to_be_bytes());
+        let mut hmtx = Vec::new();
+        for (advance, lsb) in [(500u16, 0u16), (600, 0)] {
+            push_u16(&mut hmtx, advance);
+            push_u16(&mut hmtx, lsb);
+        }
+        for _ in 2..num_glyphs {
+            push_u16(&mut hmtx, 0); // lsb only
+        }
+        let loca: Vec<u8> = loca.iter().flat_map(|v| v.to_be_bytes()).collect();
+        // cmap format 4: 'A'..='C' -> 1..=3, and the final 0xffff segment
+        let mut cmap = Vec::new();
+        push_u16(&mut cmap, 0);
+        push_u16(&mut cmap, 1);
+        push_u16(&mut cmap, 3);
+        push_u16(&mut cmap, 1);
+        push_u32(&mut cmap, 12);
+        for v in [4u16, 32, 0, 4, 4, 1, 0] {
+            push_u16(&mut cmap, v); // format, length, language, segCountX2, ...
+        }
+        for v in [b'C' as u16, 0xffff, 0, b'A' as u16, 0xffff] {
+            push_u16(&mut cmap, v); // endCode, reservedPad, startCode
+        }
+        for v in [1u16.wrapping_sub(b'A' as u16), 1, 0, 0] {
+            push_u16(&mut cmap, v); // idDelta, idRangeOffset
+        }
+        let mut kern = Vec::new();
+        for v in [0u16, 1, 0, 20, 0x0001, 1, 6, 0, 0, 1, 2] {
+            push_u16(&mut kern, v);
+        }
+        push_u16(&mut kern, -100i16 as u16);
+
+        let tables: [(&[u8; 4], Vec<u8>); 8] = [
+            (b"cmap", cmap),
+            (b"glyf", glyf),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"kern", kern),
+            (b"loca", loca),
+            (b"maxp", maxp),
+        ];
+        let mut font = Vec::new();
+        push_u32(&mut font, 0x00010000);
+        push_u16(&mut font, tables.len() as u16);
+        font.extend_from_slice(&[0; 6]);
+        let mut offset = 12 + tables.len() * 16;
+        for (tag, data) in &tables {
+            font.extend_from_slice(tag.as_slice());
+            push_u32(&mut font, 0);
+            push_u32(&mut font, offset as u32);
+            push_u32(&mut font, data.len() as u32);
+            offset += (data.len() + 3) & !3;
+        }
+        for (_, data) in &tables {
+            font.extend_from_slice(data);
+            font.resize((font.len() + 3) & !3, 0);
+        }
+        font
+    }
+
+    #[test]
+    fn tables_are_parsed() {
+        let font = TrueTypeFont::parse(build_test_font()).unwrap();
+        assert_eq!(font.units_per_em(), 1000);
+        assert_eq!(font.num_glyphs(), 4);
+        assert!(abs(font.ascent(10.0) - 8.0) < 0.001);
+        assert!(abs(font.line_height(10.0) - 10.0) < 0.001);
+        assert_eq!(font.glyph_index('A'), Some(1));
+        assert_eq!(font.glyph_index('C'), Some(3));
+        assert_eq!(font.glyph_index('D'), None);
+        assert_eq!(font.glyph_index('あ'), None);
+        assert_eq!(font.advance_width(0), 500);
+        assert_eq!(font.advance_width(1), 600);
+        // Glyphs after numberOfHMetrics share the last advance width
+        assert_eq!(font.advance_width(3), 600);
+        assert_eq!(font.kerning(1, 2), -100);
+        assert_eq!(font.kerning(2, 1), 0);
+        assert!(TrueTypeFont::parse(vec![0; 12]).is_err());
+    }
+
+    #[test]
+    fn outlines_are_parsed() {
+        let font = TrueTypeFont::parse(build_test_font()).unwrap();
+        assert!(font.outline(0).unwrap().is_empty());
+        let square = font.outline(1).unwrap();
+        assert_eq!(square.len(), 1);
+        let points: Vec<(f32, f32)> = square[0].iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(
+            points,
+            vec![(0.0, 0.0), (500.0, 0.0), (500.0, 500.0), (0.0, 500.0)]
+        );
+        let triangle = &font.outline(2).unwrap()[0];
+        assert_eq!(
+            triangle,
+            &vec![
+                OutlinePoint {
+                    x: 0.0,
+                    y: 0.0,
+                    on_curve: true
+                },
+                OutlinePoint {
+                    x: 250.0,
+                    y: 250.0,
+                    on_curve: false
+                },
+                OutlinePoint {
+                    x: 500.0,
+                    y: 0.0,
+                    on_curve: true
+                },
+            ]
+        );
+        let composite = &font.outline(3).unwrap()[0];
+        assert_eq!((composite[0].x, composite[0].y), (100.0, 0.0));
+        assert_eq!((composite[2].x, composite[2].y), (600.0, 500.0));
+        assert!(font.outline(4).is_err());
+    }
+
+    #[test]
+    fn glyphs_are_rasterized_at_any_size() {
+        let font = TrueTypeFont::parse(build_test_font()).unwrap();
+        let g = font.rasterize(1, 20.0).unwrap();
+        assert_eq!((g.width, g.height, g.left, g.top), (10, 10, 0, -10));
+        assert!(abs(g.advance - 12.0) < 0.001);
+        assert!(g.coverage.iter().all(|c| *c == 255));
+        let g = font.rasterize(1, 15.0).unwrap();
+        // 7.5px square: the top row and the right column are half covered
+        assert_eq!((g.width, g.height, g.top), (8, 8, -8));
+        assert_eq!(g.coverage[7 * 8], 255);
+        assert_eq!(g.coverage[0], 128);
+        assert_eq!(g.coverage[7 * 8 + 7], 128);
+        assert_eq!(g.coverage[7], 64);
+        let g = font.rasterize(0, 20.0).unwrap();
+        assert_eq!((g.width, g.height), (0, 0));
+    }
+
+    #[test]
+    fn strings_are_drawn_with_kerning() {
+        let mut cache = GlyphCache::new(TrueTypeFont::parse(build_test_font()).unwrap());
+        let mut buf = BitmapBuffer::new(64, 32, 64);
+        let end = cache
+            .draw_string(&mut buf, 0xffffff, 2, 20, 20.0, "AB")
+            .unwrap();
+        // 'A' advances 12px, -2px by the kerning, then 'B' advances 12px
+        assert_eq!(end, 2 + 12 - 2 + 12);
+        assert!(abs(cache.measure_string(20.0, "AB") - 22.0) < 0.001);
+        // The square of 'A' is above the baseline
+        assert_eq!(*buf.pixel_at(2, 10).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(11, 19).unwrap(), 0xffffff);
+        assert_eq!(*buf.pixel_at(2, 20).unwrap(), 0);
+        assert_eq!(*buf.pixel_at(2, 9).unwrap(), 0);
+        // Cached glyphs are reused
+        assert_eq!(cache.glyphs.len(), 2);
+        cache
+            .draw_string(&mut buf, 0xffffff, 2, 20, 20.0, "BA")
+            .unwrap();
+        assert_eq!(cache.glyphs.len(), 2);
+    }
+}
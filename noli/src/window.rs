@@ -9,10 +9,14 @@ use crate::bitmap::bitmap_draw_string_2x_with_underline;
 use crate::bitmap::bitmap_draw_string_3x;
 use crate::bitmap::bitmap_draw_string_3x_with_underline;
 use crate::bitmap::bitmap_draw_string_with_underline;
+use crate::bitmap::BitmapBuffer;
+use crate::bitmap::Canvas;
 use crate::error::Error;
 use crate::error::Result;
+use crate::prelude::*;
 use crate::rect::Rect;
 use crate::sheet::Sheet;
+use crate::sys::api::Event;
 use alloc::string::String;
 use core::cmp::max;
 use core::cmp::min;
@@ -28,8 +32,8 @@ static GREY: u32 = 0x808080;
 static DARKGREY: u32 = 0x5a5a5a;
 static BLACK: u32 = 0x000000;
 
-static TITLE_BAR_HEIGHT: i64 = 24;
-static BUTTON_SIZE: i64 = 16;
+pub static TITLE_BAR_HEIGHT: i64 = sabi::WINDOW_TITLE_BAR_HEIGHT;
+static BUTTON_SIZE: i64 = sabi::WINDOW_CLOSE_BUTTON_SIZE;
 
 #[derive(Clone, Debug)]
 pub enum StringSize {
@@ -48,6 +52,10 @@ pub struct Window {
     _background_color: u32,
     sheet: Sheet,
     _active: bool,
+    // The handle of the window in the compositor of the OS.
+    // None if the OS does not have the compositor.
+    // In that case, the sheet is flushed to the screen directly.
+    handle: Option<i64>,
 }
 
 /// <https://docs.rs/embedded-graphics/latest/embedded_graphics/geometry/trait.OriginDimensions.html>
@@ -87,11 +95,13 @@ impl Window {
 
         bitmap_draw_rect(sheet.bitmap(), color, 0, 0, width, height)?;
 
+        let handle = Api::create_window(x, y, width, height).map(|(handle, _)| handle);
         let mut window = Self {
             name,
             _background_color: color,
             sheet,
             _active: true,
+            handle,
         };
 
         window.init_titlebar().expect("failed to init titlebar");
@@ -172,15 +182,66 @@ impl Window {
         Ok(())
     }
 
-    // TODO: implement these APIs
-    pub fn move_position(&self) {}
+    /// Moves the window to (x, y) on the screen.
+    pub fn move_position(&mut self, x: i64, y: i64) -> Result<()> {
+        if let Some(handle) = self.handle {
+            if Api::move_window(handle, x, y) < 0 {
+                return Err(Error::Failed("move_position: failed to move the window"));
+            }
+            self.sheet.set_position(x, y);
+        } else {
+            self.sheet.set_position(x, y);
+            self.flush();
+        }
+        Ok(())
+    }
+
+    /// Returns an event for this window, if any.
+    /// On Event::WindowClose, the app is expected to drop the window.
+    /// The position of the window is updated on Event::WindowMove.
+    pub fn next_event(&mut self) -> Option<Event> {
+        let e = Api::next_event(self.handle?)?;
+        if let Event::WindowMove(p) = e {
+            self.sheet.set_position(p.x, p.y);
+        }
+        Some(e)
+    }
+
+    /// Delivers Event::Timer to this window every `interval_ms`.
+    /// The timer is stopped if `interval_ms` is 0.
+    pub fn set_timer(&mut self, interval_ms: u64) -> Result<()> {
+        let handle = self.handle.ok_or(Error::Failed(
+            "set_timer: the window is not created by the OS",
+        ))?;
+        if Api::set_timer(handle, interval_ms) < 0 {
+            return Err(Error::Failed("set_timer: failed to set the timer"));
+        }
+        Ok(())
+    }
 
+    // `rect` is absolute position.
     pub fn flush_area(&self, rect: Rect) {
-        self.sheet.flush_area(rect);
+        let handle = match self.handle {
+            Some(handle) => handle,
+            None => {
+                self.sheet.flush_area(rect);
+                return;
+            }
+        };
+        if let Some(rect) = self.sheet.rect().intersection(&rect) {
+            let rect = Rect::new(
+                rect.x() - self.sheet.x(),
+                rect.y() - self.sheet.y(),
+                rect.w(),
+                rect.h(),
+            )
+            .unwrap();
+            Api::blit(handle, self.sheet.buffer(), &rect, rect.x(), rect.y());
+        }
     }
 
     pub fn flush(&self) {
-        self.sheet.flush();
+        self.flush_area(self.sheet.rect());
     }
 
     pub fn fill_rect(
@@ -312,6 +373,22 @@ impl Window {
         Ok(())
     }
 
+    /// Returns a canvas to draw into the area below the title bar.
+    pub fn canvas(&mut self) -> Canvas<'_, BitmapBuffer> {
+        let client_area = Rect::new(
+            0,
+            0,
+            self.sheet.width(),
+            self.sheet.height() - TITLE_BAR_HEIGHT,
+        );
+        let mut canvas = Canvas::new(self.sheet.bitmap());
+        canvas.set_origin(0, TITLE_BAR_HEIGHT);
+        if let Some(rect) = client_area {
+            canvas.set_clip_rect(rect);
+        }
+        canvas
+    }
+
     pub fn draw_point(&mut self, color: u32, x: i64, y: i64) -> Result<()> {
         if x < 0 || x > self.sheet.width() || y < 0 || (y + TITLE_BAR_HEIGHT) > self.sheet.height()
         {
@@ -322,3 +399,11 @@ impl Window {
         Ok(())
     }
 }
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle {
+            Api::destroy_window(handle);
+        }
+    }
+}
@@ -292,10 +292,157 @@ impl Fadt {
     }
 }
 
+#[repr(packed)]
+pub struct Madt {
+    // 5.2.12 Multiple APIC Description Table (MADT)
+    header: SystemDescriptionTableHeader,
+    local_apic_address: u32,
+    _flags: u32,
+    // 44 + ... -> Interrupt Controller Structures
+}
+const _: () = assert!(size_of::<Madt>() == 44);
+impl AcpiTable for Madt {
+    const SIGNATURE: &'static [u8; 4] = b"APIC";
+    type Table = Self;
+    fn table(&self) -> &Self::Table {
+        self
+    }
+}
+impl Madt {
+    pub fn local_apic_address(&self) -> u64 {
+        self.local_apic_address as u64
+    }
+    pub fn iter(&self) -> MadtIterator {
+        MadtIterator {
+            table: self,
+            offset: size_of::<Self>(),
+        }
+    }
+    pub fn io_apics(&self) -> impl Iterator<Item = MadtIoApic> + '_ {
+        self.iter().filter_map(|e| match e {
+            MadtEntry::IoApic(e) => Some(e),
+            _ => None,
+        })
+    }
+    pub fn interrupt_source_overrides(
+        &self,
+    ) -> impl Iterator<Item = MadtInterruptSourceOverride> + '_ {
+        self.iter().filter_map(|e| match e {
+            MadtEntry::InterruptSourceOverride(e) => Some(e),
+            _ => None,
+        })
+    }
+    /// Returns an iterator over the Local APICs that can be used by the OS,
+    /// i.e. the ones that are enabled or online capable.
+    pub fn local_apics(&self) -> impl Iterator<Item = MadtLocalApic> + '_ {
+        self.iter().filter_map(|e| match e {
+            MadtEntry::LocalApic(e) if e.is_usable() => Some(e),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MadtLocalApic {
+    pub processor_uid: u8,
+    pub apic_id: u8,
+    flags: u32,
+}
+impl MadtLocalApic {
+    fn is_usable(&self) -> bool {
+        // Bit 0: Enabled, Bit 1: Online Capable
+        self.flags & 0b11 != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MadtIoApic {
+    pub io_apic_id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// Describes how an ISA IRQ is connected to a Global System Interrupt (GSI).
+/// ISA IRQs that do not have an override are identity-mapped to GSIs
+/// with the edge-triggered, active-high signal.
+#[derive(Debug, Copy, Clone)]
+pub struct MadtInterruptSourceOverride {
+    pub source_irq: u8,
+    pub gsi: u32,
+    flags: u16,
+}
+impl MadtInterruptSourceOverride {
+    /// Returns true if the polarity is active-low.
+    /// 0b00 (conforms to the bus) means active-high for ISA.
+    pub fn is_active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+    /// Returns true if the trigger mode is level-triggered.
+    /// 0b00 (conforms to the bus) means edge-triggered for ISA.
+    pub fn is_level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum MadtEntry {
+    LocalApic(MadtLocalApic),
+    IoApic(MadtIoApic),
+    InterruptSourceOverride(MadtInterruptSourceOverride),
+    Unknown { entry_type: u8 },
+}
+
+pub struct MadtIterator<'a> {
+    table: &'a Madt,
+    offset: usize,
+}
+impl<'a> Iterator for MadtIterator<'a> {
+    type Item = MadtEntry;
+    fn next(&mut self) -> Option<Self::Item> {
+        let table_len = self.table.header.length as usize;
+        if self.offset + 2 > table_len {
+            return None;
+        }
+        // This is safe since the offset is checked to be in the table.
+        let entry = unsafe {
+            slice::from_raw_parts(
+                (self.table as *const Madt as *const u8).add(self.offset),
+                table_len - self.offset,
+            )
+        };
+        let entry_type = entry[0];
+        let entry_len = entry[1] as usize;
+        if entry_len < 2 || entry_len > entry.len() {
+            return None;
+        }
+        self.offset += entry_len;
+        let entry = &entry[..entry_len];
+        Some(match (entry_type, entry_len) {
+            (0, 8) => MadtEntry::LocalApic(MadtLocalApic {
+                processor_uid: entry[2],
+                apic_id: entry[3],
+                flags: u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+            }),
+            (1, 12) => MadtEntry::IoApic(MadtIoApic {
+                io_apic_id: entry[2],
+                address: u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+                gsi_base: u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]),
+            }),
+            (2, 10) => MadtEntry::InterruptSourceOverride(MadtInterruptSourceOverride {
+                source_irq: entry[3],
+                gsi: u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+                flags: u16::from_le_bytes([entry[8], entry[9]]),
+            }),
+            _ => MadtEntry::Unknown { entry_type },
+        })
+    }
+}
+
 pub struct Acpi {
     mcfg: &'static Mcfg,
     hpet: &'static Hpet,
     dsdt: &'static Dsdt,
+    madt: &'static Madt,
 }
 impl<'a> Acpi {
     pub fn new(rsdp_struct: &RsdpStruct) -> Result<Acpi> {
@@ -312,7 +459,13 @@ impl<'a> Acpi {
         let hpet = Hpet::new(xsdt.find_table(b"HPET").expect("HPET not found"));
         let fadt = Fadt::new(xsdt.find_table(b"FACP").expect("FACP not found"));
         let dsdt = fadt.dsdt();
-        Ok(Acpi { mcfg, hpet, dsdt })
+        let madt = Madt::new(xsdt.find_table(b"APIC").expect("APIC (MADT) not found"));
+        Ok(Acpi {
+            mcfg,
+            hpet,
+            dsdt,
+            madt,
+        })
     }
     pub fn dsdt(&'a self) -> &'a Dsdt {
         self.dsdt
@@ -323,4 +476,7 @@ impl<'a> Acpi {
     pub fn mcfg(&'a self) -> &'a Mcfg {
         self.mcfg
     }
+    pub fn madt(&'a self) -> &'a Madt {
+        self.madt
+    }
 }
@@ -15,6 +15,8 @@ use core::fmt;
 use core::mem::size_of;
 use core::ops::DerefMut;
 use core::ptr::null_mut;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
 
 #[cfg(test)]
 use alloc::vec;
@@ -124,13 +126,22 @@ impl fmt::Debug for Header {
     }
 }
 
+/// Physical memory below this address is not managed by the allocator,
+/// to keep it available for the things that require low memory
+/// (e.g. the AP startup trampoline in x86_64::smp).
+pub const LOW_MEMORY_END: u64 = 0x10_0000;
+
 pub struct FirstFitAllocator {
     first_header: RefCell<Option<Box<Header>>>,
+    // Spin lock to serialize the accesses from multiple processors.
+    // Mutex can not be used here since it can allocate on panic.
+    is_taken: AtomicBool,
 }
 
 #[global_allocator]
 pub static ALLOCATOR: FirstFitAllocator = FirstFitAllocator {
     first_header: RefCell::new(None),
+    is_taken: AtomicBool::new(false),
 };
 
 unsafe impl Sync for FirstFitAllocator {}
@@ -140,15 +151,35 @@ unsafe impl GlobalAlloc for FirstFitAllocator {
         self.alloc_with_options(layout)
     }
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        self.lock();
         let mut region = Header::from_allocated_region(ptr);
         region.is_allocated = false;
         Box::leak(region);
         // region is leaked here to avoid dropping the free info on the memory.
+        self.unlock();
     }
 }
 
 impl FirstFitAllocator {
+    fn lock(&self) {
+        while self
+            .is_taken
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+    }
+    fn unlock(&self) {
+        self.is_taken.store(false, Ordering::Release);
+    }
     pub fn alloc_with_options(&self, layout: Layout) -> *mut u8 {
+        self.lock();
+        let p = self.alloc_with_options_locked(layout);
+        self.unlock();
+        p
+    }
+    fn alloc_with_options_locked(&self, layout: Layout) -> *mut u8 {
         let mut header = self.first_header.borrow_mut();
         let mut header = header.deref_mut();
         loop {
@@ -182,10 +213,15 @@ impl FirstFitAllocator {
         );
     }
     fn add_free_from_descriptor(&self, desc: &EfiMemoryDescriptor) {
-        let mut header = unsafe { Header::new_from_addr(desc.physical_start as usize) };
+        let start = max(desc.physical_start, LOW_MEMORY_END);
+        let end = desc.physical_start + desc.number_of_pages * 4096;
+        if end <= start {
+            return;
+        }
+        let mut header = unsafe { Header::new_from_addr(start as usize) };
         header.next_header = None;
         header.is_allocated = false;
-        header.size = desc.number_of_pages as usize * 4096;
+        header.size = (end - start) as usize;
         let mut first_header = self.first_header.borrow_mut();
         let prev_last = first_header.replace(header);
         drop(first_header);
@@ -0,0 +1,181 @@
+//! Block devices (e.g. USB mass storage) and the registry of them
+//!
+//! Drivers own the devices and serve the requests pushed to them
+//! in their own tasks, as NetworkInterface does for packets.
+
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::executor::yield_execution;
+use crate::mutex::Mutex;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::rc::Weak;
+use alloc::vec::Vec;
+
+static BLOCK_DEVICES: Mutex<Vec<Weak<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+pub trait BlockDevice {
+    fn name(&self) -> &str;
+    /// Bytes per block
+    fn block_size(&self) -> usize;
+    fn num_blocks(&self) -> u64;
+    /// Queues the request. The driver calls BlockRequest::complete() when it is done.
+    fn push_request(&self, req: Rc<BlockRequest>) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub enum BlockOp {
+    Read,
+    Write(Box<[u8]>),
+}
+
+#[derive(Debug)]
+pub struct BlockRequest {
+    lba: u64,
+    num_blocks: usize,
+    op: BlockOp,
+    // Holds the data read (or an empty slice for writes) once completed
+    result: Mutex<Option<Result<Box<[u8]>>>>,
+}
+impl BlockRequest {
+    pub fn lba(&self) -> u64 {
+        self.lba
+    }
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks
+    }
+    pub fn op(&self) -> &BlockOp {
+        &self.op
+    }
+    pub fn complete(&self, result: Result<Box<[u8]>>) {
+        *self.result.lock() = Some(result);
+    }
+}
+
+async fn submit(
+    dev: &dyn BlockDevice,
+    lba: u64,
+    num_blocks: usize,
+    op: BlockOp,
+) -> Result<Box<[u8]>> {
+    if num_blocks == 0 {
+        return Err(Error::Failed("num_blocks should not be zero"));
+    }
+    match lba.checked_add(num_blocks as u64) {
+        Some(end) if end <= dev.num_blocks() => {}
+        _ => return Err(Error::Failed("Out of range of the block device")),
+    }
+    let req = Rc::new(BlockRequest {
+        lba,
+        num_blocks,
+        op,
+        result: Mutex::new(None),
+    });
+    dev.push_request(req.clone())?;
+    loop {
+        if let Some(result) = req.result.lock().take() {
+            return result;
+        }
+        yield_execution().await;
+    }
+}
+
+/// Reads `num_blocks` blocks from `lba`.
+pub async fn read_blocks(dev: &dyn BlockDevice, lba: u64, num_blocks: usize) -> Result<Box<[u8]>> {
+    submit(dev, lba, num_blocks, BlockOp::Read).await
+}
+
+/// Writes `data` from `lba`. The length of `data` should be a multiple of the block size.
+pub async fn write_blocks(dev: &dyn BlockDevice, lba: u64, data: Box<[u8]>) -> Result<()> {
+    if data.len() % dev.block_size() != 0 {
+        return Err(Error::Failed(
+            "Data length is not a multiple of the block size",
+        ));
+    }
+    let num_blocks = data.len() / dev.block_size();
+    submit(dev, lba, num_blocks, BlockOp::Write(data))
+        .await
+        .map(|_| ())
+}
+
+pub fn register_block_device(dev: Weak<dyn BlockDevice>) {
+    BLOCK_DEVICES.lock().push(dev);
+}
+
+/// Returns the block devices which are still alive, in the order of registration.
+pub fn block_devices() -> Vec<Rc<dyn BlockDevice>> {
+    let mut devices = BLOCK_DEVICES.lock();
+    devices.retain(|d| d.strong_count() > 0);
+    devices.iter().filter_map(|d| d.upgrade()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::executor::block_on;
+    use alloc::vec;
+
+    struct RamDisk {
+        data: Mutex<Vec<u8>>,
+    }
+    impl RamDisk {
+        const BLOCK_SIZE: usize = 512;
+    }
+    impl BlockDevice for RamDisk {
+        fn name(&self) -> &str {
+            "ramdisk"
+        }
+        fn block_size(&self) -> usize {
+            Self::BLOCK_SIZE
+        }
+        fn num_blocks(&self) -> u64 {
+            (self.data.lock().len() / Self::BLOCK_SIZE) as u64
+        }
+        fn push_request(&self, req: Rc<BlockRequest>) -> Result<()> {
+            let mut data = self.data.lock();
+            let range = req.lba() as usize * Self::BLOCK_SIZE
+                ..(req.lba() as usize + req.num_blocks()) * Self::BLOCK_SIZE;
+            match req.op() {
+                BlockOp::Read => req.complete(Ok(data[range].into())),
+                BlockOp::Write(src) => {
+                    data[range].copy_from_slice(src);
+                    req.complete(Ok(Box::default()))
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test_case]
+    fn read_after_write() {
+        let disk = Rc::new(RamDisk {
+            data: Mutex::new(vec![0; RamDisk::BLOCK_SIZE * 4]),
+        });
+        let data = block_on(async move {
+            write_blocks(disk.as_ref(), 1, vec![0xAB; RamDisk::BLOCK_SIZE * 2].into()).await?;
+            read_blocks(disk.as_ref(), 0, 4).await
+        })
+        .expect("read and write should succeed");
+        assert!(data[..RamDisk::BLOCK_SIZE].iter().all(|v| *v == 0));
+        assert!(data[RamDisk::BLOCK_SIZE..RamDisk::BLOCK_SIZE * 3]
+            .iter()
+            .all(|v| *v == 0xAB));
+        assert!(data[RamDisk::BLOCK_SIZE * 3..].iter().all(|v| *v == 0));
+    }
+    #[test_case]
+    fn out_of_range_requests_are_rejected() {
+        let disk = Rc::new(RamDisk {
+            data: Mutex::new(vec![0; RamDisk::BLOCK_SIZE * 4]),
+        });
+        let d = disk.clone();
+        assert!(block_on(async move { read_blocks(d.as_ref(), 3, 2).await }).is_err());
+        let d = disk.clone();
+        assert!(block_on(async move { read_blocks(d.as_ref(), 0, 0).await }).is_err());
+        assert!(
+            block_on(async move { write_blocks(disk.as_ref(), 0, vec![0; 100].into()).await })
+                .is_err()
+        );
+    }
+}
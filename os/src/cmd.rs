@@ -1,49 +1,221 @@
 extern crate alloc;
 
-use crate::boot_info::BootInfo;
+use crate::block::block_devices;
+use crate::block::read_blocks;
+use crate::block::write_blocks;
+use crate::block::BlockDevice;
 #[cfg(test)]
 use crate::debug;
-use crate::efi::fs::EfiFileName;
 use crate::error;
 use crate::error::Error;
 use crate::error::Result;
 use crate::executor::yield_execution;
 use crate::info;
-use crate::loader::Elf;
+use crate::keyboard::keymap;
+use crate::keyboard::set_keymap;
+use crate::keyboard::Keymap;
+use crate::loader::spawn_app_in_root_dir;
 use crate::mutex::Mutex;
 use crate::net::dns::query_dns;
 use crate::net::dns::DnsResponseEntry;
 use crate::net::icmp::IcmpPacket;
 use crate::net::manager::Network;
+use crate::pci::capability_name;
+use crate::pci::BusDeviceFunction;
+use crate::pci::Pci;
+use crate::print::hexdump;
 use crate::println;
+use crate::tty;
+use crate::usb::descriptor::UsbDescriptor;
+use crate::x86_64::apic::LocalApic;
+use crate::x86_64::smp::online_cpus;
+use crate::x86_64::smp::run_on_cpu;
 use crate::x86_64::trigger_debug_interrupt;
+use crate::xhci::controller::controllers as xhci_controllers;
+use crate::xhci::device::UsbDeviceInfo;
+use crate::xhci::registers::PortScIteratorItem;
+use crate::xhci::registers::UsbMode;
 use alloc::format;
+use alloc::rc::Rc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::str::FromStr;
 use noli::mem::Sliceable;
 use noli::net::IpV4Addr;
 
+fn block_device_and_lba(args: &[&str]) -> Option<(Rc<dyn BlockDevice>, u64)> {
+    let index = usize::from_str(args.get(1)?).ok()?;
+    let lba = u64::from_str(args.get(2)?).ok()?;
+    let dev = block_devices().get(index)?.clone();
+    Some((dev, lba))
+}
+
 async fn run_app(name: &str, args: &[&str]) -> Result<i64> {
-    let boot_info = BootInfo::take();
-    let root_files = boot_info.root_files();
-    let root_files: alloc::vec::Vec<&crate::boot_info::File> =
-        root_files.iter().filter_map(|e| e.as_ref()).collect();
-    let name = EfiFileName::from_str(name)?;
-    let elf = root_files.iter().find(|&e| e.name() == &name);
-    if let Some(elf) = elf {
-        let elf = Elf::parse(elf)?;
-        let app = elf.load()?;
-        let result = app.exec(args).await?;
-        #[cfg(test)]
-        if result == 0 {
-            debug::exit_qemu(debug::QemuExitCode::Success);
-        } else {
-            debug::exit_qemu(debug::QemuExitCode::Fail);
+    // Ctrl-C typed before the launch should not terminate the app
+    tty::take_interrupt_request();
+    let (pid, wait) = spawn_app_in_root_dir(name, args)?;
+    tty::set_foreground_app(Some(pid));
+    let result = wait.await;
+    tty::set_foreground_app(None);
+    let result = result?;
+    #[cfg(test)]
+    if result == 0 {
+        debug::exit_qemu(debug::QemuExitCode::Success);
+    } else {
+        debug::exit_qemu(debug::QemuExitCode::Fail);
+    }
+    #[cfg(not(test))]
+    Ok(result)
+}
+
+fn lspci() -> Result<()> {
+    let pci = Pci::take();
+    for bdf in BusDeviceFunction::iter() {
+        let Some(vd) = pci.read_vendor_id_and_device_id(bdf) else {
+            continue;
+        };
+        let class = pci.class_code(bdf)?;
+        println!(
+            "{:02x}:{:02x}.{} {:04x}:{:04x} {} (class {:02x}:{:02x}:{:02x}, rev {:02x}), driver: {}",
+            bdf.bus(),
+            bdf.device(),
+            bdf.function(),
+            vd.vendor,
+            vd.device,
+            class.name(),
+            class.class,
+            class.subclass,
+            class.prog_if,
+            class.revision,
+            pci.driver_name(bdf).as_deref().unwrap_or("none")
+        );
+        for (index, bar) in pci.bars(bdf)? {
+            println!("    BAR{index}: {bar}");
+        }
+        for cap in pci.capabilities(bdf).into_iter().flatten() {
+            println!(
+                "    Capability [{:#04x}]: {} ({:#04x})",
+                cap.offset,
+                capability_name(cap.id),
+                cap.id
+            );
         }
-        #[cfg(not(test))]
-        Ok(result)
+    }
+    Ok(())
+}
+
+fn print_usb_descriptors(info: &UsbDeviceInfo) {
+    if let Some(d) = info.device_descriptor {
+        let (version, vendor_id, product_id) = (d.version, d.vendor_id, d.product_id);
+        println!(
+            "    Device: USB {:x}.{:02x}, class {:02x}:{:02x}:{:02x}, {vendor_id:04x}:{product_id:04x}, max packet size {}, {} configs",
+            version >> 8,
+            version & 0xff,
+            d.device_class,
+            d.device_subclass,
+            d.device_protocol,
+            d.max_packet_size,
+            d.num_of_config
+        );
+        println!(
+            "        Manufacturer: \"{}\", Product: \"{}\", Serial: \"{}\"",
+            info.string(d.manufacturer_idx),
+            info.string(d.product_idx),
+            info.string(d.serial_idx)
+        );
+    }
+    // 2mA units for USB2, 8mA units for USB3 (SuperSpeed)
+    let power_unit_ma = if info.location.speed() == UsbMode::SuperSpeed {
+        8
     } else {
-        Err(Error::Failed("command::run_app: No such file or app"))
+        2
+    };
+    for d in &info.descriptors {
+        match d {
+            UsbDescriptor::Config(e) => println!(
+                "    Config {}: {} interfaces, attributes {:#04x}, max power {}mA \"{}\"",
+                e.config_value(),
+                e.num_of_interfaces(),
+                e.attribute(),
+                e.max_power() as u32 * power_unit_ma,
+                info.string(e.string_index())
+            ),
+            UsbDescriptor::Interface(e) => {
+                let (class, subclass, protocol) = e.triple();
+                println!(
+                    "      Interface {} alt {}: class {class:02x}:{subclass:02x}:{protocol:02x}, {} endpoints \"{}\"",
+                    e.interface_number(),
+                    e.alt_setting(),
+                    e.num_of_endpoints(),
+                    info.string(e.string_index())
+                );
+            }
+            UsbDescriptor::Endpoint(e) => {
+                let (address, max_packet_size) = (e.endpoint_address, e.max_packet_size);
+                let transfer_type = match e.attributes & 0b11 {
+                    0 => "Control",
+                    1 => "Isochronous",
+                    2 => "Bulk",
+                    _ => "Interrupt",
+                };
+                println!(
+                    "        Endpoint {address:#04x} {} {transfer_type}, max packet size {max_packet_size}, interval {}",
+                    if address & 0x80 != 0 { "IN" } else { "OUT" },
+                    e.interval
+                );
+            }
+            UsbDescriptor::Hid(e) => {
+                let (hid_version, report_desc_length) = (e.hid_version, e.report_desc_length);
+                println!(
+                    "        HID {:x}.{:02x}, country {}, report descriptor {report_desc_length} bytes",
+                    hid_version >> 8,
+                    hid_version & 0xff,
+                    e.country_code
+                );
+            }
+            UsbDescriptor::SuperSpeedEndpointCompanion(e) => {
+                let bytes_per_interval = e.bytes_per_interval;
+                println!(
+                    "          SuperSpeed companion: max burst {}, attributes {:#04x}, {bytes_per_interval} bytes per interval",
+                    e.max_burst, e.attributes
+                );
+            }
+            UsbDescriptor::Unknown {
+                desc_len,
+                desc_type,
+            } => println!("        Unknown descriptor: type {desc_type:#04x}, {desc_len} bytes"),
+            UsbDescriptor::Device(_) | UsbDescriptor::String => {}
+        }
+    }
+}
+
+fn lsusb() {
+    for (index, xhc) in xhci_controllers().iter().enumerate() {
+        println!("xHC {index}:");
+        for PortScIteratorItem { port, portsc } in xhc.portsc_iter() {
+            let Some(portsc) = portsc.upgrade() else {
+                continue;
+            };
+            if portsc.ccs() {
+                println!(
+                    "  Port {port}: USB{}, {:?}, {:?}",
+                    if portsc.is_usb3() { 3 } else { 2 },
+                    portsc.port_speed(),
+                    portsc.state()
+                );
+            }
+        }
+        for (slot, info) in xhc.devices() {
+            let location = info.location;
+            println!(
+                "  Slot {slot}: root port {}, route string {:#07x} (tier {}), {:?}",
+                location.root_port(),
+                location.route_string(),
+                location.tier(),
+                location.speed()
+            );
+            print_usb_descriptors(&info);
+        }
     }
 }
 
@@ -140,6 +312,64 @@ pub async fn run(cmdline: &str) -> Result<()> {
             "arp" => {
                 println!("{:?}", network.arp_table_cloned())
             }
+            "cpus" => {
+                for (index, apic_id) in online_cpus() {
+                    if index == 0 {
+                        println!("CPU {index}: APIC ID {apic_id} (BSP)");
+                        continue;
+                    }
+                    // Ask the AP to tell its APIC ID to see if it runs the jobs
+                    let reported_id = run_on_cpu(index, LocalApic::current_id)?.await;
+                    println!(
+                        "CPU {index}: APIC ID {apic_id}, a job reported APIC ID {reported_id}"
+                    );
+                }
+            }
+            "keymap" => match args.get(1) {
+                Some(name) => match Keymap::from_name(name) {
+                    Some(keymap) => set_keymap(keymap),
+                    None => println!("keymap: unknown keymap {name}"),
+                },
+                None => {
+                    let names: Vec<&str> = Keymap::ALL.iter().map(|k| k.name()).collect();
+                    println!(
+                        "current: {}, available: {}",
+                        keymap().name(),
+                        names.join(" ")
+                    );
+                }
+            },
+            "lsblk" => {
+                for (index, dev) in block_devices().iter().enumerate() {
+                    println!(
+                        "{index}: {}: {} blocks * {} bytes",
+                        dev.name(),
+                        dev.num_blocks(),
+                        dev.block_size()
+                    );
+                }
+            }
+            "blkread" => {
+                if let Some((dev, lba)) = block_device_and_lba(&args) {
+                    let data = read_blocks(dev.as_ref(), lba, 1).await?;
+                    hexdump(&data);
+                } else {
+                    println!("usage: blkread <device_index> <lba>")
+                }
+            }
+            "blkwrite" => {
+                if let (Some((dev, lba)), Some(text)) = (block_device_and_lba(&args), args.get(3)) {
+                    // Writes the text at the beginning of the block and fills the rest with zeros
+                    let mut data = vec![0u8; dev.block_size()];
+                    let len = text.len().min(data.len());
+                    data[..len].copy_from_slice(&text.as_bytes()[..len]);
+                    write_blocks(dev.as_ref(), lba, data.into()).await?;
+                } else {
+                    println!("usage: blkwrite <device_index> <lba> <text>")
+                }
+            }
+            "lspci" => lspci()?,
+            "lsusb" => lsusb(),
             "nslookup" => {
                 if let Some(query) = args.get(1) {
                     let res = query_dns(query).await?;
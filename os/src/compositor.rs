@@ -0,0 +1,731 @@
+//! Window compositor
+//!
+//! Each window has its own surface, which is mapped into the app so that
+//! the app can draw into it directly. Apps tell the compositor which part
+//! of the surface is updated (damaged), and the compositor redraws the damaged
+//! region of the screen from the desktop layer (e.g. the kernel console) and
+//! the windows in the z-order, so overlapping windows never draw over each other
+//! and the region under a destroyed window is repainted.
+//! The damaged regions are redrawn into the back buffer (see vram::BackBuffer)
+//! on each tick of compositor_tick_task().
+//!
+//! The compositor also works as a window manager: windows can be dragged by
+//! the title bar, a click brings the window to the top and gives it the keyboard focus,
+//! and a click on the close button is delivered to the app as a close event.
+//! The title bar is drawn by apps (see noli::window::Window)
+//! with the geometry defined in sabi.
+
+extern crate alloc;
+
+use crate::boot_info::BootInfo;
+use crate::error::Error;
+use crate::error::Result;
+use crate::executor::yield_execution;
+use crate::executor::TimeoutFuture;
+use crate::hpet::Hpet;
+use crate::input::InputManager;
+use crate::memory::ContiguousPhysicalMemoryPages;
+use crate::mutex::Mutex;
+use crate::process::ProcessId;
+use crate::vram::BackBuffer;
+use crate::vram::DirtyRects;
+use crate::x86_64::paging::PageAttr;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::Ordering;
+use noli::bitmap::bitmap_blit;
+use noli::bitmap::Bitmap;
+use noli::rect::Rect;
+use sabi::Event;
+use sabi::MouseEvent;
+use sabi::PointerPosition;
+use sabi::WINDOW_CLOSE_BUTTON_RIGHT_MARGIN;
+use sabi::WINDOW_CLOSE_BUTTON_SIZE;
+use sabi::WINDOW_CLOSE_BUTTON_TOP;
+use sabi::WINDOW_TITLE_BAR_HEIGHT;
+
+static COMPOSITOR: Mutex<Option<Rc<Compositor>>> = Mutex::new(None);
+
+pub type WindowId = i64;
+
+// Interval of redrawing the damaged regions and flushing them to the screen.
+const COMPOSITOR_TICK_MS: u64 = 16;
+// The oldest event is dropped if the app does not consume the events.
+const MAX_EVENTS_PER_WINDOW: usize = 256;
+
+fn now_ms() -> u64 {
+    Hpet::take().uptime_ms()
+}
+
+#[derive(Debug, Copy, Clone)]
+struct WindowTimer {
+    interval_ms: u64,
+    next_ms: u64,
+}
+
+/// Pixels of a window, 32bpp without padding between lines.
+pub struct WindowSurface {
+    id: WindowId,
+    owner: ProcessId,
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+    pages: ContiguousPhysicalMemoryPages,
+    events: VecDeque<Event>,
+    timer: Option<WindowTimer>,
+}
+impl WindowSurface {
+    fn new(id: WindowId, owner: ProcessId, rect: Rect) -> Result<Self> {
+        let mut pages =
+            ContiguousPhysicalMemoryPages::alloc_bytes((rect.w() * rect.h() * 4) as usize)?;
+        pages.fill_with_bytes(0);
+        pages.set_page_attr(PageAttr::ReadWriteUser)?;
+        Ok(Self {
+            id,
+            owner,
+            x: rect.x(),
+            y: rect.y(),
+            width: rect.w(),
+            height: rect.h(),
+            pages,
+            events: VecDeque::new(),
+            timer: None,
+        })
+    }
+    pub fn id(&self) -> WindowId {
+        self.id
+    }
+    pub fn owner(&self) -> ProcessId {
+        self.owner
+    }
+    /// Returns the area of the window in the screen coordinates.
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.width, self.height).expect("size should be non-negative")
+    }
+    pub fn addr(&self) -> usize {
+        self.pages.range().start()
+    }
+    /// Returns the area of the close button in the window coordinates.
+    fn close_button_rect(&self) -> Rect {
+        Rect::new(
+            self.width - WINDOW_CLOSE_BUTTON_RIGHT_MARGIN - WINDOW_CLOSE_BUTTON_SIZE,
+            WINDOW_CLOSE_BUTTON_TOP,
+            WINDOW_CLOSE_BUTTON_SIZE,
+            WINDOW_CLOSE_BUTTON_SIZE,
+        )
+        .expect("size should be non-negative")
+    }
+    fn push_event(&mut self, e: Event) {
+        if self.events.len() >= MAX_EVENTS_PER_WINDOW {
+            self.events.pop_front();
+        }
+        self.events.push_back(e);
+    }
+    fn push_move_event(&mut self) {
+        self.push_event(Event::WindowMove(PointerPosition::from_xy(self.x, self.y)));
+    }
+}
+impl Bitmap for WindowSurface {
+    fn bytes_per_pixel(&self) -> i64 {
+        4
+    }
+    fn pixels_per_line(&self) -> i64 {
+        self.width
+    }
+    fn width(&self) -> i64 {
+        self.width
+    }
+    fn height(&self) -> i64 {
+        self.height
+    }
+    fn buf(&self) -> *const u8 {
+        self.addr() as *const u8
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.addr() as *mut u8
+    }
+}
+impl Drop for WindowSurface {
+    fn drop(&mut self) {
+        // The pages are not freed (see ContiguousPhysicalMemoryPages),
+        // so at least make them inaccessible from apps.
+        let _ = self.pages.set_page_attr(PageAttr::ReadWriteKernel);
+    }
+}
+
+/// Draws the region `rect` (in the screen coordinates) of the desktop layer and
+/// the windows into `dst`, from the bottom to the top.
+/// `desktop` and `dst` should have the same coordinates as the screen.
+fn compose_rect<D: Bitmap, T: Bitmap>(
+    windows: &[WindowSurface],
+    desktop: &D,
+    rect: &Rect,
+    dst: &mut T,
+) {
+    let screen = Rect::new(
+        0,
+        0,
+        dst.width().min(desktop.width()),
+        dst.height().min(desktop.height()),
+    )
+    .expect("size should be non-negative");
+    let rect = match rect.intersection(&screen) {
+        Some(r) if !r.is_empty() => r,
+        _ => return,
+    };
+    let mut line = vec![0; rect.w() as usize];
+    for y in rect.y()..rect.y() + rect.h() {
+        // SAFETY: the line is in the range of desktop since rect is clipped to it
+        line.copy_from_slice(unsafe {
+            core::slice::from_raw_parts(desktop.unchecked_pixel_at(rect.x(), y), rect.w() as usize)
+        });
+        for w in windows {
+            if !(w.y..w.y + w.height).contains(&y) {
+                continue;
+            }
+            let x0 = core::cmp::max(rect.x(), w.x);
+            let x1 = core::cmp::min(rect.x() + rect.w(), w.x + w.width);
+            if x0 >= x1 {
+                continue;
+            }
+            // SAFETY: (x0 - w.x)..(x1 - w.x) and y - w.y are in the range of the surface
+            let src = unsafe {
+                core::slice::from_raw_parts(
+                    w.unchecked_pixel_at(x0 - w.x, y - w.y),
+                    (x1 - x0) as usize,
+                )
+            };
+            line[(x0 - rect.x()) as usize..(x1 - rect.x()) as usize].copy_from_slice(src);
+        }
+        // SAFETY: the line is in the range of dst since rect is clipped to the screen
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                line.as_ptr(),
+                dst.unchecked_pixel_at_mut(rect.x(), y),
+                line.len(),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct DragState {
+    id: WindowId,
+    // The position of the pointer in the window coordinates
+    // when the drag started.
+    grab_x: i64,
+    grab_y: i64,
+}
+
+#[derive(Debug, Default)]
+struct WindowManagerState {
+    focused: Option<WindowId>,
+    drag: Option<DragState>,
+    last_mouse: MouseEvent,
+}
+
+pub struct Compositor {
+    // Sorted in the z-order, from the bottom to the top.
+    windows: Mutex<Vec<WindowSurface>>,
+    damage: Mutex<DirtyRects>,
+    next_id: AtomicI64,
+    // This should not be locked while the windows are locked.
+    wm_state: Mutex<WindowManagerState>,
+}
+impl Compositor {
+    fn new() -> Self {
+        Self {
+            windows: Mutex::new(Vec::new()),
+            damage: Mutex::new(DirtyRects::new()),
+            next_id: AtomicI64::new(0),
+            wm_state: Mutex::new(WindowManagerState::default()),
+        }
+    }
+    pub fn take() -> Rc<Self> {
+        let mut instance = COMPOSITOR.lock();
+        let instance = instance.get_or_insert_with(|| Rc::new(Self::new()));
+        instance.clone()
+    }
+    /// Creates a window on the top of the others.
+    /// Returns the id and the address of the surface, which is accessible from apps.
+    pub fn create_window(&self, owner: ProcessId, rect: Rect) -> Result<(WindowId, usize)> {
+        let vram = BootInfo::take().vram();
+        if rect.is_empty() || rect.w() > vram.width() || rect.h() > vram.height() {
+            return Err(Error::Failed("Invalid window size"));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let surface = WindowSurface::new(id, owner, rect)?;
+        let addr = surface.addr();
+        self.windows.lock().push(surface);
+        self.add_damage(rect);
+        self.set_focus(&mut self.wm_state.lock(), Some(id));
+        Ok((id, addr))
+    }
+    pub fn destroy_window(&self, owner: ProcessId, id: WindowId) -> Result<()> {
+        let rect = {
+            let mut windows = self.windows.lock();
+            let index = windows
+                .iter()
+                .position(|w| w.id == id && w.owner == owner)
+                .ok_or(Error::Failed("No such window"))?;
+            windows.remove(index).rect()
+        };
+        self.forget_window(id);
+        self.add_damage(rect);
+        Ok(())
+    }
+    /// Destroys all the windows of the process. Called when the process exits.
+    pub fn destroy_windows_of(&self, owner: ProcessId) {
+        let mut destroyed = Vec::new();
+        self.windows.lock().retain(|w| {
+            if w.owner == owner {
+                destroyed.push((w.id, w.rect()));
+                false
+            } else {
+                true
+            }
+        });
+        if destroyed.is_empty() {
+            return;
+        }
+        for (id, rect) in destroyed {
+            self.forget_window(id);
+            self.add_damage(rect);
+        }
+    }
+    /// Clears the window manager state for the destroyed window.
+    fn forget_window(&self, id: WindowId) {
+        let mut state = self.wm_state.lock();
+        if state.focused == Some(id) {
+            state.focused = None;
+        }
+        if state.drag.map(|d| d.id) == Some(id) {
+            state.drag = None;
+        }
+    }
+    /// Moves the window to (x, y) in the screen coordinates
+    /// and pushes Event::WindowMove to the window.
+    pub fn move_window(&self, owner: ProcessId, id: WindowId, x: i64, y: i64) -> Result<()> {
+        let (old_rect, new_rect) = {
+            let mut windows = self.windows.lock();
+            let w = windows
+                .iter_mut()
+                .find(|w| w.id == id && w.owner == owner)
+                .ok_or(Error::Failed("No such window"))?;
+            let old_rect = w.rect();
+            w.x = x;
+            w.y = y;
+            w.push_move_event();
+            (old_rect, w.rect())
+        };
+        self.add_damage(old_rect);
+        self.add_damage(new_rect);
+        Ok(())
+    }
+    pub fn next_event(&self, owner: ProcessId, id: WindowId) -> Result<Option<Event>> {
+        let mut windows = self.windows.lock();
+        let w = windows
+            .iter_mut()
+            .find(|w| w.id == id && w.owner == owner)
+            .ok_or(Error::Failed("No such window"))?;
+        Ok(w.events.pop_front())
+    }
+    /// Sets a periodic timer that pushes Event::Timer to the window
+    /// every `interval_ms`. The timer is disabled if `interval_ms` is 0.
+    pub fn set_window_timer(&self, owner: ProcessId, id: WindowId, interval_ms: u64) -> Result<()> {
+        let mut windows = self.windows.lock();
+        let w = windows
+            .iter_mut()
+            .find(|w| w.id == id && w.owner == owner)
+            .ok_or(Error::Failed("No such window"))?;
+        w.timer = if interval_ms == 0 {
+            None
+        } else {
+            Some(WindowTimer {
+                interval_ms,
+                next_ms: now_ms() + interval_ms,
+            })
+        };
+        Ok(())
+    }
+    fn dispatch_timers(&self, now_ms: u64) {
+        for w in self.windows.lock().iter_mut() {
+            if let Some(timer) = w.timer.as_mut() {
+                if timer.next_ms <= now_ms {
+                    timer.next_ms = now_ms + timer.interval_ms;
+                    w.push_event(Event::Timer { time_ms: now_ms });
+                }
+            }
+        }
+    }
+    /// Delivers the key event to the focused window, if any.
+    pub fn dispatch_key_event(&self, e: Event) {
+        let focused = self.wm_state.lock().focused;
+        if let Some(w) = self
+            .windows
+            .lock()
+            .iter_mut()
+            .find(|w| Some(w.id) == focused)
+        {
+            w.push_event(e);
+        }
+    }
+    /// Returns the owner of the focused window, which should be
+    /// the only process that receives the keyboard input.
+    /// Returns None if no window has the focus.
+    pub fn focused_owner(&self) -> Option<ProcessId> {
+        let focused = self.wm_state.lock().focused?;
+        self.windows
+            .lock()
+            .iter()
+            .find(|w| w.id == focused)
+            .map(|w| w.owner)
+    }
+    /// Moves the focus to the window `id` and brings it to the top.
+    fn set_focus(&self, state: &mut WindowManagerState, id: Option<WindowId>) {
+        if state.focused == id {
+            return;
+        }
+        let raised = {
+            let mut windows = self.windows.lock();
+            if let Some(w) = windows.iter_mut().find(|w| Some(w.id) == state.focused) {
+                w.push_event(Event::WindowUnfocus);
+            }
+            let index = id.and_then(|id| windows.iter().position(|w| w.id == id));
+            index.map(|index| {
+                let mut w = windows.remove(index);
+                w.push_event(Event::WindowFocus);
+                let rect = w.rect();
+                windows.push(w);
+                rect
+            })
+        };
+        state.focused = id;
+        if let Some(rect) = raised {
+            self.add_damage(rect);
+        }
+    }
+    /// Handles a mouse event for the window management.
+    /// Returns true if the event is consumed by the compositor,
+    /// or false if it should be delivered to apps.
+    /// In the latter case, the event is also delivered to the window under the pointer.
+    pub fn handle_mouse_event(&self, e: &MouseEvent) -> bool {
+        let mut state = self.wm_state.lock();
+        let last = state.last_mouse;
+        state.last_mouse = *e;
+        let button_l = e.button.l();
+        let pressed = button_l && !last.button.l();
+        let (x, y) = (e.position.x, e.position.y);
+        let consumed = self.handle_mouse_event_for_wm(&mut state, pressed, button_l, x, y);
+        if !consumed {
+            let is_button_changed = (e.button.l(), e.button.c(), e.button.r())
+                != (last.button.l(), last.button.c(), last.button.r());
+            let is_moved = (x, y) != (last.position.x, last.position.y);
+            self.dispatch_mouse_event(e, is_button_changed, is_moved);
+        }
+        consumed
+    }
+    fn dispatch_mouse_event(&self, e: &MouseEvent, is_button_changed: bool, is_moved: bool) {
+        let mut windows = self.windows.lock();
+        let (x, y) = (e.position.x, e.position.y);
+        if let Some(w) = windows
+            .iter_mut()
+            .rev()
+            .find(|w| w.rect().contains_point(x, y))
+        {
+            let local = MouseEvent {
+                button: e.button,
+                position: PointerPosition::from_xy(x - w.x, y - w.y),
+                wheel: e.wheel,
+            };
+            if is_button_changed {
+                w.push_event(Event::MouseButton(local));
+            } else if is_moved {
+                w.push_event(Event::MouseMove(local));
+            }
+            if e.wheel != 0 {
+                w.push_event(Event::MouseWheel(e.wheel));
+            }
+        }
+    }
+    fn handle_mouse_event_for_wm(
+        &self,
+        state: &mut WindowManagerState,
+        pressed: bool,
+        button_l: bool,
+        x: i64,
+        y: i64,
+    ) -> bool {
+        if let Some(drag) = state.drag {
+            if button_l {
+                let moved = {
+                    let mut windows = self.windows.lock();
+                    windows.iter_mut().find(|w| w.id == drag.id).map(|w| {
+                        let old_rect = w.rect();
+                        w.x = x - drag.grab_x;
+                        w.y = y - drag.grab_y;
+                        w.push_move_event();
+                        (old_rect, w.rect())
+                    })
+                };
+                if let Some((old_rect, new_rect)) = moved {
+                    self.add_damage(old_rect);
+                    self.add_damage(new_rect);
+                }
+            } else {
+                state.drag = None;
+            }
+            return true;
+        }
+        if !pressed {
+            return false;
+        }
+        let hit = self
+            .windows
+            .lock()
+            .iter()
+            .rev()
+            .find(|w| w.rect().contains_point(x, y))
+            .map(|w| (w.id, x - w.x, y - w.y, w.close_button_rect()));
+        let (id, local_x, local_y, close_button) = match hit {
+            Some(hit) => hit,
+            None => {
+                self.set_focus(state, None);
+                return false;
+            }
+        };
+        self.set_focus(state, Some(id));
+        if local_y >= WINDOW_TITLE_BAR_HEIGHT {
+            // Clicks in the client area are for the app
+            return false;
+        }
+        if close_button.contains_point(local_x, local_y) {
+            if let Some(w) = self.windows.lock().iter_mut().find(|w| w.id == id) {
+                w.push_event(Event::WindowClose);
+            }
+        } else {
+            state.drag = Some(DragState {
+                id,
+                grab_x: local_x,
+                grab_y: local_y,
+            });
+        }
+        true
+    }
+    /// Marks the `rect` in the window coordinates as damaged and updates the screen.
+    pub fn damage_window_rect(&self, owner: ProcessId, id: WindowId, rect: Rect) -> Result<()> {
+        let window_rect = self
+            .windows
+            .lock()
+            .iter()
+            .find(|w| w.id == id && w.owner == owner)
+            .map(|w| w.rect())
+            .ok_or(Error::Failed("No such window"))?;
+        let rect = Rect::new(
+            window_rect.x() + rect.x(),
+            window_rect.y() + rect.y(),
+            rect.w(),
+            rect.h(),
+        )
+        .ok_or(Error::Failed("Invalid rect"))?;
+        if let Some(rect) = rect.intersection(&window_rect) {
+            self.add_damage(rect);
+        }
+        Ok(())
+    }
+    /// Copies `src_rect` of `src` to (dx, dy) of the window surface and updates the screen.
+    pub fn blit_to_window<T: Bitmap>(
+        &self,
+        owner: ProcessId,
+        id: WindowId,
+        src: &T,
+        src_rect: &Rect,
+        dx: i64,
+        dy: i64,
+    ) -> Result<()> {
+        let updated = {
+            let mut windows = self.windows.lock();
+            let w = windows
+                .iter_mut()
+                .find(|w| w.id == id && w.owner == owner)
+                .ok_or(Error::Failed("No such window"))?;
+            bitmap_blit(w, src, src_rect, dx, dy)
+                .map(|r| Rect::new(w.x + r.x(), w.y + r.y(), r.w(), r.h()))
+        };
+        if let Some(Some(rect)) = updated {
+            self.add_damage(rect);
+        }
+        Ok(())
+    }
+    /// Marks the `rect` in the screen coordinates as damaged.
+    pub fn add_damage(&self, rect: Rect) {
+        self.damage.lock().add(rect);
+    }
+    /// Redraws the damaged regions of the screen into the back buffer.
+    pub fn compose(&self) {
+        let back_buffer = BackBuffer::take();
+        let mut damage = self.damage.lock().take();
+        damage.extend(back_buffer.take_desktop_damage());
+        if damage.is_empty() {
+            return;
+        }
+        let desktop = back_buffer.bitmap();
+        let mut screen = back_buffer.composed_bitmap();
+        let windows = self.windows.lock();
+        for rect in damage {
+            compose_rect(&windows, &desktop, &rect, &mut screen);
+            back_buffer.mark_dirty(rect);
+        }
+    }
+}
+
+/// Dispatches the input events and timer events to the window manager and the apps.
+pub async fn window_manager_task() -> Result<()> {
+    let compositor = Compositor::take();
+    let input = InputManager::take();
+    let back_buffer = BackBuffer::take();
+    loop {
+        while let Some(e) = input.pop_cursor_input_absolute() {
+            back_buffer.move_cursor(e.position.x, e.position.y);
+            if !compositor.handle_mouse_event(&e) {
+                input.push_cursor_input_for_apps(e);
+            }
+        }
+        while let Some(e) = input.pop_key_event() {
+            compositor.dispatch_key_event(e);
+        }
+        compositor.dispatch_timers(now_ms());
+        yield_execution().await;
+    }
+}
+
+/// Composes the damaged regions and copies the dirty regions of the back buffer
+/// to the screen periodically.
+pub async fn compositor_tick_task() -> Result<()> {
+    let compositor = Compositor::take();
+    let back_buffer = BackBuffer::take();
+    loop {
+        compositor.compose();
+        back_buffer.flush();
+        TimeoutFuture::new_ms(COMPOSITOR_TICK_MS).await;
+        yield_execution().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use noli::bitmap::bitmap_draw_rect;
+    use noli::bitmap::BitmapBuffer;
+
+    const DESKTOP_COLOR: u32 = 0x101010;
+
+    fn desktop() -> BitmapBuffer {
+        let mut desktop = BitmapBuffer::new(8, 8, 8);
+        bitmap_draw_rect(&mut desktop, DESKTOP_COLOR, 0, 0, 8, 8).unwrap();
+        desktop
+    }
+
+    #[test_case]
+    fn windows_are_composed_in_z_order() {
+        let mut bottom = WindowSurface::new(0, 0, Rect::new(0, 0, 4, 4).unwrap()).unwrap();
+        let mut top = WindowSurface::new(1, 0, Rect::new(2, 2, 4, 4).unwrap()).unwrap();
+        bitmap_draw_rect(&mut bottom, 0xff0000, 0, 0, 4, 4).unwrap();
+        bitmap_draw_rect(&mut top, 0x00ff00, 0, 0, 4, 4).unwrap();
+        let windows = vec![bottom, top];
+        let mut screen = BitmapBuffer::new(8, 8, 8);
+        compose_rect(
+            &windows,
+            &desktop(),
+            &Rect::new(0, 0, 8, 8).unwrap(),
+            &mut screen,
+        );
+        assert_eq!(*screen.pixel_at(0, 0).unwrap(), 0xff0000);
+        assert_eq!(*screen.pixel_at(3, 1).unwrap(), 0xff0000);
+        // Occluded by the top window
+        assert_eq!(*screen.pixel_at(3, 3).unwrap(), 0x00ff00);
+        assert_eq!(*screen.pixel_at(5, 5).unwrap(), 0x00ff00);
+        assert_eq!(*screen.pixel_at(7, 7).unwrap(), DESKTOP_COLOR);
+        assert_eq!(*screen.pixel_at(7, 0).unwrap(), DESKTOP_COLOR);
+    }
+
+    #[test_case]
+    fn only_damaged_rect_is_composed() {
+        let mut w = WindowSurface::new(0, 0, Rect::new(0, 0, 4, 4).unwrap()).unwrap();
+        bitmap_draw_rect(&mut w, 0xff0000, 0, 0, 4, 4).unwrap();
+        let windows = vec![w];
+        let mut screen = BitmapBuffer::new(8, 8, 8);
+        compose_rect(
+            &windows,
+            &desktop(),
+            &Rect::new(1, 1, 2, 2).unwrap(),
+            &mut screen,
+        );
+        assert_eq!(*screen.pixel_at(0, 0).unwrap(), 0);
+        assert_eq!(*screen.pixel_at(1, 1).unwrap(), 0xff0000);
+        assert_eq!(*screen.pixel_at(2, 2).unwrap(), 0xff0000);
+        assert_eq!(*screen.pixel_at(3, 3).unwrap(), 0);
+        // Clipped to the screen
+        compose_rect(
+            &windows,
+            &desktop(),
+            &Rect::new(-4, -4, 100, 100).unwrap(),
+            &mut screen,
+        );
+        assert_eq!(*screen.pixel_at(7, 7).unwrap(), DESKTOP_COLOR);
+    }
+
+    #[test_case]
+    fn windows_are_dragged_focused_and_closed_by_mouse() {
+        use sabi::MouseButtonState;
+        let mouse = |x, y, l| MouseEvent {
+            button: MouseButtonState::from_lcr(l, false, false),
+            position: PointerPosition::from_xy(x, y),
+            wheel: 0,
+        };
+        let c = Compositor::new();
+        c.windows
+            .lock()
+            .push(WindowSurface::new(0, 1, Rect::new(0, 0, 100, 100).unwrap()).unwrap());
+        c.windows
+            .lock()
+            .push(WindowSurface::new(1, 1, Rect::new(50, 50, 100, 100).unwrap()).unwrap());
+        // Clicking the title bar of the bottom window raises it and starts dragging
+        assert!(c.handle_mouse_event(&mouse(10, 10, true)));
+        assert_eq!(c.windows.lock().last().unwrap().id(), 0);
+        assert_eq!(c.focused_owner(), Some(1));
+        assert!(c.handle_mouse_event(&mouse(20, 30, true)));
+        assert!(c.handle_mouse_event(&mouse(20, 30, false)));
+        assert_eq!(
+            c.windows.lock().last().unwrap().rect(),
+            Rect::new(10, 20, 100, 100).unwrap()
+        );
+        // Clicks in the client area are delivered to the app
+        assert!(!c.handle_mouse_event(&mouse(50, 100, true)));
+        assert!(!c.handle_mouse_event(&mouse(50, 100, false)));
+        // Clicking the close button sends a close event
+        assert!(c.handle_mouse_event(&mouse(95, 30, true)));
+        let mut events = Vec::new();
+        while let Ok(Some(e)) = c.next_event(1, 0) {
+            events.push(e);
+        }
+        assert!(matches!(events.first(), Some(Event::WindowFocus)));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, Event::WindowMove(p) if (p.x, p.y) == (10, 20))));
+        // Mouse events are delivered in the window-local coordinates
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::MouseButton(m) if (m.position.x, m.position.y) == (40, 80) && m.button.l()
+        )));
+        assert!(matches!(events.last(), Some(Event::WindowClose)));
+        // Clicking the desktop removes the focus
+        assert!(!c.handle_mouse_event(&mouse(95, 30, false)));
+        assert!(!c.handle_mouse_event(&mouse(500, 500, true)));
+        assert_eq!(c.focused_owner(), None);
+    }
+}
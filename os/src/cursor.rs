@@ -0,0 +1,158 @@
+//! Mouse cursor sprite
+//!
+//! The cursor is drawn directly on the framebuffer after the back buffer is
+//! copied to it (see vram::BackBuffer::flush()), so it never touches the
+//! contents of the back buffer. The pixels under the cursor are saved before
+//! drawing it, and restored when the cursor is moved or hidden.
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use noli::bitmap::Bitmap;
+
+// 'X': border, '.': fill, ' ': transparent
+const CURSOR_SHAPE: [&str; 19] = [
+    "X           ",
+    "XX          ",
+    "X.X         ",
+    "X..X        ",
+    "X...X       ",
+    "X....X      ",
+    "X.....X     ",
+    "X......X    ",
+    "X.......X   ",
+    "X........X  ",
+    "X.........X ",
+    "X..........X",
+    "X......XXXXX",
+    "X...X..X    ",
+    "X..XX..X    ",
+    "X.X  X..X   ",
+    "XX   X..X   ",
+    "      X..X  ",
+    "      XXX   ",
+];
+const CURSOR_WIDTH: i64 = 12;
+const CURSOR_HEIGHT: i64 = CURSOR_SHAPE.len() as i64;
+const CURSOR_BORDER_COLOR: u32 = 0x000000;
+const CURSOR_FILL_COLOR: u32 = 0xffffff;
+
+#[derive(Debug)]
+pub struct Cursor {
+    // None until the first position is given by a pointing device
+    position: Option<(i64, i64)>,
+    // Where the cursor is drawn now, and the pixels under it
+    drawn_at: Option<(i64, i64)>,
+    saved_under: Vec<u32>,
+}
+impl Default for Cursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Cursor {
+    pub fn new() -> Self {
+        Self {
+            position: None,
+            drawn_at: None,
+            saved_under: vec![0; (CURSOR_WIDTH * CURSOR_HEIGHT) as usize],
+        }
+    }
+    /// Sets the position of the hot spot (the tip of the arrow).
+    /// The cursor will be drawn there on the next show().
+    pub fn set_position(&mut self, x: i64, y: i64) {
+        self.position = Some((x, y));
+    }
+    /// Returns true if show() needs to be called to reflect the position.
+    pub fn is_moved(&self) -> bool {
+        self.position != self.drawn_at
+    }
+    fn for_each_pixel(x: i64, y: i64, mut f: impl FnMut(i64, i64, usize, u8)) {
+        for (dy, line) in CURSOR_SHAPE.iter().enumerate() {
+            for (dx, c) in line.bytes().enumerate() {
+                let i = dy * CURSOR_WIDTH as usize + dx;
+                f(x + dx as i64, y + dy as i64, i, c);
+            }
+        }
+    }
+    /// Restores the pixels under the cursor.
+    pub fn hide<T: Bitmap>(&mut self, dst: &mut T) {
+        let (x, y) = match self.drawn_at.take() {
+            Some(p) => p,
+            None => return,
+        };
+        let saved_under = &self.saved_under;
+        Self::for_each_pixel(x, y, |x, y, i, c| {
+            if c == b' ' {
+                return;
+            }
+            if let Some(p) = dst.pixel_at_mut(x, y) {
+                *p = saved_under[i];
+            }
+        });
+    }
+    /// Draws the cursor at the current position after saving the pixels under it.
+    /// The cursor should be hidden before the pixels under it are updated.
+    pub fn show<T: Bitmap>(&mut self, dst: &mut T) {
+        self.hide(dst);
+        let (x, y) = match self.position {
+            Some(p) => p,
+            None => return,
+        };
+        let saved_under = &mut self.saved_under;
+        Self::for_each_pixel(x, y, |x, y, i, c| {
+            let color = match c {
+                b'X' => CURSOR_BORDER_COLOR,
+                b'.' => CURSOR_FILL_COLOR,
+                _ => return,
+            };
+            if let Some(p) = dst.pixel_at_mut(x, y) {
+                saved_under[i] = *p;
+                *p = color;
+            }
+        });
+        self.drawn_at = Some((x, y));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use noli::bitmap::bitmap_draw_rect;
+    use noli::bitmap::BitmapBuffer;
+
+    #[test_case]
+    fn cursor_restores_pixels_under_it() {
+        let mut screen = BitmapBuffer::new(32, 32, 32);
+        bitmap_draw_rect(&mut screen, 0x123456, 0, 0, 32, 32).unwrap();
+        let mut cursor = Cursor::new();
+        assert!(!cursor.is_moved());
+        // Not drawn until the position is given
+        cursor.show(&mut screen);
+        assert_eq!(*screen.pixel_at(0, 0).unwrap(), 0x123456);
+
+        cursor.set_position(4, 4);
+        assert!(cursor.is_moved());
+        cursor.show(&mut screen);
+        assert!(!cursor.is_moved());
+        assert_eq!(*screen.pixel_at(4, 4).unwrap(), CURSOR_BORDER_COLOR);
+        assert_eq!(*screen.pixel_at(5, 6).unwrap(), CURSOR_FILL_COLOR);
+        // Transparent pixels are kept as is
+        assert_eq!(*screen.pixel_at(15, 4).unwrap(), 0x123456);
+
+        // Moving the cursor restores the pixels at the previous position.
+        // The cursor can be partially out of the screen.
+        cursor.set_position(28, 28);
+        cursor.show(&mut screen);
+        assert_eq!(*screen.pixel_at(4, 4).unwrap(), 0x123456);
+        assert_eq!(*screen.pixel_at(5, 6).unwrap(), 0x123456);
+        assert_eq!(*screen.pixel_at(28, 28).unwrap(), CURSOR_BORDER_COLOR);
+        cursor.hide(&mut screen);
+        for y in 0..32 {
+            for x in 0..32 {
+                assert_eq!(*screen.pixel_at(x, y).unwrap(), 0x123456);
+            }
+        }
+    }
+}
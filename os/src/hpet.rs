@@ -127,6 +127,10 @@ impl Hpet {
         // This is safe as far as self is properly constructed.
         self.freq
     }
+    /// Uptime of the system in milliseconds
+    pub fn uptime_ms(&self) -> u64 {
+        self.main_counter() / (self.freq() / 1000)
+    }
     pub fn notify_end_of_interrupt(&mut self) {
         self.registers.interrupt_status.store(0, Ordering::Relaxed);
     }
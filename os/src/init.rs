@@ -13,7 +13,9 @@ use crate::serial::SerialPortIndex;
 use crate::util;
 use crate::util::size_in_pages_from_bytes;
 use crate::vram;
+use crate::vram::BackBuffer;
 use crate::vram::VRAMBufferInfo;
+use crate::warn;
 use crate::x86_64;
 use crate::x86_64::apic::IoApic;
 use crate::x86_64::block_interrupts;
@@ -183,7 +185,7 @@ pub fn init_global_allocator() {
 }
 
 pub fn init_graphical_terminal() {
-    let vram = BootInfo::take().vram();
+    let vram = BackBuffer::take().bitmap();
     let mut textarea = TextArea::new(
         vram,
         0,
@@ -224,7 +226,9 @@ pub struct InterruptConfiguration {
     idt: Pin<Box<Idt>>,
 }
 
-pub fn init_interrupts() -> Result<InterruptConfiguration> {
+/// Sets up the processor-local structures for interrupts (TSS, GDT and IDT).
+/// This is called on every processor including APs.
+pub fn init_cpu_local_interrupts() -> Result<InterruptConfiguration> {
     block_interrupts();
     let tss64 = TaskStateSegment64::new()?;
     let gdt = Gdt::new(&tss64)?;
@@ -236,13 +240,19 @@ pub fn init_interrupts() -> Result<InterruptConfiguration> {
         x86_64::write_fs(x86_64::KERNEL_DS);
         x86_64::write_gs(x86_64::KERNEL_DS);
     }
-    x86_64::disable_legacy_pic();
-    let bsp_local_apic = BootInfo::take().bsp_local_apic();
-    IoApic::init(bsp_local_apic).expect("Failed to init I/O APIC");
     let idt = Idt::new(x86_64::KERNEL_CS)?;
     Ok(InterruptConfiguration { tss64, gdt, idt })
 }
 
+pub fn init_interrupts() -> Result<InterruptConfiguration> {
+    let interrupt_config = init_cpu_local_interrupts()?;
+    x86_64::disable_legacy_pic();
+    let boot_info = BootInfo::take();
+    IoApic::init(boot_info.acpi().madt(), boot_info.bsp_local_apic())
+        .expect("Failed to init I/O APIC");
+    Ok(interrupt_config)
+}
+
 pub fn detect_fsb_freq() -> Option<u64> {
     let fsb_freq_msr = unsafe { x86_64::read_msr(x86_64::MSR_FSB_FREQ) };
     let fsb_khz = match fsb_freq_msr & 0b111 {
@@ -284,6 +294,12 @@ pub fn init_timer() {
     }
 }
 
+pub fn init_smp() {
+    if let Err(e) = x86_64::smp::start_application_processors() {
+        warn!("Failed to start application processors: {e:?}");
+    }
+}
+
 pub fn init_pci() {
     let acpi = BootInfo::take().acpi();
     let mcfg = acpi.mcfg();
@@ -1,42 +1,177 @@
 extern crate alloc;
 
+use crate::boot_info::BootInfo;
+use crate::compositor::Compositor;
 use crate::mutex::Mutex;
+use crate::tty;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
+use noli::bitmap::Bitmap;
+use sabi::Event;
+use sabi::KeyModifiers;
+use sabi::KeyboardEvent;
+use sabi::MouseButtonState;
 use sabi::MouseEvent;
+use sabi::PointerPosition;
 
 static INPUT_MANAGER: Mutex<Option<Rc<InputManager>>> = Mutex::new(None);
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum KeyEvent {
+/// A key identified by its function, after applying the keymap and the modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
     None,
     Char(char),
     Enter,
-    Right,
+    Escape,
+    Backspace,
+    Tab,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
     Left,
+    Right,
+    /// F1 to F24
+    Function(u8),
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    PrintScreen,
+    Pause,
+    Application,
+    Power,
+    Help,
+    Menu,
+    Cancel,
+    Undo,
+    Cut,
+    Copy,
+    Paste,
+    Find,
+    Mute,
+    VolumeUp,
+    VolumeDown,
+    // Keys on Japanese keyboards
+    Henkan,
+    Muhenkan,
+    KatakanaHiragana,
+    Kana,
+    Eisu,
+    /// Ctrl, Shift, Alt or GUI. See KeyEvent::modifiers for the state.
+    Modifier,
+    /// Other keys in the Keyboard/Keypad page, with the Usage ID
+    Other(u8),
 }
 
+/// A key press or release (see keyboard::KeyboardState)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// USB HID Usage ID of the key (Keyboard/Keypad page)
+    pub usage_id: u8,
+    pub key: Key,
+    /// Modifiers after this event is applied
+    pub modifiers: KeyModifiers,
+    pub pressed: bool,
+    /// True if the event is generated by holding the key (key repeat)
+    pub repeated: bool,
+}
 impl KeyEvent {
+    /// Returns the character to be typed into the console.
     pub fn to_char(&self) -> Option<char> {
-        match self {
-            KeyEvent::Char(c) => Some(*c),
-            KeyEvent::Enter => Some('\n'),
-            KeyEvent::Right => Some('>'),
-            KeyEvent::Left => Some('<'),
+        match self.key {
+            Key::Char(c) => Some(c),
+            Key::Enter => Some('\n'),
+            Key::Tab => Some('\t'),
+            Key::Backspace => Some(0x08 as char),
+            Key::Escape => Some(0x1b as char),
+            Key::Delete => Some(0x7f as char),
             _ => None,
         }
     }
+    pub fn to_keyboard_event(&self) -> KeyboardEvent {
+        KeyboardEvent {
+            scancode: self.usage_id as u32,
+            modifiers: self.modifiers,
+            ch: self.to_char().map(|c| c as u32).unwrap_or(0),
+        }
+    }
+}
+
+/// Buttons and axes of a game controller (e.g. gamepads and joysticks)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GamepadState {
+    /// Bit n is set while Button n + 1 is pressed
+    pub buttons: u32,
+    /// X, Y, Z, Rx, Ry and Rz (Usage IDs 0x30 to 0x35), scaled into [-GAMEPAD_AXIS_MAX, GAMEPAD_AXIS_MAX]
+    pub axes: [i32; 6],
+    /// Direction of the hat switch (0: up, then clockwise in steps of 45 degrees),
+    /// or None if it is released
+    pub hat: Option<u8>,
+}
+pub const GAMEPAD_AXIS_MAX: i32 = 32767;
+
+/// An input from a device, independent of how the device reports it.
+/// Drivers decode their reports into InputEvents and feed them to InputManager::push_event().
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    /// Moves the pointer by (dx, dy) from the last position (e.g. mice)
+    PointerRelative {
+        button: MouseButtonState,
+        dx: i64,
+        dy: i64,
+        wheel: i64,
+    },
+    /// Moves the pointer to (x, y), which are relative to the screen size
+    /// in [0.0, 1.0] (e.g. tablets)
+    PointerAbsolute {
+        button: MouseButtonState,
+        x: f64,
+        y: f64,
+        wheel: i64,
+    },
+    Gamepad(GamepadState),
+}
+
+/// Returns the position on the screen of width x height for (x, y) in [0.0, 1.0].
+fn scale_to_screen(x: f64, y: f64, width: i64, height: i64) -> PointerPosition {
+    let scale = |v: f64, size: i64| (v * size as f64).clamp(0.0, (size - 1) as f64) as i64;
+    PointerPosition::from_xy(scale(x, width), scale(y, height))
+}
+
+/// Moves the pointer by (dx, dy), keeping it in the screen of width x height.
+fn move_pointer(p: PointerPosition, dx: i64, dy: i64, width: i64, height: i64) -> PointerPosition {
+    PointerPosition::from_xy(
+        p.x.saturating_add(dx).clamp(0, width - 1),
+        p.y.saturating_add(dy).clamp(0, height - 1),
+    )
 }
 
 pub struct InputManager {
     input_queue: Mutex<VecDeque<char>>,
     cursor_queue: Mutex<VecDeque<MouseEvent>>,
+    // Mouse events that are not consumed by the window manager
+    // (see compositor::window_manager_task())
+    app_cursor_queue: Mutex<VecDeque<MouseEvent>>,
+    // Event::KeyDown or Event::KeyUp, delivered to the focused window
+    key_event_queue: Mutex<VecDeque<Event>>,
+    // Last position of the pointer, to apply relative movements of mice
+    pointer_position: Mutex<PointerPosition>,
+    gamepad_queue: Mutex<VecDeque<GamepadState>>,
 }
 impl InputManager {
     fn new() -> Self {
         Self {
             input_queue: Mutex::new(VecDeque::new()),
             cursor_queue: Mutex::new(VecDeque::new()),
+            app_cursor_queue: Mutex::new(VecDeque::new()),
+            key_event_queue: Mutex::new(VecDeque::new()),
+            pointer_position: Mutex::new(PointerPosition::default()),
+            gamepad_queue: Mutex::new(VecDeque::new()),
         }
     }
     pub fn take() -> Rc<Self> {
@@ -45,17 +180,129 @@ impl InputManager {
         instance.clone()
     }
     pub fn push_input(&self, value: char) {
+        if value == tty::INTR {
+            tty::request_interrupt();
+        }
         self.input_queue.lock().push_back(value)
     }
     pub fn pop_input(&self) -> Option<char> {
         self.input_queue.lock().pop_front()
     }
+    /// Discards the input that is not read yet (e.g. on Ctrl-C)
+    pub fn clear_input(&self) {
+        self.input_queue.lock().clear()
+    }
 
-    // x, y: 0f32..1f32, top left origin
+    // position: in the screen coordinates, top left origin
     pub fn push_cursor_input_absolute(&self, e: MouseEvent) {
+        *self.pointer_position.lock() = e.position;
         self.cursor_queue.lock().push_back(e)
     }
+    /// Moves the pointer by (dx, dy) from the last position (e.g. for mice),
+    /// and queues the event with the absolute position in the screen.
+    pub fn push_cursor_input_relative(
+        &self,
+        button: MouseButtonState,
+        dx: i64,
+        dy: i64,
+        wheel: i64,
+    ) {
+        let vram = BootInfo::take().vram();
+        let position = {
+            let mut position = self.pointer_position.lock();
+            *position = move_pointer(*position, dx, dy, vram.width(), vram.height());
+            *position
+        };
+        self.cursor_queue.lock().push_back(MouseEvent {
+            button,
+            position,
+            wheel,
+        })
+    }
     pub fn pop_cursor_input_absolute(&self) -> Option<MouseEvent> {
         self.cursor_queue.lock().pop_front()
     }
+    pub fn push_cursor_input_for_apps(&self, e: MouseEvent) {
+        self.app_cursor_queue.lock().push_back(e)
+    }
+    pub fn pop_cursor_input_for_apps(&self) -> Option<MouseEvent> {
+        self.app_cursor_queue.lock().pop_front()
+    }
+    pub fn push_key_event(&self, e: KeyboardEvent, pressed: bool) {
+        self.key_event_queue.lock().push_back(if pressed {
+            Event::KeyDown(e)
+        } else {
+            Event::KeyUp(e)
+        })
+    }
+    /// Delivers the key event to the focused window, and the character
+    /// of the pressed key to the console.
+    pub fn push_key(&self, e: &KeyEvent) {
+        self.push_key_event(e.to_keyboard_event(), e.pressed);
+        if !e.pressed {
+            return;
+        }
+        if let Some(c) = e.to_char() {
+            // Ctrl-C typed into a window is for the window, not for the console
+            if c == tty::INTR && Compositor::take().focused_owner().is_some() {
+                return;
+            }
+            self.push_input(c);
+        }
+    }
+    pub fn pop_key_event(&self) -> Option<Event> {
+        self.key_event_queue.lock().pop_front()
+    }
+    pub fn pop_gamepad_input(&self) -> Option<GamepadState> {
+        self.gamepad_queue.lock().pop_front()
+    }
+    /// Delivers the input to where the input of the kind goes.
+    pub fn push_event(&self, e: &InputEvent) {
+        match *e {
+            InputEvent::Key(ref e) => self.push_key(e),
+            InputEvent::PointerRelative {
+                button,
+                dx,
+                dy,
+                wheel,
+            } => self.push_cursor_input_relative(button, dx, dy, wheel),
+            InputEvent::PointerAbsolute {
+                button,
+                x,
+                y,
+                wheel,
+            } => {
+                let vram = BootInfo::take().vram();
+                self.push_cursor_input_absolute(MouseEvent {
+                    button,
+                    position: scale_to_screen(x, y, vram.width(), vram.height()),
+                    wheel,
+                })
+            }
+            InputEvent::Gamepad(state) => self.gamepad_queue.lock().push_back(state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn pointer_is_moved_within_the_screen() {
+        let p = PointerPosition::from_xy(10, 20);
+        let p = move_pointer(p, 5, -5, 100, 50);
+        assert_eq!((p.x, p.y), (15, 15));
+        let p = move_pointer(p, -100, 100, 100, 50);
+        assert_eq!((p.x, p.y), (0, 49));
+        let p = move_pointer(p, 1000, -1000, 100, 50);
+        assert_eq!((p.x, p.y), (99, 0));
+    }
+    #[test_case]
+    fn absolute_position_is_scaled_to_the_screen() {
+        let p = scale_to_screen(0.5, 0.25, 100, 40);
+        assert_eq!((p.x, p.y), (50, 10));
+        let p = scale_to_screen(1.0, -0.5, 100, 40);
+        assert_eq!((p.x, p.y), (99, 0));
+    }
 }
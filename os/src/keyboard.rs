@@ -0,0 +1,493 @@
+//! Keyboard state and keymaps shared by keyboard drivers
+//!
+//! Keys are identified by the Usage IDs of the USB HID Keyboard/Keypad page,
+//! and translated into KeyEvents with the modifiers, the lock keys and the
+//! current keymap. Drivers for other keyboards should convert their scancodes
+//! into the Usage IDs and feed them to KeyboardState.
+//! c.f. https://usb.org/sites/default/files/hut1_5.pdf (10 Keyboard/Keypad Page (0x07))
+
+extern crate alloc;
+
+use crate::bitset::BitSet;
+use crate::input::Key;
+use crate::input::KeyEvent;
+use crate::mutex::Mutex;
+use alloc::vec::Vec;
+use sabi::KeyModifiers;
+
+// Usage IDs of the Keyboard/Keypad page that need special handling
+pub const USAGE_ERROR_ROLL_OVER: u8 = 0x01;
+pub const USAGE_A: u8 = 0x04;
+pub const USAGE_Z: u8 = 0x1d;
+pub const USAGE_1: u8 = 0x1e;
+pub const USAGE_0: u8 = 0x27;
+pub const USAGE_ENTER: u8 = 0x28;
+pub const USAGE_CAPS_LOCK: u8 = 0x39;
+pub const USAGE_F1: u8 = 0x3a;
+pub const USAGE_F12: u8 = 0x45;
+pub const USAGE_SCROLL_LOCK: u8 = 0x47;
+pub const USAGE_NUM_LOCK: u8 = 0x53;
+pub const USAGE_KEYPAD_1: u8 = 0x59;
+pub const USAGE_KEYPAD_DOT: u8 = 0x63;
+pub const USAGE_F13: u8 = 0x68;
+pub const USAGE_F24: u8 = 0x73;
+pub const USAGE_LOCKING_CAPS_LOCK: u8 = 0x82;
+pub const USAGE_LOCKING_SCROLL_LOCK: u8 = 0x84;
+pub const USAGE_LEFT_CTRL: u8 = 0xe0;
+pub const USAGE_RIGHT_GUI: u8 = 0xe7;
+
+// Bits of the LED output report of boot keyboards ([hid_1_11] B.1)
+pub const LED_NUM_LOCK: u8 = 1 << 0;
+pub const LED_CAPS_LOCK: u8 = 1 << 1;
+pub const LED_SCROLL_LOCK: u8 = 1 << 2;
+
+// A held key starts repeating after KEY_REPEAT_DELAY_MS, every KEY_REPEAT_INTERVAL_MS.
+pub const KEY_REPEAT_DELAY_MS: u64 = 500;
+pub const KEY_REPEAT_INTERVAL_MS: u64 = 33;
+
+/// Layouts of the keys that produce characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keymap {
+    Us,
+    Jis,
+}
+impl Keymap {
+    pub const ALL: [Keymap; 2] = [Keymap::Us, Keymap::Jis];
+    pub fn name(self) -> &'static str {
+        match self {
+            Keymap::Us => "us",
+            Keymap::Jis => "jis",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+    /// Returns the characters of the key without and with Shift,
+    /// or None if the key is not a character key in this layout.
+    /// Letters and the keypad are the same in all the layouts.
+    pub fn chars(self, usage_id: u8) -> Option<(char, Option<char>)> {
+        let (c, shifted) = match (self, usage_id) {
+            (_, USAGE_A..=USAGE_Z) => {
+                let c = (b'a' + usage_id - USAGE_A) as char;
+                (c, c.to_ascii_uppercase())
+            }
+            (_, USAGE_0) => ('0', if self == Keymap::Us { ')' } else { '\0' }),
+            (Keymap::Us, USAGE_1..=0x26) => {
+                let i = (usage_id - USAGE_1) as usize;
+                ((b'1' + i as u8) as char, b"!@#$%^&*("[i] as char)
+            }
+            (Keymap::Jis, USAGE_1..=0x26) => {
+                let i = (usage_id - USAGE_1) as usize;
+                ((b'1' + i as u8) as char, b"!\"#$%&'()"[i] as char)
+            }
+            (_, 0x2c) => (' ', ' '),
+            (Keymap::Us, 0x2d) => ('-', '_'),
+            (Keymap::Us, 0x2e) => ('=', '+'),
+            (Keymap::Us, 0x2f) => ('[', '{'),
+            (Keymap::Us, 0x30) => (']', '}'),
+            // Non-US # and ~ is where the backslash is on US keyboards
+            (Keymap::Us, 0x31 | 0x32) => ('\\', '|'),
+            (Keymap::Us, 0x33) => (';', ':'),
+            (Keymap::Us, 0x34) => ('\'', '"'),
+            (Keymap::Us, 0x35) => ('`', '~'),
+            (Keymap::Us, 0x64) => ('\\', '|'),
+            (Keymap::Jis, 0x2d) => ('-', '='),
+            (Keymap::Jis, 0x2e) => ('^', '~'),
+            (Keymap::Jis, 0x2f) => ('@', '`'),
+            (Keymap::Jis, 0x30) => ('[', '{'),
+            (Keymap::Jis, 0x31 | 0x32) => (']', '}'),
+            (Keymap::Jis, 0x33) => (';', '+'),
+            (Keymap::Jis, 0x34) => (':', '*'),
+            // International1 (Ro) and International3 (Yen)
+            (Keymap::Jis, 0x87) => ('\\', '_'),
+            (Keymap::Jis, 0x89) => ('\\', '|'),
+            (_, 0x36) => (',', '<'),
+            (_, 0x37) => ('.', '>'),
+            (_, 0x38) => ('/', '?'),
+            _ => return keypad_char(usage_id).map(|c| (c, Some(c))),
+        };
+        Some((c, if shifted == '\0' { None } else { Some(shifted) }))
+    }
+}
+
+// Characters of the keypad keys that don't depend on Num Lock
+fn keypad_char(usage_id: u8) -> Option<char> {
+    Some(match usage_id {
+        0x54 => '/',
+        0x55 => '*',
+        0x56 => '-',
+        0x57 => '+',
+        0x67 | 0x86 => '=',
+        0x85 => ',',
+        0xb6 => '(',
+        0xb7 => ')',
+        0xb8 => '{',
+        0xb9 => '}',
+        0xbc..=0xc1 => (b'A' + usage_id - 0xbc) as char,
+        0xc3 => '^',
+        0xc4 => '%',
+        0xc5 => '<',
+        0xc6 => '>',
+        0xc7 => '&',
+        0xc9 => '|',
+        0xcb => ':',
+        0xcc => '#',
+        0xcd => ' ',
+        0xce => '@',
+        0xcf => '!',
+        _ => return None,
+    })
+}
+
+/// Returns the key for the Usage ID that doesn't produce characters.
+/// Usage IDs that are reserved or have no meaning here are mapped to Key::Other.
+pub fn named_key(usage_id: u8, num_lock: bool) -> Key {
+    match usage_id {
+        0x00..=0x03 => Key::None,
+        USAGE_ENTER | 0x58 | 0x9e => Key::Enter,
+        0x29 => Key::Escape,
+        0x2a | 0x99 | 0xbb => Key::Backspace,
+        0x2b | 0xba => Key::Tab,
+        USAGE_CAPS_LOCK | USAGE_LOCKING_CAPS_LOCK => Key::CapsLock,
+        USAGE_F1..=USAGE_F12 => Key::Function(usage_id - USAGE_F1 + 1),
+        USAGE_F13..=USAGE_F24 => Key::Function(usage_id - USAGE_F13 + 13),
+        0x46 | 0x9a => Key::PrintScreen,
+        USAGE_SCROLL_LOCK | USAGE_LOCKING_SCROLL_LOCK => Key::ScrollLock,
+        0x48 => Key::Pause,
+        0x49 => Key::Insert,
+        0x4a => Key::Home,
+        0x4b | 0x9d => Key::PageUp,
+        0x4c => Key::Delete,
+        0x4d => Key::End,
+        0x4e => Key::PageDown,
+        0x4f => Key::Right,
+        0x50 => Key::Left,
+        0x51 => Key::Down,
+        0x52 => Key::Up,
+        USAGE_NUM_LOCK | 0x83 => Key::NumLock,
+        // The keypad works as cursor keys while Num Lock is off
+        USAGE_KEYPAD_1..=USAGE_KEYPAD_DOT if num_lock => {
+            let i = (usage_id - USAGE_KEYPAD_1) as usize;
+            Key::Char(b"1234567890."[i] as char)
+        }
+        0x59 => Key::End,
+        0x5a => Key::Down,
+        0x5b => Key::PageDown,
+        0x5c => Key::Left,
+        0x5d => Key::None,
+        0x5e => Key::Right,
+        0x5f => Key::Home,
+        0x60 => Key::Up,
+        0x61 => Key::PageUp,
+        0x62 => Key::Insert,
+        USAGE_KEYPAD_DOT => Key::Delete,
+        0x65 => Key::Application,
+        0x66 => Key::Power,
+        0x75 => Key::Help,
+        0x76 => Key::Menu,
+        0x78 | 0x9b => Key::Cancel,
+        0x7a => Key::Undo,
+        0x7b => Key::Cut,
+        0x7c => Key::Copy,
+        0x7d => Key::Paste,
+        0x7e => Key::Find,
+        0x7f => Key::Mute,
+        0x80 => Key::VolumeUp,
+        0x81 => Key::VolumeDown,
+        0x88 => Key::KatakanaHiragana,
+        0x8a => Key::Henkan,
+        0x8b => Key::Muhenkan,
+        0x90 => Key::Kana,
+        0x91 => Key::Eisu,
+        USAGE_LEFT_CTRL..=USAGE_RIGHT_GUI => Key::Modifier,
+        _ => Key::Other(usage_id),
+    }
+}
+
+static KEYMAP: Mutex<Keymap> = Mutex::new(Keymap::Us);
+
+/// Returns the keymap used by all the keyboards.
+pub fn keymap() -> Keymap {
+    *KEYMAP.lock()
+}
+pub fn set_keymap(keymap: Keymap) {
+    *KEYMAP.lock() = keymap;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct KeyRepeat {
+    usage_id: u8,
+    next_ms: u64,
+}
+
+/// Tracks the state of a keyboard to generate KeyEvents from its reports.
+#[derive(Debug)]
+pub struct KeyboardState {
+    modifiers: KeyModifiers,
+    pressed: BitSet<32>,
+    caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
+    leds_changed: bool,
+    repeat: Option<KeyRepeat>,
+}
+impl Default for KeyboardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl KeyboardState {
+    pub fn new() -> Self {
+        Self {
+            modifiers: KeyModifiers::default(),
+            pressed: BitSet::new(),
+            caps_lock: false,
+            num_lock: true,
+            scroll_lock: false,
+            // Let the driver set the LEDs to the initial state
+            leds_changed: true,
+            repeat: None,
+        }
+    }
+    pub fn modifiers(&self) -> KeyModifiers {
+        self.modifiers
+    }
+    /// Returns the LED output report, if it has been changed since the last call.
+    pub fn take_led_update(&mut self) -> Option<u8> {
+        if !core::mem::take(&mut self.leds_changed) {
+            return None;
+        }
+        let mut leds = 0;
+        if self.num_lock {
+            leds |= LED_NUM_LOCK;
+        }
+        if self.caps_lock {
+            leds |= LED_CAPS_LOCK;
+        }
+        if self.scroll_lock {
+            leds |= LED_SCROLL_LOCK;
+        }
+        Some(leds)
+    }
+    /// Translates the key with the current modifiers and lock keys.
+    pub fn key_for_usage(&self, usage_id: u8) -> Key {
+        let (c, shifted) = match keymap().chars(usage_id) {
+            Some(chars) => chars,
+            None => return named_key(usage_id, self.num_lock),
+        };
+        let mut shift = self.modifiers.shift();
+        if self.caps_lock && c.is_ascii_lowercase() {
+            shift = !shift;
+        }
+        let c = match shifted {
+            Some(shifted) if shift => shifted,
+            _ => c,
+        };
+        if self.modifiers.ctrl() && matches!(c, '@'..='_' | 'a'..='z') {
+            // Ctrl-A is 0x01, Ctrl-[ is ESC, and so on
+            return Key::Char((c as u8 & 0x1f) as char);
+        }
+        Key::Char(c)
+    }
+    fn key_event(&self, usage_id: u8, pressed: bool, repeated: bool) -> KeyEvent {
+        KeyEvent {
+            usage_id,
+            key: self.key_for_usage(usage_id),
+            modifiers: self.modifiers,
+            pressed,
+            repeated,
+        }
+    }
+    // Lock keys and modifiers don't repeat
+    fn is_repeatable(key: Key) -> bool {
+        !matches!(
+            key,
+            Key::None | Key::Modifier | Key::CapsLock | Key::NumLock | Key::ScrollLock
+        )
+    }
+    fn press(&mut self, usage_id: u8, now_ms: u64) -> KeyEvent {
+        let _ = self.pressed.insert(usage_id as usize);
+        let lock = match named_key(usage_id, self.num_lock) {
+            Key::CapsLock => Some(&mut self.caps_lock),
+            Key::NumLock => Some(&mut self.num_lock),
+            Key::ScrollLock => Some(&mut self.scroll_lock),
+            _ => None,
+        };
+        if let Some(lock) = lock {
+            *lock = !*lock;
+            self.leds_changed = true;
+        }
+        let e = self.key_event(usage_id, true, false);
+        if Self::is_repeatable(e.key) {
+            self.repeat = Some(KeyRepeat {
+                usage_id,
+                next_ms: now_ms + KEY_REPEAT_DELAY_MS,
+            });
+        }
+        e
+    }
+    fn release(&mut self, usage_id: u8) -> KeyEvent {
+        let _ = self.pressed.remove(usage_id as usize);
+        if matches!(self.repeat, Some(r) if r.usage_id == usage_id) {
+            self.repeat = None;
+        }
+        self.key_event(usage_id, false, false)
+    }
+    fn update_modifiers(&mut self, modifiers: u8, events: &mut Vec<KeyEvent>) {
+        let prev = self.modifiers.0;
+        self.modifiers = KeyModifiers(modifiers as u32);
+        for bit in 0..8 {
+            let mask = 1 << bit;
+            if (prev ^ self.modifiers.0) & mask != 0 {
+                let pressed = self.modifiers.0 & mask != 0;
+                events.push(self.key_event(USAGE_LEFT_CTRL + bit, pressed, false));
+            }
+        }
+    }
+    /// Processes an input report of the boot protocol
+    /// (modifiers, reserved, and up to 6 pressed keys), and returns the key events
+    /// for the keys that are pressed or released since the previous report.
+    pub fn process_boot_report(&mut self, report: &[u8], now_ms: u64) -> Vec<KeyEvent> {
+        match report {
+            [modifiers, _, keys @ ..] => self.process_keys(*modifiers, keys, now_ms),
+            _ => Vec::new(),
+        }
+    }
+    /// Takes the bitmap of the modifiers (bit n for Usage ID 0xE0 + n) and the Usage IDs
+    /// of all the keys being pressed (0 is ignored), and returns the key events
+    /// for the keys that are pressed or released since the previous call.
+    pub fn process_keys(&mut self, modifiers: u8, keys: &[u8], now_ms: u64) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        self.update_modifiers(modifiers, &mut events);
+        // Too many keys are pressed to tell which ones. Only the modifiers are valid.
+        if keys.contains(&USAGE_ERROR_ROLL_OVER) {
+            return events;
+        }
+        // Only the Usage IDs of keys (not the error codes) are in self.pressed.
+        let released: Vec<u8> = self
+            .pressed
+            .iter()
+            .map(|k| k as u8)
+            .filter(|k| !keys.contains(k))
+            .collect();
+        for usage_id in released {
+            events.push(self.release(usage_id));
+        }
+        for &usage_id in keys {
+            if usage_id >= USAGE_A && !self.pressed.get(usage_id as usize).unwrap_or(true) {
+                events.push(self.press(usage_id, now_ms));
+            }
+        }
+        events
+    }
+    /// Returns a repeated key press event if it's time to repeat the held key.
+    pub fn poll_repeat(&mut self, now_ms: u64) -> Option<KeyEvent> {
+        let repeat = self.repeat.as_mut()?;
+        if now_ms < repeat.next_ms {
+            return None;
+        }
+        repeat.next_ms = now_ms + KEY_REPEAT_INTERVAL_MS;
+        let usage_id = repeat.usage_id;
+        Some(self.key_event(usage_id, true, true))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report(modifiers: u8, keys: &[u8]) -> [u8; 8] {
+        let mut r = [0u8; 8];
+        r[0] = modifiers;
+        r[2..2 + keys.len()].copy_from_slice(keys);
+        r
+    }
+    fn chars(events: &[KeyEvent]) -> Vec<char> {
+        events
+            .iter()
+            .filter(|e| e.pressed)
+            .filter_map(|e| e.to_char())
+            .collect()
+    }
+
+    #[test_case]
+    fn keys_are_translated_with_modifiers() {
+        let mut kbd = KeyboardState::new();
+        // 'a', then Shift + 'a' and '1'
+        let e = kbd.process_boot_report(&report(0, &[0x04]), 0);
+        assert_eq!(chars(&e), ['a']);
+        let e = kbd.process_boot_report(&report(0, &[]), 0);
+        assert_eq!(e.len(), 1);
+        assert!(!e[0].pressed);
+        let e = kbd.process_boot_report(&report(0x02, &[0x04, 0x1e]), 0);
+        assert_eq!(e[0].key, Key::Modifier);
+        assert_eq!(e[0].usage_id, 0xe1);
+        assert_eq!(chars(&e), ['A', '!']);
+        assert!(kbd.modifiers().shift());
+        // Ctrl + 'c'
+        let e = kbd.process_boot_report(&report(0x10, &[0x06]), 0);
+        assert_eq!(chars(&e), ['\x03']);
+        // Keys without characters
+        let e = kbd.process_boot_report(&report(0, &[0x28, 0x52, 0x3b]), 0);
+        let keys: Vec<Key> = e.iter().filter(|e| e.pressed).map(|e| e.key).collect();
+        assert_eq!(keys, [Key::Enter, Key::Up, Key::Function(2)]);
+        // Roll over errors don't change the pressed keys
+        let e = kbd.process_boot_report(&report(0, &[1, 1, 1, 1, 1, 1]), 0);
+        assert!(e.is_empty());
+    }
+
+    #[test_case]
+    fn caps_lock_toggles_letters_and_leds() {
+        let mut kbd = KeyboardState::new();
+        assert_eq!(kbd.take_led_update(), Some(LED_NUM_LOCK));
+        assert_eq!(kbd.take_led_update(), None);
+        kbd.process_boot_report(&report(0, &[USAGE_CAPS_LOCK]), 0);
+        assert_eq!(kbd.take_led_update(), Some(LED_NUM_LOCK | LED_CAPS_LOCK));
+        let e = kbd.process_boot_report(&report(0, &[0x05, 0x1f]), 0);
+        assert_eq!(chars(&e), ['B', '2']);
+        let e = kbd.process_boot_report(&report(0x20, &[0x06]), 0);
+        assert_eq!(chars(&e), ['c']);
+        // Num Lock switches the keypad to cursor keys
+        kbd.process_boot_report(&report(0, &[USAGE_NUM_LOCK]), 0);
+        assert_eq!(kbd.take_led_update(), Some(LED_CAPS_LOCK));
+        assert_eq!(kbd.key_for_usage(0x60), Key::Up);
+    }
+
+    #[test_case]
+    fn keymaps_are_switchable() {
+        let kbd = KeyboardState::new();
+        let mut kbd_shift = KeyboardState::new();
+        kbd_shift.update_modifiers(0x02, &mut Vec::new());
+        assert_eq!(kbd_shift.key_for_usage(0x1f), Key::Char('@'));
+        assert_eq!(kbd.key_for_usage(0x2f), Key::Char('['));
+        set_keymap(Keymap::Jis);
+        assert_eq!(kbd_shift.key_for_usage(0x1f), Key::Char('"'));
+        assert_eq!(kbd.key_for_usage(0x2f), Key::Char('@'));
+        assert_eq!(kbd_shift.key_for_usage(0x34), Key::Char('*'));
+        assert_eq!(kbd.key_for_usage(0x87), Key::Char('\\'));
+        // Shift + 0 doesn't produce a character on JIS keyboards
+        assert_eq!(kbd_shift.key_for_usage(USAGE_0), Key::Char('0'));
+        set_keymap(Keymap::Us);
+        assert_eq!(Keymap::from_name("jis"), Some(Keymap::Jis));
+        assert_eq!(Keymap::from_name("dvorak"), None);
+    }
+
+    #[test_case]
+    fn held_keys_are_repeated() {
+        let mut kbd = KeyboardState::new();
+        kbd.process_boot_report(&report(0, &[0x04]), 1000);
+        assert!(kbd.poll_repeat(1000 + KEY_REPEAT_DELAY_MS - 1).is_none());
+        let e = kbd.poll_repeat(1000 + KEY_REPEAT_DELAY_MS).unwrap();
+        assert!(e.repeated);
+        assert_eq!(e.to_char(), Some('a'));
+        assert!(kbd.poll_repeat(1000 + KEY_REPEAT_DELAY_MS + 1).is_none());
+        assert!(kbd
+            .poll_repeat(1000 + KEY_REPEAT_DELAY_MS + KEY_REPEAT_INTERVAL_MS)
+            .is_some());
+        // Modifiers don't stop repeating, but releasing the key does
+        kbd.process_boot_report(&report(0x02, &[0x04]), 2000);
+        assert_eq!(kbd.poll_repeat(2000).unwrap().to_char(), Some('A'));
+        kbd.process_boot_report(&report(0x02, &[]), 2000);
+        assert!(kbd.poll_repeat(3000).is_none());
+    }
+}
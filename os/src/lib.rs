@@ -30,8 +30,11 @@ mod acpi;
 pub mod allocator;
 mod ax88179;
 pub mod bitset;
+pub mod block;
 pub mod boot_info;
 pub mod cmd;
+pub mod compositor;
+pub mod cursor;
 pub mod debug;
 pub mod efi;
 pub mod elf;
@@ -40,6 +43,7 @@ pub mod executor;
 pub mod hpet;
 pub mod init;
 pub mod input;
+pub mod keyboard;
 pub mod loader;
 mod memory;
 mod memory_map_holder;
@@ -50,16 +54,19 @@ mod panic;
 pub mod pci;
 pub mod print;
 pub mod process;
+pub mod ps2;
 mod rtl8139;
 pub mod serial;
 mod syscall;
 pub mod test_runner;
+pub mod tty;
 mod usb;
-mod usb_hid_keyboard;
-mod usb_hid_tablet;
+mod usb_hid;
+mod usb_hub;
+mod usb_mass_storage;
 mod util;
 mod volatile;
-mod vram;
+pub mod vram;
 pub mod x86_64;
 pub mod xhci;
 
@@ -1,6 +1,8 @@
 extern crate alloc;
 
+use crate::boot_info::BootInfo;
 use crate::boot_info::File;
+use crate::efi::fs::EfiFileName;
 use crate::elf;
 use crate::elf::SectionHeader;
 use crate::elf::SegmentHeader;
@@ -10,14 +12,15 @@ use crate::memory::AddressRange;
 use crate::memory::ContiguousPhysicalMemoryPages;
 use crate::process::ProcessCompletionFuture;
 use crate::process::ProcessContext;
+use crate::process::ProcessId;
 use crate::process::Scheduler;
 use crate::util::read_le_u16;
 use crate::util::read_le_u32;
 use crate::util::read_le_u64;
 use crate::util::write_le_u64;
 use crate::x86_64::context::exec_app_context_proc_func;
-use crate::x86_64::context::CONTEXT_APP;
 use crate::x86_64::paging::PageAttr;
+use crate::x86_64::RFLAGS_IF;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::format;
@@ -28,6 +31,24 @@ use core::cmp::max;
 use core::cmp::min;
 use core::fmt;
 use core::mem::size_of;
+use core::str::FromStr;
+
+/// Loads the app `name` in the root directory and starts it as a new process.
+pub fn spawn_app_in_root_dir(
+    name: &str,
+    args: &[&str],
+) -> Result<(ProcessId, ProcessCompletionFuture<'static>)> {
+    let name = EfiFileName::from_str(name)?;
+    let file = BootInfo::take()
+        .root_files()
+        .iter()
+        .filter_map(|e| e.as_ref())
+        .find(|e| e.name() == &name)
+        .ok_or(Error::Failed("No such file or app"))?;
+    let elf = Elf::parse(file)?;
+    let app = elf.load()?;
+    app.spawn(args)
+}
 
 pub struct LoadedElf<'a> {
     elf: &'a Elf<'a>,
@@ -44,20 +65,25 @@ impl<'a> LoadedElf<'a> {
         }
         Err(Error::Failed("vaddr not found"))
     }
-    pub async fn exec(self, args: &[&str]) -> Result<i64> {
+    /// Starts the app as a new process.
+    /// The returned future can be used to wait for the process to exit.
+    pub fn spawn(self, args: &[&str]) -> Result<(ProcessId, ProcessCompletionFuture<'static>)> {
         let stack_size = 1024 * 1024;
         let mut stack = ContiguousPhysicalMemoryPages::alloc_bytes(stack_size)?;
         let stack_range = stack.range();
         stack.fill_with_bytes(0);
         stack.set_page_attr(PageAttr::ReadWriteUser)?;
         let entry_point = self.resolve_vaddr(self.elf.entry_vaddr as usize)?;
+        let mut app_proc = Box::new(ProcessContext::new(Some(stack), Some(args))?);
         {
-            let mut app_ctx = CONTEXT_APP.lock();
+            let mut app_ctx = app_proc.context().lock();
             app_ctx.cpu.rip = entry_point as u64;
-            app_ctx.cpu.rflags = 2;
+            // Interrupts are enabled so that the timer can terminate the app on Ctrl-C
+            app_ctx.cpu.rflags = 2 | RFLAGS_IF;
             app_ctx.cpu.rsp = stack_range.end() as u64; // stack grows toward 0, so empty stack pointer will be the end addr
         }
-        let app_proc = Box::new(ProcessContext::new(Some(stack), Some(args))?);
+        let pid = app_proc.pid();
+        app_proc.set_app_region(self.region);
         let proc = ProcessContext::new_with_fn(
             exec_app_context_proc_func,
             Box::into_raw(app_proc) as u64,
@@ -65,8 +91,14 @@ impl<'a> LoadedElf<'a> {
         let scheduler = Scheduler::root();
         let wait = ProcessCompletionFuture::new(&proc, scheduler);
         scheduler.schedule(proc);
-        let exit_code = wait.await?;
-        Ok(exit_code)
+        Ok((pid, wait))
+    }
+    /// Runs the app and returns its exit code.
+    /// If the app is killed by a CPU exception (e.g. a page fault),
+    /// the exit code will be the one from [crate::process::exit_code_for_exception].
+    pub async fn exec(self, args: &[&str]) -> Result<i64> {
+        let (_, wait) = self.spawn(args)?;
+        wait.await
     }
     pub fn slice_of_vaddr_range(&self, range_on_vaddr: AddressRange) -> Result<&[u8]> {
         let range = range_on_vaddr.to_range_in(&self.app_vaddr_range)?;
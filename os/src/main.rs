@@ -15,9 +15,13 @@ use core::pin::Pin;
 use core::str::FromStr;
 use noli::bitmap::bitmap_draw_line;
 use noli::bitmap::Bitmap;
+use noli::rect::Rect;
 use os::boot_info::BootInfo;
 use os::boot_info::File;
 use os::cmd;
+use os::compositor::compositor_tick_task;
+use os::compositor::window_manager_task;
+use os::compositor::Compositor;
 use os::debug;
 use os::efi::fs::EfiFileName;
 use os::efi::types::EfiHandle;
@@ -35,17 +39,25 @@ use os::net::manager::Network;
 use os::net::tcp::TcpSocket;
 use os::print;
 use os::println;
+use os::ps2::ps2_task;
 use os::serial::SerialPort;
+use os::tty;
+use os::tty::LineDiscipline;
+use os::tty::LineEvent;
+use os::tty::SerialInput;
+use os::vram::BackBuffer;
 use os::x86_64;
 use os::x86_64::read_rsp;
 use os::x86_64::syscall::init_syscall;
+use sabi::KeyboardEvent;
 
 fn paint_wasabi_logo() {
     const SIZE: i64 = 256;
     const COL_SABI: u32 = 0xe33b26;
     const COL_WASABI: u32 = 0x7ec288;
 
-    let mut vram = BootInfo::take().vram();
+    let back_buffer = BackBuffer::take();
+    let mut vram = back_buffer.bitmap();
     let dx = vram.width() / 2 - SIZE;
     let dy = vram.height() / 2 - SIZE;
 
@@ -76,6 +88,9 @@ fn paint_wasabi_logo() {
         )
         .unwrap();
     }
+    if let Some(rect) = Rect::new(dx, dy, SIZE * 2 + 1, SIZE + 1) {
+        back_buffer.damage(rect);
+    }
 }
 
 async fn draw_progress_bar(
@@ -85,13 +100,17 @@ async fn draw_progress_bar(
     height: i64,
     interval_ms: u64,
 ) -> Result<()> {
-    let mut vram = BootInfo::take().vram();
+    let back_buffer = BackBuffer::take();
+    let mut vram = back_buffer.bitmap();
     let colors = [0xFF0000, 0x00FF00, 0x0000FF];
     let y = top;
     let mut x = left;
     let mut c = 0;
     loop {
         let _ = bitmap_draw_line(&mut vram, colors[c % 3], x, y, x, y + height);
+        if let Some(rect) = Rect::new(x, y, 1, height + 1) {
+            back_buffer.damage(rect);
+        }
         x += 1;
         if x >= left + width {
             x = left;
@@ -120,11 +139,20 @@ fn run_tasks() -> Result<()> {
     );
     let serial_task = async {
         let sp = SerialPort::default();
+        let mut serial_input = SerialInput::new();
         loop {
-            if let Some(c) = sp.try_read() {
-                if let Some(c) = char::from_u32(c as u32) {
-                    let c = if c == '\r' { '\n' } else { c };
-                    InputManager::take().push_input(c);
+            while let Some(c) = sp.try_read() {
+                if let Some(c) = serial_input.translate(c) {
+                    let input = InputManager::take();
+                    input.push_input(c);
+                    // The serial console does not tell key releases,
+                    // so each char is delivered as a key press and release.
+                    let e = KeyboardEvent {
+                        ch: c as u32,
+                        ..Default::default()
+                    };
+                    input.push_key_event(e, true);
+                    input.push_key_event(e, false);
                 }
             }
             TimeoutFuture::new_ms(20).await;
@@ -153,32 +181,32 @@ fn run_tasks() -> Result<()> {
     let console_task = async {
         // Note: this message is used by e2e_test. Please do not remove.
         info!("console_task has started");
-        let mut s = String::new();
+        let mut line_discipline = LineDiscipline::new();
         print!("> ");
         loop {
-            if let Some(c) = InputManager::take().pop_input() {
-                if c == '\r' || c == '\n' {
-                    println!();
-                    if let Err(e) = cmd::run(&s).await {
-                        error!("{e:?}");
-                    };
-                    s.clear();
-                    print!("> ");
-                }
-                match c {
-                    '\x7f' | '\x08' => {
-                        if !s.is_empty() {
-                            print!("{0} {0}", 0x08 as char);
-                            s.pop();
-                        }
-                    }
-                    '\n' => {
-                        // Do nothing
+            // The keyboard input is for the app if its window has the focus.
+            let c = if Compositor::take().focused_owner().is_none() {
+                InputManager::take().pop_input()
+            } else {
+                None
+            };
+            if let Some(c) = c {
+                let mut echo = String::new();
+                let event = line_discipline.input(c, &mut echo);
+                print!("{echo}");
+                match event {
+                    Some(LineEvent::Line(s)) => {
+                        if let Err(e) = cmd::run(&s).await {
+                            error!("{e:?}");
+                        };
+                        print!("> ");
                     }
-                    _ => {
-                        print!("{c}");
-                        s.push(c);
+                    Some(LineEvent::Interrupt) => {
+                        // No app is running, so Ctrl-C only discards the line
+                        tty::take_interrupt_request();
+                        print!("> ");
                     }
+                    None => {}
                 }
             }
             TimeoutFuture::new_ms(20).await;
@@ -210,6 +238,9 @@ fn run_tasks() -> Result<()> {
     spawn_global(console_task);
     spawn_global(init_task);
     spawn_global(tcp_echo_task);
+    spawn_global(window_manager_task());
+    spawn_global(compositor_tick_task());
+    spawn_global(ps2_task());
     init::init_pci();
     // Start executing tasks
     run_global_poll_loop();
@@ -224,6 +255,7 @@ fn main() -> Result<()> {
     core::mem::forget(interrupt_config);
     init::init_paging()?;
     init::init_timer();
+    init::init_smp();
     os::process::init();
     init_syscall();
 
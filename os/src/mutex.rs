@@ -10,9 +10,16 @@
 //! to ensure that the access to the contents
 //! is unique so taking a mutable reference
 //! to it will be safe.
+//!
+//! The lock can be taken from multiple processors.
+//! A processor that fails to take the lock keeps spinning
+//! while the lock is held by another processor, but
+//! it panics if the lock is held by the processor itself
+//! for a long time since it will never be released (deadlock).
 
 use crate::error::Error;
 use crate::error::Result;
+use crate::x86_64::apic::LocalApic;
 use core::cell::SyncUnsafeCell;
 use core::fmt::Debug;
 use core::ops::Deref;
@@ -65,6 +72,7 @@ pub struct Mutex<T> {
     data: SyncUnsafeCell<T>,
     is_taken: AtomicBool,
     taker_line_num: AtomicU32,
+    taker_apic_id: AtomicU32,
     created_at_file: &'static str,
     created_at_line: u32,
 }
@@ -84,6 +92,7 @@ impl<T: Sized> Mutex<T> {
             data: SyncUnsafeCell::new(data),
             is_taken: AtomicBool::new(false),
             taker_line_num: AtomicU32::new(0),
+            taker_apic_id: AtomicU32::new(0),
             created_at_file: Location::caller().file(),
             created_at_line: Location::caller().line(),
         }
@@ -97,6 +106,8 @@ impl<T: Sized> Mutex<T> {
         {
             self.taker_line_num
                 .store(Location::caller().line(), Ordering::SeqCst);
+            self.taker_apic_id
+                .store(LocalApic::current_id(), Ordering::SeqCst);
             Ok(unsafe { MutexGuard::new(self, &self.data) })
         } else {
             Err(Error::LockFailed)
@@ -104,10 +115,17 @@ impl<T: Sized> Mutex<T> {
     }
     #[track_caller]
     pub fn lock(&self) -> MutexGuard<T> {
-        for _ in 0..10000 {
-            if let Ok(locked) = self.try_lock() {
-                return locked;
+        loop {
+            for _ in 0..10000 {
+                if let Ok(locked) = self.try_lock() {
+                    return locked;
+                }
+                core::hint::spin_loop();
+            }
+            if self.taker_apic_id.load(Ordering::SeqCst) == LocalApic::current_id() {
+                break;
             }
+            // The lock is held by another processor. It will be released soon.
         }
         panic!(
             "Failed to lock Mutex at {}:{}, caller: {:?}, taker_line_num: {}",
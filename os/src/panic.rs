@@ -1,5 +1,6 @@
 use crate::println;
 use crate::serial::SerialPort;
+use crate::vram::BackBuffer;
 use crate::x86_64::dump_stack;
 use core::fmt::Write;
 use core::panic::PanicInfo;
@@ -14,6 +15,10 @@ fn panic(info: &PanicInfo) -> ! {
     dump_stack();
     writeln!(serial_writer, "[PANIC] {info:?}").unwrap();
     println!("[PANIC] {info:?}");
+    // The compositor will not flush the screen anymore
+    if let Some(back_buffer) = BackBuffer::try_take() {
+        back_buffer.flush_desktop();
+    }
     #[cfg(not(test))]
     crate::x86_64::rest_in_peace();
     #[cfg(test)]
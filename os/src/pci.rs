@@ -1,17 +1,23 @@
 extern crate alloc;
 
 use crate::acpi::Mcfg;
+use crate::boot_info::BootInfo;
 use crate::error;
 use crate::error::Error;
 use crate::error::Result;
 use crate::info;
 use crate::rtl8139::Rtl8139Driver;
+use crate::x86_64::apic::IoApic;
+use crate::x86_64::interrupt::InterruptHandler;
+use crate::x86_64::interrupt::InterruptVector;
 use crate::x86_64::paging::with_current_page_table;
 use crate::x86_64::paging::PageAttr;
 use crate::xhci::driver::XhciDriverForPci;
 use alloc::boxed::Box;
 use alloc::collections::btree_map::BTreeMap;
 use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefCell;
@@ -22,6 +28,8 @@ use core::ops::Range;
 use core::ptr::read_volatile;
 use core::ptr::write_volatile;
 
+const MSI_ADDRESS_BASE: u32 = 0xFEE0_0000;
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct VendorDeviceId {
     pub vendor: u16,
@@ -122,6 +130,16 @@ pub struct CapabilityHeader {
     pub next: u8,
 }
 
+pub const CAPABILITY_ID_MSI: u8 = 0x05;
+pub const CAPABILITY_ID_MSIX: u8 = 0x11;
+
+/// A capability in the configuration space, located at `offset`.
+#[derive(Debug, Copy, Clone)]
+pub struct CapabilityPointer {
+    pub offset: usize,
+    pub id: u8,
+}
+
 pub struct CapabilityIterator<'a> {
     pci: &'a Pci,
     bdf: BusDeviceFunction,
@@ -133,14 +151,21 @@ impl<'a> CapabilityIterator<'a> {
     }
 }
 impl<'a> Iterator for CapabilityIterator<'a> {
-    type Item = &'a CapabilityHeader;
+    type Item = CapabilityPointer;
     fn next(&mut self) -> Option<Self::Item> {
         if self.ptr == 0 {
             None
         } else {
-            let item = unsafe { &*self.pci.ecm_base::<CapabilityHeader>(self.bdf) };
+            let offset = self.ptr as usize;
+            // The lowest 2 bits are reserved
+            let item = unsafe {
+                &*(self.pci.ecm_base::<u8>(self.bdf).add(offset & !0b11) as *const CapabilityHeader)
+            };
             self.ptr = item.next;
-            Some(item)
+            Some(CapabilityPointer {
+                offset: offset & !0b11,
+                id: item.id,
+            })
         }
     }
 }
@@ -186,6 +211,128 @@ impl<T> ConfigRegisters<T> {
     }
 }
 
+/// Class Code and Revision ID registers (offset 0x08) of the configuration space
+#[derive(Debug, Copy, Clone)]
+pub struct ClassCode {
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+}
+impl ClassCode {
+    pub fn from_register(value: u32) -> Self {
+        Self {
+            class: (value >> 24) as u8,
+            subclass: (value >> 16) as u8,
+            prog_if: (value >> 8) as u8,
+            revision: value as u8,
+        }
+    }
+    /// Human readable name of the class, for the classes commonly seen in VMs
+    pub fn name(&self) -> &'static str {
+        match (self.class, self.subclass, self.prog_if) {
+            (0x01, 0x01, _) => "IDE controller",
+            (0x01, 0x06, _) => "SATA controller",
+            (0x01, 0x08, _) => "NVMe controller",
+            (0x01, _, _) => "Mass storage controller",
+            (0x02, 0x00, _) => "Ethernet controller",
+            (0x02, _, _) => "Network controller",
+            (0x03, _, _) => "Display controller",
+            (0x04, _, _) => "Multimedia controller",
+            (0x05, _, _) => "Memory controller",
+            (0x06, 0x00, _) => "Host bridge",
+            (0x06, 0x01, _) => "ISA bridge",
+            (0x06, 0x04, _) => "PCI bridge",
+            (0x06, _, _) => "Bridge",
+            (0x07, _, _) => "Communication controller",
+            (0x08, _, _) => "System peripheral",
+            (0x0C, 0x03, 0x00) => "USB UHCI controller",
+            (0x0C, 0x03, 0x10) => "USB OHCI controller",
+            (0x0C, 0x03, 0x20) => "USB EHCI controller",
+            (0x0C, 0x03, 0x30) => "USB xHCI controller",
+            (0x0C, 0x03, _) => "USB controller",
+            (0x0C, 0x05, _) => "SMBus controller",
+            (0x0C, _, _) => "Serial bus controller",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Base Address Register, decoded without probing the size of the region
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bar {
+    Io(u32),
+    Mem32 { addr: u32, prefetchable: bool },
+    Mem64 { addr: u64, prefetchable: bool },
+}
+impl Bar {
+    /// Decodes a BAR from its value and the value of the next BAR (used for 64-bit BARs).
+    /// Returns None if it is not implemented or invalid.
+    pub fn decode(lo: u32, hi: u32) -> Option<Self> {
+        if lo & 1 == 1 {
+            let addr = lo & !0b11;
+            return (addr != 0).then_some(Bar::Io(addr));
+        }
+        let prefetchable = lo & 0b1000 != 0;
+        match lo & 0b0110 {
+            0b0000 => {
+                let addr = lo & !0b1111;
+                (addr != 0).then_some(Bar::Mem32 { addr, prefetchable })
+            }
+            0b0100 => {
+                let addr = ((hi as u64) << 32) | (lo & !0b1111) as u64;
+                (addr != 0).then_some(Bar::Mem64 { addr, prefetchable })
+            }
+            _ => None,
+        }
+    }
+    /// Number of BAR registers that this BAR occupies
+    pub fn num_registers(&self) -> usize {
+        match self {
+            Bar::Mem64 { .. } => 2,
+            _ => 1,
+        }
+    }
+}
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bar::Io(addr) => write!(f, "I/O ports at {addr:#06X}"),
+            Bar::Mem32 { addr, prefetchable } => write!(
+                f,
+                "Memory at {addr:#010X} (32-bit, {})",
+                if *prefetchable {
+                    "prefetchable"
+                } else {
+                    "non-prefetchable"
+                }
+            ),
+            Bar::Mem64 { addr, prefetchable } => write!(
+                f,
+                "Memory at {addr:#018X} (64-bit, {})",
+                if *prefetchable {
+                    "prefetchable"
+                } else {
+                    "non-prefetchable"
+                }
+            ),
+        }
+    }
+}
+
+pub fn capability_name(id: u8) -> &'static str {
+    match id {
+        0x01 => "Power Management",
+        CAPABILITY_ID_MSI => "MSI",
+        0x09 => "Vendor Specific",
+        0x0D => "Bridge Subsystem Vendor ID",
+        0x10 => "PCI Express",
+        CAPABILITY_ID_MSIX => "MSI-X",
+        0x12 => "SATA",
+        _ => "Unknown",
+    }
+}
+
 pub struct BarMem64 {
     addr: *mut u8,
     size: u64,
@@ -209,10 +356,80 @@ impl BarMem64 {
     }
 }
 
+/// An entry of the MSI-X Table
+/// c.f. PCI Express Base Specification 7.7.2 MSI-X Capability and Table Structure
+#[repr(C)]
+struct MsixTableEntry {
+    message_address: u32,
+    message_upper_address: u32,
+    message_data: u32,
+    vector_control: u32,
+}
+const _: () = assert!(size_of::<MsixTableEntry>() == 16);
+const MSIX_VECTOR_CONTROL_MASK: u32 = 1;
+
+/// The MSI-X Table of a device, mapped from one of its BARs.
+pub struct MsixTable {
+    entries: *mut MsixTableEntry,
+    num_entries: usize,
+}
+impl MsixTable {
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+    fn entry(&self, index: usize) -> Result<*mut MsixTableEntry> {
+        if index < self.num_entries {
+            Ok(unsafe { self.entries.add(index) })
+        } else {
+            Err(Error::Failed("MSI-X table index out of range"))
+        }
+    }
+    pub fn set_masked(&self, index: usize, masked: bool) -> Result<()> {
+        let e = self.entry(index)?;
+        unsafe {
+            let ctrl = read_volatile(&(*e).vector_control);
+            let ctrl = if masked {
+                ctrl | MSIX_VECTOR_CONTROL_MASK
+            } else {
+                ctrl & !MSIX_VECTOR_CONTROL_MASK
+            };
+            write_volatile(&mut (*e).vector_control, ctrl);
+        }
+        Ok(())
+    }
+    /// Programs the message of the entry and unmasks it.
+    pub fn set_message(&self, index: usize, address: u64, data: u32) -> Result<()> {
+        self.set_masked(index, true)?;
+        let e = self.entry(index)?;
+        unsafe {
+            write_volatile(&mut (*e).message_address, address as u32);
+            write_volatile(&mut (*e).message_upper_address, (address >> 32) as u32);
+            write_volatile(&mut (*e).message_data, data);
+        }
+        self.set_masked(index, false)
+    }
+}
+
+/// Returns the message address and data to deliver the vector to the BSP
+/// (fixed delivery mode, edge-triggered).
+fn msi_message_for_vector(vector: &InterruptVector) -> (u32, u32) {
+    let bsp_apic_id = BootInfo::take().bsp_local_apic().id();
+    (
+        MSI_ADDRESS_BASE | (bsp_apic_id << 12),
+        vector.vector() as u32,
+    )
+}
+
+// The driver and its instance for an attached device
+type AttachedDevice = (
+    Rc<Box<dyn PciDeviceDriver>>,
+    Rc<Box<dyn PciDeviceDriverInstance>>,
+);
+
 pub struct Pci {
     ecm_range: Range<usize>,
     drivers: Vec<Rc<Box<dyn PciDeviceDriver>>>,
-    devices: RefCell<BTreeMap<BusDeviceFunction, Rc<Box<dyn PciDeviceDriverInstance>>>>,
+    devices: RefCell<BTreeMap<BusDeviceFunction, AttachedDevice>>,
 }
 impl Pci {
     pub fn new(mcfg: &Mcfg) -> Self {
@@ -257,6 +474,49 @@ impl Pci {
             Err(Error::PciBarInvalid)
         }
     }
+    /// Returns the memory region of the BAR at `index`, which can be either 32-bit or 64-bit.
+    pub fn try_bar_mem(&self, bdf: BusDeviceFunction, index: usize) -> Result<BarMem64> {
+        if index >= 6 {
+            return Err(Error::PciBarInvalid);
+        }
+        let offset = 0x10 + index * 4;
+        let bar = self.read_register_u32(bdf, offset)?;
+        match bar & 0b0111 {
+            0b0100 /* Memory, 64bit */ => {
+                if index >= 5 {
+                    return Err(Error::PciBarInvalid);
+                }
+                let bar = self.read_register_u64(bdf, offset)?;
+                self.write_register_u64(bdf, offset, !0u64)?;
+                let mask = self.read_register_u64(bdf, offset)? & !0b1111;
+                self.write_register_u64(bdf, offset, bar)?;
+                if mask == 0 {
+                    // The BAR is not implemented
+                    return Err(Error::PciBarInvalid);
+                }
+                let size = 1 + !mask;
+                Ok(BarMem64 {
+                    addr: (bar & !0b1111) as *mut u8,
+                    size,
+                })
+            }
+            0b0000 /* Memory, 32bit */ => {
+                self.write_register_u32(bdf, offset, !0u32)?;
+                let mask = self.read_register_u32(bdf, offset)? & !0b1111;
+                self.write_register_u32(bdf, offset, bar)?;
+                if mask == 0 {
+                    // The BAR is not implemented
+                    return Err(Error::PciBarInvalid);
+                }
+                let size = 1 + !mask;
+                Ok(BarMem64 {
+                    addr: (bar & !0b1111) as usize as *mut u8,
+                    size: size as u64,
+                })
+            }
+            _ => Err(Error::PciBarInvalid),
+        }
+    }
     pub fn ecm_base<T>(&self, id: BusDeviceFunction) -> *mut T {
         (self.ecm_range.start + ((id.id as usize) << 12)) as *mut T
     }
@@ -274,6 +534,14 @@ impl Pci {
         let hi = self.read_register_u32(bdf, byte_offset + 4)?;
         Ok(((hi as u64) << 32) | (lo as u64))
     }
+    pub fn write_register_u16(
+        &self,
+        bdf: BusDeviceFunction,
+        byte_offset: usize,
+        data: u16,
+    ) -> Result<()> {
+        ConfigRegisters::write(self.ecm_base(bdf), byte_offset, data)
+    }
     pub fn write_register_u32(
         &self,
         bdf: BusDeviceFunction,
@@ -308,6 +576,166 @@ impl Pci {
     pub fn disable_interrupt(&self, bdf: BusDeviceFunction) -> Result<()> {
         self.set_command_and_status_flags(bdf, 1 << 10 /* Interrupt Disable */)
     }
+    pub fn enable_interrupt(&self, bdf: BusDeviceFunction) -> Result<()> {
+        let cmd = self.read_register_u16(bdf, 0x04 /* Command */)?;
+        self.write_register_u16(bdf, 0x04 /* Command */, cmd & !(1 << 10))
+    }
+    pub fn find_capability(&self, bdf: BusDeviceFunction, id: u8) -> Option<CapabilityPointer> {
+        self.capabilities(bdf)?.find(|e| e.id == id)
+    }
+    /// Returns true if the host bridge is the one of QEMU's q35 machine
+    /// (Intel 82G33 MCH with the subsystem vendor ID of QEMU).
+    fn is_qemu_q35(&self) -> bool {
+        let Ok(host_bridge) = BusDeviceFunction::new(0, 0, 0) else {
+            return false;
+        };
+        let is_q35_mch = self
+            .read_vendor_id_and_device_id(host_bridge)
+            .is_some_and(|vd| vd.vendor == 0x8086 && vd.device == 0x29c0);
+        let subsystem_vendor =
+            self.read_register_u16(host_bridge, 0x2C /* Subsystem Vendor ID */);
+        is_q35_mch && subsystem_vendor.is_ok_and(|v| v == 0x1af4)
+    }
+    /// Returns the GSI that the INTx line of the device is connected to.
+    /// Since the AML (_PRT) is not parsed, this is only supported on QEMU's q35 machine:
+    /// INT[A-D] of a device on bus 0 is connected to PIRQ[E-H] (GSI 20-23),
+    /// rotated by its device number (see ich9_cc_init() in QEMU).
+    /// On other platforms, the drivers should use MSI(-X) or poll the device.
+    pub fn legacy_interrupt_gsi(&self, bdf: BusDeviceFunction) -> Result<u32> {
+        if !self.is_qemu_q35() {
+            return Err(Error::Failed(
+                "INTx routing is unknown on this platform since _PRT is not parsed",
+            ));
+        }
+        let pin = self.read_register_u8(bdf, 0x3D /* Interrupt Pin */)?;
+        if !(1..=4).contains(&pin) {
+            return Err(Error::Failed("The device does not use INTx"));
+        }
+        if bdf.bus() != 0 {
+            return Err(Error::Failed(
+                "INTx routing for devices behind bridges is not supported",
+            ));
+        }
+        let intx = (pin - 1) as usize;
+        Ok(16 + 4 + ((bdf.device() + intx) % 4) as u32)
+    }
+    /// Enables the legacy INTx interrupt of the device, routed via the I/O APIC.
+    /// INTx is level-triggered, so the handler should deassert the interrupt
+    /// on the device side (e.g. by clearing its interrupt status register).
+    pub fn request_legacy_interrupt(
+        &self,
+        bdf: BusDeviceFunction,
+        handler: Option<(InterruptHandler, u64)>,
+    ) -> Result<InterruptVector> {
+        let gsi = self.legacy_interrupt_gsi(bdf)?;
+        let vector = InterruptVector::alloc(handler)?;
+        let bsp_apic_id = BootInfo::take().bsp_local_apic().id();
+        IoApic::route_pci_gsi(gsi, vector.vector(), bsp_apic_id)?;
+        self.enable_interrupt(bdf)?;
+        info!(
+            "{bdf}: INTx is routed to GSI {gsi}, vector {}",
+            vector.vector()
+        );
+        Ok(vector)
+    }
+    /// Enables the Message Signaled Interrupt (MSI) of the device with a single vector.
+    pub fn request_msi(
+        &self,
+        bdf: BusDeviceFunction,
+        handler: Option<(InterruptHandler, u64)>,
+    ) -> Result<InterruptVector> {
+        let cap = self
+            .find_capability(bdf, CAPABILITY_ID_MSI)
+            .ok_or(Error::Failed("MSI capability not found"))?;
+        let vector = InterruptVector::alloc(handler)?;
+        let (addr, data) = msi_message_for_vector(&vector);
+        let ctrl = self.read_register_u16(bdf, cap.offset + 2)?;
+        let is_64bit = ctrl & (1 << 7) != 0;
+        self.write_register_u32(bdf, cap.offset + 4, addr)?;
+        let data_offset = if is_64bit {
+            self.write_register_u32(bdf, cap.offset + 8, 0)?;
+            cap.offset + 0x0C
+        } else {
+            cap.offset + 0x08
+        };
+        self.write_register_u16(bdf, data_offset, data as u16)?;
+        // Multiple Message Enable = 0 (1 vector), MSI Enable = 1
+        self.write_register_u16(bdf, cap.offset + 2, (ctrl & !(0b111 << 4)) | 1)?;
+        self.disable_interrupt(bdf)?;
+        info!("{bdf}: MSI is enabled with vector {}", vector.vector());
+        Ok(vector)
+    }
+    /// Locates the MSI-X Table of the device and maps it as uncacheable.
+    pub fn msix_table(&self, bdf: BusDeviceFunction) -> Result<MsixTable> {
+        let cap = self
+            .find_capability(bdf, CAPABILITY_ID_MSIX)
+            .ok_or(Error::Failed("MSI-X capability not found"))?;
+        let ctrl = self.read_register_u16(bdf, cap.offset + 2)?;
+        let table = self.read_register_u32(bdf, cap.offset + 4)?;
+        let bir = (table & 0b111) as usize;
+        let table_offset = (table & !0b111) as usize;
+        let num_entries = (ctrl & 0x7FF) as usize + 1;
+        let bar = self.try_bar_mem(bdf, bir)?;
+        if (table_offset + num_entries * size_of::<MsixTableEntry>()) as u64 > bar.size() {
+            return Err(Error::Failed("MSI-X table is out of the BAR"));
+        }
+        bar.disable_cache();
+        Ok(MsixTable {
+            entries: unsafe { bar.addr().add(table_offset) } as *mut MsixTableEntry,
+            num_entries,
+        })
+    }
+    /// Enables MSI-X of the device, delivering the entry 0 to a new vector.
+    /// All the other entries are masked.
+    pub fn request_msix(
+        &self,
+        bdf: BusDeviceFunction,
+        handler: Option<(InterruptHandler, u64)>,
+    ) -> Result<InterruptVector> {
+        let cap = self
+            .find_capability(bdf, CAPABILITY_ID_MSIX)
+            .ok_or(Error::Failed("MSI-X capability not found"))?;
+        let table = self.msix_table(bdf)?;
+        let vector = InterruptVector::alloc(handler)?;
+        let (addr, data) = msi_message_for_vector(&vector);
+        const MSIX_ENABLE: u16 = 1 << 15;
+        const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+        let ctrl = self.read_register_u16(bdf, cap.offset + 2)?;
+        // Mask the whole function while the table is being programmed
+        self.write_register_u16(bdf, cap.offset + 2, ctrl | MSIX_ENABLE | MSIX_FUNCTION_MASK)?;
+        for i in 1..table.num_entries() {
+            table.set_masked(i, true)?;
+        }
+        table.set_message(0, addr as u64, data)?;
+        self.write_register_u16(
+            bdf,
+            cap.offset + 2,
+            (ctrl | MSIX_ENABLE) & !MSIX_FUNCTION_MASK,
+        )?;
+        self.disable_interrupt(bdf)?;
+        info!("{bdf}: MSI-X is enabled with vector {}", vector.vector());
+        Ok(vector)
+    }
+    /// Enables a message signaled interrupt of the device, preferring MSI-X over MSI.
+    /// Since message signaled interrupts are edge-triggered,
+    /// the handler is not required to acknowledge the device.
+    pub fn request_message_signaled_interrupt(
+        &self,
+        bdf: BusDeviceFunction,
+        handler: Option<(InterruptHandler, u64)>,
+    ) -> Result<InterruptVector> {
+        self.request_msix(bdf, handler)
+            .or_else(|_| self.request_msi(bdf, handler))
+    }
+    /// Enables an interrupt of the device, preferring MSI-X, MSI and the legacy INTx in this order.
+    pub fn request_interrupt(
+        &self,
+        bdf: BusDeviceFunction,
+        handler: Option<(InterruptHandler, u64)>,
+    ) -> Result<InterruptVector> {
+        self.request_message_signaled_interrupt(bdf, handler)
+            .or_else(|_| self.request_legacy_interrupt(bdf, handler))
+    }
     pub fn capabilities(&self, id: BusDeviceFunction) -> Option<CapabilityIterator> {
         let status = self.read_register_u16(id, 0x06).ok()?;
 
@@ -321,6 +749,43 @@ impl Pci {
             None
         }
     }
+    pub fn class_code(&self, bdf: BusDeviceFunction) -> Result<ClassCode> {
+        Ok(ClassCode::from_register(self.read_register_u32(bdf, 0x08)?))
+    }
+    /// Returns the BARs implemented by the device, with their indices.
+    pub fn bars(&self, bdf: BusDeviceFunction) -> Result<Vec<(usize, Bar)>> {
+        // Type 0 headers have 6 BARs, and Type 1 (PCI-to-PCI bridge) headers have 2.
+        let num_bars = match self.read_register_u8(bdf, 0x0E)? & 0x7F {
+            0 => 6,
+            1 => 2,
+            _ => 0,
+        };
+        let mut bars = Vec::new();
+        let mut index = 0;
+        while index < num_bars {
+            let lo = self.read_register_u32(bdf, 0x10 + index * 4)?;
+            let hi = if index + 1 < num_bars {
+                self.read_register_u32(bdf, 0x10 + (index + 1) * 4)?
+            } else {
+                0
+            };
+            match Bar::decode(lo, hi) {
+                Some(bar) => {
+                    bars.push((index, bar));
+                    index += bar.num_registers();
+                }
+                None => index += 1,
+            }
+        }
+        Ok(bars)
+    }
+    /// Returns the name of the driver attached to the device, if any.
+    pub fn driver_name(&self, bdf: BusDeviceFunction) -> Option<String> {
+        self.devices
+            .borrow()
+            .get(&bdf)
+            .map(|(driver, _)| driver.name().to_string())
+    }
     pub fn read_vendor_id_and_device_id(&self, id: BusDeviceFunction) -> Option<VendorDeviceId> {
         let vendor = self.read_register_u16(id, 0).ok()?;
         let device = self.read_register_u16(id, 2).ok()?;
@@ -342,7 +807,9 @@ impl Pci {
                         match d.attach(bdf) {
                             Ok(di) => {
                                 info!("Driver loaded: {:?}: {}", bdf, di.name());
-                                self.devices.borrow_mut().insert(bdf, Rc::new(di));
+                                self.devices
+                                    .borrow_mut()
+                                    .insert(bdf, (d.clone(), Rc::new(di)));
                             }
                             Err(e) => {
                                 error!("Failed to attach {:?} for {:?}: {:?}", d, bdf, e);
@@ -400,4 +867,29 @@ mod tests {
         }
         assert_eq!(count, 0x10000);
     }
+    #[test_case]
+    fn decode_bar() {
+        assert_eq!(Bar::decode(0xC001, 0), Some(Bar::Io(0xC000)));
+        assert_eq!(
+            Bar::decode(0xFEB0_0000, 0),
+            Some(Bar::Mem32 {
+                addr: 0xFEB0_0000,
+                prefetchable: false
+            })
+        );
+        assert_eq!(
+            Bar::decode(0x0000_000C, 0x0000_0008),
+            Some(Bar::Mem64 {
+                addr: 0x8_0000_0000,
+                prefetchable: true
+            })
+        );
+        assert_eq!(Bar::decode(0, 0), None);
+    }
+    #[test_case]
+    fn class_code_name() {
+        let class = ClassCode::from_register(0x0C03_3001);
+        assert_eq!(class.name(), "USB xHCI controller");
+        assert_eq!(class.revision, 1);
+    }
 }
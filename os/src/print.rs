@@ -1,5 +1,6 @@
 use crate::println;
 use crate::serial::SerialPort;
+use crate::vram::BackBuffer;
 use crate::vram::VRAMBufferInfo;
 use core::cell::RefCell;
 use core::fmt;
@@ -59,7 +60,10 @@ pub fn _print(args: fmt::Arguments) {
     let mut writer = SerialPort::default();
     fmt::write(&mut writer, args).unwrap();
     match &mut *GLOBAL_PRINTER.text_area.borrow_mut() {
-        Some(w) => fmt::write(w, args).unwrap(),
+        Some(w) => {
+            fmt::write(w, args).unwrap();
+            BackBuffer::take().damage(w.rect());
+        }
         None => {}
     }
 }
@@ -9,13 +9,18 @@ use crate::net::tcp::TcpSocket;
 use crate::x86_64::context::unchecked_load_context;
 use crate::x86_64::context::unchecked_switch_context;
 use crate::x86_64::context::ExecutionContext;
+use crate::x86_64::context::CONTEXT_APP;
+use crate::x86_64::context::CONTEXT_OS;
 use crate::x86_64::paging::PageAttr;
 use alloc::boxed::Box;
 use alloc::collections::btree_map;
 use alloc::collections::BTreeMap;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
 use core::future::Future;
+use core::mem::swap;
 use core::pin::Pin;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicI64;
@@ -29,6 +34,20 @@ use noli::net::IpV4Addr;
 static ROOT_SCHEDULER: Scheduler = Scheduler::new();
 pub static CURRENT_PROCESS: Mutex<Option<Box<ProcessContext>>> = Mutex::new(None);
 
+/// Exit codes of processes that are killed by a CPU exception are
+/// reported as `EXIT_CODE_EXCEPTION_BASE - vector`,
+/// e.g. a page fault (#PF, vector 14) results in -0x10E.
+pub const EXIT_CODE_EXCEPTION_BASE: i64 = -0x100;
+pub fn exit_code_for_exception(vector: usize) -> i64 {
+    EXIT_CODE_EXCEPTION_BASE - vector as i64
+}
+
+/// Exit code of processes that are terminated by Ctrl-C (see tty::INTR)
+pub const EXIT_CODE_INTERRUPTED: i64 = -2;
+
+pub type ProcessId = i64;
+static NEXT_PROCESS_ID: AtomicI64 = AtomicI64::new(1);
+
 pub fn init() {
     ROOT_SCHEDULER.clear_queue();
     ROOT_SCHEDULER.schedule(ProcessContext::default()); // context for current
@@ -36,6 +55,9 @@ pub fn init() {
 
 #[derive(Default)]
 pub struct ProcessContext {
+    pid: ProcessId,
+    name: String,
+    app_region: Option<ContiguousPhysicalMemoryPages>,
     args_region: Option<ContiguousPhysicalMemoryPages>,
     stack_region: Option<ContiguousPhysicalMemoryPages>,
     context: Mutex<ExecutionContext>,
@@ -43,6 +65,12 @@ pub struct ProcessContext {
     exit_code: Rc<AtomicI64>,
     tcp_sockets: BTreeMap<i64, Rc<TcpSocket>>,
     next_tcp_socket_handle: i64,
+    children: BTreeMap<ProcessId, ProcessCompletionFuture<'static>>,
+    // CONTEXT_OS, CONTEXT_APP and CURRENT_PROCESS are saved here
+    // while this process is not running. See swap_globals().
+    saved_context_os: ExecutionContext,
+    saved_context_app: ExecutionContext,
+    saved_current_process: Option<Box<ProcessContext>>,
 }
 impl ProcessContext {
     pub fn new(
@@ -60,7 +88,13 @@ impl ProcessContext {
             }
             None => None,
         };
+        let name = args
+            .and_then(|args| args.first())
+            .map(|s| s.to_string())
+            .unwrap_or_default();
         Ok(Self {
+            pid: NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst),
+            name,
             args_region,
             stack_region,
             ..Default::default()
@@ -80,6 +114,15 @@ impl ProcessContext {
         proc.context().lock().cpu.rflags = 2;
         Ok(proc)
     }
+    pub fn pid(&self) -> ProcessId {
+        self.pid
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn set_app_region(&mut self, app_region: ContiguousPhysicalMemoryPages) {
+        self.app_region = Some(app_region);
+    }
     pub fn stack_mut(&mut self) -> Option<&mut ContiguousPhysicalMemoryPages> {
         self.stack_region.as_mut()
     }
@@ -106,6 +149,24 @@ impl ProcessContext {
     pub fn tcp_socket(&self, handle: i64) -> Option<Rc<TcpSocket>> {
         self.tcp_sockets.get(&handle).cloned()
     }
+    pub fn add_child(&mut self, pid: ProcessId, wait: ProcessCompletionFuture<'static>) {
+        self.children.insert(pid, wait);
+    }
+    pub fn take_child(&mut self, pid: ProcessId) -> Option<ProcessCompletionFuture<'static>> {
+        self.children.remove(&pid)
+    }
+    /// Swaps the global states that belong to the running process
+    /// (CONTEXT_OS, CONTEXT_APP and CURRENT_PROCESS) with the ones saved in this context.
+    /// The scheduler calls this for both of the processes on a context switch,
+    /// so that an app running on a process is not affected by apps on other processes.
+    fn swap_globals(&mut self) {
+        swap(&mut *CONTEXT_OS.lock(), &mut self.saved_context_os);
+        swap(&mut *CONTEXT_APP.lock(), &mut self.saved_context_app);
+        swap(
+            &mut *CURRENT_PROCESS.lock(),
+            &mut self.saved_current_process,
+        );
+    }
 }
 
 pub struct Scheduler {
@@ -139,14 +200,11 @@ impl Scheduler {
                 .expect("queue should have a process to exit");
             from.exit_code.store(exit_code, Ordering::SeqCst);
             from.exited.store(true, Ordering::SeqCst);
-            let to = unsafe {
-                queue
-                    .front_mut()
-                    .expect("queue should have a process to swith to")
-                    .context()
-                    .lock()
-                    .as_mut_ptr()
-            };
+            let to = queue
+                .front_mut()
+                .expect("queue should have a process to swith to");
+            to.swap_globals();
+            let to = unsafe { to.context().lock().as_mut_ptr() };
             to
         };
         unsafe { unchecked_load_context(to) };
@@ -162,6 +220,14 @@ impl Scheduler {
                 return;
             }
             queue.rotate_left(1);
+            queue
+                .back_mut()
+                .expect("queue should have a process to swith from")
+                .swap_globals();
+            queue
+                .front_mut()
+                .expect("queue should have a process to swith to")
+                .swap_globals();
             // SAFETY: to and from is valid until the context switch happens. Also, the execution
             // should not be interrupted until the context switch completes.
             unsafe {
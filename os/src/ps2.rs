@@ -0,0 +1,1009 @@
+//! Driver for the PS/2 keyboard and mouse behind the i8042 controller
+//!
+//! Scancodes (set 2, or set 1 if the controller translates them) are converted
+//! into the Usage IDs of the USB HID Keyboard/Keypad page and fed to KeyboardState,
+//! so that the keys behave the same as the ones on USB keyboards.
+//! c.f. https://wiki.osdev.org/I8042_PS/2_Controller
+//! c.f. https://wiki.osdev.org/PS/2_Keyboard
+//! c.f. https://wiki.osdev.org/PS/2_Mouse
+
+extern crate alloc;
+
+use crate::boot_info::BootInfo;
+use crate::error::Error;
+use crate::error::Result;
+use crate::executor::with_timeout_ms;
+use crate::executor::yield_execution;
+use crate::executor::SelectFuture;
+use crate::hpet::Hpet;
+use crate::info;
+use crate::input::InputEvent;
+use crate::input::InputManager;
+use crate::input::KeyEvent;
+use crate::keyboard::KeyboardState;
+use crate::keyboard::LED_CAPS_LOCK;
+use crate::keyboard::LED_NUM_LOCK;
+use crate::keyboard::LED_SCROLL_LOCK;
+use crate::keyboard::USAGE_LEFT_CTRL;
+use crate::keyboard::USAGE_RIGHT_GUI;
+use crate::warn;
+use crate::x86_64::apic::IoApic;
+use crate::x86_64::interrupt::InterruptVector;
+use crate::x86_64::read_io_port_u8;
+use crate::x86_64::write_io_port_u8;
+use alloc::format;
+use alloc::vec::Vec;
+use sabi::MouseButtonState;
+
+const PORT_DATA: u16 = 0x60;
+// Status register on read, command register on write
+const PORT_STATUS: u16 = 0x64;
+const PORT_COMMAND: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+// Set if the data in the output buffer is from the second (mouse) port
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xA7;
+const CMD_ENABLE_AUX: u8 = 0xA8;
+const CMD_TEST_AUX: u8 = 0xA9;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_KEYBOARD: u8 = 0xAB;
+const CMD_DISABLE_KEYBOARD: u8 = 0xAD;
+const CMD_ENABLE_KEYBOARD: u8 = 0xAE;
+const CMD_WRITE_AUX: u8 = 0xD4;
+
+const CONFIG_KEYBOARD_INTERRUPT: u8 = 1 << 0;
+const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const INTERFACE_TEST_PASSED: u8 = 0x00;
+
+// Commands and responses of the devices
+const DEV_SET_LEDS: u8 = 0xED;
+const DEV_SCANCODE_SET: u8 = 0xF0;
+const DEV_GET_ID: u8 = 0xF2;
+const DEV_SET_SAMPLE_RATE: u8 = 0xF3;
+const DEV_ENABLE_REPORTING: u8 = 0xF4;
+const DEV_SET_DEFAULTS: u8 = 0xF6;
+const DEV_RESET: u8 = 0xFF;
+const DEV_ACK: u8 = 0xFA;
+const DEV_RESEND: u8 = 0xFE;
+const DEV_SELF_TEST_PASSED: u8 = 0xAA;
+// Device ID of mice with a scroll wheel (IntelliMouse), which send 4-byte packets
+const MOUSE_ID_WHEEL: u8 = 3;
+
+const IRQ_KEYBOARD: u8 = 1;
+const IRQ_MOUSE: u8 = 12;
+
+// LED bits of the Set LEDs command
+const PS2_LED_SCROLL_LOCK: u8 = 1 << 0;
+const PS2_LED_NUM_LOCK: u8 = 1 << 1;
+const PS2_LED_CAPS_LOCK: u8 = 1 << 2;
+
+const USAGE_PAUSE: u8 = 0x48;
+
+const IO_TIMEOUT_MS: u64 = 100;
+// Devices can take hundreds of milliseconds to complete the self test after a reset
+const RESET_TIMEOUT_MS: u64 = 1000;
+// The devices are polled at this interval even without interrupts,
+// which is also the interval to check the held key for repeating.
+const POLL_INTERVAL_MS: u64 = 10;
+
+/// Scancode sets that keyboards send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    /// The set of IBM PC XT, which the i8042 translates set 2 into by default
+    Set1,
+    /// The default set of PS/2 keyboards
+    Set2,
+}
+
+// Usage IDs of the keys, by the make codes of set 1 (without the 0xE0 prefix)
+fn usage_from_set1(code: u8) -> Option<u8> {
+    Some(match code {
+        0x01 => 0x29,
+        0x02..=0x0B => 0x1E + (code - 0x02),
+        0x0C => 0x2D,
+        0x0D => 0x2E,
+        0x0E => 0x2A,
+        0x0F => 0x2B,
+        0x10 => 0x14,
+        0x11 => 0x1A,
+        0x12 => 0x08,
+        0x13 => 0x15,
+        0x14 => 0x17,
+        0x15 => 0x1C,
+        0x16 => 0x18,
+        0x17 => 0x0C,
+        0x18 => 0x12,
+        0x19 => 0x13,
+        0x1A => 0x2F,
+        0x1B => 0x30,
+        0x1C => 0x28,
+        0x1D => 0xE0,
+        0x1E => 0x04,
+        0x1F => 0x16,
+        0x20 => 0x07,
+        0x21 => 0x09,
+        0x22 => 0x0A,
+        0x23 => 0x0B,
+        0x24 => 0x0D,
+        0x25 => 0x0E,
+        0x26 => 0x0F,
+        0x27 => 0x33,
+        0x28 => 0x34,
+        0x29 => 0x35,
+        0x2A => 0xE1,
+        0x2B => 0x31,
+        0x2C => 0x1D,
+        0x2D => 0x1B,
+        0x2E => 0x06,
+        0x2F => 0x19,
+        0x30 => 0x05,
+        0x31 => 0x11,
+        0x32 => 0x10,
+        0x33 => 0x36,
+        0x34 => 0x37,
+        0x35 => 0x38,
+        0x36 => 0xE5,
+        0x37 => 0x55,
+        0x38 => 0xE2,
+        0x39 => 0x2C,
+        0x3A => 0x39,
+        // F1 to F10
+        0x3B..=0x44 => 0x3A + (code - 0x3B),
+        0x45 => 0x53,
+        0x46 => 0x47,
+        0x47 => 0x5F,
+        0x48 => 0x60,
+        0x49 => 0x61,
+        0x4A => 0x56,
+        0x4B => 0x5C,
+        0x4C => 0x5D,
+        0x4D => 0x5E,
+        0x4E => 0x57,
+        0x4F => 0x59,
+        0x50 => 0x5A,
+        0x51 => 0x5B,
+        0x52 => 0x62,
+        0x53 => 0x63,
+        0x56 => 0x64,
+        0x57 => 0x44,
+        0x58 => 0x45,
+        // Keys of Japanese keyboards: Katakana/Hiragana, Ro, Henkan, Muhenkan and Yen
+        0x70 => 0x88,
+        0x73 => 0x87,
+        0x79 => 0x8A,
+        0x7B => 0x8B,
+        0x7D => 0x89,
+        _ => return None,
+    })
+}
+
+// Usage IDs of the keys, by the make codes of set 1 with the 0xE0 prefix
+fn usage_from_set1_extended(code: u8) -> Option<u8> {
+    Some(match code {
+        0x1C => 0x58,
+        0x1D => 0xE4,
+        0x35 => 0x54,
+        0x37 => 0x46,
+        0x38 => 0xE6,
+        0x47 => 0x4A,
+        0x48 => 0x52,
+        0x49 => 0x4B,
+        0x4B => 0x50,
+        0x4D => 0x4F,
+        0x4F => 0x4D,
+        0x50 => 0x51,
+        0x51 => 0x4E,
+        0x52 => 0x49,
+        0x53 => 0x4C,
+        0x5B => 0xE3,
+        0x5C => 0xE7,
+        0x5D => 0x65,
+        _ => return None,
+    })
+}
+
+// Usage IDs of the keys, by the make codes of set 2 (without the 0xE0 prefix)
+fn usage_from_set2(code: u8) -> Option<u8> {
+    Some(match code {
+        0x01 => 0x42,
+        0x03 => 0x3E,
+        0x04 => 0x3C,
+        0x05 => 0x3A,
+        0x06 => 0x3B,
+        0x07 => 0x45,
+        0x09 => 0x43,
+        0x0A => 0x41,
+        0x0B => 0x3F,
+        0x0C => 0x3D,
+        0x0D => 0x2B,
+        0x0E => 0x35,
+        0x11 => 0xE2,
+        0x12 => 0xE1,
+        0x13 => 0x88,
+        0x14 => 0xE0,
+        0x15 => 0x14,
+        0x16 => 0x1E,
+        0x1A => 0x1D,
+        0x1B => 0x16,
+        0x1C => 0x04,
+        0x1D => 0x1A,
+        0x1E => 0x1F,
+        0x21 => 0x06,
+        0x22 => 0x1B,
+        0x23 => 0x07,
+        0x24 => 0x08,
+        0x25 => 0x21,
+        0x26 => 0x20,
+        0x29 => 0x2C,
+        0x2A => 0x19,
+        0x2B => 0x09,
+        0x2C => 0x17,
+        0x2D => 0x15,
+        0x2E => 0x22,
+        0x31 => 0x11,
+        0x32 => 0x05,
+        0x33 => 0x0B,
+        0x34 => 0x0A,
+        0x35 => 0x1C,
+        0x36 => 0x23,
+        0x3A => 0x10,
+        0x3B => 0x0D,
+        0x3C => 0x18,
+        0x3D => 0x24,
+        0x3E => 0x25,
+        0x41 => 0x36,
+        0x42 => 0x0E,
+        0x43 => 0x0C,
+        0x44 => 0x12,
+        0x45 => 0x27,
+        0x46 => 0x26,
+        0x49 => 0x37,
+        0x4A => 0x38,
+        0x4B => 0x0F,
+        0x4C => 0x33,
+        0x4D => 0x13,
+        0x4E => 0x2D,
+        0x51 => 0x87,
+        0x52 => 0x34,
+        0x54 => 0x2F,
+        0x55 => 0x2E,
+        0x58 => 0x39,
+        0x59 => 0xE5,
+        0x5A => 0x28,
+        0x5B => 0x30,
+        0x5D => 0x31,
+        0x61 => 0x64,
+        0x64 => 0x8A,
+        0x66 => 0x2A,
+        0x67 => 0x8B,
+        0x69 => 0x59,
+        0x6A => 0x89,
+        0x6B => 0x5C,
+        0x6C => 0x5F,
+        0x70 => 0x62,
+        0x71 => 0x63,
+        0x72 => 0x5A,
+        0x73 => 0x5D,
+        0x74 => 0x5E,
+        0x75 => 0x60,
+        0x76 => 0x29,
+        0x77 => 0x53,
+        0x78 => 0x44,
+        0x79 => 0x57,
+        0x7A => 0x5B,
+        0x7B => 0x56,
+        0x7C => 0x55,
+        0x7D => 0x61,
+        0x7E => 0x47,
+        0x83 => 0x40,
+        _ => return None,
+    })
+}
+
+// Usage IDs of the keys, by the make codes of set 2 with the 0xE0 prefix
+fn usage_from_set2_extended(code: u8) -> Option<u8> {
+    Some(match code {
+        0x11 => 0xE6,
+        0x14 => 0xE4,
+        0x1F => 0xE3,
+        0x27 => 0xE7,
+        0x2F => 0x65,
+        0x4A => 0x54,
+        0x5A => 0x58,
+        0x69 => 0x4D,
+        0x6B => 0x50,
+        0x6C => 0x4A,
+        0x70 => 0x49,
+        0x71 => 0x4C,
+        0x72 => 0x51,
+        0x74 => 0x4F,
+        0x75 => 0x52,
+        0x7A => 0x4E,
+        0x7C => 0x46,
+        0x7D => 0x4B,
+        _ => return None,
+    })
+}
+
+/// Assembles the bytes from a keyboard into key presses and releases.
+#[derive(Debug)]
+pub struct ScancodeDecoder {
+    set: ScancodeSet,
+    extended: bool,
+    // Set 2 only: the next code is of a released key
+    released: bool,
+    // Remaining bytes of the Pause key sequence, which starts with 0xE1
+    pause_bytes: u8,
+}
+impl ScancodeDecoder {
+    pub fn new(set: ScancodeSet) -> Self {
+        Self {
+            set,
+            extended: false,
+            released: false,
+            pause_bytes: 0,
+        }
+    }
+    /// Returns the Usage ID of the key and true if it is pressed (false if released)
+    /// when a scancode is completed by the byte.
+    /// The Pause key is reported only as pressed, since it has no break code.
+    pub fn decode(&mut self, byte: u8) -> Option<(u8, bool)> {
+        if self.pause_bytes > 0 {
+            self.pause_bytes -= 1;
+            return (self.pause_bytes == 0).then_some((USAGE_PAUSE, true));
+        }
+        match (self.set, byte) {
+            // Responses to commands and errors, which are not scancodes
+            (_, 0x00 | 0xFF | DEV_ACK | DEV_RESEND | 0xEE) => return None,
+            (ScancodeSet::Set2, DEV_SELF_TEST_PASSED) => return None,
+            (_, 0xE0) => {
+                self.extended = true;
+                return None;
+            }
+            (ScancodeSet::Set1, 0xE1) => {
+                self.pause_bytes = 5;
+                return None;
+            }
+            (ScancodeSet::Set2, 0xE1) => {
+                self.pause_bytes = 7;
+                return None;
+            }
+            (ScancodeSet::Set2, 0xF0) => {
+                self.released = true;
+                return None;
+            }
+            _ => {}
+        }
+        let extended = core::mem::take(&mut self.extended);
+        let (code, pressed) = match self.set {
+            ScancodeSet::Set1 => (byte & 0x7F, byte & 0x80 == 0),
+            ScancodeSet::Set2 => (byte, !core::mem::take(&mut self.released)),
+        };
+        // Keyboards send fake Shift codes around some extended keys (e.g. Print Screen),
+        // which are not in the tables below and ignored.
+        let usage_id = match (self.set, extended) {
+            (ScancodeSet::Set1, false) => usage_from_set1(code),
+            (ScancodeSet::Set1, true) => usage_from_set1_extended(code),
+            (ScancodeSet::Set2, false) => usage_from_set2(code),
+            (ScancodeSet::Set2, true) => usage_from_set2_extended(code),
+        }?;
+        Some((usage_id, pressed))
+    }
+}
+
+/// Converts the scancodes from a PS/2 keyboard into KeyEvents via KeyboardState.
+#[derive(Debug)]
+pub struct Ps2Keyboard {
+    decoder: ScancodeDecoder,
+    // Bitmap of the modifiers and the Usage IDs of the other keys being pressed,
+    // as in the reports of USB keyboards
+    modifiers: u8,
+    keys: Vec<u8>,
+    state: KeyboardState,
+}
+impl Ps2Keyboard {
+    pub fn new(set: ScancodeSet) -> Self {
+        Self {
+            decoder: ScancodeDecoder::new(set),
+            modifiers: 0,
+            keys: Vec::new(),
+            state: KeyboardState::new(),
+        }
+    }
+    pub fn process_byte(&mut self, byte: u8, now_ms: u64) -> Vec<KeyEvent> {
+        let Some((usage_id, pressed)) = self.decoder.decode(byte) else {
+            return Vec::new();
+        };
+        if usage_id == USAGE_PAUSE {
+            // Pause has no break code, so release it right away
+            let mut events = self.update_key(usage_id, true, now_ms);
+            events.extend(self.update_key(usage_id, false, now_ms));
+            return events;
+        }
+        self.update_key(usage_id, pressed, now_ms)
+    }
+    fn update_key(&mut self, usage_id: u8, pressed: bool, now_ms: u64) -> Vec<KeyEvent> {
+        if (USAGE_LEFT_CTRL..=USAGE_RIGHT_GUI).contains(&usage_id) {
+            let mask = 1 << (usage_id - USAGE_LEFT_CTRL);
+            if pressed {
+                self.modifiers |= mask;
+            } else {
+                self.modifiers &= !mask;
+            }
+        } else if pressed {
+            // The keyboard repeats the make code of a held key
+            if !self.keys.contains(&usage_id) {
+                self.keys.push(usage_id);
+            }
+        } else {
+            self.keys.retain(|k| *k != usage_id);
+        }
+        self.state.process_keys(self.modifiers, &self.keys, now_ms)
+    }
+    pub fn poll_repeat(&mut self, now_ms: u64) -> Option<KeyEvent> {
+        self.state.poll_repeat(now_ms)
+    }
+    /// Returns the data byte of the Set LEDs command if the LEDs should be updated.
+    pub fn take_led_update(&mut self) -> Option<u8> {
+        let leds = self.state.take_led_update()?;
+        let mut ps2_leds = 0;
+        if leds & LED_SCROLL_LOCK != 0 {
+            ps2_leds |= PS2_LED_SCROLL_LOCK;
+        }
+        if leds & LED_NUM_LOCK != 0 {
+            ps2_leds |= PS2_LED_NUM_LOCK;
+        }
+        if leds & LED_CAPS_LOCK != 0 {
+            ps2_leds |= PS2_LED_CAPS_LOCK;
+        }
+        Some(ps2_leds)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum LedUpdateStep {
+    /// The byte is not a response to the command, so it should be decoded as a scancode.
+    NotConsumed,
+    /// The byte should be sent to the keyboard.
+    Send(u8),
+    /// The LEDs are updated.
+    Done,
+}
+
+/// Sends the Set LEDs command and its data byte to the keyboard. The responses are
+/// handled in the main loop of ps2_task(), so that the scancodes and the mouse packets
+/// received while waiting for them are not lost.
+#[derive(Debug, Default)]
+struct LedUpdater {
+    // The byte waiting for the ACK, and the data byte to send after it
+    sending: Option<(u8, Option<u8>)>,
+    sent_ms: u64,
+    resends: usize,
+}
+impl LedUpdater {
+    fn is_busy(&self) -> bool {
+        self.sending.is_some()
+    }
+    /// Starts the update. Returns the byte to send.
+    fn start(&mut self, leds: u8, now_ms: u64) -> u8 {
+        self.sending = Some((DEV_SET_LEDS, Some(leds)));
+        self.sent_ms = now_ms;
+        self.resends = 0;
+        DEV_SET_LEDS
+    }
+    fn process_byte(&mut self, byte: u8, now_ms: u64) -> Result<LedUpdateStep> {
+        let Some((sent, next)) = self.sending else {
+            return Ok(LedUpdateStep::NotConsumed);
+        };
+        match byte {
+            DEV_ACK => {
+                self.sent_ms = now_ms;
+                self.resends = 0;
+                if let Some(next) = next {
+                    self.sending = Some((next, None));
+                    Ok(LedUpdateStep::Send(next))
+                } else {
+                    self.sending = None;
+                    Ok(LedUpdateStep::Done)
+                }
+            }
+            DEV_RESEND => {
+                self.resends += 1;
+                if self.resends >= 3 {
+                    self.sending = None;
+                    return Err(Error::Failed("ps2: the device kept requesting resends"));
+                }
+                self.sent_ms = now_ms;
+                Ok(LedUpdateStep::Send(sent))
+            }
+            _ => Ok(LedUpdateStep::NotConsumed),
+        }
+    }
+    fn check_timeout(&mut self, now_ms: u64) -> Result<()> {
+        if self.is_busy() && now_ms.saturating_sub(self.sent_ms) > IO_TIMEOUT_MS {
+            self.sending = None;
+            return Err(Error::Failed("ps2: no ACK for the Set LEDs command"));
+        }
+        Ok(())
+    }
+}
+
+/// Assembles the bytes from a mouse into movement packets.
+#[derive(Debug)]
+pub struct Ps2MouseDecoder {
+    packet: [u8; 4],
+    len: usize,
+    // 3, or 4 for mice with a scroll wheel
+    packet_size: usize,
+}
+impl Ps2MouseDecoder {
+    pub fn new(has_wheel: bool) -> Self {
+        Self {
+            packet: [0; 4],
+            len: 0,
+            packet_size: if has_wheel { 4 } else { 3 },
+        }
+    }
+    pub fn process_byte(&mut self, byte: u8) -> Option<InputEvent> {
+        // Bit 3 of the first byte is always set. Skip bytes until it is found
+        // to resynchronize with the packets.
+        if self.len == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_size {
+            return None;
+        }
+        self.len = 0;
+        let flags = self.packet[0];
+        // The movements are 9-bit two's complement values, with the sign bits in the flags
+        let movement = |value: u8, sign_bit: u8| -> i64 {
+            if flags & sign_bit != 0 {
+                value as i64 - 0x100
+            } else {
+                value as i64
+            }
+        };
+        // The movements are not reliable on overflow
+        let overflow = flags & 0xC0 != 0;
+        let (dx, dy) = if overflow {
+            (0, 0)
+        } else {
+            // Y grows upwards on PS/2 mice, but downwards on the screen
+            (
+                movement(self.packet[1], 1 << 4),
+                -movement(self.packet[2], 1 << 5),
+            )
+        };
+        // Positive values are for scrolling down, unlike USB mice
+        let wheel = if self.packet_size == 4 {
+            -(self.packet[3] as i8 as i64)
+        } else {
+            0
+        };
+        Some(InputEvent::PointerRelative {
+            button: MouseButtonState::from_lcr(
+                flags & 0b001 != 0,
+                flags & 0b010 != 0,
+                flags & 0b100 != 0,
+            ),
+            dx,
+            dy,
+            wheel,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ps2Port {
+    Keyboard,
+    Mouse,
+}
+
+fn read_status() -> u8 {
+    read_io_port_u8(PORT_STATUS)
+}
+
+async fn wait_until(cond: impl Fn() -> bool, timeout_ms: u64) -> Result<()> {
+    with_timeout_ms(
+        async {
+            while !cond() {
+                yield_execution().await;
+            }
+        },
+        timeout_ms,
+    )
+    .await
+}
+
+async fn write_command(cmd: u8) -> Result<()> {
+    wait_until(|| read_status() & STATUS_INPUT_FULL == 0, IO_TIMEOUT_MS).await?;
+    write_io_port_u8(PORT_COMMAND, cmd);
+    Ok(())
+}
+
+async fn write_data(data: u8) -> Result<()> {
+    wait_until(|| read_status() & STATUS_INPUT_FULL == 0, IO_TIMEOUT_MS).await?;
+    write_io_port_u8(PORT_DATA, data);
+    Ok(())
+}
+
+async fn read_data(timeout_ms: u64) -> Result<u8> {
+    wait_until(|| read_status() & STATUS_OUTPUT_FULL != 0, timeout_ms).await?;
+    Ok(read_io_port_u8(PORT_DATA))
+}
+
+/// Reads a byte from the device on the port. Bytes from the other port are dropped.
+async fn read_from(port: Ps2Port, timeout_ms: u64) -> Result<u8> {
+    with_timeout_ms(
+        async {
+            loop {
+                let status = read_status();
+                if status & STATUS_OUTPUT_FULL != 0 {
+                    let data = read_io_port_u8(PORT_DATA);
+                    let from = if status & STATUS_AUX_DATA != 0 {
+                        Ps2Port::Mouse
+                    } else {
+                        Ps2Port::Keyboard
+                    };
+                    if from == port {
+                        return data;
+                    }
+                }
+                yield_execution().await;
+            }
+        },
+        timeout_ms,
+    )
+    .await
+}
+
+fn flush_output_buffer() {
+    while read_status() & STATUS_OUTPUT_FULL != 0 {
+        read_io_port_u8(PORT_DATA);
+    }
+}
+
+async fn read_config() -> Result<u8> {
+    write_command(CMD_READ_CONFIG).await?;
+    read_data(IO_TIMEOUT_MS).await
+}
+
+async fn write_config(config: u8) -> Result<()> {
+    write_command(CMD_WRITE_CONFIG).await?;
+    write_data(config).await
+}
+
+/// Sends a byte to the device on the port and waits for the ACK, resending it if requested.
+async fn send_to_device(port: Ps2Port, data: u8) -> Result<()> {
+    for _ in 0..3 {
+        if port == Ps2Port::Mouse {
+            write_command(CMD_WRITE_AUX).await?;
+        }
+        write_data(data).await?;
+        match read_from(port, IO_TIMEOUT_MS).await? {
+            DEV_ACK => return Ok(()),
+            DEV_RESEND => continue,
+            r => {
+                return Err(Error::FailedString(format!(
+                    "ps2: unexpected response {r:#04X} to {data:#04X} from {port:?}"
+                )))
+            }
+        }
+    }
+    Err(Error::Failed("ps2: the device kept requesting resends"))
+}
+
+async fn reset_device(port: Ps2Port) -> Result<()> {
+    send_to_device(port, DEV_RESET).await?;
+    match read_from(port, RESET_TIMEOUT_MS).await? {
+        DEV_SELF_TEST_PASSED => {}
+        r => {
+            return Err(Error::FailedString(format!(
+                "ps2: self test of {port:?} failed: {r:#04X}"
+            )))
+        }
+    }
+    if port == Ps2Port::Mouse {
+        // Mice send their device ID after the self test
+        read_from(port, IO_TIMEOUT_MS).await?;
+    }
+    Ok(())
+}
+
+/// Initializes the controller, and returns the config byte
+/// and whether the second (mouse) port is available.
+async fn init_controller() -> Result<(u8, bool)> {
+    // No controller responds on the port
+    if read_status() == 0xFF {
+        return Err(Error::Failed("ps2: i8042 not found"));
+    }
+    write_command(CMD_DISABLE_KEYBOARD).await?;
+    write_command(CMD_DISABLE_AUX).await?;
+    flush_output_buffer();
+    // Disable the interrupts while setting up the devices
+    let config = read_config().await? & !(CONFIG_KEYBOARD_INTERRUPT | CONFIG_AUX_INTERRUPT);
+    write_config(config).await?;
+    write_command(CMD_SELF_TEST).await?;
+    if read_data(IO_TIMEOUT_MS).await? != SELF_TEST_PASSED {
+        return Err(Error::Failed("ps2: self test of i8042 failed"));
+    }
+    // The self test can reset the controller
+    write_config(config).await?;
+    // The clock of the second port is enabled by CMD_ENABLE_AUX only if it exists
+    write_command(CMD_ENABLE_AUX).await?;
+    let has_aux = read_config().await? & CONFIG_AUX_CLOCK_DISABLED == 0;
+    write_command(CMD_DISABLE_AUX).await?;
+    write_command(CMD_TEST_KEYBOARD).await?;
+    if read_data(IO_TIMEOUT_MS).await? != INTERFACE_TEST_PASSED {
+        return Err(Error::Failed("ps2: keyboard port test failed"));
+    }
+    let has_aux = has_aux && {
+        write_command(CMD_TEST_AUX).await?;
+        read_data(IO_TIMEOUT_MS).await? == INTERFACE_TEST_PASSED
+    };
+    write_command(CMD_ENABLE_KEYBOARD).await?;
+    if has_aux {
+        write_command(CMD_ENABLE_AUX).await?;
+    }
+    Ok((config, has_aux))
+}
+
+async fn init_keyboard() -> Result<()> {
+    reset_device(Ps2Port::Keyboard).await?;
+    // Set 2 is translated into set 1 by the controller if CONFIG_TRANSLATION is set
+    send_to_device(Ps2Port::Keyboard, DEV_SCANCODE_SET).await?;
+    send_to_device(Ps2Port::Keyboard, 2).await?;
+    send_to_device(Ps2Port::Keyboard, DEV_ENABLE_REPORTING).await
+}
+
+/// Initializes the mouse, and returns true if it has a scroll wheel.
+async fn init_mouse() -> Result<bool> {
+    reset_device(Ps2Port::Mouse).await?;
+    send_to_device(Ps2Port::Mouse, DEV_SET_DEFAULTS).await?;
+    // This sequence of sample rates enables the scroll wheel of IntelliMouse compatibles,
+    // which then report MOUSE_ID_WHEEL as their ID.
+    for rate in [200, 100, 80] {
+        send_to_device(Ps2Port::Mouse, DEV_SET_SAMPLE_RATE).await?;
+        send_to_device(Ps2Port::Mouse, rate).await?;
+    }
+    send_to_device(Ps2Port::Mouse, DEV_GET_ID).await?;
+    let id = read_from(Ps2Port::Mouse, IO_TIMEOUT_MS).await?;
+    send_to_device(Ps2Port::Mouse, DEV_ENABLE_REPORTING).await?;
+    Ok(id == MOUSE_ID_WHEEL)
+}
+
+fn route_irq(irq: u8) -> Result<InterruptVector> {
+    let vector = InterruptVector::alloc(None)?;
+    let bsp_apic_id = BootInfo::take().bsp_local_apic().id();
+    IoApic::route_isa_irq(irq, vector.vector(), bsp_apic_id)?;
+    Ok(vector)
+}
+
+pub async fn ps2_task() -> Result<()> {
+    let (mut config, has_aux) = init_controller().await?;
+    let set = if config & CONFIG_TRANSLATION != 0 {
+        ScancodeSet::Set1
+    } else {
+        ScancodeSet::Set2
+    };
+    let mut keyboard = match init_keyboard().await {
+        Ok(()) => {
+            config |= CONFIG_KEYBOARD_INTERRUPT;
+            // Note: this message is used by e2etest - please keep it as is!
+            info!("ps2_keyboard is ready ({set:?})");
+            Some(Ps2Keyboard::new(set))
+        }
+        Err(e) => {
+            warn!("ps2: no keyboard: {e:?}");
+            None
+        }
+    };
+    let mut mouse = if has_aux {
+        match init_mouse().await {
+            Ok(has_wheel) => {
+                config |= CONFIG_AUX_INTERRUPT;
+                info!("ps2_mouse is ready (wheel: {has_wheel})");
+                Some(Ps2MouseDecoder::new(has_wheel))
+            }
+            Err(e) => {
+                warn!("ps2: no mouse: {e:?}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    if keyboard.is_none() && mouse.is_none() {
+        return Err(Error::Failed("ps2: no devices found"));
+    }
+    flush_output_buffer();
+    write_config(config).await?;
+    let keyboard_irq = route_irq(IRQ_KEYBOARD)?;
+    let mouse_irq = route_irq(IRQ_MOUSE)?;
+    let mut leds = LedUpdater::default();
+    loop {
+        // The interrupts only wake up this task earlier than the polling interval
+        let _ = with_timeout_ms(
+            SelectFuture::new(keyboard_irq.wait(), mouse_irq.wait()),
+            POLL_INTERVAL_MS,
+        )
+        .await;
+        let input = InputManager::take();
+        let now_ms = Hpet::take().uptime_ms();
+        loop {
+            let status = read_status();
+            if status & STATUS_OUTPUT_FULL == 0 {
+                break;
+            }
+            let data = read_io_port_u8(PORT_DATA);
+            if status & STATUS_AUX_DATA != 0 {
+                if let Some(e) = mouse.as_mut().and_then(|m| m.process_byte(data)) {
+                    input.push_event(&e);
+                }
+            } else if let Some(keyboard) = keyboard.as_mut() {
+                match leds.process_byte(data, now_ms) {
+                    Ok(LedUpdateStep::NotConsumed) => {
+                        for e in keyboard.process_byte(data, now_ms) {
+                            input.push_key(&e);
+                        }
+                    }
+                    Ok(LedUpdateStep::Send(byte)) => write_data(byte).await?,
+                    Ok(LedUpdateStep::Done) => {}
+                    Err(e) => warn!("ps2: failed to set LEDs: {e:?}"),
+                }
+            }
+        }
+        let Some(keyboard) = keyboard.as_mut() else {
+            continue;
+        };
+        if let Some(e) = keyboard.poll_repeat(now_ms) {
+            input.push_key(&e);
+        }
+        if let Err(e) = leds.check_timeout(now_ms) {
+            warn!("ps2: failed to set LEDs: {e:?}");
+        }
+        // Further updates wait in the keyboard until the current one is done
+        if !leds.is_busy() {
+            if let Some(data) = keyboard.take_led_update() {
+                write_data(leds.start(data, now_ms)).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode_all(decoder: &mut ScancodeDecoder, bytes: &[u8]) -> Vec<(u8, bool)> {
+        bytes.iter().filter_map(|b| decoder.decode(*b)).collect()
+    }
+
+    #[test_case]
+    fn set1_scancodes_are_decoded() {
+        let mut d = ScancodeDecoder::new(ScancodeSet::Set1);
+        // 'a' pressed and released, then Right Ctrl and the Up arrow (extended)
+        assert_eq!(
+            decode_all(&mut d, &[0x1E, 0x9E, 0xE0, 0x1D, 0xE0, 0x48, 0xE0, 0xC8]),
+            [
+                (0x04, true),
+                (0x04, false),
+                (0xE4, true),
+                (0x52, true),
+                (0x52, false)
+            ]
+        );
+        // Print Screen with the fake Left Shift
+        assert_eq!(
+            decode_all(&mut d, &[0xE0, 0x2A, 0xE0, 0x37]),
+            [(0x46, true)]
+        );
+        // Pause
+        assert_eq!(
+            decode_all(&mut d, &[0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5]),
+            [(USAGE_PAUSE, true)]
+        );
+        // Left Shift released, which looks like the self test passed response of set 2
+        assert_eq!(decode_all(&mut d, &[0xAA]), [(0xE1, false)]);
+    }
+
+    #[test_case]
+    fn set2_scancodes_are_decoded() {
+        let mut d = ScancodeDecoder::new(ScancodeSet::Set2);
+        // 'a' pressed and released, then Enter on the keypad (extended)
+        assert_eq!(
+            decode_all(&mut d, &[0x1C, 0xF0, 0x1C, 0xE0, 0x5A, 0xE0, 0xF0, 0x5A]),
+            [(0x04, true), (0x04, false), (0x58, true), (0x58, false)]
+        );
+        // F7 has a make code above 0x7F
+        assert_eq!(
+            decode_all(&mut d, &[0x83, 0xF0, 0x83]),
+            [(0x40, true), (0x40, false)]
+        );
+        // Pause
+        assert_eq!(
+            decode_all(&mut d, &[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77]),
+            [(USAGE_PAUSE, true)]
+        );
+    }
+
+    #[test_case]
+    fn ps2_keyboard_generates_key_events() {
+        let mut kbd = Ps2Keyboard::new(ScancodeSet::Set2);
+        // Left Shift + '1', with a repeated make code of '1'
+        let mut events = Vec::new();
+        for b in [0x12, 0x16, 0x16, 0xF0, 0x16, 0xF0, 0x12] {
+            events.extend(kbd.process_byte(b, 0));
+        }
+        let chars: Vec<char> = events
+            .iter()
+            .filter(|e| e.pressed)
+            .filter_map(|e| e.to_char())
+            .collect();
+        assert_eq!(chars, ['!']);
+        assert_eq!(events.iter().filter(|e| !e.pressed).count(), 2);
+        // The LEDs are set to the initial state (Num Lock on)
+        assert_eq!(kbd.take_led_update(), Some(PS2_LED_NUM_LOCK));
+        assert_eq!(kbd.take_led_update(), None);
+    }
+
+    #[test_case]
+    fn leds_are_updated_without_consuming_scancodes() {
+        let mut leds = LedUpdater::default();
+        assert_eq!(leds.start(PS2_LED_CAPS_LOCK, 0), DEV_SET_LEDS);
+        // A scancode received before the ACK is left for the decoder
+        assert_eq!(
+            leds.process_byte(0x1C, 0).unwrap(),
+            LedUpdateStep::NotConsumed
+        );
+        assert_eq!(
+            leds.process_byte(DEV_ACK, 1).unwrap(),
+            LedUpdateStep::Send(PS2_LED_CAPS_LOCK)
+        );
+        assert_eq!(
+            leds.process_byte(DEV_RESEND, 2).unwrap(),
+            LedUpdateStep::Send(PS2_LED_CAPS_LOCK)
+        );
+        assert_eq!(leds.process_byte(DEV_ACK, 3).unwrap(), LedUpdateStep::Done);
+        assert!(!leds.is_busy());
+        // ACKs are scancodes (ignored by the decoder) when no command is sent
+        assert_eq!(
+            leds.process_byte(DEV_ACK, 4).unwrap(),
+            LedUpdateStep::NotConsumed
+        );
+        // The update is given up if the keyboard does not respond
+        leds.start(0, 10);
+        assert!(leds.check_timeout(10 + IO_TIMEOUT_MS).is_ok());
+        assert!(leds.check_timeout(11 + IO_TIMEOUT_MS).is_err());
+        assert!(!leds.is_busy());
+    }
+
+    #[test_case]
+    fn mouse_packets_are_decoded() {
+        let mut m = Ps2MouseDecoder::new(true);
+        // A stray byte without bit 3 is skipped.
+        // Then left button, dx = -2 (with the X sign bit), dy = 3 (upwards), wheel = -1
+        let bytes = [0x00, 0x19, 0xFE, 0x03, 0xFF];
+        let events: Vec<InputEvent> = bytes.iter().filter_map(|b| m.process_byte(*b)).collect();
+        assert_eq!(
+            events,
+            [InputEvent::PointerRelative {
+                button: MouseButtonState::from_lcr(true, false, false),
+                dx: -2,
+                dy: -3,
+                wheel: 1,
+            }]
+        );
+    }
+}
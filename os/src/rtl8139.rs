@@ -3,6 +3,7 @@ extern crate alloc;
 use crate::error::Error;
 use crate::error::Result;
 use crate::executor::spawn_global;
+use crate::executor::with_timeout_ms;
 use crate::executor::TimeoutFuture;
 use crate::info;
 use crate::mutex::Mutex;
@@ -14,7 +15,10 @@ use crate::pci::Pci;
 use crate::pci::PciDeviceDriver;
 use crate::pci::PciDeviceDriverInstance;
 use crate::pci::VendorDeviceId;
+use crate::warn;
 use crate::x86_64::busy_loop_hint;
+use crate::x86_64::interrupt::InterruptVector;
+use crate::x86_64::read_io_port_u16;
 use crate::x86_64::read_io_port_u32;
 use crate::x86_64::read_io_port_u8;
 use crate::x86_64::write_io_port_u16;
@@ -85,9 +89,22 @@ impl TxContext {
     }
 }
 
+// ISR: Interrupt Status Register (write 1 to clear)
+const RTL8139_REG_ISR: u16 = 0x3E;
+
+/// Deasserts the INTx by clearing the interrupt status.
+/// The packets are processed in Rtl8139::poll().
+fn rtl8139_interrupt_handler(io_base: u64) {
+    let io_base = io_base as u16;
+    let status = read_io_port_u16(io_base + RTL8139_REG_ISR);
+    write_io_port_u16(io_base + RTL8139_REG_ISR, status);
+}
+
 struct Rtl8139 {
     _bdf: BusDeviceFunction,
     io_base: u16,
+    // None if the interrupt is not available. The device is polled periodically in that case.
+    interrupt: Option<InterruptVector>,
     eth_addr: EthernetAddr,
     rx: Mutex<RxContext>,
     tx: Mutex<TxContext>,
@@ -96,7 +113,6 @@ impl Rtl8139 {
     // https://wiki.osdev.org/RTL8139
     fn new(bdf: BusDeviceFunction) -> Result<Self> {
         let pci = Pci::take();
-        pci.disable_interrupt(bdf)?;
         pci.enable_bus_master(bdf)?;
         // Assume that BAR0 has IO Port address
         let io_base = pci.try_bar0_io(bdf)?;
@@ -114,9 +130,18 @@ impl Rtl8139 {
             busy_loop_hint();
         }
 
+        let interrupt =
+            match pci.request_interrupt(bdf, Some((rtl8139_interrupt_handler, io_base as u64))) {
+                Ok(interrupt) => Some(interrupt),
+                Err(e) => {
+                    warn!("rtl8139: interrupt is not available, fallback to polling: {e:?}");
+                    None
+                }
+            };
         let d = Self {
             _bdf: bdf,
             io_base,
+            interrupt,
             eth_addr,
             rx: Mutex::new(RxContext::new()),
             tx: Mutex::new(TxContext::new()),
@@ -134,9 +159,9 @@ impl Rtl8139 {
         Ok(d)
     }
     fn push_packet(&self, packet: Box<[u8]>) -> Result<()> {
-        let mut tx = self.tx.lock();
-        tx.pending_packets.push_back(packet);
-        Ok(())
+        self.tx.lock().pending_packets.push_back(packet);
+        // Start the transmission now rather than waiting for the next interrupt
+        self.poll_tx()
     }
     fn pop_packet(&self) -> Result<Box<[u8]>> {
         let mut rx = self.rx.lock();
@@ -229,13 +254,13 @@ impl Rtl8139 {
             // Erase the packet for the next cycle
             unsafe { rx_desc_ptr.write_bytes(0, packet_len + 4) };
             self.update_rx_buf_read_ptr(rx.next_index.try_into().unwrap());
-            write_io_port_u16(self.io_base + 0x3E, 0x1);
+            write_io_port_u16(self.io_base + RTL8139_REG_ISR, 0x1);
             rx.next_index += (packet_len + 4 + 3) & !3; // align to 4-byte boundary
             if rx.next_index >= 8192 {
                 rx.next_index %= 8192;
                 self.update_rx_buf_read_ptr(rx.next_index.try_into().unwrap());
                 write_io_port_u16(
-                    self.io_base + 0x3E,
+                    self.io_base + RTL8139_REG_ISR,
                     0x11, /* Clear RxOverflow + RxOk (written bits will be cleared) */
                 );
             }
@@ -247,7 +272,13 @@ impl Rtl8139 {
     async fn poll(&self) -> Result<()> {
         self.poll_tx()?;
         self.poll_rx()?;
-        TimeoutFuture::new_ms(100).await;
+        match &self.interrupt {
+            Some(interrupt) => {
+                // Wake up on Rx OK / Tx OK. The timeout is a safety net for missed interrupts.
+                let _ = with_timeout_ms(interrupt.wait(), 100).await;
+            }
+            None => TimeoutFuture::new_ms(100).await,
+        }
         Ok(())
     }
 }
@@ -1,23 +1,43 @@
+extern crate alloc;
+
 use crate::boot_info::BootInfo;
+use crate::compositor::Compositor;
+use crate::efi::fs::EfiFileName;
 use crate::error;
+use crate::executor::block_on;
 use crate::executor::block_on_and_schedule;
 use crate::info;
 use crate::input::InputManager;
+use crate::loader::spawn_app_in_root_dir;
 use crate::net::dns::query_dns;
 use crate::net::dns::DnsResponseEntry;
 use crate::print;
 use crate::println;
+use crate::process::ProcessId;
 use crate::process::Scheduler;
 use crate::process::CURRENT_PROCESS;
+use crate::process::EXIT_CODE_INTERRUPTED;
+use crate::tty;
+use crate::vram::BackBuffer;
 use crate::x86_64::syscall::return_to_os;
 use crate::x86_64::syscall::write_exit_reason;
 use crate::x86_64::syscall::write_return_value;
+use alloc::vec::Vec;
+use core::ptr::read_volatile;
 use core::ptr::write_volatile;
+use core::str::FromStr;
+use noli::args::deserialize_args;
+use noli::bitmap::bitmap_blit;
 use noli::bitmap::bitmap_draw_point;
+use noli::bitmap::Bitmap;
 use noli::net::IpV4Addr;
+use noli::rect::Rect;
+use sabi::BlitRequest;
+use sabi::Event;
 use sabi::MouseEvent;
+use sabi::BLIT_TARGET_SCREEN;
 
-fn exit_to_os(retv: u64) -> ! {
+pub fn exit_to_os(retv: u64) -> ! {
     write_exit_reason(0);
     write_return_value(retv);
     return_to_os();
@@ -43,7 +63,8 @@ fn sys_noop(_args: &[u64; 5]) -> u64 {
 }
 
 fn sys_draw_point(args: &[u64; 5]) -> u64 {
-    let mut vram = BootInfo::take().vram();
+    let back_buffer = BackBuffer::take();
+    let mut vram = back_buffer.bitmap();
     let x = args[0] as i64;
     let y = args[1] as i64;
     let c = args[2] as u32;
@@ -51,11 +72,23 @@ fn sys_draw_point(args: &[u64; 5]) -> u64 {
     if result.is_err() {
         1
     } else {
+        if let Some(rect) = Rect::new(x, y, 1, 1) {
+            back_buffer.damage(rect);
+        }
         0
     }
 }
 
 fn sys_read_key(_args: &[u64; 5]) -> u64 {
+    // Only the owner of the focused window can read the keyboard input, if any.
+    let has_focus = match Compositor::take().focused_owner() {
+        Some(owner) => current_pid() == Some(owner),
+        None => true,
+    };
+    if !has_focus {
+        Scheduler::root().switch_process();
+        return 0;
+    }
     if let Some(c) = InputManager::take().pop_input() {
         c as u64
     } else {
@@ -65,7 +98,7 @@ fn sys_read_key(_args: &[u64; 5]) -> u64 {
 }
 
 fn sys_get_mouse_cursor_position(args: &[u64; 5]) -> u64 {
-    if let Some(e) = InputManager::take().pop_cursor_input_absolute() {
+    if let Some(e) = InputManager::take().pop_cursor_input_for_apps() {
         unsafe { write_volatile(args[0] as *mut MouseEvent, e) }
         0
     } else {
@@ -222,7 +255,289 @@ fn sys_tcp_read(args: &[u64; 5]) -> i64 {
     }
 }
 
+fn sys_spawn(args: &[u64; 5]) -> i64 {
+    let path = {
+        let path = args[0] as *const u8;
+        let len = args[1] as usize;
+        // TODO(hikalium): validate the buffer
+        unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(path, len)) }
+    };
+    let app_args = {
+        let app_args = args[2] as *const u8;
+        let len = args[3] as usize;
+        // TODO(hikalium): validate the buffer
+        unsafe { core::slice::from_raw_parts(app_args, len) }
+    };
+    let app_args: Vec<&str> = match deserialize_args(app_args) {
+        Ok(app_args) => app_args,
+        Err(e) => {
+            error!("sys_spawn: {e:?}");
+            return -1;
+        }
+    };
+    if CURRENT_PROCESS.lock().is_none() {
+        return -1;
+    }
+    match spawn_app_in_root_dir(path, &app_args) {
+        Ok((pid, wait)) => {
+            if let Some(proc) = CURRENT_PROCESS.lock().as_mut() {
+                proc.add_child(pid, wait);
+            }
+            pid
+        }
+        Err(e) => {
+            error!("sys_spawn: {e:?}");
+            -1
+        }
+    }
+}
+
+fn sys_wait(args: &[u64; 5]) -> i64 {
+    let pid = args[0] as i64;
+    let wait = CURRENT_PROCESS
+        .lock()
+        .as_mut()
+        .and_then(|proc| proc.take_child(pid));
+    let wait = if let Some(wait) = wait {
+        wait
+    } else {
+        return -1;
+    };
+    match block_on(wait) {
+        Ok(exit_code) => {
+            // TODO(hikalium): validate the buffer
+            unsafe { write_volatile(args[1] as *mut i64, exit_code) }
+            0
+        }
+        Err(e) => {
+            error!("sys_wait: {e:?}");
+            -1
+        }
+    }
+}
+
+fn current_pid() -> Option<ProcessId> {
+    CURRENT_PROCESS.lock().as_ref().map(|proc| proc.pid())
+}
+
+fn sys_create_window(args: &[u64; 5]) -> i64 {
+    let pid = if let Some(pid) = current_pid() {
+        pid
+    } else {
+        return -1;
+    };
+    let rect = if let Some(rect) = Rect::new(
+        args[0] as i64,
+        args[1] as i64,
+        args[2] as i64,
+        args[3] as i64,
+    ) {
+        rect
+    } else {
+        return -1;
+    };
+    match Compositor::take().create_window(pid, rect) {
+        Ok((id, addr)) => {
+            // TODO(hikalium): validate the buffer
+            unsafe { write_volatile(args[4] as *mut u64, addr as u64) }
+            id
+        }
+        Err(e) => {
+            error!("sys_create_window: {e:?}");
+            -1
+        }
+    }
+}
+
+fn sys_destroy_window(args: &[u64; 5]) -> i64 {
+    let pid = if let Some(pid) = current_pid() {
+        pid
+    } else {
+        return -1;
+    };
+    match Compositor::take().destroy_window(pid, args[0] as i64) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+fn sys_damage_window_rect(args: &[u64; 5]) -> i64 {
+    let pid = if let Some(pid) = current_pid() {
+        pid
+    } else {
+        return -1;
+    };
+    let rect = if let Some(rect) = Rect::new(
+        args[1] as i64,
+        args[2] as i64,
+        args[3] as i64,
+        args[4] as i64,
+    ) {
+        rect
+    } else {
+        return -1;
+    };
+    match Compositor::take().damage_window_rect(pid, args[0] as i64, rect) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// A bitmap in the app memory, given via sabi::BlitRequest
+struct UserBitmap {
+    buf: *mut u8,
+    width: i64,
+    height: i64,
+    pixels_per_line: i64,
+}
+impl Bitmap for UserBitmap {
+    fn bytes_per_pixel(&self) -> i64 {
+        4
+    }
+    fn pixels_per_line(&self) -> i64 {
+        self.pixels_per_line
+    }
+    fn width(&self) -> i64 {
+        self.width
+    }
+    fn height(&self) -> i64 {
+        self.height
+    }
+    fn buf(&self) -> *const u8 {
+        self.buf
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.buf
+    }
+}
+
+fn sys_next_event(args: &[u64; 5]) -> i64 {
+    let pid = if let Some(pid) = current_pid() {
+        pid
+    } else {
+        return -1;
+    };
+    match Compositor::take().next_event(pid, args[0] as i64) {
+        Ok(Some(e)) => {
+            // TODO(hikalium): validate the buffer
+            unsafe { write_volatile(args[1] as *mut Event, e) }
+            1
+        }
+        Ok(None) => 0,
+        Err(_) => -1,
+    }
+}
+
+fn sys_set_timer(args: &[u64; 5]) -> i64 {
+    let pid = if let Some(pid) = current_pid() {
+        pid
+    } else {
+        return -1;
+    };
+    match Compositor::take().set_window_timer(pid, args[0] as i64, args[1]) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+fn sys_move_window(args: &[u64; 5]) -> i64 {
+    let pid = if let Some(pid) = current_pid() {
+        pid
+    } else {
+        return -1;
+    };
+    match Compositor::take().move_window(pid, args[0] as i64, args[1] as i64, args[2] as i64) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+fn sys_read_file(args: &[u64; 5]) -> i64 {
+    let path = {
+        let path = args[0] as *const u8;
+        let len = args[1] as usize;
+        // TODO(hikalium): validate the buffer
+        unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(path, len)) }
+    };
+    let buf = {
+        let buf = args[2] as *mut u8;
+        let len = args[3] as usize;
+        // TODO(hikalium): validate the buffer
+        unsafe { core::slice::from_raw_parts_mut(buf, len) }
+    };
+    let name = match EfiFileName::from_str(path) {
+        Ok(name) => name,
+        Err(_) => return -1,
+    };
+    let file = BootInfo::take()
+        .root_files()
+        .iter()
+        .filter_map(|e| e.as_ref())
+        .find(|e| e.name() == &name);
+    match file {
+        Some(file) => {
+            let data = file.data();
+            let len = core::cmp::min(data.len(), buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            data.len() as i64
+        }
+        None => -1,
+    }
+}
+
+fn sys_blit(args: &[u64; 5]) -> i64 {
+    // TODO(hikalium): validate the buffer
+    let req = unsafe { read_volatile(args[0] as *const BlitRequest) };
+    if req.width < 0 || req.height < 0 || req.pixels_per_line < req.width {
+        return -1;
+    }
+    let src = UserBitmap {
+        buf: req.buf as *mut u8,
+        width: req.width,
+        height: req.height,
+        pixels_per_line: req.pixels_per_line,
+    };
+    let src_rect = if let Some(rect) = Rect::new(req.sx, req.sy, req.w, req.h) {
+        rect
+    } else {
+        return -1;
+    };
+    if req.window == BLIT_TARGET_SCREEN {
+        let back_buffer = BackBuffer::take();
+        if let Some(rect) = bitmap_blit(&mut back_buffer.bitmap(), &src, &src_rect, req.dx, req.dy)
+        {
+            back_buffer.damage(rect);
+        }
+        return 0;
+    }
+    let pid = if let Some(pid) = current_pid() {
+        pid
+    } else {
+        return -1;
+    };
+    match Compositor::take().blit_to_window(pid, req.window, &src, &src_rect, req.dx, req.dy) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Returns true if Ctrl-C is typed for the current app, which should be terminated
+/// with interrupt_current_app().
+pub fn is_current_app_interrupted() -> bool {
+    current_pid().is_some_and(tty::take_interrupt_request_for)
+}
+
+/// Terminates the current app and discards the pending input, on Ctrl-C.
+pub fn interrupt_current_app() -> ! {
+    InputManager::take().clear_input();
+    println!("^C");
+    exit_to_os(EXIT_CODE_INTERRUPTED as u64);
+}
+
 pub fn syscall_handler(op: u64, args: &[u64; 5]) -> u64 {
+    if is_current_app_interrupted() {
+        interrupt_current_app();
+    }
     match op {
         0 => sys_exit(args),
         1 => sys_print(args),
@@ -235,6 +550,16 @@ pub fn syscall_handler(op: u64, args: &[u64; 5]) -> u64 {
         8 => sys_tcp_connect(args) as u64,
         9 => sys_tcp_write(args) as u64,
         10 => sys_tcp_read(args) as u64,
+        11 => sys_spawn(args) as u64,
+        12 => sys_wait(args) as u64,
+        13 => sys_create_window(args) as u64,
+        14 => sys_destroy_window(args) as u64,
+        15 => sys_damage_window_rect(args) as u64,
+        16 => sys_blit(args) as u64,
+        17 => sys_next_event(args) as u64,
+        18 => sys_move_window(args) as u64,
+        19 => sys_set_timer(args) as u64,
+        20 => sys_read_file(args) as u64,
         op => {
             println!("syscall: unimplemented syscall: {}", op);
             // Return u64::MAX here as it may be the "most unexpected value" that can crash the
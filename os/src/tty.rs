@@ -0,0 +1,317 @@
+//! Line discipline of the console
+//!
+//! The console can be used from the screen and the keyboard, or from a
+//! terminal emulator connected to the serial port (COM2). LineDiscipline
+//! edits the line with the input characters and tells what to echo back.
+//! Echoes only use '\x08' (BS) to move the cursor to the left, since it
+//! works on both the TextArea on the screen and the serial terminals.
+//! c.f. https://vt100.net/emu/dec_ansi_parser
+//! c.f. https://invisible-island.net/xterm/ctlseqs/ctlseqs.html
+
+extern crate alloc;
+
+use crate::process::ProcessId;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::Ordering;
+
+/// End of Text (Ctrl-C): interrupts the running app or discards the line
+pub const INTR: char = '\x03';
+/// Negative Acknowledge (Ctrl-U): erases the line
+pub const KILL: char = '\x15';
+const BS: char = '\x08';
+const DEL: char = '\x7f';
+const ESC: char = '\x1b';
+
+static INTERRUPT_REQUESTED: AtomicBool = AtomicBool::new(false);
+const NO_FOREGROUND_APP: ProcessId = -1;
+static FOREGROUND_APP: AtomicI64 = AtomicI64::new(NO_FOREGROUND_APP);
+
+/// Sets the app launched from the console, which is terminated by Ctrl-C.
+pub fn set_foreground_app(pid: Option<ProcessId>) {
+    FOREGROUND_APP.store(pid.unwrap_or(NO_FOREGROUND_APP), Ordering::SeqCst);
+}
+/// Requests to terminate the foreground app
+/// (see syscall::syscall_handler() and idt::inthandler()).
+pub fn request_interrupt() {
+    INTERRUPT_REQUESTED.store(true, Ordering::SeqCst);
+}
+/// Returns true if an interrupt was requested, and clears the request.
+pub fn take_interrupt_request() -> bool {
+    INTERRUPT_REQUESTED.swap(false, Ordering::SeqCst)
+}
+/// Returns true if an interrupt was requested and `pid` is the foreground app,
+/// and clears the request.
+pub fn take_interrupt_request_for(pid: ProcessId) -> bool {
+    pid == FOREGROUND_APP.load(Ordering::SeqCst) && take_interrupt_request()
+}
+
+/// Translates the bytes from a serial terminal into the console input.
+/// CR is delivered as LF (like ICRNL of termios), and CR LF as a single LF.
+#[derive(Default)]
+pub struct SerialInput {
+    last_was_cr: bool,
+}
+impl SerialInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn translate(&mut self, byte: u8) -> Option<char> {
+        let c = char::from_u32(byte as u32)?;
+        let last_was_cr = core::mem::replace(&mut self.last_was_cr, c == '\r');
+        match c {
+            '\r' => Some('\n'),
+            '\n' if last_was_cr => None,
+            c => Some(c),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditKey {
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+enum EscapeState {
+    #[default]
+    Ground,
+    /// After ESC
+    Escape,
+    /// In a Control Sequence (ESC [ params intermediates final)
+    Csi(String),
+    /// After ESC O (Single Shift 3, sent by some terminals for the cursor keys)
+    Ss3,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LineEvent {
+    /// A line is entered (without the line terminator)
+    Line(String),
+    /// The line is discarded by Ctrl-C
+    Interrupt,
+}
+
+#[derive(Default)]
+pub struct LineDiscipline {
+    line: Vec<char>,
+    // Index in the line where the next char will be inserted
+    cursor: usize,
+    escape: EscapeState,
+}
+impl LineDiscipline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Processes an input char. Chars to be printed back are appended to echo.
+    pub fn input(&mut self, c: char, echo: &mut String) -> Option<LineEvent> {
+        match core::mem::take(&mut self.escape) {
+            EscapeState::Ground => {}
+            EscapeState::Escape => {
+                match c {
+                    '[' => self.escape = EscapeState::Csi(String::new()),
+                    'O' => self.escape = EscapeState::Ss3,
+                    // Other escape sequences (e.g. Alt + key) are ignored
+                    _ => {}
+                }
+                return None;
+            }
+            EscapeState::Csi(mut params) => {
+                match c {
+                    // Parameter and intermediate bytes
+                    '\x20'..='\x3f' => {
+                        params.push(c);
+                        self.escape = EscapeState::Csi(params);
+                    }
+                    // Final byte
+                    '\x40'..='\x7e' => {
+                        if let Some(key) = Self::decode_csi(&params, c) {
+                            self.edit(key, echo);
+                        }
+                    }
+                    // Invalid sequence: abort it
+                    _ => {}
+                }
+                return None;
+            }
+            EscapeState::Ss3 => {
+                if let Some(key) = Self::decode_csi("", c) {
+                    self.edit(key, echo);
+                }
+                return None;
+            }
+        }
+        match c {
+            '\n' | '\r' => {
+                echo.extend(&self.line[self.cursor..]);
+                echo.push('\n');
+                let line = self.line.drain(..).collect();
+                self.cursor = 0;
+                Some(LineEvent::Line(line))
+            }
+            INTR => {
+                echo.push_str("^C\n");
+                self.line.clear();
+                self.cursor = 0;
+                Some(LineEvent::Interrupt)
+            }
+            KILL => {
+                self.move_cursor_to(0, echo);
+                self.erase_to_end(echo);
+                None
+            }
+            BS | DEL => {
+                if self.cursor > 0 {
+                    self.move_cursor_to(self.cursor - 1, echo);
+                    self.line.remove(self.cursor);
+                    self.redraw_to_end(1, echo);
+                }
+                None
+            }
+            ESC => {
+                self.escape = EscapeState::Escape;
+                None
+            }
+            c if c.is_control() => None,
+            c => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+                echo.push(c);
+                self.redraw_to_end(0, echo);
+                None
+            }
+        }
+    }
+    fn decode_csi(params: &str, final_byte: char) -> Option<EditKey> {
+        match (params, final_byte) {
+            ("", 'C') => Some(EditKey::Right),
+            ("", 'D') => Some(EditKey::Left),
+            ("", 'H') | ("1", '~') | ("7", '~') => Some(EditKey::Home),
+            ("", 'F') | ("4", '~') | ("8", '~') => Some(EditKey::End),
+            ("3", '~') => Some(EditKey::Delete),
+            // Others (e.g. Up and Down) are not supported
+            _ => None,
+        }
+    }
+    fn edit(&mut self, key: EditKey, echo: &mut String) {
+        match key {
+            EditKey::Left => self.move_cursor_to(self.cursor.saturating_sub(1), echo),
+            EditKey::Right => self.move_cursor_to(self.line.len().min(self.cursor + 1), echo),
+            EditKey::Home => self.move_cursor_to(0, echo),
+            EditKey::End => self.move_cursor_to(self.line.len(), echo),
+            EditKey::Delete => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                    self.redraw_to_end(1, echo);
+                }
+            }
+        }
+    }
+    fn move_cursor_to(&mut self, index: usize, echo: &mut String) {
+        if index < self.cursor {
+            echo.extend(core::iter::repeat(BS).take(self.cursor - index));
+        } else {
+            echo.extend(&self.line[self.cursor..index]);
+        }
+        self.cursor = index;
+    }
+    /// Prints the chars after the cursor, clears `erased` cells after them
+    /// and moves the cursor back.
+    fn redraw_to_end(&mut self, erased: usize, echo: &mut String) {
+        let rest = &self.line[self.cursor..];
+        echo.extend(rest);
+        echo.extend(core::iter::repeat(' ').take(erased));
+        echo.extend(core::iter::repeat(BS).take(rest.len() + erased));
+    }
+    fn erase_to_end(&mut self, echo: &mut String) {
+        let erased = self.line.len() - self.cursor;
+        self.line.truncate(self.cursor);
+        self.redraw_to_end(erased, echo);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn input_str(ld: &mut LineDiscipline, s: &str) -> (String, Option<LineEvent>) {
+        let mut echo = String::new();
+        let mut event = None;
+        for c in s.chars() {
+            if let Some(e) = ld.input(c, &mut echo) {
+                event = Some(e);
+            }
+        }
+        (echo, event)
+    }
+
+    #[test_case]
+    fn line_is_echoed_and_entered() {
+        let mut ld = LineDiscipline::new();
+        let (echo, event) = input_str(&mut ld, "ls\n");
+        assert_eq!(echo, "ls\n");
+        assert_eq!(event, Some(LineEvent::Line("ls".into())));
+        let (_, event) = input_str(&mut ld, "\n");
+        assert_eq!(event, Some(LineEvent::Line("".into())));
+    }
+    #[test_case]
+    fn backspace_erases_the_last_char() {
+        let mut ld = LineDiscipline::new();
+        let (echo, event) = input_str(&mut ld, "ab\x7f\x7f\x7fc\x08d\n");
+        assert_eq!(echo, "ab\x08 \x08\x08 \x08c\x08 \x08d\n");
+        assert_eq!(event, Some(LineEvent::Line("d".into())));
+    }
+    #[test_case]
+    fn ctrl_c_discards_the_line() {
+        let mut ld = LineDiscipline::new();
+        let (echo, event) = input_str(&mut ld, "abc\x03");
+        assert_eq!(echo, "abc^C\n");
+        assert_eq!(event, Some(LineEvent::Interrupt));
+        let (_, event) = input_str(&mut ld, "d\n");
+        assert_eq!(event, Some(LineEvent::Line("d".into())));
+    }
+    #[test_case]
+    fn ctrl_u_erases_the_line() {
+        let mut ld = LineDiscipline::new();
+        let (echo, _) = input_str(&mut ld, "ab\x1b[D\x15");
+        assert_eq!(echo, "ab\x08\x08  \x08\x08");
+        let (_, event) = input_str(&mut ld, "c\n");
+        assert_eq!(event, Some(LineEvent::Line("c".into())));
+    }
+    #[test_case]
+    fn cursor_keys_edit_the_line() {
+        let mut ld = LineDiscipline::new();
+        // Left, insert, Home, Delete, End
+        let (echo, event) = input_str(&mut ld, "acd\x1b[D\x1b[Db\x1b[H\x1b[3~\x1bOFe\n");
+        assert_eq!(
+            echo,
+            "acd\x08\x08bcd\x08\x08\x08\x08bcd \x08\x08\x08\x08bcde\n"
+        );
+        assert_eq!(event, Some(LineEvent::Line("bcde".into())));
+        // Enter in the middle of the line takes the whole line
+        let (_, event) = input_str(&mut ld, "xy\x1b[D\r");
+        assert_eq!(event, Some(LineEvent::Line("xy".into())));
+    }
+    #[test_case]
+    fn unsupported_escape_sequences_are_ignored() {
+        let mut ld = LineDiscipline::new();
+        let (echo, event) = input_str(&mut ld, "a\x1b[A\x1b[1;5C\x1bxb\x1b[\x01c\n");
+        assert_eq!(echo, "abc\n");
+        assert_eq!(event, Some(LineEvent::Line("abc".into())));
+    }
+    #[test_case]
+    fn serial_input_translates_cr() {
+        let mut serial = SerialInput::new();
+        let s: String = b"a\rb\r\nc\n\n"
+            .iter()
+            .filter_map(|b| serial.translate(*b))
+            .collect();
+        assert_eq!(s, "a\nb\nc\n\n");
+    }
+}
@@ -1 +1,3 @@
 pub mod descriptor;
+pub mod hid_input;
+pub mod hid_report;
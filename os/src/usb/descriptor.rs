@@ -1,4 +1,7 @@
+extern crate alloc;
+
 use crate::util::IntoPinnedMutableSlice;
+use alloc::vec::Vec;
 use core::marker::PhantomPinned;
 use core::mem::size_of;
 use noli::mem::Sliceable;
@@ -14,6 +17,9 @@ pub enum DescriptorType {
     String = 3,
     Interface = 4,
     Endpoint = 5,
+    Hid = 0x21,
+    HidReport = 0x22,
+    SuperSpeedEndpointCompanion = 48,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -23,6 +29,8 @@ pub enum UsbDescriptor {
     String,
     Interface(InterfaceDescriptor),
     Endpoint(EndpointDescriptor),
+    Hid(HidDescriptor),
+    SuperSpeedEndpointCompanion(SuperSpeedEndpointCompanionDescriptor),
     Unknown { desc_len: u8, desc_type: u8 },
 }
 
@@ -54,6 +62,14 @@ impl<'a> Iterator for DescriptorIterator<'a> {
                 e if e == DescriptorType::Endpoint as u8 => {
                     UsbDescriptor::Endpoint(EndpointDescriptor::copy_from_slice(buf).ok()?)
                 }
+                e if e == DescriptorType::Hid as u8 => {
+                    UsbDescriptor::Hid(HidDescriptor::copy_from_slice(buf).ok()?)
+                }
+                e if e == DescriptorType::SuperSpeedEndpointCompanion as u8 => {
+                    UsbDescriptor::SuperSpeedEndpointCompanion(
+                        SuperSpeedEndpointCompanionDescriptor::copy_from_slice(buf).ok()?,
+                    )
+                }
                 _ => UsbDescriptor::Unknown {
                     desc_len,
                     desc_type,
@@ -109,6 +125,19 @@ impl ConfigDescriptor {
     pub fn config_value(&self) -> u8 {
         self.config_value
     }
+    pub fn num_of_interfaces(&self) -> u8 {
+        self.num_of_interfaces
+    }
+    pub fn string_index(&self) -> u8 {
+        self.config_string_index
+    }
+    pub fn attribute(&self) -> u8 {
+        self.attribute
+    }
+    /// Maximum power consumption in 2mA units (for SuperSpeed devices, 8mA units)
+    pub fn max_power(&self) -> u8 {
+        self.max_power
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -140,6 +169,12 @@ impl InterfaceDescriptor {
     pub fn alt_setting(&self) -> u8 {
         self.alt_setting
     }
+    pub fn num_of_endpoints(&self) -> u8 {
+        self.num_of_endpoints
+    }
+    pub fn string_index(&self) -> u8 {
+        self.interface_index
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -172,12 +207,73 @@ impl EndpointDescriptor {
 }
 const _: () = assert!(size_of::<EndpointDescriptor>() == 7);
 
+// [usb3] 9.6.7 SuperSpeed Endpoint Companion
+// Follows each Endpoint Descriptor of SuperSpeed devices.
+#[derive(Debug, Copy, Clone, Default)]
+#[allow(unused)]
+#[repr(packed)]
+pub struct SuperSpeedEndpointCompanionDescriptor {
+    pub desc_length: u8,
+    pub desc_type: u8,
+    // Number of additional packets in a burst (0-15)
+    pub max_burst: u8,
+    // attributes:
+    //   - bit[0..=4]: MaxStreams (for Bulk)
+    //   - bit[0..=1]: Mult (for Isochronous)
+    pub attributes: u8,
+    // Total bytes transferred every service interval (for periodic endpoints)
+    pub bytes_per_interval: u16,
+}
+const _: () = assert!(size_of::<SuperSpeedEndpointCompanionDescriptor>() == 6);
+
+// [hid_1_11] 6.2.1 HID Descriptor
+// Follows the Interface Descriptor of HID class interfaces.
+// Only the first class descriptor (which is the Report Descriptor) is held here.
+#[derive(Debug, Copy, Clone, Default)]
+#[allow(unused)]
+#[repr(packed)]
+pub struct HidDescriptor {
+    pub desc_length: u8,
+    pub desc_type: u8,
+    pub hid_version: u16,
+    pub country_code: u8,
+    pub num_descriptors: u8,
+    pub report_desc_type: u8,
+    pub report_desc_length: u16,
+}
+const _: () = assert!(size_of::<HidDescriptor>() == 9);
+
+/// Returns Endpoint Descriptors in the list, each paired with the SuperSpeed
+/// Endpoint Companion Descriptor that immediately follows it (if any).
+pub fn endpoints_with_companion(
+    descriptors: &[UsbDescriptor],
+) -> Vec<(
+    EndpointDescriptor,
+    Option<SuperSpeedEndpointCompanionDescriptor>,
+)> {
+    let mut list = Vec::new();
+    for (i, d) in descriptors.iter().enumerate() {
+        if let UsbDescriptor::Endpoint(e) = d {
+            let companion = match descriptors.get(i + 1) {
+                Some(UsbDescriptor::SuperSpeedEndpointCompanion(c)) => Some(*c),
+                _ => None,
+            };
+            list.push((*e, companion));
+        }
+    }
+    list
+}
+
 unsafe impl IntoPinnedMutableSlice for DeviceDescriptor {}
 unsafe impl IntoPinnedMutableSlice for ConfigDescriptor {}
 unsafe impl IntoPinnedMutableSlice for InterfaceDescriptor {}
 unsafe impl IntoPinnedMutableSlice for EndpointDescriptor {}
+unsafe impl IntoPinnedMutableSlice for HidDescriptor {}
+unsafe impl IntoPinnedMutableSlice for SuperSpeedEndpointCompanionDescriptor {}
 
 unsafe impl Sliceable for DeviceDescriptor {}
 unsafe impl Sliceable for ConfigDescriptor {}
 unsafe impl Sliceable for InterfaceDescriptor {}
 unsafe impl Sliceable for EndpointDescriptor {}
+unsafe impl Sliceable for HidDescriptor {}
+unsafe impl Sliceable for SuperSpeedEndpointCompanionDescriptor {}
@@ -0,0 +1,399 @@
+//! Decoder of HID Input reports into InputEvents
+//!
+//! The fields of the reports are interpreted by their usages, as described
+//! in the report descriptor, so that keyboards, mice, tablets and gamepads
+//! share the same path to InputManager.
+
+extern crate alloc;
+
+use crate::input::GamepadState;
+use crate::input::InputEvent;
+use crate::input::KeyEvent;
+use crate::input::GAMEPAD_AXIS_MAX;
+use crate::keyboard::KeyboardState;
+use crate::keyboard::USAGE_LEFT_CTRL;
+use crate::keyboard::USAGE_RIGHT_GUI;
+use crate::usb::hid_report::ReportDescriptor;
+use crate::usb::hid_report::ReportField;
+use crate::usb::hid_report::USAGE_GAMEPAD;
+use crate::usb::hid_report::USAGE_HAT_SWITCH;
+use crate::usb::hid_report::USAGE_JOYSTICK;
+use crate::usb::hid_report::USAGE_KEYBOARD;
+use crate::usb::hid_report::USAGE_KEYPAD;
+use crate::usb::hid_report::USAGE_LED_NUM_LOCK;
+use crate::usb::hid_report::USAGE_LED_SCROLL_LOCK;
+use crate::usb::hid_report::USAGE_MOUSE;
+use crate::usb::hid_report::USAGE_PAGE_BUTTON;
+use crate::usb::hid_report::USAGE_PAGE_GENERIC_DESKTOP;
+use crate::usb::hid_report::USAGE_PAGE_KEYBOARD;
+use crate::usb::hid_report::USAGE_PAGE_LED;
+use crate::usb::hid_report::USAGE_POINTER;
+use crate::usb::hid_report::USAGE_RZ;
+use crate::usb::hid_report::USAGE_WHEEL;
+use crate::usb::hid_report::USAGE_X;
+use crate::usb::hid_report::USAGE_Y;
+use alloc::vec;
+use alloc::vec::Vec;
+use sabi::MouseButtonState;
+
+/// What a HID device is, told by its Application Collections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HidDeviceKind {
+    Keyboard,
+    /// A pointing device with relative axes
+    Mouse,
+    /// A pointing device with absolute axes
+    Tablet,
+    Gamepad,
+}
+impl HidDeviceKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            HidDeviceKind::Keyboard => "keyboard",
+            HidDeviceKind::Mouse => "mouse",
+            HidDeviceKind::Tablet => "tablet",
+            HidDeviceKind::Gamepad => "gamepad",
+        }
+    }
+}
+
+fn is_gamepad(application: (u16, u16)) -> bool {
+    matches!(
+        application,
+        (USAGE_PAGE_GENERIC_DESKTOP, USAGE_JOYSTICK | USAGE_GAMEPAD)
+    )
+}
+
+// Scales the value of an absolute axis into [0.0, 1.0]
+fn normalize(field: &ReportField, value: i64) -> f64 {
+    let range = field.logical_max as i64 - field.logical_min as i64 + 1;
+    if range <= 0 {
+        return 0.0;
+    }
+    (value - field.logical_min as i64) as f64 / range as f64
+}
+
+// Scales the value of an absolute axis of gamepads into [-GAMEPAD_AXIS_MAX, GAMEPAD_AXIS_MAX]
+fn scale_axis(field: &ReportField, value: i64) -> i32 {
+    let (min, max) = (field.logical_min as i64, field.logical_max as i64);
+    if max <= min {
+        return 0;
+    }
+    let value = value.clamp(min, max);
+    let max_out = GAMEPAD_AXIS_MAX as i64;
+    ((value - min) * 2 * max_out / (max - min) - max_out) as i32
+}
+
+/// Decodes Input reports of a HID device into InputEvents,
+/// keeping the state of the device across the reports (e.g. keys held).
+#[derive(Debug)]
+pub struct HidInputDecoder {
+    desc: ReportDescriptor,
+    keyboard: KeyboardState,
+}
+impl HidInputDecoder {
+    pub fn new(desc: ReportDescriptor) -> Self {
+        Self {
+            desc,
+            keyboard: KeyboardState::new(),
+        }
+    }
+    pub fn descriptor(&self) -> &ReportDescriptor {
+        &self.desc
+    }
+    /// Returns the kinds of the device, e.g. [Keyboard, Mouse] for a keyboard with a touchpad.
+    pub fn kinds(&self) -> Vec<HidDeviceKind> {
+        let mut kinds = Vec::new();
+        for &application in self.desc.applications() {
+            let kind = match application {
+                (USAGE_PAGE_GENERIC_DESKTOP, USAGE_KEYBOARD | USAGE_KEYPAD) => {
+                    HidDeviceKind::Keyboard
+                }
+                (USAGE_PAGE_GENERIC_DESKTOP, USAGE_MOUSE | USAGE_POINTER) => {
+                    let x = self
+                        .desc
+                        .find_input_field(USAGE_PAGE_GENERIC_DESKTOP, USAGE_X);
+                    match x {
+                        Some(x) if !x.relative => HidDeviceKind::Tablet,
+                        _ => HidDeviceKind::Mouse,
+                    }
+                }
+                application if is_gamepad(application) => HidDeviceKind::Gamepad,
+                _ => continue,
+            };
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        kinds
+    }
+    /// Decodes the Input report (including the Report ID byte if used) into InputEvents.
+    pub fn decode(&mut self, report: &[u8], now_ms: u64) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let (report_id, report) = match self.desc.split_report_id(report) {
+            Some(r) => r,
+            None => return events,
+        };
+        let mut has_keys = false;
+        let mut modifiers = 0u8;
+        let mut keys: Vec<u8> = Vec::new();
+        // Buttons and axes of pointing devices and gamepads
+        let mut pointer_buttons = 0u32;
+        let mut pointer_xy: [Option<(&ReportField, i64)>; 2] = [None, None];
+        let mut wheel = 0;
+        let mut gamepad: Option<GamepadState> = None;
+        for field in self.desc.input_fields(report_id) {
+            let value = match field.extract(report) {
+                Some(value) => value,
+                None => continue,
+            };
+            // The usage that is active, if any
+            let usage = if field.array {
+                match field.array_usage(value) {
+                    Some(usage) => Some(usage),
+                    None => continue,
+                }
+            } else if value != 0 {
+                Some(field.usage)
+            } else {
+                None
+            };
+            if field.usage_page == USAGE_PAGE_KEYBOARD {
+                has_keys = true;
+                match usage.map(|u| u as u8) {
+                    Some(u @ USAGE_LEFT_CTRL..=USAGE_RIGHT_GUI) => {
+                        modifiers |= 1 << (u - USAGE_LEFT_CTRL)
+                    }
+                    Some(u) if u != 0 => keys.push(u),
+                    _ => {}
+                }
+                continue;
+            }
+            if is_gamepad(field.application) {
+                let gamepad = gamepad.get_or_insert_with(GamepadState::default);
+                match (field.usage_page, usage, field.usage) {
+                    (USAGE_PAGE_BUTTON, Some(n @ 1..=32), _) => gamepad.buttons |= 1 << (n - 1),
+                    (USAGE_PAGE_GENERIC_DESKTOP, _, u @ USAGE_X..=USAGE_RZ) if !field.array => {
+                        gamepad.axes[(u - USAGE_X) as usize] = scale_axis(field, value)
+                    }
+                    (USAGE_PAGE_GENERIC_DESKTOP, _, USAGE_HAT_SWITCH)
+                        if !field.array && field.is_in_range(value) =>
+                    {
+                        gamepad.hat = Some((value - field.logical_min as i64) as u8)
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match (field.usage_page, usage, field.usage) {
+                (USAGE_PAGE_BUTTON, Some(n @ 1..=32), _) => pointer_buttons |= 1 << (n - 1),
+                (USAGE_PAGE_GENERIC_DESKTOP, _, USAGE_X) if !field.array => {
+                    pointer_xy[0] = Some((field, value))
+                }
+                (USAGE_PAGE_GENERIC_DESKTOP, _, USAGE_Y) if !field.array => {
+                    pointer_xy[1] = Some((field, value))
+                }
+                (USAGE_PAGE_GENERIC_DESKTOP, _, USAGE_WHEEL) if !field.array => wheel = value,
+                _ => {}
+            }
+        }
+        if has_keys {
+            events.extend(
+                self.keyboard
+                    .process_keys(modifiers, &keys, now_ms)
+                    .into_iter()
+                    .map(InputEvent::Key),
+            );
+        }
+        if let [Some((x_field, x)), Some((y_field, y))] = pointer_xy {
+            let pressed = |n: u32| pointer_buttons & (1 << (n - 1)) != 0;
+            let button = MouseButtonState::from_lcr(pressed(1), pressed(2), pressed(3));
+            events.push(if x_field.relative {
+                InputEvent::PointerRelative {
+                    button,
+                    dx: x,
+                    dy: y,
+                    wheel,
+                }
+            } else {
+                InputEvent::PointerAbsolute {
+                    button,
+                    x: normalize(x_field, x),
+                    y: normalize(y_field, y),
+                    wheel,
+                }
+            });
+        }
+        if let Some(gamepad) = gamepad {
+            events.push(InputEvent::Gamepad(gamepad));
+        }
+        events
+    }
+    /// Returns a repeated key press event if it's time to repeat the held key.
+    pub fn poll_repeat(&mut self, now_ms: u64) -> Option<KeyEvent> {
+        self.keyboard.poll_repeat(now_ms)
+    }
+    /// Returns the Report ID and the Output report (including the Report ID byte if used)
+    /// to update the LEDs of the keyboard, if the lock keys have been changed.
+    pub fn take_led_report(&mut self) -> Option<(u8, Vec<u8>)> {
+        let leds = self.keyboard.take_led_update()?;
+        // LED usages 1 to 3 (Num Lock, Caps Lock and Scroll Lock) are the bits of leds.
+        let fields: Vec<&ReportField> = self
+            .desc
+            .output_fields()
+            .iter()
+            .filter(|f| {
+                f.usage_page == USAGE_PAGE_LED
+                    && !f.array
+                    && (USAGE_LED_NUM_LOCK..=USAGE_LED_SCROLL_LOCK).contains(&f.usage)
+            })
+            .collect();
+        let report_id = fields.first()?.report_id;
+        let mut report = vec![0u8; self.desc.output_report_len(report_id)];
+        for f in fields.iter().filter(|f| f.report_id == report_id) {
+            let on = leds & (1 << (f.usage - USAGE_LED_NUM_LOCK)) != 0;
+            f.insert(&mut report, on as i64).ok()?;
+        }
+        if self.desc.uses_report_id() {
+            report.insert(0, report_id);
+        }
+        Some((report_id, report))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::Key;
+    use crate::usb::hid_report::BOOT_KEYBOARD_REPORT_DESCRIPTOR;
+    use crate::usb::hid_report::BOOT_MOUSE_REPORT_DESCRIPTOR;
+
+    fn decoder(desc: &[u8]) -> HidInputDecoder {
+        HidInputDecoder::new(ReportDescriptor::parse(desc).expect("should be parsed"))
+    }
+
+    #[test_case]
+    fn keyboard_reports_are_decoded() {
+        let mut kbd = decoder(&BOOT_KEYBOARD_REPORT_DESCRIPTOR);
+        assert_eq!(kbd.kinds(), [HidDeviceKind::Keyboard]);
+        // Shift + 'a'
+        let events = kbd.decode(&[0x02, 0, 0x04, 0, 0, 0, 0, 0], 0);
+        let keys: Vec<Key> = events
+            .iter()
+            .filter_map(|e| match e {
+                InputEvent::Key(e) if e.pressed => Some(e.key),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keys, [Key::Modifier, Key::Char('A')]);
+        // The LEDs are initialized (Num Lock on), then updated by Caps Lock
+        assert_eq!(kbd.take_led_report(), Some((0, vec![0b001])));
+        assert_eq!(kbd.take_led_report(), None);
+        kbd.decode(&[0, 0, 0x39, 0, 0, 0, 0, 0], 0);
+        assert_eq!(kbd.take_led_report(), Some((0, vec![0b011])));
+    }
+    #[test_case]
+    fn mouse_reports_are_decoded() {
+        let mut mouse = decoder(&BOOT_MOUSE_REPORT_DESCRIPTOR);
+        assert_eq!(mouse.kinds(), [HidDeviceKind::Mouse]);
+        let events = mouse.decode(&[0b110, 0x03, 0xFE], 0);
+        let InputEvent::PointerRelative {
+            button,
+            dx,
+            dy,
+            wheel,
+        } = events[0]
+        else {
+            panic!("should be a relative pointer event: {events:?}");
+        };
+        assert!(!button.l() && button.r() && button.c());
+        assert_eq!((dx, dy, wheel), (3, -2, 0));
+        assert_eq!(mouse.take_led_report(), None);
+    }
+    #[test_case]
+    fn tablet_reports_with_report_id_are_decoded() {
+        let mut tablet = decoder(&[
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x02, // Usage (Mouse)
+            0xa1, 0x01, // Collection (Application)
+            0x85, 0x03, // Report ID (3)
+            0x05, 0x09, // Usage Page (Button)
+            0x09, 0x01, // Usage (1)
+            0x15, 0x00, // Logical Minimum (0)
+            0x25, 0x01, // Logical Maximum (1)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x30, // Usage (X)
+            0x09, 0x31, // Usage (Y)
+            0x26, 0xff, 0x7f, // Logical Maximum (32767)
+            0x75, 0x10, // Report Size (16)
+            0x95, 0x02, // Report Count (2)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0xc0, // End Collection
+        ]);
+        assert_eq!(tablet.kinds(), [HidDeviceKind::Tablet]);
+        let events = tablet.decode(&[3, 1, 0x00, 0x40, 0x00, 0x20], 0);
+        assert_eq!(
+            events,
+            [InputEvent::PointerAbsolute {
+                button: MouseButtonState::from_lcr(true, false, false),
+                x: 0.5,
+                y: 0.25,
+                wheel: 0,
+            }]
+        );
+        // Reports with other IDs are ignored
+        assert!(tablet.decode(&[2, 1, 0, 0, 0, 0], 0).is_empty());
+    }
+    #[test_case]
+    fn gamepad_reports_are_decoded() {
+        let mut gamepad = decoder(&[
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x05, // Usage (Gamepad)
+            0xa1, 0x01, // Collection (Application)
+            0x05, 0x09, // Usage Page (Button)
+            0x19, 0x01, // Usage Minimum (1)
+            0x29, 0x08, // Usage Maximum (8)
+            0x15, 0x00, // Logical Minimum (0)
+            0x25, 0x01, // Logical Maximum (1)
+            0x75, 0x01, // Report Size (1)
+            0x95, 0x08, // Report Count (8)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x30, // Usage (X)
+            0x09, 0x31, // Usage (Y)
+            0x15, 0x81, // Logical Minimum (-127)
+            0x25, 0x7f, // Logical Maximum (127)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x02, // Report Count (2)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0x09, 0x39, // Usage (Hat switch)
+            0x15, 0x00, // Logical Minimum (0)
+            0x25, 0x07, // Logical Maximum (7)
+            0x75, 0x04, // Report Size (4)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x42, // Input (Data, Variable, Absolute, Null State)
+            0x75, 0x04, // Report Size (4)
+            0x81, 0x01, // Input (Constant)
+            0xc0, // End Collection
+        ]);
+        assert_eq!(gamepad.kinds(), [HidDeviceKind::Gamepad]);
+        let events = gamepad.decode(&[0b1000_0001, 0x7f, 0x81, 0x02], 0);
+        let expected = GamepadState {
+            buttons: 0b1000_0001,
+            axes: [GAMEPAD_AXIS_MAX, -GAMEPAD_AXIS_MAX, 0, 0, 0, 0],
+            hat: Some(2),
+        };
+        assert_eq!(events, [InputEvent::Gamepad(expected)]);
+        // The hat switch is released (null state)
+        let events = gamepad.decode(&[0, 0, 0, 0x0f], 0);
+        let expected = GamepadState {
+            buttons: 0,
+            axes: [0, 0, 0, 0, 0, 0],
+            hat: None,
+        };
+        assert_eq!(events, [InputEvent::Gamepad(expected)]);
+    }
+}
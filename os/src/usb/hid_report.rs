@@ -0,0 +1,507 @@
+//! Parser of HID Report Descriptors
+//!
+//! [hid_1_11] 6.2.2 Report Descriptor
+//! The fields of Input and Output reports are located with their usages,
+//! so that the reports of any HID device can be decoded without knowing
+//! the layout in advance (see usb::hid_input).
+
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+// [hut_1_12] 3 Usage Pages
+pub const USAGE_PAGE_GENERIC_DESKTOP: u16 = 0x01;
+pub const USAGE_PAGE_KEYBOARD: u16 = 0x07;
+pub const USAGE_PAGE_LED: u16 = 0x08;
+pub const USAGE_PAGE_BUTTON: u16 = 0x09;
+// [hut_1_12] 4 Generic Desktop Page
+pub const USAGE_POINTER: u16 = 0x01;
+pub const USAGE_MOUSE: u16 = 0x02;
+pub const USAGE_JOYSTICK: u16 = 0x04;
+pub const USAGE_GAMEPAD: u16 = 0x05;
+pub const USAGE_KEYBOARD: u16 = 0x06;
+pub const USAGE_KEYPAD: u16 = 0x07;
+pub const USAGE_X: u16 = 0x30;
+pub const USAGE_Y: u16 = 0x31;
+pub const USAGE_RZ: u16 = 0x35;
+pub const USAGE_WHEEL: u16 = 0x38;
+pub const USAGE_HAT_SWITCH: u16 = 0x39;
+// [hut_1_12] 11 LED Page
+pub const USAGE_LED_NUM_LOCK: u16 = 0x01;
+pub const USAGE_LED_SCROLL_LOCK: u16 = 0x03;
+
+/// [hid_1_11] E.6 Report Descriptor (Keyboard), for the Boot Protocol
+pub const BOOT_KEYBOARD_REPORT_DESCRIPTOR: [u8; 63] = [
+    0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15, 0x00, 0x25, 0x01,
+    0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x01, 0x95, 0x05, 0x75, 0x01,
+    0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02, 0x95, 0x01, 0x75, 0x03, 0x91, 0x01, 0x95, 0x06,
+    0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65, 0x81, 0x00, 0xc0,
+];
+/// [hid_1_11] E.10 Report Descriptor (Mouse), for the Boot Protocol
+pub const BOOT_MOUSE_REPORT_DESCRIPTOR: [u8; 50] = [
+    0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29, 0x03,
+    0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05, 0x81, 0x01,
+    0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f, 0x75, 0x08, 0x95, 0x02, 0x81, 0x06,
+    0xc0, 0xc0,
+];
+
+// 6.2.2.2 Short Items: bType
+const ITEM_TYPE_MAIN: u8 = 0;
+const ITEM_TYPE_GLOBAL: u8 = 1;
+const ITEM_TYPE_LOCAL: u8 = 2;
+const ITEM_LONG: u8 = 0xFE;
+// 6.2.2.4 Main Items
+const MAIN_INPUT: u8 = 0x8;
+const MAIN_OUTPUT: u8 = 0x9;
+const MAIN_COLLECTION: u8 = 0xA;
+const MAIN_END_COLLECTION: u8 = 0xC;
+// 6.2.2.6 Collection, End Collection Items
+const COLLECTION_APPLICATION: u32 = 0x01;
+// 6.2.2.7 Global Items
+const GLOBAL_USAGE_PAGE: u8 = 0x0;
+const GLOBAL_LOGICAL_MINIMUM: u8 = 0x1;
+const GLOBAL_LOGICAL_MAXIMUM: u8 = 0x2;
+const GLOBAL_REPORT_SIZE: u8 = 0x7;
+const GLOBAL_REPORT_ID: u8 = 0x8;
+const GLOBAL_REPORT_COUNT: u8 = 0x9;
+const GLOBAL_PUSH: u8 = 0xA;
+const GLOBAL_POP: u8 = 0xB;
+// 6.2.2.8 Local Items
+const LOCAL_USAGE: u8 = 0x0;
+const LOCAL_USAGE_MINIMUM: u8 = 0x1;
+const LOCAL_USAGE_MAXIMUM: u8 = 0x2;
+// 6.2.2.5 Input, Output, and Feature Items
+const MAIN_FLAG_CONSTANT: u32 = 1 << 0;
+const MAIN_FLAG_VARIABLE: u32 = 1 << 1;
+const MAIN_FLAG_RELATIVE: u32 = 1 << 2;
+
+/// A field (a value for a usage, or an index of usages) in an Input or Output report
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReportField {
+    /// 0 if the device does not use Report IDs
+    pub report_id: u8,
+    pub usage_page: u16,
+    /// For array fields, the first usage of the array (see ReportField::array_usage())
+    pub usage: u16,
+    /// Offset in bits from the start of the report, excluding the Report ID byte
+    pub bit_offset: usize,
+    pub bit_size: usize,
+    pub logical_min: i32,
+    pub logical_max: i32,
+    pub relative: bool,
+    /// True if the value is an index of the usages that are active (e.g. pressed keys)
+    pub array: bool,
+    /// (Usage Page, Usage) of the Application Collection that the field belongs to,
+    /// e.g. (USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD)
+    pub application: (u16, u16),
+}
+impl ReportField {
+    /// Extracts the value of the field from the report (excluding the Report ID byte).
+    /// The value is sign-extended if the logical minimum is negative.
+    pub fn extract(&self, report: &[u8]) -> Option<i64> {
+        if self.bit_size == 0 || self.bit_size > 32 {
+            return None;
+        }
+        let mut value = 0u64;
+        for i in 0..self.bit_size {
+            let bit = self.bit_offset + i;
+            let byte = *report.get(bit / 8)?;
+            value |= (((byte >> (bit % 8)) & 1) as u64) << i;
+        }
+        if self.logical_min < 0 && value & (1 << (self.bit_size - 1)) != 0 {
+            Some(value as i64 - (1i64 << self.bit_size))
+        } else {
+            Some(value as i64)
+        }
+    }
+    /// Writes the value of the field into the report (excluding the Report ID byte).
+    pub fn insert(&self, report: &mut [u8], value: i64) -> Result<()> {
+        if (self.bit_offset + self.bit_size).div_ceil(8) > report.len() {
+            return Err(Error::Failed("The report is too short for the field"));
+        }
+        for i in 0..self.bit_size {
+            let bit = self.bit_offset + i;
+            let mask = 1 << (bit % 8);
+            if (value >> i) & 1 != 0 {
+                report[bit / 8] |= mask;
+            } else {
+                report[bit / 8] &= !mask;
+            }
+        }
+        Ok(())
+    }
+    /// Returns the usage selected by the value of an array field,
+    /// or None if the value is out of the logical range (no usage is selected).
+    pub fn array_usage(&self, value: i64) -> Option<u16> {
+        if value < self.logical_min as i64 || value > self.logical_max as i64 {
+            return None;
+        }
+        u16::try_from(self.usage as i64 + value - self.logical_min as i64).ok()
+    }
+    /// Returns true if the value is in the logical range.
+    /// Some fields (e.g. hat switches) report a value out of the range as the null state.
+    pub fn is_in_range(&self, value: i64) -> bool {
+        (self.logical_min as i64..=self.logical_max as i64).contains(&value)
+    }
+}
+
+// 6.2.2.7 Global Items: the state is kept across Main items, and can be pushed and popped.
+#[derive(Debug, Default, Copy, Clone)]
+struct GlobalState {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: usize,
+    report_count: usize,
+    report_id: u8,
+}
+
+#[derive(Debug, Default)]
+pub struct ReportDescriptor {
+    inputs: Vec<ReportField>,
+    outputs: Vec<ReportField>,
+    // Lengths in bits of the Output reports, for each Report ID
+    output_bits: BTreeMap<u8, usize>,
+    applications: Vec<(u16, u16)>,
+    uses_report_id: bool,
+}
+impl ReportDescriptor {
+    pub fn parse(desc: &[u8]) -> Result<Self> {
+        let mut this = Self::default();
+        let mut global = GlobalState::default();
+        let mut global_stack: Vec<GlobalState> = Vec::new();
+        // Local items, which are cleared after each Main item.
+        // Usages are (usage_page, usage). The page is None unless the usage has it
+        // in the upper 16 bits, and the Usage Page at the Main item applies then.
+        let mut usages: Vec<(Option<u16>, u16)> = Vec::new();
+        let mut usage_min: Option<(Option<u16>, u16)> = None;
+        let mut usage_max: Option<u16> = None;
+        // Application Collections that the current item is in
+        let mut collections: Vec<Option<(u16, u16)>> = Vec::new();
+        // Bit offset of the next Input field, for each Report ID
+        let mut input_bits: BTreeMap<u8, usize> = BTreeMap::new();
+
+        let mut i = 0;
+        while i < desc.len() {
+            let prefix = desc[i];
+            if prefix == ITEM_LONG {
+                // 6.2.2.3 Long Items: bDataSize follows the prefix
+                let size = *desc
+                    .get(i + 1)
+                    .ok_or(Error::Failed("Truncated long item"))?;
+                i += 3 + size as usize;
+                continue;
+            }
+            let size = match prefix & 0b11 {
+                3 => 4,
+                n => n as usize,
+            };
+            let data = desc
+                .get(i + 1..i + 1 + size)
+                .ok_or(Error::Failed("Truncated item in the report descriptor"))?;
+            i += 1 + size;
+            let mut udata = 0u32;
+            for (k, b) in data.iter().enumerate() {
+                udata |= (*b as u32) << (k * 8);
+            }
+            let sdata = match size {
+                1 => udata as u8 as i8 as i32,
+                2 => udata as u16 as i16 as i32,
+                _ => udata as i32,
+            };
+            let tag = prefix >> 4;
+            match (prefix >> 2) & 0b11 {
+                ITEM_TYPE_MAIN => {
+                    if let (Some((page, min)), Some(max)) = (usage_min, usage_max) {
+                        usages.extend((min..=max).map(|u| (page, u)));
+                    }
+                    let resolved: Vec<(u16, u16)> = usages
+                        .iter()
+                        .map(|(page, usage)| (page.unwrap_or(global.usage_page), *usage))
+                        .collect();
+                    let application = collections.iter().rev().find_map(|c| *c);
+                    match tag {
+                        MAIN_INPUT | MAIN_OUTPUT => {
+                            let (fields, bits) = if tag == MAIN_INPUT {
+                                (&mut this.inputs, &mut input_bits)
+                            } else {
+                                (&mut this.outputs, &mut this.output_bits)
+                            };
+                            let offset = bits.entry(global.report_id).or_default();
+                            if udata & MAIN_FLAG_CONSTANT == 0 {
+                                Self::push_fields(
+                                    fields,
+                                    &global,
+                                    *offset,
+                                    udata,
+                                    &resolved,
+                                    application.unwrap_or_default(),
+                                );
+                            }
+                            *offset += global.report_size * global.report_count;
+                        }
+                        MAIN_COLLECTION => {
+                            let usage = resolved.first().copied();
+                            // Nested Application Collections are in the outermost one
+                            let application = match (application, usage) {
+                                (None, Some(usage)) if udata == COLLECTION_APPLICATION => {
+                                    this.applications.push(usage);
+                                    Some(usage)
+                                }
+                                _ => None,
+                            };
+                            collections.push(application);
+                        }
+                        MAIN_END_COLLECTION => {
+                            collections.pop();
+                        }
+                        // Feature items are not used
+                        _ => {}
+                    }
+                    usages.clear();
+                    usage_min = None;
+                    usage_max = None;
+                }
+                ITEM_TYPE_GLOBAL => match tag {
+                    GLOBAL_USAGE_PAGE => global.usage_page = udata as u16,
+                    GLOBAL_LOGICAL_MINIMUM => global.logical_min = sdata,
+                    GLOBAL_LOGICAL_MAXIMUM => {
+                        // Treated as unsigned if the minimum is not negative
+                        global.logical_max = if global.logical_min < 0 {
+                            sdata
+                        } else {
+                            udata as i32
+                        }
+                    }
+                    GLOBAL_REPORT_SIZE => global.report_size = udata as usize,
+                    GLOBAL_REPORT_ID => {
+                        global.report_id = udata as u8;
+                        this.uses_report_id = true;
+                    }
+                    GLOBAL_REPORT_COUNT => global.report_count = udata as usize,
+                    GLOBAL_PUSH => global_stack.push(global),
+                    GLOBAL_POP => {
+                        global = global_stack
+                            .pop()
+                            .ok_or(Error::Failed("Pop without Push in the report descriptor"))?
+                    }
+                    _ => {}
+                },
+                ITEM_TYPE_LOCAL => {
+                    let usage = if size == 4 {
+                        (Some((udata >> 16) as u16), udata as u16)
+                    } else {
+                        (None, udata as u16)
+                    };
+                    match tag {
+                        LOCAL_USAGE => usages.push(usage),
+                        LOCAL_USAGE_MINIMUM => usage_min = Some(usage),
+                        LOCAL_USAGE_MAXIMUM => usage_max = Some(usage.1),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(this)
+    }
+    fn push_fields(
+        fields: &mut Vec<ReportField>,
+        global: &GlobalState,
+        offset: usize,
+        flags: u32,
+        usages: &[(u16, u16)],
+        application: (u16, u16),
+    ) {
+        let array = flags & MAIN_FLAG_VARIABLE == 0;
+        for k in 0..global.report_count {
+            // Each field of a variable item has its own usage, and the last usage applies
+            // to the rest of the fields. All the fields of an array item select one of
+            // the usages, which are assumed to be contiguous.
+            let usage = if array {
+                usages.first()
+            } else {
+                usages.get(k).or(usages.last())
+            };
+            if let Some((usage_page, usage)) = usage.copied() {
+                fields.push(ReportField {
+                    report_id: global.report_id,
+                    usage_page,
+                    usage,
+                    bit_offset: offset + k * global.report_size,
+                    bit_size: global.report_size,
+                    logical_min: global.logical_min,
+                    logical_max: global.logical_max,
+                    relative: flags & MAIN_FLAG_RELATIVE != 0,
+                    array,
+                    application,
+                });
+            }
+        }
+    }
+    /// If true, each report starts with a byte of the Report ID
+    pub fn uses_report_id(&self) -> bool {
+        self.uses_report_id
+    }
+    /// Returns (Usage Page, Usage) of the top-level Application Collections,
+    /// which tell what the device is (e.g. a keyboard or a mouse).
+    pub fn applications(&self) -> &[(u16, u16)] {
+        &self.applications
+    }
+    pub fn find_input_field(&self, usage_page: u16, usage: u16) -> Option<ReportField> {
+        self.inputs
+            .iter()
+            .find(|f| !f.array && f.usage_page == usage_page && f.usage == usage)
+            .copied()
+    }
+    /// Returns the fields of the Input report with the Report ID.
+    pub fn input_fields(&self, report_id: u8) -> impl Iterator<Item = &ReportField> {
+        self.inputs.iter().filter(move |f| f.report_id == report_id)
+    }
+    /// Returns the fields of all the Output reports.
+    pub fn output_fields(&self) -> &[ReportField] {
+        &self.outputs
+    }
+    /// Length of the Output report with the Report ID in bytes, excluding the Report ID byte
+    pub fn output_report_len(&self, report_id: u8) -> usize {
+        self.output_bits
+            .get(&report_id)
+            .map(|bits| bits.div_ceil(8))
+            .unwrap_or(0)
+    }
+    /// Splits the report into the Report ID and the rest.
+    /// The Report ID is 0 if the device does not use Report IDs.
+    pub fn split_report_id<'a>(&self, report: &'a [u8]) -> Option<(u8, &'a [u8])> {
+        if self.uses_report_id {
+            report.split_first().map(|(id, report)| (*id, report))
+        } else {
+            Some((0, report))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Report descriptor of QEMU usb-mouse
+    const QEMU_MOUSE: [u8; 52] = [
+        0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29,
+        0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05,
+        0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x09, 0x38, 0x15, 0x81, 0x25, 0x7f, 0x75,
+        0x08, 0x95, 0x03, 0x81, 0x06, 0xc0, 0xc0,
+    ];
+
+    #[test_case]
+    fn qemu_mouse_is_parsed() {
+        let desc = ReportDescriptor::parse(&QEMU_MOUSE).expect("should be parsed");
+        assert!(!desc.uses_report_id());
+        assert_eq!(
+            desc.applications(),
+            [(USAGE_PAGE_GENERIC_DESKTOP, USAGE_MOUSE)]
+        );
+        assert!(desc.find_input_field(USAGE_PAGE_BUTTON, 4).is_none());
+        let b2 = desc
+            .find_input_field(USAGE_PAGE_BUTTON, 2)
+            .expect("button 2 should exist");
+        assert_eq!((b2.bit_offset, b2.bit_size), (1, 1));
+        assert_eq!(b2.application, (USAGE_PAGE_GENERIC_DESKTOP, USAGE_MOUSE));
+        let wheel = desc
+            .find_input_field(USAGE_PAGE_GENERIC_DESKTOP, USAGE_WHEEL)
+            .expect("wheel should exist");
+        assert_eq!((wheel.bit_offset, wheel.bit_size), (24, 8));
+        assert_eq!((wheel.logical_min, wheel.logical_max), (-127, 127));
+        assert!(wheel.relative);
+        let report = [0b101, 0x05, 0xFB, 0xFF];
+        let x = desc
+            .find_input_field(USAGE_PAGE_GENERIC_DESKTOP, USAGE_X)
+            .unwrap();
+        let y = desc
+            .find_input_field(USAGE_PAGE_GENERIC_DESKTOP, USAGE_Y)
+            .unwrap();
+        assert_eq!(x.extract(&report), Some(5));
+        assert_eq!(y.extract(&report), Some(-5));
+        assert_eq!(wheel.extract(&report), Some(-1));
+        assert_eq!(b2.extract(&report), Some(0));
+        assert_eq!(wheel.extract(&report[..3]), None);
+    }
+    #[test_case]
+    fn report_ids_and_unsigned_fields_are_handled() {
+        let desc = ReportDescriptor::parse(&[
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x85, 0x02, // Report ID (2)
+            0x09, 0x30, // Usage (X)
+            0x15, 0x00, // Logical Minimum (0)
+            0x26, 0xff, 0x7f, // Logical Maximum (32767)
+            0x75, 0x10, // Report Size (16)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+        ])
+        .expect("should be parsed");
+        assert!(desc.uses_report_id());
+        let x = desc
+            .find_input_field(USAGE_PAGE_GENERIC_DESKTOP, USAGE_X)
+            .unwrap();
+        assert_eq!(x.report_id, 2);
+        assert_eq!(x.logical_max, 32767);
+        assert!(!x.relative);
+        assert_eq!(x.extract(&[0xff, 0x7f]), Some(32767));
+        assert_eq!(
+            desc.split_report_id(&[2, 0xff, 0x7f]),
+            Some((2, &[0xff, 0x7f][..]))
+        );
+        assert!(ReportDescriptor::parse(&[0x05]).is_err());
+    }
+    #[test_case]
+    fn boot_keyboard_arrays_and_outputs_are_parsed() {
+        let desc =
+            ReportDescriptor::parse(&BOOT_KEYBOARD_REPORT_DESCRIPTOR).expect("should be parsed");
+        assert_eq!(
+            desc.applications(),
+            [(USAGE_PAGE_GENERIC_DESKTOP, USAGE_KEYBOARD)]
+        );
+        // 8 modifiers and 6 keys
+        assert_eq!(desc.input_fields(0).count(), 14);
+        let keys: Vec<&ReportField> = desc.input_fields(0).filter(|f| f.array).collect();
+        assert_eq!(keys.len(), 6);
+        assert_eq!((keys[0].bit_offset, keys[0].bit_size), (16, 8));
+        assert_eq!(keys[0].array_usage(0x04), Some(0x04));
+        assert_eq!(keys[0].array_usage(0x66), None);
+        // 5 LEDs and the padding
+        assert_eq!(desc.output_fields().len(), 5);
+        assert_eq!(desc.output_report_len(0), 1);
+        let mut report = [0u8; 1];
+        let caps_lock = desc.output_fields()[1];
+        assert_eq!((caps_lock.usage_page, caps_lock.usage), (USAGE_PAGE_LED, 2));
+        caps_lock
+            .insert(&mut report, 1)
+            .expect("should be inserted");
+        assert_eq!(report, [0b10]);
+        assert!(caps_lock.insert(&mut [], 1).is_err());
+    }
+    #[test_case]
+    fn push_and_pop_restore_global_items() {
+        let desc = ReportDescriptor::parse(&[
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0xa4, // Push
+            0x05, 0x09, // Usage Page (Button)
+            0x75, 0x01, // Report Size (1)
+            0x09, 0x01, // Usage (1)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0xb4, // Pop
+            0x09, 0x30, // Usage (X)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+        ])
+        .expect("should be parsed");
+        let x = desc
+            .find_input_field(USAGE_PAGE_GENERIC_DESKTOP, USAGE_X)
+            .expect("X should be in the Generic Desktop page");
+        assert_eq!((x.bit_offset, x.bit_size), (1, 8));
+        assert!(ReportDescriptor::parse(&[0xb4]).is_err());
+    }
+}
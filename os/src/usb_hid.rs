@@ -0,0 +1,224 @@
+//! Driver for USB HID devices (keyboards, mice, tablets and gamepads)
+//!
+//! The layout of the reports is taken from the report descriptor of the device,
+//! and the reports are decoded by usb::hid_input. Boot devices without a usable
+//! report descriptor are driven with the Boot Protocol and its fixed layout.
+
+extern crate alloc;
+
+use crate::error;
+use crate::error::Error;
+use crate::error::Result;
+use crate::executor::with_timeout_ms;
+use crate::hpet::Hpet;
+use crate::info;
+use crate::input::InputManager;
+use crate::usb::descriptor::ConfigDescriptor;
+use crate::usb::descriptor::EndpointDescriptor;
+use crate::usb::descriptor::HidDescriptor;
+use crate::usb::descriptor::InterfaceDescriptor;
+use crate::usb::descriptor::UsbDescriptor;
+use crate::usb::hid_input::HidInputDecoder;
+use crate::usb::hid_report::ReportDescriptor;
+use crate::usb::hid_report::BOOT_KEYBOARD_REPORT_DESCRIPTOR;
+use crate::usb::hid_report::BOOT_MOUSE_REPORT_DESCRIPTOR;
+use crate::warn;
+use crate::xhci::device::UsbDeviceDriverContext;
+use crate::xhci::device::UsbHidProtocol;
+use crate::xhci::future::EventFuture;
+use alloc::format;
+use alloc::vec::Vec;
+
+pub const USB_CLASS_HID: u8 = 3;
+// [hid_1_11] 4.2 Subclass, 4.3 Protocols
+const HID_SUBCLASS_BOOT: u8 = 1;
+const HID_PROTOCOL_KEYBOARD: u8 = 1;
+const HID_PROTOCOL_MOUSE: u8 = 2;
+
+// Long enough for the reports of usual devices. Longer reports are truncated.
+const MAX_REPORT_SIZE: usize = 64;
+// The held key is checked at this interval even if no reports arrive
+const KEY_REPEAT_POLL_MS: u64 = 10;
+
+/// Picks the first HID interface, with its HID descriptor and endpoints.
+pub fn pick_config(
+    descriptors: &Vec<UsbDescriptor>,
+) -> Result<(
+    ConfigDescriptor,
+    InterfaceDescriptor,
+    Option<HidDescriptor>,
+    Vec<EndpointDescriptor>,
+)> {
+    let mut last_config: Option<ConfigDescriptor> = None;
+    let mut hid_interface: Option<InterfaceDescriptor> = None;
+    let mut hid_desc: Option<HidDescriptor> = None;
+    let mut ep_desc_list: Vec<EndpointDescriptor> = Vec::new();
+    for d in descriptors {
+        match d {
+            UsbDescriptor::Config(e) => {
+                if hid_interface.is_some() {
+                    break;
+                }
+                last_config = Some(*e);
+            }
+            UsbDescriptor::Interface(e) => {
+                if hid_interface.is_some() {
+                    break;
+                }
+                if e.triple().0 == USB_CLASS_HID {
+                    hid_interface = Some(*e)
+                }
+            }
+            UsbDescriptor::Hid(e) => {
+                if hid_interface.is_some() {
+                    hid_desc = Some(*e);
+                }
+            }
+            UsbDescriptor::Endpoint(e) => {
+                if hid_interface.is_some() {
+                    ep_desc_list.push(*e);
+                }
+            }
+            _ => {}
+        }
+    }
+    let config_desc = last_config.ok_or(Error::Failed("No USB HID config found"))?;
+    let interface_desc = hid_interface.ok_or(Error::Failed("No USB HID interface found"))?;
+    Ok((config_desc, interface_desc, hid_desc, ep_desc_list))
+}
+
+async fn request_report_descriptor(
+    ddc: &mut UsbDeviceDriverContext,
+    interface_desc: &InterfaceDescriptor,
+    hid_desc: Option<HidDescriptor>,
+) -> Result<ReportDescriptor> {
+    let hid_desc = hid_desc.ok_or(Error::Failed("No HID descriptor found"))?;
+    let len = hid_desc.report_desc_length as usize;
+    let desc = ddc.request_report_descriptor(interface_desc, len).await?;
+    let desc = ReportDescriptor::parse(&desc)?;
+    if desc.applications().is_empty() {
+        return Err(Error::Failed("No Application Collection in the report"));
+    }
+    Ok(desc)
+}
+
+async fn init_usb_hid(
+    ddc: &mut UsbDeviceDriverContext,
+) -> Result<(
+    InterfaceDescriptor,
+    Vec<EndpointDescriptor>,
+    HidInputDecoder,
+)> {
+    let descriptors = ddc.descriptors();
+    let (config_desc, interface_desc, hid_desc, ep_desc_list) = pick_config(descriptors)?;
+    ddc.set_config(config_desc.config_value()).await?;
+    ddc.set_interface(&interface_desc).await?;
+    let (_, subclass, protocol) = interface_desc.triple();
+    // Devices start with the Report Protocol, and only boot devices support
+    // SET_PROTOCOL ([hid_1_11] 7.2.6). Fall back to the Boot Protocol if the report
+    // descriptor is not usable, since its layout is fixed.
+    let desc = match request_report_descriptor(ddc, &interface_desc, hid_desc).await {
+        Ok(desc) => {
+            if subclass == HID_SUBCLASS_BOOT {
+                ddc.set_protocol(&interface_desc, UsbHidProtocol::ReportProtocol)
+                    .await?;
+            }
+            desc
+        }
+        Err(e) if subclass == HID_SUBCLASS_BOOT => {
+            warn!("usb_hid: using the boot protocol: {e:?}");
+            let boot_desc: &[u8] = match protocol {
+                HID_PROTOCOL_KEYBOARD => &BOOT_KEYBOARD_REPORT_DESCRIPTOR,
+                HID_PROTOCOL_MOUSE => &BOOT_MOUSE_REPORT_DESCRIPTOR,
+                _ => return Err(e),
+            };
+            ddc.set_protocol(&interface_desc, UsbHidProtocol::BootProtocol)
+                .await?;
+            ReportDescriptor::parse(boot_desc)?
+        }
+        Err(e) => return Err(e),
+    };
+    // 4.6.6 Configure Endpoint
+    // When configuring or deconfiguring a device, only after completing a successful
+    // Configure Endpoint Command and a successful USB SET_CONFIGURATION
+    // request may software schedule data transfers through a newly enabled endpoint
+    // or Stream Transfer Ring of the Device Slot.
+    for ep_desc in &ep_desc_list {
+        let ep_ring = ddc
+            .ep_ring(ep_desc.dci())?
+            .as_ref()
+            .ok_or(Error::Failed("Endpoint not created"))?;
+        ep_ring.fill_ring()?;
+        ddc.notify_ep(ep_desc)?;
+    }
+    Ok((interface_desc, ep_desc_list, HidInputDecoder::new(desc)))
+}
+
+async fn usb_hid_mainloop(
+    mut ddc: UsbDeviceDriverContext,
+    interface_desc: InterfaceDescriptor,
+    ep_desc_list: Vec<EndpointDescriptor>,
+    mut decoder: HidInputDecoder,
+) -> Result<()> {
+    let slot = ddc.slot();
+    let xhci = ddc.xhci();
+    let event_trb = EventFuture::new_transfer_event_on_slot(xhci.primary_event_ring(), slot);
+    loop {
+        let input = InputManager::take();
+        match with_timeout_ms(event_trb.clone(), KEY_REPEAT_POLL_MS).await {
+            // Completions of the requests on the control endpoint (e.g. SET_REPORT)
+            // are waited for by the requests.
+            Ok(Ok(trb)) if !ep_desc_list.iter().any(|e| e.dci() == trb.dci()) => {}
+            Ok(Ok(trb)) => {
+                let mut report = [0u8; MAX_REPORT_SIZE];
+                if let Some(ref mut tring) = ddc.ep_ring(trb.dci())?.as_ref() {
+                    tring.read_transfer_buffer(trb.data(), &mut report)?;
+                    tring.dequeue_trb(trb.data() as usize)?;
+                    xhci.notify_ep(slot, trb.dci())?;
+                }
+                for e in decoder.decode(&report, Hpet::take().uptime_ms()) {
+                    input.push_event(&e);
+                }
+            }
+            Ok(Err(e)) => {
+                error!("e: {:?}", e);
+            }
+            // Timed out
+            Err(_) => {}
+        }
+        if let Some(e) = decoder.poll_repeat(Hpet::take().uptime_ms()) {
+            input.push_key(&e);
+        }
+        if let Some((report_id, report)) = decoder.take_led_report() {
+            // Some keyboards don't have LEDs and reject the request
+            if let Err(e) = ddc
+                .set_output_report(&interface_desc, report_id, &report)
+                .await
+            {
+                warn!("usb_hid: failed to set LEDs: {e:?}");
+            }
+        }
+        if !ddc.is_attached() {
+            return Err(Error::FailedString(format!("slot {} detached", slot)));
+        }
+    }
+}
+
+pub async fn attach_usb_device(mut ddc: UsbDeviceDriverContext) -> Result<()> {
+    let (interface_desc, ep_desc_list, decoder) = init_usb_hid(&mut ddc).await?;
+    let kinds = decoder.kinds();
+    if kinds.is_empty() {
+        warn!(
+            "usb_hid: no known devices in the report descriptor: applications = {:X?}",
+            decoder.descriptor().applications()
+        );
+    }
+    for kind in kinds {
+        // Note: these messages (e.g. "usb_hid_keyboard is ready") are used by e2etest
+        // - please keep them as is!
+        info!("usb_hid_{} is ready", kind.name());
+    }
+    let e = usb_hid_mainloop(ddc, interface_desc, ep_desc_list, decoder).await;
+    info!("usb_hid exited: {e:?}");
+    e
+}
@@ -0,0 +1,352 @@
+extern crate alloc;
+
+use crate::error;
+use crate::error::Error;
+use crate::error::Result;
+use crate::executor::with_timeout_ms;
+use crate::executor::yield_execution;
+use crate::executor::TimeoutFuture;
+use crate::info;
+use crate::memory::Mmio;
+use crate::usb::descriptor::ConfigDescriptor;
+use crate::usb::descriptor::EndpointDescriptor;
+use crate::usb::descriptor::UsbDescriptor;
+use crate::xhci::controller::EndpointType;
+use crate::xhci::device::UsbDeviceDriverContext;
+use crate::xhci::driver::XhciDriverForPci;
+use crate::xhci::future::EventFuture;
+use crate::xhci::registers::UsbMode;
+use crate::xhci::trb::SetupStageTrb;
+use alloc::boxed::Box;
+use alloc::format;
+
+// [usb2] 11.23 Hub Descriptors / 11.24 Hub-specific Requests
+pub const USB_CLASS_HUB: u8 = 9;
+const DESC_TYPE_HUB: u16 = 0x29;
+
+// Table 11-17: Hub Class Feature Selectors
+const HUB_FEATURE_C_LOCAL_POWER: u16 = 0;
+const HUB_FEATURE_C_OVER_CURRENT: u16 = 1;
+const PORT_FEATURE_RESET: u16 = 4;
+const PORT_FEATURE_POWER: u16 = 8;
+const PORT_FEATURE_C_CONNECTION: u16 = 16;
+const PORT_FEATURE_C_ENABLE: u16 = 17;
+const PORT_FEATURE_C_SUSPEND: u16 = 18;
+const PORT_FEATURE_C_OVER_CURRENT: u16 = 19;
+const PORT_FEATURE_C_RESET: u16 = 20;
+
+// Table 11-21: Port Status Field, wPortStatus
+const PORT_STATUS_CONNECTION: u16 = 1 << 0;
+const PORT_STATUS_ENABLE: u16 = 1 << 1;
+const PORT_STATUS_LOW_SPEED: u16 = 1 << 9;
+const PORT_STATUS_HIGH_SPEED: u16 = 1 << 10;
+
+// Table 11-22: Port Change Field, wPortChange
+// bit[n] corresponds to PORT_FEATURE_C_* == 16 + n
+const PORT_CHANGE_CONNECTION: u16 = 1 << 0;
+const PORT_CHANGE_RESET: u16 = 1 << 4;
+
+// 7.1.7.3: Debounce interval after a connection (TATTDB)
+const ATTACH_DEBOUNCE_MS: u64 = 100;
+// 7.1.7.5: Reset recovery time (TRSTRCY)
+const RESET_RECOVERY_MS: u64 = 10;
+const PORT_RESET_TIMEOUT_MS: u64 = 500;
+
+/// [usb2] 11.23.2.1 Hub Descriptor
+#[derive(Debug, Copy, Clone)]
+struct HubDescriptor {
+    num_ports: u8,
+    // wHubCharacteristics:
+    //   - bit[0..=1]: Logical Power Switching Mode
+    //   - bit[5..=6]: TT Think Time
+    characteristics: u16,
+    // Time from power-on to power-good on a port, in 2ms units
+    power_on_to_power_good: u8,
+}
+impl HubDescriptor {
+    fn parse(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 7 || buf[0] < 7 || buf[1] as u16 != DESC_TYPE_HUB {
+            return Err(Error::Failed("Invalid hub descriptor"));
+        }
+        Ok(Self {
+            num_ports: buf[2],
+            characteristics: u16::from_le_bytes([buf[3], buf[4]]),
+            power_on_to_power_good: buf[5],
+        })
+    }
+    fn tt_think_time(&self) -> u8 {
+        (self.characteristics >> 5 & 0b11) as u8
+    }
+    fn power_good_ms(&self) -> u64 {
+        self.power_on_to_power_good as u64 * 2
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PortStatus {
+    status: u16,
+    change: u16,
+}
+impl PortStatus {
+    fn connected(&self) -> bool {
+        self.status & PORT_STATUS_CONNECTION != 0
+    }
+    fn enabled(&self) -> bool {
+        self.status & PORT_STATUS_ENABLE != 0
+    }
+    fn speed(&self) -> UsbMode {
+        if self.status & PORT_STATUS_LOW_SPEED != 0 {
+            UsbMode::LowSpeed
+        } else if self.status & PORT_STATUS_HIGH_SPEED != 0 {
+            UsbMode::HighSpeed
+        } else {
+            UsbMode::FullSpeed
+        }
+    }
+}
+
+async fn request_hub_descriptor(ddc: &mut UsbDeviceDriverContext) -> Result<HubDescriptor> {
+    // 7 bytes + DeviceRemovable and PortPwrCtrlMask for up to 31 ports
+    let mut buf = Box::pin([0u8; 15]);
+    ddc.control_in(
+        SetupStageTrb::new(
+            SetupStageTrb::REQ_TYPE_DIR_DEVICE_TO_HOST
+                | SetupStageTrb::REQ_TYPE_TYPE_CLASS
+                | SetupStageTrb::REQ_TYPE_TO_DEVICE,
+            SetupStageTrb::REQ_GET_DESCRIPTOR,
+            DESC_TYPE_HUB << 8,
+            0,
+            buf.len() as u16,
+        ),
+        buf.as_mut(),
+    )
+    .await?;
+    HubDescriptor::parse(buf.as_ref().get_ref())
+}
+
+async fn clear_hub_feature(ddc: &mut UsbDeviceDriverContext, feature: u16) -> Result<()> {
+    ddc.control_no_data(SetupStageTrb::new(
+        SetupStageTrb::REQ_TYPE_TYPE_CLASS | SetupStageTrb::REQ_TYPE_TO_DEVICE,
+        SetupStageTrb::REQ_CLEAR_FEATURE,
+        feature,
+        0,
+        0,
+    ))
+    .await
+}
+
+async fn set_port_feature(ddc: &mut UsbDeviceDriverContext, port: u8, feature: u16) -> Result<()> {
+    ddc.control_no_data(SetupStageTrb::new(
+        SetupStageTrb::REQ_TYPE_TYPE_CLASS | SetupStageTrb::REQ_TYPE_TO_OTHER,
+        SetupStageTrb::REQ_SET_FEATURE,
+        feature,
+        port as u16,
+        0,
+    ))
+    .await
+}
+
+async fn clear_port_feature(
+    ddc: &mut UsbDeviceDriverContext,
+    port: u8,
+    feature: u16,
+) -> Result<()> {
+    ddc.control_no_data(SetupStageTrb::new(
+        SetupStageTrb::REQ_TYPE_TYPE_CLASS | SetupStageTrb::REQ_TYPE_TO_OTHER,
+        SetupStageTrb::REQ_CLEAR_FEATURE,
+        feature,
+        port as u16,
+        0,
+    ))
+    .await
+}
+
+async fn get_port_status(ddc: &mut UsbDeviceDriverContext, port: u8) -> Result<PortStatus> {
+    let mut buf = Box::pin([0u8; 4]);
+    ddc.control_in(
+        SetupStageTrb::new(
+            SetupStageTrb::REQ_TYPE_DIR_DEVICE_TO_HOST
+                | SetupStageTrb::REQ_TYPE_TYPE_CLASS
+                | SetupStageTrb::REQ_TYPE_TO_OTHER,
+            SetupStageTrb::REQ_GET_STATUS,
+            0,
+            port as u16,
+            buf.len() as u16,
+        ),
+        buf.as_mut(),
+    )
+    .await?;
+    Ok(PortStatus {
+        status: u16::from_le_bytes([buf[0], buf[1]]),
+        change: u16::from_le_bytes([buf[2], buf[3]]),
+    })
+}
+
+fn pick_config(descriptors: &[UsbDescriptor]) -> Result<(ConfigDescriptor, EndpointDescriptor)> {
+    let mut config_desc: Option<ConfigDescriptor> = None;
+    let mut in_hub_interface = false;
+    for d in descriptors {
+        match d {
+            UsbDescriptor::Config(e) => {
+                config_desc = Some(*e);
+            }
+            UsbDescriptor::Interface(e) => {
+                let (class, _, _) = e.triple();
+                in_hub_interface = class == USB_CLASS_HUB && e.alt_setting() == 0;
+            }
+            UsbDescriptor::Endpoint(e) => {
+                if in_hub_interface && EndpointType::from(e) == EndpointType::InterruptIn {
+                    let config_desc =
+                        config_desc.ok_or(Error::Failed("No config for the USB hub found"))?;
+                    return Ok((config_desc, *e));
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(Error::Failed(
+        "No status change endpoint of the USB hub found",
+    ))
+}
+
+async fn init_usb_hub(
+    ddc: &mut UsbDeviceDriverContext,
+) -> Result<(HubDescriptor, EndpointDescriptor)> {
+    if ddc.location().speed() == UsbMode::SuperSpeed {
+        return Err(Error::Failed("SuperSpeed hubs are not supported yet"));
+    }
+    let (config_desc, status_change_ep) = pick_config(ddc.descriptors())?;
+    ddc.set_config(config_desc.config_value()).await?;
+    let hub_desc = request_hub_descriptor(ddc).await?;
+    // Only the default (single TT) setting is used
+    ddc.configure_as_hub(hub_desc.num_ports, false, hub_desc.tt_think_time())
+        .await?;
+    for port in 1..=hub_desc.num_ports {
+        set_port_feature(ddc, port, PORT_FEATURE_POWER).await?;
+    }
+    TimeoutFuture::new_ms(hub_desc.power_good_ms()).await;
+    let ep_ring = ddc
+        .ep_ring(status_change_ep.dci())?
+        .as_ref()
+        .ok_or(Error::Failed("Endpoint not created"))?;
+    ep_ring.fill_ring()?;
+    ddc.notify_ep(&status_change_ep)?;
+    Ok((hub_desc, status_change_ep))
+}
+
+async fn wait_for_port_reset(ddc: &mut UsbDeviceDriverContext, port: u8) -> Result<PortStatus> {
+    loop {
+        let status = get_port_status(ddc, port).await?;
+        if status.change & PORT_CHANGE_RESET != 0 {
+            return Ok(status);
+        }
+        yield_execution().await;
+    }
+}
+
+/// Resets the port and enumerates the device attached to it.
+async fn attach_device_on_port(ddc: &mut UsbDeviceDriverContext, port: u8) -> Result<()> {
+    TimeoutFuture::new_ms(ATTACH_DEBOUNCE_MS).await;
+    set_port_feature(ddc, port, PORT_FEATURE_RESET).await?;
+    let status = with_timeout_ms(wait_for_port_reset(ddc, port), PORT_RESET_TIMEOUT_MS).await??;
+    clear_port_feature(ddc, port, PORT_FEATURE_C_RESET).await?;
+    if !status.connected() || !status.enabled() {
+        return Err(Error::FailedString(format!(
+            "port {port} was not enabled after reset: {status:?}"
+        )));
+    }
+    TimeoutFuture::new_ms(RESET_RECOVERY_MS).await;
+    let location = ddc.location().child(ddc.slot(), port, status.speed())?;
+    let device_future = XhciDriverForPci::enable_slot(ddc.xhci(), location).await?;
+    ddc.xhci().device_futures().lock().push_back(device_future);
+    Ok(())
+}
+
+async fn handle_port_change(ddc: &mut UsbDeviceDriverContext, port: u8) -> Result<()> {
+    let status = get_port_status(ddc, port).await?;
+    // Acknowledge all the changes so that they are not reported again
+    for feature in [
+        PORT_FEATURE_C_CONNECTION,
+        PORT_FEATURE_C_ENABLE,
+        PORT_FEATURE_C_SUSPEND,
+        PORT_FEATURE_C_OVER_CURRENT,
+        PORT_FEATURE_C_RESET,
+    ] {
+        if status.change & (1 << (feature - PORT_FEATURE_C_CONNECTION)) != 0 {
+            clear_port_feature(ddc, port, feature).await?;
+        }
+    }
+    if status.change & PORT_CHANGE_CONNECTION == 0 {
+        return Ok(());
+    }
+    let slot = ddc.slot();
+    if status.connected() {
+        info!("Hub (slot {slot}) port {port}: Device attached: {status:?}");
+        attach_device_on_port(ddc, port).await
+    } else {
+        info!("Hub (slot {slot}) port {port}: Device detached: {status:?}");
+        let location = ddc.location().child(slot, port, status.speed())?;
+        ddc.xhci().detach_devices_at(&location);
+        Ok(())
+    }
+}
+
+pub async fn attach_usb_device(mut ddc: UsbDeviceDriverContext) -> Result<()> {
+    let (hub_desc, status_change_ep) = init_usb_hub(&mut ddc).await?;
+    info!("usb_hub is ready: {} ports", hub_desc.num_ports);
+
+    let slot = ddc.slot();
+    let xhci = ddc.xhci();
+
+    let event_trb = EventFuture::new_transfer_event_on_slot(xhci.primary_event_ring(), slot);
+    // Check all the ports once since devices attached before powering on the ports
+    // are not always reported via the status change endpoint.
+    for hub_port in 1..=hub_desc.num_ports {
+        if let Err(e) = handle_port_change(&mut ddc, hub_port).await {
+            error!("Hub (slot {slot}) port {hub_port}: {e:?}");
+        }
+    }
+    loop {
+        match event_trb.clone().await {
+            // Completions of the requests on the control endpoint
+            // are waited for by the requests.
+            Ok(trb) if trb.dci() != status_change_ep.dci() => {}
+            Ok(trb) => {
+                // 11.12.4: bit[0] is for the hub itself, bit[n] is for the port n.
+                let transfer_trb_ptr = trb.data() as usize;
+                let mut bitmap = [0u8; 4];
+                bitmap.copy_from_slice(
+                    unsafe {
+                        Mmio::<[u8; 4]>::from_raw(
+                            *(transfer_trb_ptr as *const usize) as *mut [u8; 4],
+                        )
+                    }
+                    .as_ref(),
+                );
+                if let Some(ref mut tring) = ddc.ep_ring(trb.dci())?.as_ref() {
+                    tring.dequeue_trb(transfer_trb_ptr)?;
+                    xhci.notify_ep(slot, trb.dci())?;
+                }
+                let bitmap = u32::from_le_bytes(bitmap);
+                if bitmap & 1 != 0 {
+                    clear_hub_feature(&mut ddc, HUB_FEATURE_C_LOCAL_POWER).await?;
+                    clear_hub_feature(&mut ddc, HUB_FEATURE_C_OVER_CURRENT).await?;
+                }
+                for hub_port in 1..=hub_desc.num_ports.min(31) {
+                    if bitmap & (1 << hub_port) == 0 {
+                        continue;
+                    }
+                    if let Err(e) = handle_port_change(&mut ddc, hub_port).await {
+                        error!("Hub (slot {slot}) port {hub_port}: {e:?}");
+                    }
+                }
+            }
+            Err(e) => {
+                error!("e: {:?}", e);
+            }
+        }
+        if !ddc.is_attached() {
+            return Err(Error::FailedString(format!("slot {} detached", slot)));
+        }
+    }
+}
@@ -0,0 +1,552 @@
+extern crate alloc;
+
+use crate::block::register_block_device;
+use crate::block::BlockDevice;
+use crate::block::BlockOp;
+use crate::block::BlockRequest;
+use crate::error::Error;
+use crate::error::Result;
+use crate::executor::with_timeout_ms;
+use crate::executor::TimeoutFuture;
+use crate::info;
+use crate::mutex::Mutex;
+use crate::usb::descriptor::ConfigDescriptor;
+use crate::usb::descriptor::EndpointDescriptor;
+use crate::usb::descriptor::InterfaceDescriptor;
+use crate::usb::descriptor::UsbDescriptor;
+use crate::warn;
+use crate::xhci::controller::EndpointType;
+use crate::xhci::device::UsbDeviceDriverContext;
+use crate::xhci::trb::CompletionCode;
+use crate::xhci::trb::GenericTrbEntry;
+use crate::xhci::trb::SetupStageTrb;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use core::future::Future;
+
+// [usbmassbulk_10]: Universal Serial Bus Mass Storage Class Bulk-Only Transport
+// Interface class / subclass (SCSI transparent command set) / protocol (Bulk-Only)
+pub const USB_MASS_STORAGE_TRIPLE: (u8, u8, u8) = (8, 6, 0x50);
+// 3.1 Bulk-Only Mass Storage Reset
+const REQ_BULK_ONLY_MASS_STORAGE_RESET: u8 = 0xFF;
+
+// 5.1 Command Block Wrapper (CBW)
+const CBW_SIGNATURE: u32 = 0x43425355; // "USBC"
+const CBW_SIZE: usize = 31;
+const CBW_FLAG_DATA_IN: u8 = 1 << 7;
+// 5.2 Command Status Wrapper (CSW)
+const CSW_SIGNATURE: u32 = 0x53425355; // "USBS"
+const CSW_SIZE: usize = 13;
+const CSW_STATUS_PASSED: u8 = 0;
+const CSW_STATUS_FAILED: u8 = 1;
+const CSW_STATUS_PHASE_ERROR: u8 = 2;
+
+// [SPC-4] / [SBC-3] operation codes
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2A;
+const INQUIRY_DATA_SIZE: usize = 36;
+const SENSE_DATA_SIZE: usize = 18;
+
+const TRANSFER_TIMEOUT_MS: u64 = 5000;
+// A transfer is done with a TRB and its buffer, so commands are split into this size
+const MAX_TRANSFER_SIZE: usize = 4096;
+const TEST_UNIT_READY_RETRY_COUNT: usize = 10;
+const TEST_UNIT_READY_RETRY_INTERVAL_MS: u64 = 100;
+const REQUEST_POLL_INTERVAL_MS: u64 = 10;
+
+struct CommandBlockWrapper {
+    tag: u32,
+    data_transfer_length: u32,
+    data_in: bool,
+    command_block: [u8; 16],
+    command_block_length: u8,
+}
+impl CommandBlockWrapper {
+    fn new(tag: u32, data_transfer_length: usize, data_in: bool, cb: &[u8]) -> Result<Self> {
+        if cb.is_empty() || cb.len() > 16 {
+            return Err(Error::Failed("Invalid length of a command block"));
+        }
+        let mut command_block = [0u8; 16];
+        command_block[..cb.len()].copy_from_slice(cb);
+        Ok(Self {
+            tag,
+            data_transfer_length: data_transfer_length as u32,
+            data_in,
+            command_block,
+            command_block_length: cb.len() as u8,
+        })
+    }
+    fn to_bytes(&self) -> [u8; CBW_SIZE] {
+        let mut bytes = [0u8; CBW_SIZE];
+        bytes[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.data_transfer_length.to_le_bytes());
+        bytes[12] = if self.data_in { CBW_FLAG_DATA_IN } else { 0 };
+        // bCBWLUN: Only LUN 0 is used
+        bytes[13] = 0;
+        bytes[14] = self.command_block_length;
+        bytes[15..31].copy_from_slice(&self.command_block);
+        bytes
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct CommandStatusWrapper {
+    data_residue: u32,
+    status: u8,
+}
+impl CommandStatusWrapper {
+    /// 6.3 Valid and Meaningful CSW
+    fn parse(bytes: &[u8], expected_tag: u32) -> Result<Self> {
+        if bytes.len() != CSW_SIZE {
+            return Err(Error::Failed("CSW: invalid length"));
+        }
+        let signature = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let tag = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if signature != CSW_SIGNATURE || tag != expected_tag {
+            return Err(Error::Failed("CSW: invalid signature or tag"));
+        }
+        Ok(Self {
+            data_residue: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            status: bytes[12],
+        })
+    }
+}
+
+enum DataPhase<'a> {
+    None,
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CommandStatus {
+    /// Number of bytes transferred in the data phase
+    Passed(usize),
+    Failed,
+}
+
+async fn wait_transfer<F: Future<Output = Result<GenericTrbEntry>>>(
+    f: F,
+) -> Result<GenericTrbEntry> {
+    with_timeout_ms(f, TRANSFER_TIMEOUT_MS).await?
+}
+
+fn is_stall(trb: &GenericTrbEntry) -> bool {
+    trb.completion_code() == CompletionCode::StallError as u32
+}
+
+struct BulkOnlyTransport {
+    ddc: UsbDeviceDriverContext,
+    interface_number: u8,
+    bulk_in: EndpointDescriptor,
+    bulk_out: EndpointDescriptor,
+    tag: u32,
+}
+impl BulkOnlyTransport {
+    /// 5.3.4 Reset Recovery
+    async fn reset_recovery(&mut self) -> Result<()> {
+        warn!("usb_mass_storage: Reset Recovery");
+        self.ddc
+            .control_no_data(SetupStageTrb::new(
+                SetupStageTrb::REQ_TYPE_TYPE_CLASS | SetupStageTrb::REQ_TYPE_TO_INTERFACE,
+                REQ_BULK_ONLY_MASS_STORAGE_RESET,
+                0,
+                self.interface_number as u16,
+                0,
+            ))
+            .await?;
+        let bulk_in = self.bulk_in;
+        let bulk_out = self.bulk_out;
+        self.ddc.clear_halt(&bulk_in).await?;
+        self.ddc.clear_halt(&bulk_out).await
+    }
+    /// Does Reset Recovery and returns `e` (or the error of the recovery if it failed)
+    async fn fail_with_reset_recovery(&mut self, e: Error) -> Result<CommandStatus> {
+        self.reset_recovery().await?;
+        Err(e)
+    }
+    async fn receive_csw(&mut self) -> Result<(GenericTrbEntry, [u8; CSW_SIZE])> {
+        let bulk_in = self.bulk_in;
+        let mut buf = [0u8; CSW_SIZE];
+        let trb = wait_transfer(self.ddc.transfer_in(&bulk_in, &mut buf)).await?;
+        Ok((trb, buf))
+    }
+    /// Runs a command with the CBW - Data - CSW sequence (5.3)
+    async fn command(&mut self, cb: &[u8], data: DataPhase<'_>) -> Result<CommandStatus> {
+        let bulk_in = self.bulk_in;
+        let bulk_out = self.bulk_out;
+        self.tag = self.tag.wrapping_add(1);
+        let tag = self.tag;
+        let (len, data_in) = match &data {
+            DataPhase::None => (0, false),
+            DataPhase::In(buf) => (buf.len(), true),
+            DataPhase::Out(buf) => (buf.len(), false),
+        };
+        let cbw = CommandBlockWrapper::new(tag, len, data_in, cb)?.to_bytes();
+        let trb = wait_transfer(self.ddc.transfer_out(&bulk_out, &cbw)).await?;
+        if let Err(e) = trb.completed() {
+            // 6.6.1 CBW Not Valid
+            return self.fail_with_reset_recovery(e).await;
+        }
+
+        // 6.7.2 / 6.7.3: A STALL in the data phase is cleared then the CSW is read.
+        let transferred = match data {
+            DataPhase::None => 0,
+            DataPhase::In(buf) => {
+                let trb = wait_transfer(self.ddc.transfer_in(&bulk_in, buf)).await?;
+                if is_stall(&trb) {
+                    self.ddc.clear_halt(&bulk_in).await?;
+                } else if let Err(e) = trb.completed() {
+                    return self.fail_with_reset_recovery(e).await;
+                }
+                len.saturating_sub(trb.transfer_length())
+            }
+            DataPhase::Out(buf) => {
+                let trb = wait_transfer(self.ddc.transfer_out(&bulk_out, buf)).await?;
+                if is_stall(&trb) {
+                    self.ddc.clear_halt(&bulk_out).await?;
+                } else if let Err(e) = trb.completed() {
+                    return self.fail_with_reset_recovery(e).await;
+                }
+                len.saturating_sub(trb.transfer_length())
+            }
+        };
+
+        // 6.7.2: If the CSW is STALLed, clear it and try to receive the CSW again.
+        let (mut trb, mut bytes) = self.receive_csw().await?;
+        if is_stall(&trb) {
+            self.ddc.clear_halt(&bulk_in).await?;
+            (trb, bytes) = self.receive_csw().await?;
+        }
+        if let Err(e) = trb.completed() {
+            return self.fail_with_reset_recovery(e).await;
+        }
+        let received = CSW_SIZE.saturating_sub(trb.transfer_length());
+        let csw = match CommandStatusWrapper::parse(&bytes[..received], tag) {
+            Ok(csw) => csw,
+            Err(e) => return self.fail_with_reset_recovery(e).await,
+        };
+        match csw.status {
+            CSW_STATUS_PASSED => Ok(CommandStatus::Passed(
+                transferred.min(len.saturating_sub(csw.data_residue as usize)),
+            )),
+            CSW_STATUS_FAILED => Ok(CommandStatus::Failed),
+            CSW_STATUS_PHASE_ERROR => {
+                self.fail_with_reset_recovery(Error::Failed("CSW: Phase Error"))
+                    .await
+            }
+            _ => {
+                self.fail_with_reset_recovery(Error::Failed("CSW: invalid status"))
+                    .await
+            }
+        }
+    }
+    /// Runs a SCSI command. On failures, the sense data is returned as an error.
+    async fn scsi_command(&mut self, cb: &[u8], data: DataPhase<'_>) -> Result<usize> {
+        match self.command(cb, data).await? {
+            CommandStatus::Passed(transferred) => Ok(transferred),
+            CommandStatus::Failed => {
+                let (key, asc, ascq) = self.request_sense().await?;
+                Err(Error::FailedString(format!(
+                    "SCSI command {:#04X} failed: sense key = {key:#X}, ASC/ASCQ = {asc:#04X}/{ascq:#04X}",
+                    cb[0]
+                )))
+            }
+        }
+    }
+    /// Returns (sense key, additional sense code, additional sense code qualifier)
+    async fn request_sense(&mut self) -> Result<(u8, u8, u8)> {
+        let mut buf = [0u8; SENSE_DATA_SIZE];
+        let cb = [SCSI_REQUEST_SENSE, 0, 0, 0, SENSE_DATA_SIZE as u8, 0];
+        match self.command(&cb, DataPhase::In(&mut buf)).await? {
+            CommandStatus::Passed(_) => Ok((buf[2] & 0xF, buf[12], buf[13])),
+            CommandStatus::Failed => Err(Error::Failed("REQUEST SENSE failed")),
+        }
+    }
+    /// Returns (vendor, product, revision)
+    async fn inquiry(&mut self) -> Result<(String, String, String)> {
+        let mut buf = [0u8; INQUIRY_DATA_SIZE];
+        let cb = [SCSI_INQUIRY, 0, 0, 0, INQUIRY_DATA_SIZE as u8, 0];
+        self.scsi_command(&cb, DataPhase::In(&mut buf)).await?;
+        let field =
+            |range: core::ops::Range<usize>| String::from_utf8_lossy(&buf[range]).trim().into();
+        Ok((field(8..16), field(16..32), field(32..36)))
+    }
+    async fn wait_until_ready(&mut self) -> Result<()> {
+        let cb = [SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0];
+        for _ in 0..TEST_UNIT_READY_RETRY_COUNT {
+            // Devices report UNIT ATTENTION or NOT READY for a while after the attachment
+            match self.scsi_command(&cb, DataPhase::None).await {
+                Ok(_) => return Ok(()),
+                Err(e) => info!("usb_mass_storage: not ready yet: {e:?}"),
+            }
+            TimeoutFuture::new_ms(TEST_UNIT_READY_RETRY_INTERVAL_MS).await;
+        }
+        Err(Error::Failed(
+            "usb_mass_storage: the unit did not become ready",
+        ))
+    }
+    /// Returns (number of blocks, block size in bytes)
+    async fn read_capacity(&mut self) -> Result<(u64, usize)> {
+        let mut buf = [0u8; 8];
+        let cb = [SCSI_READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        if self.scsi_command(&cb, DataPhase::In(&mut buf)).await? != buf.len() {
+            return Err(Error::Failed("READ CAPACITY: short response"));
+        }
+        Ok(parse_read_capacity_10(&buf))
+    }
+    async fn read_10(&mut self, lba: u32, num_blocks: u16, buf: &mut [u8]) -> Result<()> {
+        let cb = rw_10_command_block(SCSI_READ_10, lba, num_blocks);
+        let len = buf.len();
+        if self.scsi_command(&cb, DataPhase::In(buf)).await? != len {
+            return Err(Error::Failed("READ(10): short read"));
+        }
+        Ok(())
+    }
+    async fn write_10(&mut self, lba: u32, num_blocks: u16, data: &[u8]) -> Result<()> {
+        let cb = rw_10_command_block(SCSI_WRITE_10, lba, num_blocks);
+        if self.scsi_command(&cb, DataPhase::Out(data)).await? != data.len() {
+            return Err(Error::Failed("WRITE(10): short write"));
+        }
+        Ok(())
+    }
+}
+
+fn rw_10_command_block(opcode: u8, lba: u32, num_blocks: u16) -> [u8; 10] {
+    let lba = lba.to_be_bytes();
+    let num_blocks = num_blocks.to_be_bytes();
+    [
+        opcode,
+        0,
+        lba[0],
+        lba[1],
+        lba[2],
+        lba[3],
+        0,
+        num_blocks[0],
+        num_blocks[1],
+        0,
+    ]
+}
+
+/// Returns (number of blocks, block size in bytes) from READ CAPACITY (10) parameter data
+fn parse_read_capacity_10(buf: &[u8; 8]) -> (u64, usize) {
+    let last_lba = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let block_size = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    (last_lba as u64 + 1, block_size as usize)
+}
+
+struct UsbMassStorage {
+    name: String,
+    block_size: usize,
+    num_blocks: u64,
+    requests: Mutex<VecDeque<Rc<BlockRequest>>>,
+}
+impl BlockDevice for UsbMassStorage {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+    fn push_request(&self, req: Rc<BlockRequest>) -> Result<()> {
+        self.requests.lock().push_back(req);
+        Ok(())
+    }
+}
+
+async fn serve_request(
+    bot: &mut BulkOnlyTransport,
+    storage: &UsbMassStorage,
+    req: &BlockRequest,
+) -> Result<Box<[u8]>> {
+    let block_size = storage.block_size;
+    let blocks_per_command = MAX_TRANSFER_SIZE / block_size;
+    let mut data = match req.op() {
+        BlockOp::Read => vec![0u8; req.num_blocks() * block_size].into_boxed_slice(),
+        BlockOp::Write(_) => Box::default(),
+    };
+    let mut done = 0;
+    while done < req.num_blocks() {
+        let n = (req.num_blocks() - done).min(blocks_per_command);
+        let lba = u32::try_from(req.lba() + done as u64)
+            .or(Err(Error::Failed("LBA out of range for READ/WRITE(10)")))?;
+        let range = done * block_size..(done + n) * block_size;
+        match req.op() {
+            BlockOp::Read => bot.read_10(lba, n as u16, &mut data[range]).await?,
+            BlockOp::Write(src) => bot.write_10(lba, n as u16, &src[range]).await?,
+        }
+        done += n;
+    }
+    Ok(data)
+}
+
+fn pick_config(
+    descriptors: &[UsbDescriptor],
+) -> Result<(
+    ConfigDescriptor,
+    InterfaceDescriptor,
+    EndpointDescriptor,
+    EndpointDescriptor,
+)> {
+    let mut config_desc: Option<ConfigDescriptor> = None;
+    let mut interface_desc: Option<InterfaceDescriptor> = None;
+    let mut bulk_in: Option<EndpointDescriptor> = None;
+    let mut bulk_out: Option<EndpointDescriptor> = None;
+    for d in descriptors {
+        match d {
+            UsbDescriptor::Config(e) => {
+                if interface_desc.is_some() {
+                    break;
+                }
+                config_desc = Some(*e);
+            }
+            UsbDescriptor::Interface(e) => {
+                if interface_desc.is_some() {
+                    break;
+                }
+                if e.triple() == USB_MASS_STORAGE_TRIPLE && e.alt_setting() == 0 {
+                    interface_desc = Some(*e);
+                }
+            }
+            UsbDescriptor::Endpoint(e) if interface_desc.is_some() => match EndpointType::from(e) {
+                EndpointType::BulkIn => bulk_in = Some(*e),
+                EndpointType::BulkOut => bulk_out = Some(*e),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Ok((
+        config_desc.ok_or(Error::Failed("No config for the USB mass storage found"))?,
+        interface_desc.ok_or(Error::Failed("No Bulk-Only interface found"))?,
+        bulk_in.ok_or(Error::Failed("No Bulk-In endpoint found"))?,
+        bulk_out.ok_or(Error::Failed("No Bulk-Out endpoint found"))?,
+    ))
+}
+
+async fn init_usb_mass_storage(
+    mut ddc: UsbDeviceDriverContext,
+) -> Result<(BulkOnlyTransport, UsbMassStorage)> {
+    let (config_desc, interface_desc, bulk_in, bulk_out) = pick_config(ddc.descriptors())?;
+    ddc.set_config(config_desc.config_value()).await?;
+    let slot = ddc.slot();
+    let mut bot = BulkOnlyTransport {
+        ddc,
+        interface_number: interface_desc.interface_number(),
+        bulk_in,
+        bulk_out,
+        tag: 0,
+    };
+    let (vendor, product, revision) = bot.inquiry().await?;
+    bot.wait_until_ready().await?;
+    let (num_blocks, block_size) = bot.read_capacity().await?;
+    if block_size == 0 || block_size > MAX_TRANSFER_SIZE {
+        return Err(Error::FailedString(format!(
+            "Unsupported block size: {block_size}"
+        )));
+    }
+    info!(
+        "usb_mass_storage is ready: {vendor} {product} {revision}, {num_blocks} blocks * {block_size} bytes"
+    );
+    let storage = UsbMassStorage {
+        name: format!("usb{slot}"),
+        block_size,
+        num_blocks,
+        requests: Mutex::new(VecDeque::new()),
+    };
+    Ok((bot, storage))
+}
+
+pub async fn attach_usb_device(ddc: UsbDeviceDriverContext) -> Result<()> {
+    let (mut bot, storage) = init_usb_mass_storage(ddc).await?;
+    let storage = Rc::new(storage);
+    {
+        let storage = Rc::downgrade(&storage);
+        register_block_device(storage);
+    }
+
+    loop {
+        if !bot.ddc.is_attached() {
+            for req in storage.requests.lock().drain(..) {
+                req.complete(Err(Error::Failed("USB mass storage disconnected")));
+            }
+            return Err(Error::FailedString(format!(
+                "slot {} detached",
+                bot.ddc.slot()
+            )));
+        }
+        let req = storage.requests.lock().pop_front();
+        if let Some(req) = req {
+            let result = serve_request(&mut bot, &storage, &req).await;
+            req.complete(result);
+        } else {
+            TimeoutFuture::new_ms(REQUEST_POLL_INTERVAL_MS).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn cbw_is_encoded() {
+        let cbw = CommandBlockWrapper::new(0x12345678, 36, true, &[SCSI_INQUIRY, 0, 0, 0, 36, 0])
+            .expect("CBW should be created")
+            .to_bytes();
+        assert_eq!(&cbw[0..4], b"USBC");
+        assert_eq!(&cbw[4..8], &[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(&cbw[8..12], &[36, 0, 0, 0]);
+        assert_eq!(cbw[12], 0x80);
+        assert_eq!(cbw[14], 6);
+        assert_eq!(&cbw[15..21], &[SCSI_INQUIRY, 0, 0, 0, 36, 0]);
+        assert!(cbw[21..].iter().all(|v| *v == 0));
+        assert!(CommandBlockWrapper::new(1, 0, false, &[0; 17]).is_err());
+    }
+    #[test_case]
+    fn csw_is_validated() {
+        let mut csw = [0u8; CSW_SIZE];
+        csw[0..4].copy_from_slice(b"USBS");
+        csw[4..8].copy_from_slice(&7u32.to_le_bytes());
+        csw[8..12].copy_from_slice(&512u32.to_le_bytes());
+        csw[12] = CSW_STATUS_FAILED;
+        assert_eq!(
+            CommandStatusWrapper::parse(&csw, 7),
+            Ok(CommandStatusWrapper {
+                data_residue: 512,
+                status: CSW_STATUS_FAILED
+            })
+        );
+        assert!(CommandStatusWrapper::parse(&csw, 8).is_err());
+        assert!(CommandStatusWrapper::parse(&csw[..12], 7).is_err());
+        csw[3] = b'C';
+        assert!(CommandStatusWrapper::parse(&csw, 7).is_err());
+    }
+    #[test_case]
+    fn read_capacity_is_parsed() {
+        assert_eq!(
+            parse_read_capacity_10(&[0, 0, 0x07, 0xFF, 0, 0, 0x02, 0]),
+            (2048, 512)
+        );
+    }
+    #[test_case]
+    fn rw_10_command_block_is_big_endian() {
+        assert_eq!(
+            rw_10_command_block(SCSI_READ_10, 0x01020304, 0x0506),
+            [SCSI_READ_10, 0, 1, 2, 3, 4, 0, 5, 6, 0]
+        );
+    }
+}
@@ -1,8 +1,31 @@
+//! Framebuffer and the back buffer of the screen
+//!
+//! The screen has two layers: the desktop layer at the bottom, where the kernel
+//! console and the direct drawings by apps go, and the windows on top of it.
+//! The compositor composes the windows over the desktop layer into the back
+//! buffer, and the regions marked as dirty are copied to the framebuffer
+//! provided by GOP on each tick of the compositor (see compositor::compositor_tick_task()).
+//! This avoids showing half-drawn frames on the screen.
+//! The mouse cursor is drawn only on the framebuffer (see cursor::Cursor).
+
+extern crate alloc;
+
+use crate::boot_info::BootInfo;
+use crate::cursor::Cursor;
 use crate::efi::locate_graphic_protocol;
 use crate::efi::EfiSystemTable;
 use crate::error::Result;
+use crate::memory::ContiguousPhysicalMemoryPages;
+use crate::mutex::Mutex;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
 use core::pin::Pin;
+use noli::bitmap::bitmap_blit;
 use noli::bitmap::Bitmap;
+use noli::rect::Rect;
+
+// Dirty rects more than this are merged into one to bound the cost of copying.
+const MAX_DIRTY_RECTS: usize = 16;
 
 #[derive(Clone, Copy)]
 pub struct VRAMBufferInfo {
@@ -42,3 +65,178 @@ pub fn init_vram(efi_system_table: Pin<&EfiSystemTable>) -> Result<VRAMBufferInf
         pixels_per_line: gp.mode.info.pixels_per_scan_line as usize,
     })
 }
+
+/// A list of regions to be redrawn.
+/// Overlapping rects are merged into one, and all the rects are merged
+/// into one if there are too many rects.
+#[derive(Debug, Default)]
+pub struct DirtyRects {
+    rects: Vec<Rect>,
+}
+impl DirtyRects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        if let Some(r) = self
+            .rects
+            .iter_mut()
+            .find(|r| r.intersection(&rect).is_some())
+        {
+            *r = r.union(&rect);
+            return;
+        }
+        if self.rects.len() >= MAX_DIRTY_RECTS {
+            let merged = self.rects.drain(..).fold(rect, |acc, r| acc.union(&r));
+            self.rects.push(merged);
+        } else {
+            self.rects.push(rect);
+        }
+    }
+    pub fn take(&mut self) -> Vec<Rect> {
+        core::mem::take(&mut self.rects)
+    }
+}
+
+static BACK_BUFFER: Mutex<Option<Rc<BackBuffer>>> = Mutex::new(None);
+
+/// Allocates a buffer with the same size as the framebuffer,
+/// filled with what is on the screen now.
+fn alloc_screen_buffer(front: &VRAMBufferInfo) -> Result<VRAMBufferInfo> {
+    let mut pages = ContiguousPhysicalMemoryPages::alloc_bytes(
+        front.pixels_per_line * front.height * front.bytes_per_pixel() as usize,
+    )?;
+    let mut buf = VRAMBufferInfo {
+        buf: pages.as_mut_slice().as_mut_ptr(),
+        ..*front
+    };
+    // The pages are used as long as the system is running.
+    // This is fine since ContiguousPhysicalMemoryPages does not free the region on drop.
+    let rect = Rect::new(0, 0, front.width(), front.height()).expect("Invalid screen size");
+    bitmap_blit(&mut buf, front, &rect, 0, 0);
+    Ok(buf)
+}
+
+/// The back buffer of the screen, which has the same size as the framebuffer.
+pub struct BackBuffer {
+    front: VRAMBufferInfo,
+    // The windows composed over the desktop layer
+    back: VRAMBufferInfo,
+    desktop: VRAMBufferInfo,
+    // Regions of the back buffer to be copied to the framebuffer
+    dirty: Mutex<DirtyRects>,
+    // Regions of the desktop layer to be composed again
+    desktop_damage: Mutex<DirtyRects>,
+    cursor: Mutex<Cursor>,
+}
+impl BackBuffer {
+    fn new() -> Result<Self> {
+        let front = BootInfo::take().vram();
+        Ok(Self {
+            front,
+            back: alloc_screen_buffer(&front)?,
+            desktop: alloc_screen_buffer(&front)?,
+            dirty: Mutex::new(DirtyRects::new()),
+            desktop_damage: Mutex::new(DirtyRects::new()),
+            cursor: Mutex::new(Cursor::new()),
+        })
+    }
+    /// Returns the back buffer. It is allocated on the first call.
+    pub fn take() -> Rc<Self> {
+        let mut instance = BACK_BUFFER.lock();
+        let instance = instance.get_or_insert_with(|| {
+            Rc::new(Self::new().expect("Failed to allocate the back buffer"))
+        });
+        instance.clone()
+    }
+    /// Returns the back buffer if it is already allocated.
+    pub fn try_take() -> Option<Rc<Self>> {
+        BACK_BUFFER.lock().clone()
+    }
+    /// Returns the bitmap of the desktop layer, which is under all the windows.
+    /// Regions drawn via this should be marked with damage()
+    /// so that they are composed with the windows.
+    pub fn bitmap(&self) -> VRAMBufferInfo {
+        self.desktop
+    }
+    pub fn rect(&self) -> Rect {
+        Rect::new(0, 0, self.back.width(), self.back.height()).expect("Invalid screen size")
+    }
+    /// Marks the `rect` of the desktop layer as updated,
+    /// so that it is composed with the windows again.
+    pub fn damage(&self, rect: Rect) {
+        if let Some(rect) = rect.intersection(&self.rect()) {
+            self.desktop_damage.lock().add(rect);
+        }
+    }
+    pub fn take_desktop_damage(&self) -> Vec<Rect> {
+        self.desktop_damage.lock().take()
+    }
+    /// Returns the bitmap where the compositor composes the screen.
+    /// Regions drawn via this should be marked with mark_dirty().
+    pub fn composed_bitmap(&self) -> VRAMBufferInfo {
+        self.back
+    }
+    /// Marks the `rect` as dirty, so that it is copied to the framebuffer on the next flush().
+    pub fn mark_dirty(&self, rect: Rect) {
+        if let Some(rect) = rect.intersection(&self.rect()) {
+            self.dirty.lock().add(rect);
+        }
+    }
+    /// Shows the updated regions of the desktop layer as is, over the windows.
+    /// This is for the panic handler, since the compositor will not run anymore.
+    pub fn flush_desktop(&self) {
+        for rect in self.take_desktop_damage() {
+            let mut back = self.back;
+            bitmap_blit(&mut back, &self.desktop, &rect, rect.x(), rect.y());
+            self.mark_dirty(rect);
+        }
+        self.flush();
+    }
+    /// Moves the mouse cursor to (x, y) on the next flush().
+    pub fn move_cursor(&self, x: i64, y: i64) {
+        self.cursor.lock().set_position(x, y);
+    }
+    /// Copies the dirty regions of the back buffer to the framebuffer
+    /// and draws the mouse cursor on it.
+    pub fn flush(&self) {
+        let dirty = self.dirty.lock().take();
+        let mut cursor = self.cursor.lock();
+        if dirty.is_empty() && !cursor.is_moved() {
+            return;
+        }
+        let mut front = self.front;
+        cursor.hide(&mut front);
+        for rect in dirty {
+            bitmap_blit(&mut front, &self.back, &rect, rect.x(), rect.y());
+        }
+        cursor.show(&mut front);
+    }
+}
+
+#[test_case]
+fn dirty_rects_are_merged() {
+    let mut dirty = DirtyRects::new();
+    dirty.add(Rect::new(0, 0, 10, 10).unwrap());
+    dirty.add(Rect::new(5, 5, 10, 10).unwrap());
+    dirty.add(Rect::new(100, 100, 10, 10).unwrap());
+    dirty.add(Rect::new(0, 0, 0, 0).unwrap());
+    assert_eq!(
+        dirty.take(),
+        alloc::vec![
+            Rect::new(0, 0, 15, 15).unwrap(),
+            Rect::new(100, 100, 10, 10).unwrap()
+        ]
+    );
+    assert!(dirty.take().is_empty());
+    for i in 0..(MAX_DIRTY_RECTS as i64 + 1) {
+        dirty.add(Rect::new(i * 10, 0, 5, 5).unwrap());
+    }
+    assert_eq!(
+        dirty.take(),
+        alloc::vec![Rect::new(0, 0, MAX_DIRTY_RECTS as i64 * 10 + 5, 5).unwrap()]
+    );
+}
@@ -58,7 +58,9 @@ pub mod apic;
 pub mod context;
 pub mod gdt;
 pub mod idt;
+pub mod interrupt;
 pub mod paging;
+pub mod smp;
 pub mod syscall;
 
 extern crate alloc;
@@ -79,10 +81,14 @@ pub const USER_DS: u16 = 4 << 3 | 0b11 /* RPL=3 */;
 pub const USER64_CS: u16 = 5 << 3 | 0b11 /* RPL=3 */;
 pub const TSS64_SEL: u16 = 6 << 3;
 
+// Interrupt Enable Flag
+pub const RFLAGS_IF: u64 = 1 << 9;
+
 pub const MSR_IA32_APIC_BASE: u32 = 0x1b;
 pub const MSR_FSB_FREQ: u32 = 0xcd;
 pub const MSR_PLATFORM_INFO: u32 = 0xce;
 pub const MSR_X2APIC_EOI: u32 = 0x80b;
+pub const MSR_X2APIC_ICR: u32 = 0x830;
 pub const MSR_EFER: u32 = 0xC0000080;
 pub const MSR_STAR: u32 = 0xC0000081;
 pub const MSR_LSTAR: u32 = 0xC0000082;
@@ -1,8 +1,34 @@
+extern crate alloc;
+
+use crate::acpi::Madt;
+use crate::acpi::MadtInterruptSourceOverride;
 use crate::error::Error;
 use crate::error::Result;
+use crate::info;
+use crate::mutex::Mutex;
 use crate::x86_64;
+use crate::x86_64::interrupt::TIMER_VECTOR;
 use crate::x86_64::CpuidRequest;
+use alloc::vec::Vec;
+use core::ptr::read_volatile;
 use core::ptr::write_volatile;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+const LAPIC_REG_ID: usize = 0x20;
+const LAPIC_REG_EOI: usize = 0xB0;
+const LAPIC_REG_ICR_LOW: usize = 0x300;
+const LAPIC_REG_ICR_HIGH: usize = 0x310;
+
+const ICR_DELIVERY_MODE_FIXED: u32 = 0b000 << 8;
+const ICR_DELIVERY_MODE_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_MODE_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_STATUS_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+/// Base address of the xAPIC registers, or 0 if the Local APIC is in x2APIC mode
+/// or not initialized yet. All the processors share the same address.
+static LOCAL_APIC_BASE_ADDR: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -13,15 +39,69 @@ pub struct LocalApic {
 }
 
 impl LocalApic {
-    fn id(&self) -> u32 {
+    pub fn id(&self) -> u32 {
         self.x2apic_id
     }
     pub fn notify_end_of_interrupt(&self) {
         // This is safe as far as this LocalApic struct is properly set up.
         unsafe {
-            write_volatile((self.base_addr as usize + 0xB0) as *mut u32, 0);
+            write_volatile((self.base_addr as usize + LAPIC_REG_EOI) as *mut u32, 0);
+        }
+    }
+    /// Returns the APIC ID of the processor which executes this function.
+    /// This does not use CPUID so that it can be called frequently (e.g. from Mutex).
+    pub fn current_id() -> u32 {
+        let base_addr = LOCAL_APIC_BASE_ADDR.load(Ordering::Relaxed);
+        if base_addr != 0 {
+            // This is safe since base_addr is set only after the xAPIC is detected.
+            unsafe { read_volatile((base_addr as usize + LAPIC_REG_ID) as *const u32) >> 24 }
+        } else {
+            // x2APIC mode, or the Local APIC is not initialized yet.
+            x86_64::read_cpuid(CpuidRequest { eax: 0x0b, ecx: 0 }).edx()
         }
     }
+    fn write_icr(&self, dest_apic_id: u32, value: u32) {
+        if self.status.x2apic_mode_enable {
+            // This is safe since the ICR MSR exists when the x2APIC mode is enabled.
+            unsafe {
+                x86_64::write_msr(
+                    x86_64::MSR_X2APIC_ICR,
+                    ((dest_apic_id as u64) << 32) | value as u64,
+                );
+            }
+            return;
+        }
+        let icr_high = (self.base_addr as usize + LAPIC_REG_ICR_HIGH) as *mut u32;
+        let icr_low = (self.base_addr as usize + LAPIC_REG_ICR_LOW) as *mut u32;
+        // This is safe as far as this LocalApic struct is properly set up.
+        unsafe {
+            write_volatile(icr_high, dest_apic_id << 24);
+            // Writing to the lower half triggers sending the IPI.
+            write_volatile(icr_low, value);
+            while read_volatile(icr_low) & ICR_DELIVERY_STATUS_PENDING != 0 {
+                x86_64::busy_loop_hint();
+            }
+        }
+    }
+    /// Sends an interrupt of the vector to the processor.
+    pub fn send_fixed_ipi(&self, dest_apic_id: u32, vector: u8) {
+        self.write_icr(
+            dest_apic_id,
+            ICR_DELIVERY_MODE_FIXED | ICR_LEVEL_ASSERT | vector as u32,
+        )
+    }
+    /// Sends an INIT IPI to the processor to make it wait for a Startup IPI.
+    pub fn send_init_ipi(&self, dest_apic_id: u32) {
+        self.write_icr(dest_apic_id, ICR_DELIVERY_MODE_INIT | ICR_LEVEL_ASSERT)
+    }
+    /// Sends a Startup IPI (SIPI) to the processor.
+    /// The processor will start its execution in real mode at (vector << 12).
+    pub fn send_startup_ipi(&self, dest_apic_id: u32, vector: u8) {
+        self.write_icr(
+            dest_apic_id,
+            ICR_DELIVERY_MODE_STARTUP | ICR_LEVEL_ASSERT | vector as u32,
+        )
+    }
 }
 impl Default for LocalApic {
     /// creates an instance to manage Local APIC for the current processor
@@ -33,9 +113,13 @@ impl Default for LocalApic {
             x86_64::read_msr(x86_64::MSR_IA32_APIC_BASE)
         };
         let status = LocalApicStatus::new(apic_base);
+        let base_addr = apic_base & !((1u64 << 12) - 1);
+        if !status.x2apic_mode_enable {
+            LOCAL_APIC_BASE_ADDR.store(base_addr, Ordering::Relaxed);
+        }
         Self {
             x2apic_id,
-            base_addr: apic_base & !((1u64 << 12) - 1),
+            base_addr,
             status,
         }
     }
@@ -59,63 +143,160 @@ impl LocalApicStatus {
     }
 }
 
-const IOAPIC_INDEX_ADDR: *mut u8 = 0xfec00000 as *mut u8;
-const IOAPIC_DATA_ADDR: *mut u32 = 0xfec00010 as *mut u32;
-const IOAPIC_EOIR_ADDR: *mut u32 = 0xfec00040 as *mut u32;
+// Offsets from the base address of an I/O APIC
+const IOAPIC_INDEX: usize = 0x00;
+const IOAPIC_DATA: usize = 0x10;
+const IOAPIC_REG_VERSION: usize = 0x01;
+const IOAPIC_REG_REDIRECTION_TABLE_BASE: usize = 0x10;
+
+const REDIRECTION_ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_ENTRY_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_ENTRY_MASKED: u64 = 1 << 16;
+
+/// An I/O APIC listed in the MADT.
+/// Its inputs are numbered as Global System Interrupts (GSIs), starting from gsi_base.
+#[derive(Debug, Copy, Clone)]
+struct IoApicInfo {
+    base_addr: usize,
+    gsi_base: u32,
+    num_entries: u32,
+}
+
+static IO_APICS: Mutex<Vec<IoApicInfo>> = Mutex::new(Vec::new());
+static ISA_IRQ_OVERRIDES: Mutex<Vec<MadtInterruptSourceOverride>> = Mutex::new(Vec::new());
 
 pub struct IoApic {}
 impl IoApic {
-    fn read_register(index: usize) -> Result<u32> {
+    fn read_register(base_addr: usize, index: usize) -> Result<u32> {
         if index >= 0x100 {
             Err(Error::ApicRegIndexOutOfRange)
         } else {
-            // This is safe since the index is checked
+            // This is safe since the index is checked and base_addr is taken from the MADT
             unsafe {
-                IOAPIC_INDEX_ADDR.write_volatile(index as u8);
-                Ok(IOAPIC_DATA_ADDR.read_volatile())
+                write_volatile((base_addr + IOAPIC_INDEX) as *mut u8, index as u8);
+                Ok(read_volatile((base_addr + IOAPIC_DATA) as *const u32))
             }
         }
     }
-    fn write_register(index: usize, data: u32) -> Result<()> {
+    fn write_register(base_addr: usize, index: usize, data: u32) -> Result<()> {
         if index >= 0x100 {
             Err(Error::ApicRegIndexOutOfRange)
         } else {
-            // This is safe since the index is checked
+            // This is safe since the index is checked and base_addr is taken from the MADT
             unsafe {
-                IOAPIC_INDEX_ADDR.write_volatile(index as u8);
-                IOAPIC_DATA_ADDR.write_volatile(data);
+                write_volatile((base_addr + IOAPIC_INDEX) as *mut u8, index as u8);
+                write_volatile((base_addr + IOAPIC_DATA) as *mut u32, data);
             }
             Ok(())
         }
     }
-    pub fn notify_end_of_interrupt(vector: u8) {
-        unsafe { IOAPIC_EOIR_ADDR.write_volatile(vector as u32) }
-    }
-    pub fn read_redirection_entry(irq: usize) -> Result<u64> {
-        if irq < 24 {
-            let v = (
-                Self::read_register(0x10 + irq * 2),
-                Self::read_register(0x10 + irq * 2 + 1),
-            );
-            if let (Ok(low), Ok(high)) = v {
-                return Ok((low as u64) | (high as u64) << 32);
-            }
-        }
-        Err(Error::ApicRegIndexOutOfRange)
+    fn find_io_apic(gsi: u32) -> Result<(usize, usize)> {
+        IO_APICS
+            .lock()
+            .iter()
+            .find(|e| (e.gsi_base..e.gsi_base + e.num_entries).contains(&gsi))
+            .map(|e| (e.base_addr, (gsi - e.gsi_base) as usize))
+            .ok_or(Error::Failed("No I/O APIC found for the GSI"))
+    }
+    pub fn read_redirection_entry(gsi: u32) -> Result<u64> {
+        let (base_addr, index) = Self::find_io_apic(gsi)?;
+        let index = IOAPIC_REG_REDIRECTION_TABLE_BASE + index * 2;
+        let low = Self::read_register(base_addr, index)?;
+        let high = Self::read_register(base_addr, index + 1)?;
+        Ok((low as u64) | (high as u64) << 32)
     }
-    fn write_redirection_entry(irq: usize, entry: u64) -> Result<()> {
-        Self::write_register(0x10 + irq * 2, entry as u32)?;
-        Self::write_register(0x10 + irq * 2 + 1, (entry >> 32) as u32)?;
+    fn write_redirection_entry(gsi: u32, entry: u64) -> Result<()> {
+        let (base_addr, index) = Self::find_io_apic(gsi)?;
+        let index = IOAPIC_REG_REDIRECTION_TABLE_BASE + index * 2;
+        // Write the higher half first since writing the lower half can unmask the entry
+        Self::write_register(base_addr, index + 1, (entry >> 32) as u32)?;
+        Self::write_register(base_addr, index, entry as u32)?;
         Ok(())
     }
-    fn set_redirection(from_irq: usize, to_vector: u8, to_apic: u32) -> Result<()> {
-        let entry: u64 = ((to_apic as u64) << 56) | (to_vector as u64);
-        Self::write_redirection_entry(from_irq, entry)
+    fn set_redirection(
+        gsi: u32,
+        to_vector: u8,
+        to_apic: u32,
+        is_active_low: bool,
+        is_level_triggered: bool,
+    ) -> Result<()> {
+        let mut entry: u64 = ((to_apic as u64) << 56) | (to_vector as u64);
+        if is_active_low {
+            entry |= REDIRECTION_ENTRY_ACTIVE_LOW;
+        }
+        if is_level_triggered {
+            entry |= REDIRECTION_ENTRY_LEVEL_TRIGGERED;
+        }
+        Self::write_redirection_entry(gsi, entry)
+    }
+    /// Routes an ISA IRQ to the vector, taking the interrupt source overrides into account.
+    pub fn route_isa_irq(irq: u8, to_vector: u8, to_apic: u32) -> Result<()> {
+        let ovr = ISA_IRQ_OVERRIDES
+            .lock()
+            .iter()
+            .find(|e| e.source_irq == irq)
+            .cloned();
+        match ovr {
+            Some(ovr) => Self::set_redirection(
+                ovr.gsi,
+                to_vector,
+                to_apic,
+                ovr.is_active_low(),
+                ovr.is_level_triggered(),
+            ),
+            None => Self::set_redirection(irq as u32, to_vector, to_apic, false, false),
+        }
+    }
+    /// Routes a GSI connected to a PCI INTx line (level-triggered, active-low) to the vector.
+    pub fn route_pci_gsi(gsi: u32, to_vector: u8, to_apic: u32) -> Result<()> {
+        Self::set_redirection(gsi, to_vector, to_apic, true, true)
     }
-    pub fn init(bsp_lapic: &LocalApic) -> Result<()> {
+    pub fn mask(gsi: u32) -> Result<()> {
+        let entry = Self::read_redirection_entry(gsi)?;
+        Self::write_redirection_entry(gsi, entry | REDIRECTION_ENTRY_MASKED)
+    }
+    pub fn init(madt: &Madt, bsp_lapic: &LocalApic) -> Result<()> {
+        {
+            let mut io_apics = IO_APICS.lock();
+            io_apics.clear();
+            for e in madt.io_apics() {
+                let base_addr = e.address as usize;
+                let version = Self::read_register(base_addr, IOAPIC_REG_VERSION)?;
+                let num_entries = ((version >> 16) & 0xFF) + 1;
+                info!(
+                    "I/O APIC {}: {base_addr:#X}, GSI {}..{}",
+                    e.io_apic_id,
+                    e.gsi_base,
+                    e.gsi_base + num_entries
+                );
+                io_apics.push(IoApicInfo {
+                    base_addr,
+                    gsi_base: e.gsi_base,
+                    num_entries,
+                });
+            }
+            if io_apics.is_empty() {
+                return Err(Error::Failed("No I/O APIC found in the MADT"));
+            }
+        }
+        {
+            let mut overrides = ISA_IRQ_OVERRIDES.lock();
+            overrides.clear();
+            overrides.extend(madt.interrupt_source_overrides());
+        }
+        // Mask all the inputs until someone requests it
+        let gsis: Vec<u32> = IO_APICS
+            .lock()
+            .iter()
+            .flat_map(|e| e.gsi_base..e.gsi_base + e.num_entries)
+            .collect();
+        for gsi in gsis {
+            Self::mask(gsi)?;
+        }
         let to_apic_id = bsp_lapic.id();
-        Self::set_redirection(2, 32, to_apic_id)?; // HPET
-        Self::set_redirection(0, 32, to_apic_id)?; // HPET
+        // HPET timer 0 is routed to IRQ0 (GSI 0) or its overridden GSI (typically GSI 2)
+        Self::set_redirection(0, TIMER_VECTOR, to_apic_id, false, false)?;
+        Self::route_isa_irq(0, TIMER_VECTOR, to_apic_id)?;
         Ok(())
     }
 }
@@ -1,5 +1,6 @@
 extern crate alloc;
 
+use crate::compositor::Compositor;
 use crate::error::Result;
 use crate::executor::block_on;
 use crate::executor::yield_execution;
@@ -220,7 +221,9 @@ pub extern "sysv64" fn exec_app_context_proc_func(proc_ctx_ptr: u64) {
     Scheduler::root().exit_current_process(exit_code);
 }
 
-pub async fn exec_app_context(proc_context: Box<ProcessContext>) -> Result<i64> {
+pub async fn exec_app_context(mut proc_context: Box<ProcessContext>) -> Result<i64> {
+    // The initial state of the app (e.g. RIP and RSP) is passed via the context of the app process.
+    *CONTEXT_APP.lock() = proc_context.context().lock().clone();
     let mut proc_context = Some(proc_context);
     let mut retcode: i64;
     loop {
@@ -355,6 +358,9 @@ pub async fn exec_app_context(proc_context: Box<ProcessContext>) -> Result<i64>
         }
         yield_execution().await;
     }
+    if let Some(proc_context) = &proc_context {
+        Compositor::take().destroy_windows_of(proc_context.pid());
+    }
     Ok(retcode)
 }
 
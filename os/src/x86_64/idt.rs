@@ -5,9 +5,20 @@ use crate::error;
 use crate::error::Result;
 use crate::info;
 use crate::memory::alloc_pages;
+use crate::process::exit_code_for_exception;
+use crate::process::CURRENT_PROCESS;
+use crate::syscall::exit_to_os;
+use crate::syscall::interrupt_current_app;
+use crate::syscall::is_current_app_interrupted;
 use crate::util::PAGE_SIZE;
+use crate::x86_64::interrupt::handle_device_interrupt;
+use crate::x86_64::interrupt::DEVICE_VECTOR_RANGE;
+use crate::x86_64::interrupt::TIMER_VECTOR;
 use crate::x86_64::read_cr2;
+use crate::x86_64::write_ss;
+use crate::x86_64::KERNEL_DS;
 use alloc::boxed::Box;
+use alloc::string::ToString;
 use core::arch::asm;
 use core::arch::global_asm;
 use core::fmt;
@@ -165,22 +176,121 @@ macro_rules! interrupt_entrypoint_with_ecode {
     };
 }
 
+interrupt_entrypoint!(0);
 interrupt_entrypoint!(3);
 interrupt_entrypoint!(6);
 interrupt_entrypoint_with_ecode!(8);
 interrupt_entrypoint_with_ecode!(13);
 interrupt_entrypoint_with_ecode!(14);
 interrupt_entrypoint!(32);
+interrupt_entrypoint!(33);
+interrupt_entrypoint!(34);
+interrupt_entrypoint!(35);
+interrupt_entrypoint!(36);
+interrupt_entrypoint!(37);
+interrupt_entrypoint!(38);
+interrupt_entrypoint!(39);
+interrupt_entrypoint!(40);
+interrupt_entrypoint!(41);
+interrupt_entrypoint!(42);
+interrupt_entrypoint!(43);
+interrupt_entrypoint!(44);
+interrupt_entrypoint!(45);
+interrupt_entrypoint!(46);
+interrupt_entrypoint!(47);
+interrupt_entrypoint!(48);
+interrupt_entrypoint!(49);
+interrupt_entrypoint!(50);
+interrupt_entrypoint!(51);
+interrupt_entrypoint!(52);
+interrupt_entrypoint!(53);
+interrupt_entrypoint!(54);
+interrupt_entrypoint!(55);
+interrupt_entrypoint!(56);
+interrupt_entrypoint!(57);
+interrupt_entrypoint!(58);
+interrupt_entrypoint!(59);
+interrupt_entrypoint!(60);
+interrupt_entrypoint!(61);
+interrupt_entrypoint!(62);
+interrupt_entrypoint!(63);
 
 extern "sysv64" {
+    fn interrupt_entrypoint0();
     fn interrupt_entrypoint3();
     fn interrupt_entrypoint6();
     fn interrupt_entrypoint8();
     fn interrupt_entrypoint13();
     fn interrupt_entrypoint14();
     fn interrupt_entrypoint32();
+    fn interrupt_entrypoint33();
+    fn interrupt_entrypoint34();
+    fn interrupt_entrypoint35();
+    fn interrupt_entrypoint36();
+    fn interrupt_entrypoint37();
+    fn interrupt_entrypoint38();
+    fn interrupt_entrypoint39();
+    fn interrupt_entrypoint40();
+    fn interrupt_entrypoint41();
+    fn interrupt_entrypoint42();
+    fn interrupt_entrypoint43();
+    fn interrupt_entrypoint44();
+    fn interrupt_entrypoint45();
+    fn interrupt_entrypoint46();
+    fn interrupt_entrypoint47();
+    fn interrupt_entrypoint48();
+    fn interrupt_entrypoint49();
+    fn interrupt_entrypoint50();
+    fn interrupt_entrypoint51();
+    fn interrupt_entrypoint52();
+    fn interrupt_entrypoint53();
+    fn interrupt_entrypoint54();
+    fn interrupt_entrypoint55();
+    fn interrupt_entrypoint56();
+    fn interrupt_entrypoint57();
+    fn interrupt_entrypoint58();
+    fn interrupt_entrypoint59();
+    fn interrupt_entrypoint60();
+    fn interrupt_entrypoint61();
+    fn interrupt_entrypoint62();
+    fn interrupt_entrypoint63();
 }
 
+// Entrypoints for the vectors in DEVICE_VECTOR_RANGE
+const DEVICE_INTERRUPT_ENTRYPOINTS: [unsafe extern "sysv64" fn(); 31] = [
+    interrupt_entrypoint33,
+    interrupt_entrypoint34,
+    interrupt_entrypoint35,
+    interrupt_entrypoint36,
+    interrupt_entrypoint37,
+    interrupt_entrypoint38,
+    interrupt_entrypoint39,
+    interrupt_entrypoint40,
+    interrupt_entrypoint41,
+    interrupt_entrypoint42,
+    interrupt_entrypoint43,
+    interrupt_entrypoint44,
+    interrupt_entrypoint45,
+    interrupt_entrypoint46,
+    interrupt_entrypoint47,
+    interrupt_entrypoint48,
+    interrupt_entrypoint49,
+    interrupt_entrypoint50,
+    interrupt_entrypoint51,
+    interrupt_entrypoint52,
+    interrupt_entrypoint53,
+    interrupt_entrypoint54,
+    interrupt_entrypoint55,
+    interrupt_entrypoint56,
+    interrupt_entrypoint57,
+    interrupt_entrypoint58,
+    interrupt_entrypoint59,
+    interrupt_entrypoint60,
+    interrupt_entrypoint61,
+    interrupt_entrypoint62,
+    interrupt_entrypoint63,
+];
+
 global_asm!(
     r#"
 .global inthandler_common
@@ -241,7 +351,22 @@ inthandler_common:
 
 #[no_mangle]
 extern "sysv64" fn inthandler(info: &InterruptInfo, index: usize) {
-    if index == 32 {
+    if index == TIMER_VECTOR as usize {
+        let bsp_local_apic = BootInfo::take().bsp_local_apic();
+        bsp_local_apic.notify_end_of_interrupt();
+        // Apps that do not make syscalls (e.g. busy loops) are terminated here on Ctrl-C
+        let is_user_mode = info.ctx.cs & 0b11 == 3;
+        if is_user_mode && is_current_app_interrupted() {
+            // SAFETY: KERNEL_DS is a valid data segment in the current GDT.
+            unsafe { write_ss(KERNEL_DS) };
+            interrupt_current_app();
+        }
+        return;
+    }
+    if DEVICE_VECTOR_RANGE.contains(&(index as u8)) {
+        handle_device_interrupt(index as u8);
+        // All the processors share the same address for the Local APIC registers,
+        // so this signals EOI to the Local APIC of the current processor.
         let bsp_local_apic = BootInfo::take().bsp_local_apic();
         bsp_local_apic.notify_end_of_interrupt();
         return;
@@ -249,6 +374,9 @@ extern "sysv64" fn inthandler(info: &InterruptInfo, index: usize) {
     error!("Interrupt Info: {:?}", info);
     error!("Exception {index:#04X}: ");
     match index {
+        0 => {
+            error!("Divide Error");
+        }
         3 => {
             error!("Breakpoint");
         }
@@ -299,9 +427,35 @@ extern "sysv64" fn inthandler(info: &InterruptInfo, index: usize) {
             error!("Not handled");
         }
     }
+    let is_user_mode = info.ctx.cs & 0b11 == 3;
+    if is_user_mode && matches!(index, 0 | 6 | 13 | 14) {
+        kill_current_app(info, index);
+    }
     panic!("fatal exception");
 }
 
+/// Terminates the app that caused the exception and returns to the OS,
+/// in the same way as the app calls sys_exit with an abnormal exit code.
+/// The kernel and the other tasks (e.g. the shell) keep running.
+fn kill_current_app(info: &InterruptInfo, index: usize) -> ! {
+    let name = CURRENT_PROCESS
+        .lock()
+        .as_ref()
+        .map(|proc| proc.name().to_string())
+        .unwrap_or_default();
+    let exit_code = exit_code_for_exception(index);
+    error!(
+        "App {name:?} is killed by exception {index:#04X} at RIP={:#018X}, CR2={:#018X} (exit code: {exit_code})",
+        info.ctx.rip,
+        read_cr2(),
+    );
+    // SS was loaded with a NULL selector by the CPU on the privilege level change,
+    // so recover it here before going back to the OS context.
+    // SAFETY: KERNEL_DS is a valid data segment in the current GDT.
+    unsafe { write_ss(KERNEL_DS) };
+    exit_to_os(exit_code as u64)
+}
+
 #[no_mangle]
 extern "sysv64" fn int_handler_unimplemented() {
     panic!("unexpected interrupt!");
@@ -376,6 +530,12 @@ impl Idt {
                 int_handler_unimplemented,
             ); 0x100],
         };
+        idt.entries[0] = IdtDescriptor::new(
+            segment_selector,
+            1,
+            IdtAttr::IntGateDPL0,
+            interrupt_entrypoint0,
+        );
         idt.entries[3] = IdtDescriptor::new(
             segment_selector,
             1,
@@ -407,12 +567,16 @@ impl Idt {
             IdtAttr::IntGateDPL0,
             interrupt_entrypoint14,
         );
-        idt.entries[32] = IdtDescriptor::new(
+        idt.entries[TIMER_VECTOR as usize] = IdtDescriptor::new(
             segment_selector,
             1,
             IdtAttr::IntGateDPL0,
             interrupt_entrypoint32,
         );
+        for (vector, f) in DEVICE_VECTOR_RANGE.zip(DEVICE_INTERRUPT_ENTRYPOINTS) {
+            idt.entries[vector as usize] =
+                IdtDescriptor::new(segment_selector, 1, IdtAttr::IntGateDPL0, f);
+        }
         let idt = Box::pin(idt);
         let params = IdtrParameters {
             limit: size_of::<Self>() as u16 - 1,
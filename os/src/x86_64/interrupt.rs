@@ -0,0 +1,231 @@
+//! Interrupt vector management
+//!
+//! Vector 32 is used for the timer (HPET), and the vectors in
+//! DEVICE_VECTOR_RANGE are allocated on demand for device interrupts
+//! (I/O APIC inputs or MSIs). The interrupt handler counts the interrupts
+//! for each vector and wakes the task waiting on it (see InterruptFuture).
+//! An optional handler function can be registered to acknowledge the device
+//! in the interrupt context (e.g. for level-triggered interrupts).
+
+use crate::error::Error;
+use crate::error::Result;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::Range;
+use core::pin::Pin;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+pub const TIMER_VECTOR: u8 = 32;
+// This should be in sync with the entrypoints defined in idt.rs
+pub const DEVICE_VECTOR_RANGE: Range<u8> = 33..64;
+
+/// Called in the interrupt context with the arg given on the registration.
+pub type InterruptHandler = fn(u64);
+
+struct VectorState {
+    is_allocated: AtomicBool,
+    count: AtomicU64,
+    // InterruptHandler as usize, or 0 if no handler is registered
+    handler: AtomicUsize,
+    handler_arg: AtomicU64,
+    // The waker of the task waiting on this vector, guarded by waker_lock.
+    // The lock is never spun on in the interrupt context to avoid deadlocks.
+    waker_lock: AtomicBool,
+    waker: UnsafeCell<Option<Waker>>,
+}
+// SAFETY: waker is only accessed while waker_lock is held.
+unsafe impl Sync for VectorState {}
+impl VectorState {
+    const fn new() -> Self {
+        Self {
+            is_allocated: AtomicBool::new(false),
+            count: AtomicU64::new(0),
+            handler: AtomicUsize::new(0),
+            handler_arg: AtomicU64::new(0),
+            waker_lock: AtomicBool::new(false),
+            waker: UnsafeCell::new(None),
+        }
+    }
+    fn try_with_waker<T>(&self, f: impl FnOnce(&mut Option<Waker>) -> T) -> Option<T> {
+        if self
+            .waker_lock
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        // SAFETY: waker_lock is held
+        let result = f(unsafe { &mut *self.waker.get() });
+        self.waker_lock.store(false, Ordering::Release);
+        Some(result)
+    }
+    fn with_waker<T>(&self, f: impl FnOnce(&mut Option<Waker>) -> T) -> T {
+        let mut f = Some(f);
+        loop {
+            if let Some(result) = self.try_with_waker(|w| f.take().expect("f is called once")(w)) {
+                return result;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+#[allow(clippy::declare_interior_mutable_const)]
+const VECTOR_STATE_INIT: VectorState = VectorState::new();
+static VECTORS: [VectorState; 256] = [VECTOR_STATE_INIT; 256];
+
+/// Called from the interrupt handler (see idt.rs) for the vectors in DEVICE_VECTOR_RANGE
+pub fn handle_device_interrupt(vector: u8) {
+    let state = &VECTORS[vector as usize];
+    state.count.fetch_add(1, Ordering::SeqCst);
+    let handler = state.handler.load(Ordering::SeqCst);
+    if handler != 0 {
+        // SAFETY: handler is set only from a valid InterruptHandler in InterruptVector::alloc()
+        let handler: InterruptHandler = unsafe { core::mem::transmute(handler) };
+        handler(state.handler_arg.load(Ordering::SeqCst));
+    }
+    // If the lock is held, the waiting task is registering its waker right now
+    // and will see the updated count after that, so skipping the wake is fine.
+    if let Some(Some(waker)) = state.try_with_waker(|w| w.take()) {
+        waker.wake();
+    }
+}
+
+/// An interrupt vector allocated for a device.
+/// The vector will be freed when this is dropped.
+#[derive(Debug)]
+pub struct InterruptVector {
+    vector: u8,
+    last_count: AtomicU64,
+}
+impl InterruptVector {
+    pub fn alloc(handler: Option<(InterruptHandler, u64)>) -> Result<Self> {
+        for vector in DEVICE_VECTOR_RANGE {
+            let state = &VECTORS[vector as usize];
+            if state
+                .is_allocated
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                continue;
+            }
+            let (handler, arg) = handler.map(|(f, arg)| (f as usize, arg)).unwrap_or((0, 0));
+            state.handler_arg.store(arg, Ordering::SeqCst);
+            state.handler.store(handler, Ordering::SeqCst);
+            return Ok(Self {
+                vector,
+                last_count: AtomicU64::new(state.count.load(Ordering::SeqCst)),
+            });
+        }
+        Err(Error::Failed("No more interrupt vectors available"))
+    }
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+    pub fn count(&self) -> u64 {
+        VECTORS[self.vector as usize].count.load(Ordering::SeqCst)
+    }
+    /// Returns true if the interrupt has happened since the last call of this function.
+    pub fn take_pending(&self) -> bool {
+        let count = self.count();
+        self.last_count.swap(count, Ordering::SeqCst) != count
+    }
+    /// Returns a future that completes when the interrupt happens.
+    /// If the interrupt has happened after the last take_pending() or wait(),
+    /// it completes immediately.
+    /// The waker of the task is woken in the interrupt context,
+    /// so it should not block.
+    pub fn wait(&self) -> InterruptFuture {
+        InterruptFuture { vector: self }
+    }
+}
+
+pub struct InterruptFuture<'a> {
+    vector: &'a InterruptVector,
+}
+impl<'a> Future for InterruptFuture<'a> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<()> {
+        let state = &VECTORS[self.vector.vector as usize];
+        // Register the waker before checking the count
+        // so that an interrupt between them is not missed.
+        state.with_waker(|w| match w {
+            Some(w) if w.will_wake(ctx.waker()) => {}
+            _ => *w = Some(ctx.waker().clone()),
+        });
+        if self.vector.take_pending() {
+            state.with_waker(|w| *w = None);
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+impl Drop for InterruptVector {
+    fn drop(&mut self) {
+        let state = &VECTORS[self.vector as usize];
+        state.handler.store(0, Ordering::SeqCst);
+        state.with_waker(|w| *w = None);
+        state.is_allocated.store(false, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn interrupt_vectors_are_allocated_uniquely() {
+    let v0 = InterruptVector::alloc(None).expect("Failed to alloc a vector");
+    let v1 = InterruptVector::alloc(None).expect("Failed to alloc a vector");
+    assert!(v0.vector() != v1.vector());
+    assert!(DEVICE_VECTOR_RANGE.contains(&v0.vector()));
+    assert!(DEVICE_VECTOR_RANGE.contains(&v1.vector()));
+    let freed = v0.vector();
+    drop(v0);
+    let v2 = InterruptVector::alloc(None).expect("Failed to alloc a vector");
+    assert_eq!(v2.vector(), freed);
+}
+
+#[test_case]
+fn interrupt_handler_is_called() {
+    static ARG: AtomicU64 = AtomicU64::new(0);
+    fn handler(arg: u64) {
+        ARG.store(arg, Ordering::SeqCst);
+    }
+    let v = InterruptVector::alloc(Some((handler, 42))).expect("Failed to alloc a vector");
+    assert!(!v.take_pending());
+    handle_device_interrupt(v.vector());
+    assert_eq!(ARG.load(Ordering::SeqCst), 42);
+    assert!(v.take_pending());
+    assert!(!v.take_pending());
+}
+
+#[test_case]
+fn interrupt_wakes_the_waiting_task() {
+    use core::task::RawWaker;
+    use core::task::RawWakerVTable;
+    static WAKE_COUNT: AtomicUsize = AtomicUsize::new(0);
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    fn wake(_: *const ()) {
+        WAKE_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+    fn no_op(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, no_op);
+    let waker = unsafe { Waker::from_raw(clone(core::ptr::null())) };
+    let mut ctx = Context::from_waker(&waker);
+
+    let v = InterruptVector::alloc(None).expect("Failed to alloc a vector");
+    let mut future = v.wait();
+    assert!(Pin::new(&mut future).poll(&mut ctx).is_pending());
+    assert_eq!(WAKE_COUNT.load(Ordering::SeqCst), 0);
+    handle_device_interrupt(v.vector());
+    assert_eq!(WAKE_COUNT.load(Ordering::SeqCst), 1);
+    assert!(Pin::new(&mut future).poll(&mut ctx).is_ready());
+    // The waker is consumed by the first interrupt
+    handle_device_interrupt(v.vector());
+    assert_eq!(WAKE_COUNT.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,367 @@
+//! Multiprocessor support
+//!
+//! Application Processors (APs) are started with the INIT-SIPI-SIPI sequence.
+//! An AP starts its execution in real mode at the trampoline code copied
+//! to the low memory, then it switches to the long mode with the same page table
+//! as the BSP and jumps into ap_entry() on its own stack.
+//! Each processor has its own GDT, TSS (with interrupt stacks) and IDT.
+//! Apps and the kernel tasks run only on the BSP. The APs run the jobs
+//! given with run_on_cpu(), and sleep with HLT until a wakeup IPI comes.
+
+extern crate alloc;
+
+use crate::boot_info::BootInfo;
+use crate::efi::EfiMemoryType;
+use crate::error::Error;
+use crate::error::Result;
+use crate::hpet::Hpet;
+use crate::info;
+use crate::init::init_cpu_local_interrupts;
+use crate::memory::ContiguousPhysicalMemoryPages;
+use crate::mutex::Mutex;
+use crate::warn;
+use crate::x86_64::allow_interrupts;
+use crate::x86_64::apic::LocalApic;
+use crate::x86_64::block_interrupts;
+use crate::x86_64::busy_loop_hint;
+use crate::x86_64::interrupt::InterruptVector;
+use crate::x86_64::paging::read_cr3;
+use crate::x86_64::stihlt;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::sync::atomic::fence;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+use core::task::Poll;
+
+pub const MAX_CPUS: usize = 16;
+const AP_STACK_SIZE: usize = 1024 * 1024;
+// The trampoline should be placed at a 4KiB-aligned address below 1MiB,
+// which is not managed by the allocator (see allocator::LOW_MEMORY_END).
+const AP_TRAMPOLINE_ADDR: usize = 0x8000;
+const AP_STARTUP_TIMEOUT_MS: u64 = 100;
+
+/// A function to be executed on another processor
+type Job = Box<dyn FnOnce() + Send>;
+
+struct CpuInfo {
+    apic_id: AtomicU32,
+    is_online: AtomicBool,
+    // Jobs to be executed on this processor (see run_on_cpu())
+    jobs: Mutex<VecDeque<Job>>,
+}
+impl CpuInfo {
+    const fn new() -> Self {
+        Self {
+            apic_id: AtomicU32::new(0),
+            is_online: AtomicBool::new(false),
+            jobs: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_INFO_INIT: CpuInfo = CpuInfo::new();
+// Index 0 is always the BSP.
+static CPUS: [CpuInfo; MAX_CPUS] = [CPU_INFO_INIT; MAX_CPUS];
+static NUM_CPUS: AtomicUsize = AtomicUsize::new(1);
+// Vector of the IPI to wake up the APs from HLT, or 0 if not allocated yet
+static WAKEUP_VECTOR: AtomicU8 = AtomicU8::new(0);
+
+/// Returns the index of the processor which executes this function.
+/// The index of the BSP is 0, and the APs are numbered in the order of MADT entries.
+pub fn current_cpu_index() -> usize {
+    let apic_id = LocalApic::current_id();
+    CPUS[..NUM_CPUS.load(Ordering::SeqCst)]
+        .iter()
+        .position(|e| e.apic_id.load(Ordering::SeqCst) == apic_id)
+        .unwrap_or(0)
+}
+
+/// Returns a list of (cpu_index, apic_id) of the processors that are online.
+pub fn online_cpus() -> Vec<(usize, u32)> {
+    CPUS[..NUM_CPUS.load(Ordering::SeqCst)]
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.is_online.load(Ordering::SeqCst))
+        .map(|(i, e)| (i, e.apic_id.load(Ordering::SeqCst)))
+        .collect()
+}
+
+/// Completes with the return value of the job given to run_on_cpu().
+pub struct JobFuture<T> {
+    result: Arc<Mutex<Option<T>>>,
+}
+impl<T> Future for JobFuture<T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<T> {
+        match self.result.lock().take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Runs the function on the AP at `cpu_index`.
+/// The returned future completes with the return value of the function.
+pub fn run_on_cpu<T: Send + 'static>(
+    cpu_index: usize,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<JobFuture<T>> {
+    if cpu_index == 0 {
+        return Err(Error::Failed("Jobs can not be queued to the BSP"));
+    }
+    let cpu = CPUS[..NUM_CPUS.load(Ordering::SeqCst)]
+        .get(cpu_index)
+        .filter(|cpu| cpu.is_online.load(Ordering::SeqCst))
+        .ok_or(Error::Failed("The processor is not online"))?;
+    let result = Arc::new(Mutex::new(None));
+    let job_result = result.clone();
+    cpu.jobs.lock().push_back(Box::new(move || {
+        let value = f();
+        *job_result.lock() = Some(value);
+    }));
+    BootInfo::take().bsp_local_apic().send_fixed_ipi(
+        cpu.apic_id.load(Ordering::SeqCst),
+        WAKEUP_VECTOR.load(Ordering::SeqCst),
+    );
+    Ok(JobFuture { result })
+}
+
+/// Runs the jobs queued to this processor forever.
+fn run_jobs(cpu_index: usize) -> ! {
+    let cpu = &CPUS[cpu_index];
+    loop {
+        block_interrupts();
+        let job = cpu.jobs.lock().pop_front();
+        match job {
+            Some(job) => {
+                allow_interrupts();
+                job();
+            }
+            // The interrupts are enabled right before HLT (STI takes effect after
+            // the next instruction), so a wakeup IPI that comes after the check
+            // above will not be missed.
+            None => stihlt(),
+        }
+    }
+}
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// Parameters passed to the AP via the copied trampoline.
+/// The layout should be in sync with the asm code below.
+#[repr(C)]
+struct ApTrampolineParams {
+    cr3: u64,
+    rsp: u64,
+    entry: u64,
+    cpu_index: u64,
+}
+const _: () = assert!(size_of::<ApTrampolineParams>() == 32);
+
+global_asm!(
+    ".code16",
+    ".global ap_trampoline_start",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "xor %ax, %ax",
+    "mov %ax, %ds",
+    "lgdtl (ap_trampoline_gdtr - ap_trampoline_start + {addr})",
+    "mov %cr0, %eax",
+    "or $1, %eax", // CR0.PE
+    "mov %eax, %cr0",
+    "ljmpl $0x08, $(ap_trampoline_32 - ap_trampoline_start + {addr})",
+    ".code32",
+    "ap_trampoline_32:",
+    "mov $0x10, %ax",
+    "mov %ax, %ds",
+    "mov %ax, %es",
+    "mov %ax, %ss",
+    "mov %cr4, %eax",
+    "or $((1 << 5) | (1 << 9) | (1 << 10)), %eax", // CR4.PAE, OSFXSR, OSXMMEXCPT
+    "mov %eax, %cr4",
+    "mov (ap_trampoline_params - ap_trampoline_start + {addr}), %eax",
+    "mov %eax, %cr3",
+    "mov $0xC0000080, %ecx", // IA32_EFER
+    "rdmsr",
+    "or $(1 << 8), %eax", // EFER.LME
+    "wrmsr",
+    "mov %cr0, %eax",
+    "or $(1 << 31), %eax", // CR0.PG
+    "and $~(1 << 2), %eax", // CR0.EM
+    "or $(1 << 1), %eax",  // CR0.MP
+    "mov %eax, %cr0",
+    "ljmpl $0x18, $(ap_trampoline_64 - ap_trampoline_start + {addr})",
+    ".code64",
+    "ap_trampoline_64:",
+    "xor %eax, %eax",
+    "mov %ax, %ds",
+    "mov %ax, %es",
+    "mov %ax, %ss",
+    "mov (ap_trampoline_params - ap_trampoline_start + {addr} + 8), %rsp",
+    "mov (ap_trampoline_params - ap_trampoline_start + {addr} + 16), %rax",
+    "mov (ap_trampoline_params - ap_trampoline_start + {addr} + 24), %rdi",
+    "call *%rax",
+    "0:",
+    "hlt",
+    "jmp 0b",
+    ".balign 8",
+    "ap_trampoline_gdt:",
+    ".quad 0",                  // null
+    ".quad 0x00CF9A000000FFFF", // 0x08: 32-bit code
+    ".quad 0x00CF92000000FFFF", // 0x10: 32-bit data
+    ".quad 0x00AF9A000000FFFF", // 0x18: 64-bit code
+    "ap_trampoline_gdtr:",
+    ".word 4 * 8 - 1",
+    ".long ap_trampoline_gdt - ap_trampoline_start + {addr}",
+    ".balign 8",
+    ".global ap_trampoline_params",
+    "ap_trampoline_params:",
+    ".fill 4, 8, 0",
+    ".global ap_trampoline_end",
+    "ap_trampoline_end:",
+    addr = const AP_TRAMPOLINE_ADDR,
+    options(att_syntax)
+);
+
+extern "sysv64" fn ap_entry(cpu_index: u64) -> ! {
+    let cpu_index = cpu_index as usize;
+    let interrupt_config = init_cpu_local_interrupts().expect("Failed to init interrupts on an AP");
+    // The configuration should live forever on this processor
+    core::mem::forget(interrupt_config);
+    CPUS[cpu_index].is_online.store(true, Ordering::SeqCst);
+    info!(
+        "CPU {cpu_index} (APIC ID {}) is online",
+        LocalApic::current_id()
+    );
+    run_jobs(cpu_index)
+}
+
+fn wait_us(us: u64) {
+    let hpet = Hpet::take();
+    let end = hpet.main_counter() + hpet.freq() * us / 1_000_000;
+    while hpet.main_counter() < end {
+        busy_loop_hint();
+    }
+}
+
+/// Returns true if the page for the trampoline is a free memory in the UEFI memory map.
+fn is_trampoline_page_available() -> bool {
+    let page = AP_TRAMPOLINE_ADDR as u64..(AP_TRAMPOLINE_ADDR + 0x1000) as u64;
+    BootInfo::take().memory_map().iter().any(|e| {
+        e.memory_type == EfiMemoryType::CONVENTIONAL_MEMORY
+            && e.physical_start <= page.start
+            && page.end <= e.physical_start + e.number_of_pages * 4096
+    })
+}
+
+fn copy_trampoline() -> Result<&'static mut ApTrampolineParams> {
+    // SAFETY: these symbols are defined in the global_asm above.
+    let (start, params, end) = unsafe {
+        (
+            &ap_trampoline_start as *const u8 as usize,
+            &ap_trampoline_params as *const u8 as usize,
+            &ap_trampoline_end as *const u8 as usize,
+        )
+    };
+    if end - start > 0x1000 {
+        return Err(Error::Failed("AP trampoline is too large"));
+    }
+    if !is_trampoline_page_available() {
+        return Err(Error::Failed(
+            "The page for the AP trampoline is not a free memory",
+        ));
+    }
+    // SAFETY: The region is identity-mapped and not used by anyone else
+    // since it is below LOW_MEMORY_END.
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            start as *const u8,
+            AP_TRAMPOLINE_ADDR as *mut u8,
+            end - start,
+        );
+        Ok(&mut *((AP_TRAMPOLINE_ADDR + params - start) as *mut ApTrampolineParams))
+    }
+}
+
+/// Starts all the Application Processors listed in the MADT.
+/// This should be called on the BSP after the paging and the timer are initialized.
+pub fn start_application_processors() -> Result<()> {
+    let bsp_local_apic = BootInfo::take().bsp_local_apic();
+    CPUS[0].apic_id.store(bsp_local_apic.id(), Ordering::SeqCst);
+    CPUS[0].is_online.store(true, Ordering::SeqCst);
+    let cr3 = read_cr3() as u64;
+    if cr3 >= 1 << 32 {
+        return Err(Error::Failed(
+            "PML4 should be below 4GiB to be loaded from the AP trampoline",
+        ));
+    }
+    let params = copy_trampoline()?;
+    let wakeup_vector = InterruptVector::alloc(None)?;
+    WAKEUP_VECTOR.store(wakeup_vector.vector(), Ordering::SeqCst);
+    // The vector is used forever
+    core::mem::forget(wakeup_vector);
+    let madt = BootInfo::take().acpi().madt();
+    for lapic in madt.local_apics() {
+        let apic_id = lapic.apic_id as u32;
+        if apic_id == bsp_local_apic.id() {
+            continue;
+        }
+        let cpu_index = NUM_CPUS.load(Ordering::SeqCst);
+        if cpu_index >= MAX_CPUS {
+            warn!("Too many processors. APIC ID {apic_id} is ignored");
+            break;
+        }
+        let mut stack = ContiguousPhysicalMemoryPages::alloc_bytes(AP_STACK_SIZE)?;
+        params.cr3 = cr3;
+        params.rsp = stack.range().end() as u64;
+        params.entry = ap_entry as usize as u64;
+        params.cpu_index = cpu_index as u64;
+        // The stack will be used by the AP forever.
+        // It is never freed since ContiguousPhysicalMemoryPages does not free the region on drop.
+        stack.fill_with_bytes(0);
+        CPUS[cpu_index].apic_id.store(apic_id, Ordering::SeqCst);
+        NUM_CPUS.store(cpu_index + 1, Ordering::SeqCst);
+
+        // Make sure that the params are visible to the AP before waking it up
+        fence(Ordering::SeqCst);
+        // c.f. Intel SDM Vol.3A 8.4.4.1 Typical BSP Initialization Sequence
+        bsp_local_apic.send_init_ipi(apic_id);
+        wait_us(10_000);
+        for _ in 0..2 {
+            bsp_local_apic.send_startup_ipi(apic_id, (AP_TRAMPOLINE_ADDR >> 12) as u8);
+            wait_us(200);
+        }
+        let mut waited_ms = 0;
+        while !CPUS[cpu_index].is_online.load(Ordering::SeqCst) {
+            if waited_ms >= AP_STARTUP_TIMEOUT_MS {
+                break;
+            }
+            wait_us(1000);
+            waited_ms += 1;
+        }
+        if !CPUS[cpu_index].is_online.load(Ordering::SeqCst) {
+            // The AP may still be starting up with the params,
+            // so they can not be reused for the other APs.
+            warn!("CPU {cpu_index} (APIC ID {apic_id}) did not respond. The remaining APs are not started");
+            break;
+        }
+    }
+    info!("{} processors are online", online_cpus().len());
+    Ok(())
+}
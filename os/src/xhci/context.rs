@@ -2,6 +2,8 @@ extern crate alloc;
 
 use crate::error::Error;
 use crate::error::Result;
+use crate::usb::descriptor::EndpointDescriptor;
+use crate::usb::descriptor::SuperSpeedEndpointCompanionDescriptor;
 use crate::volatile::Volatile;
 use crate::xhci::controller::EndpointType;
 use crate::xhci::registers::UsbMode;
@@ -17,10 +19,13 @@ use core::pin::Pin;
 #[derive(Default, Debug)]
 pub struct EndpointContext {
     // data[0]:
+    //   - bit[8..=9]: Mult (for SS Isoch)
     //   - bit[16..=23]: Interval (Table 6-12: Endpoint Type vs. Interval Calculation)
+    //   - bit[24..=31]: Max Endpoint Service Time Interval Payload High (Max ESIT Payload Hi)
     // data[1]:
     //   - bit[1..=2]: Error Count (CErr)
     //   - bit[3..=5]: EndpointType (EPType)
+    //   - bit[8..=15]: Max Burst Size
     //   - bit[16..=31]: Max Packet Size (taken from EndpointDescriptor)
     data: [u32; 2],
 
@@ -38,101 +43,89 @@ impl EndpointContext {
     unsafe fn new() -> Self {
         unsafe { MaybeUninit::zeroed().assume_init() }
     }
-    pub fn new_interrupt_in_endpoint(
-        max_packet_size: u16,
-        tr_dequeue_ptr: u64,
+    /// Creates an Endpoint Context for an Interrupt, Bulk or Isoch endpoint
+    /// described by the descriptors (4.14 and Table 6-12 of [xhci]).
+    pub fn new_transfer_endpoint(
+        ep_desc: &EndpointDescriptor,
+        ss_companion: Option<&SuperSpeedEndpointCompanionDescriptor>,
         mode: UsbMode,
-        interval_from_ep_desc: u8,
+        tr_dequeue_ptr: u64,
         average_trb_length: u16,
     ) -> Result<Self> {
-        // See [xhci] Table 6-12
-        let interval = match mode {
-            UsbMode::LowSpeed | UsbMode::FullSpeed => {
-                // interval_ms == 2^interval / 8 == interval_from_ep_desc
-                interval_from_ep_desc.ilog2() as u8 + 3
+        let ep_type = EndpointType::from(ep_desc);
+        let periodic = !matches!(
+            ep_type,
+            EndpointType::Control | EndpointType::BulkIn | EndpointType::BulkOut
+        );
+        let isoch = matches!(ep_type, EndpointType::IsochIn | EndpointType::IsochOut);
+        // wMaxPacketSize:
+        //   - bit[0..=10]: Max Packet Size
+        //   - bit[11..=12]: Additional transactions per microframe (HS periodic)
+        let max_packet_size = ep_desc.max_packet_size & 0x7FF;
+        let (max_burst, mult, max_esit_payload) = match (mode, ss_companion) {
+            (UsbMode::SuperSpeed, Some(c)) => {
+                let max_burst = c.max_burst.min(15);
+                let mult = if isoch { c.attributes & 0b11 } else { 0 };
+                let max_esit_payload = if periodic {
+                    c.bytes_per_interval as u32
+                } else {
+                    max_packet_size as u32 * (max_burst as u32 + 1)
+                };
+                (max_burst, mult, max_esit_payload)
             }
-            UsbMode::HighSpeed | UsbMode::SuperSpeed => interval_from_ep_desc - 1,
-            mode => {
-                return Err(Error::FailedString(format!(
-                    "Failed to calc interval for {:?}",
-                    mode
-                )))
+            (UsbMode::HighSpeed, _) if periodic => {
+                let max_burst = ((ep_desc.max_packet_size >> 11) & 0b11) as u8;
+                let max_esit_payload = max_packet_size as u32 * (max_burst as u32 + 1);
+                (max_burst, 0, max_esit_payload)
             }
+            _ => (0, 0, max_packet_size as u32),
         };
+        let interval = Self::interval(ep_type, mode, ep_desc.interval)?;
         let mut ep = unsafe { Self::new() };
-        ep.set_ep_type(EndpointType::InterruptIn)?;
+        ep.set_ep_type(ep_type)?;
         ep.set_dequeue_cycle_state(true)?;
-        ep.set_error_count(3)?;
+        // 4.10.2.7: Isoch endpoints are never retried
+        ep.set_error_count(if isoch { 0 } else { 3 })?;
         ep.set_max_packet_size(max_packet_size);
         ep.set_ring_dequeue_pointer(tr_dequeue_ptr)?;
-        ep.data[0] = (interval as u32) << 16;
+        ep.data[0] =
+            (interval as u32) << 16 | (mult as u32) << 8 | (max_esit_payload >> 16 & 0xFF) << 24;
+        ep.data[1] |= (max_burst as u32) << 8;
         ep.average_trb_length = average_trb_length;
-        ep.max_esit_payload_low = max_packet_size; // 4.14.2
+        ep.max_esit_payload_low = max_esit_payload as u16; // 4.14.2
         Ok(ep)
     }
-    pub fn new_bulk_in_endpoint(
-        max_packet_size: u16,
-        tr_dequeue_ptr: u64,
-        mode: UsbMode,
-        interval_from_ep_desc: u8,
-        average_trb_length: u16,
-    ) -> Result<Self> {
-        // See [xhci] Table 6-12
-        let interval = match mode {
-            UsbMode::LowSpeed | UsbMode::FullSpeed => {
-                // interval_ms == 2^interval / 8 == interval_from_ep_desc
-                interval_from_ep_desc.ilog2() as u8 + 3
+    // See [xhci] Table 6-12: Endpoint Type vs. Interval Calculation
+    fn interval(ep_type: EndpointType, mode: UsbMode, interval_from_ep_desc: u8) -> Result<u8> {
+        let interval = match (ep_type, mode) {
+            // bInterval is not used (or is a NAK rate) for non-periodic endpoints
+            (
+                EndpointType::Control | EndpointType::BulkIn | EndpointType::BulkOut,
+                UsbMode::LowSpeed | UsbMode::FullSpeed | UsbMode::HighSpeed | UsbMode::SuperSpeed,
+            ) => 0,
+            (
+                EndpointType::InterruptIn | EndpointType::InterruptOut,
+                UsbMode::LowSpeed | UsbMode::FullSpeed,
+            ) => {
+                // interval_ms == 2^interval / 8 == interval_from_ep_desc (1-255)
+                interval_from_ep_desc.max(1).ilog2() as u8 + 3
             }
-            UsbMode::HighSpeed | UsbMode::SuperSpeed => interval_from_ep_desc - 1,
-            mode => {
-                return Err(Error::FailedString(format!(
-                    "Failed to calc interval for {:?}",
-                    mode
-                )))
+            (EndpointType::IsochIn | EndpointType::IsochOut, UsbMode::FullSpeed) => {
+                // interval_ms == 2^(interval_from_ep_desc - 1) (1-16)
+                interval_from_ep_desc.clamp(1, 16) - 1 + 3
             }
-        };
-        let mut ep = unsafe { Self::new() };
-        ep.set_ep_type(EndpointType::BulkIn)?;
-        ep.set_dequeue_cycle_state(true)?;
-        ep.set_error_count(3)?;
-        ep.set_max_packet_size(max_packet_size);
-        ep.set_ring_dequeue_pointer(tr_dequeue_ptr)?;
-        ep.data[0] = (interval as u32) << 16;
-        ep.average_trb_length = average_trb_length;
-        ep.max_esit_payload_low = max_packet_size; // 4.14.2
-        Ok(ep)
-    }
-    pub fn new_bulk_out_endpoint(
-        max_packet_size: u16,
-        tr_dequeue_ptr: u64,
-        mode: UsbMode,
-        interval_from_ep_desc: u8,
-        average_trb_length: u16,
-    ) -> Result<Self> {
-        // See [xhci] Table 6-12
-        let interval = match mode {
-            UsbMode::LowSpeed | UsbMode::FullSpeed => {
-                // interval_ms == 2^interval / 8 == interval_from_ep_desc
-                interval_from_ep_desc.ilog2() as u8 + 3
+            (_, UsbMode::HighSpeed | UsbMode::SuperSpeed) => {
+                // interval_us == 125 * 2^(interval_from_ep_desc - 1) (1-16)
+                interval_from_ep_desc.clamp(1, 16) - 1
             }
-            UsbMode::HighSpeed | UsbMode::SuperSpeed => interval_from_ep_desc - 1,
-            mode => {
+            (_, mode) => {
                 return Err(Error::FailedString(format!(
                     "Failed to calc interval for {:?}",
                     mode
                 )))
             }
         };
-        let mut ep = unsafe { Self::new() };
-        ep.set_ep_type(EndpointType::BulkOut)?;
-        ep.set_dequeue_cycle_state(true)?;
-        ep.set_error_count(3)?;
-        ep.set_max_packet_size(max_packet_size);
-        ep.set_ring_dequeue_pointer(tr_dequeue_ptr)?;
-        ep.data[0] = (interval as u32) << 16;
-        ep.average_trb_length = average_trb_length;
-        ep.max_esit_payload_low = max_packet_size; // 4.14.2
-        Ok(ep)
+        Ok(interval)
     }
     pub fn new_control_endpoint(max_packet_size: u16, tr_dequeue_ptr: u64) -> Result<Self> {
         let mut ep = unsafe { Self::new() };
@@ -223,6 +216,35 @@ impl DeviceContext {
             Err(Error::Failed("psi out of range"))
         }
     }
+    fn set_route_string(&mut self, route_string: u32) -> Result<()> {
+        // 8.9 Route String Field: 4 bits for each tier of hubs (up to 5 tiers)
+        if route_string < 1 << 20 {
+            self.slot_ctx[0] &= !0xFFFFF;
+            self.slot_ctx[0] |= route_string;
+            Ok(())
+        } else {
+            Err(Error::Failed("route_string out of range"))
+        }
+    }
+    fn set_tt_hub(&mut self, hub_slot: u8, hub_port: u8) {
+        // 6.2.2: TT Hub Slot ID and TT Port Number are valid only for LS/FS devices
+        // connected through a HS hub.
+        self.slot_ctx[2] &= !0xFFFF;
+        self.slot_ctx[2] |= (hub_port as u32) << 8 | hub_slot as u32;
+    }
+    fn set_hub(&mut self, num_ports: u8, multi_tt: bool, tt_think_time: u8) -> Result<()> {
+        if tt_think_time < 4 {
+            self.slot_ctx[0] &= !(0b11 << 25);
+            self.slot_ctx[0] |= (multi_tt as u32) << 25 | 1 << 26;
+            self.slot_ctx[1] &= !(0xFF << 24);
+            self.slot_ctx[1] |= (num_ports as u32) << 24;
+            self.slot_ctx[2] &= !(0b11 << 16);
+            self.slot_ctx[2] |= (tt_think_time as u32) << 16;
+            Ok(())
+        } else {
+            Err(Error::Failed("tt_think_time out of range"))
+        }
+    }
 }
 
 #[repr(C, align(32))]
@@ -269,6 +291,27 @@ impl InputContext {
             .device_ctx
             .set_port_speed(psi)
     }
+    pub fn set_route_string(self: &mut Pin<&mut Self>, route_string: u32) -> Result<()> {
+        unsafe { self.as_mut().get_unchecked_mut() }
+            .device_ctx
+            .set_route_string(route_string)
+    }
+    pub fn set_tt_hub(self: &mut Pin<&mut Self>, hub_slot: u8, hub_port: u8) {
+        unsafe { self.as_mut().get_unchecked_mut() }
+            .device_ctx
+            .set_tt_hub(hub_slot, hub_port)
+    }
+    /// Marks the device as a hub. Takes effect on the next Configure Endpoint Command.
+    pub fn set_hub(
+        self: &mut Pin<&mut Self>,
+        num_ports: u8,
+        multi_tt: bool,
+        tt_think_time: u8,
+    ) -> Result<()> {
+        unsafe { self.as_mut().get_unchecked_mut() }
+            .device_ctx
+            .set_hub(num_ports, multi_tt, tt_think_time)
+    }
     /// # Arguments
     /// * `dci` - destination device context index. [slot_ctx, ctrl_ep, ep1_out, ep1_in, ...]
     pub fn set_ep_ctx(
@@ -349,4 +392,84 @@ impl DeviceContextBaseAddressArray {
                     .get_ref() as *const OutputContext as u64;
         }
     }
+    /// Releases the output context of the slot. The slot should be disabled beforehand.
+    pub fn clear_output_context(&mut self, slot: u8) {
+        let slot = slot as usize;
+        unsafe {
+            self.inner.as_mut().get_unchecked_mut().context[slot] = 0;
+        }
+        self.context[slot] = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn ep_desc(
+        endpoint_address: u8,
+        attributes: u8,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> EndpointDescriptor {
+        EndpointDescriptor {
+            desc_length: 7,
+            desc_type: 5,
+            endpoint_address,
+            attributes,
+            max_packet_size,
+            interval,
+        }
+    }
+    #[test_case]
+    fn full_speed_interrupt_endpoint() {
+        let ep = EndpointContext::new_transfer_endpoint(
+            &ep_desc(0x81, 3, 8, 10),
+            None,
+            UsbMode::FullSpeed,
+            0x1000,
+            8,
+        )
+        .unwrap();
+        // 2^6 / 8 = 8ms (<= 10ms)
+        assert_eq!(ep.data[0] >> 16 & 0xFF, 6);
+        assert_eq!(ep.data[1] >> 16, 8);
+        assert_eq!(ep.data[1] >> 8 & 0xFF, 0);
+    }
+    #[test_case]
+    fn high_speed_interrupt_endpoint_with_additional_transactions() {
+        let ep = EndpointContext::new_transfer_endpoint(
+            &ep_desc(0x81, 3, 1 << 11 | 512, 4),
+            None,
+            UsbMode::HighSpeed,
+            0x1000,
+            8,
+        )
+        .unwrap();
+        assert_eq!(ep.data[0] >> 16 & 0xFF, 3);
+        assert_eq!(ep.data[1] >> 16, 512);
+        assert_eq!(ep.data[1] >> 8 & 0xFF, 1);
+        assert_eq!(ep.max_esit_payload_low, 1024);
+    }
+    #[test_case]
+    fn super_speed_bulk_endpoint() {
+        let companion = SuperSpeedEndpointCompanionDescriptor {
+            desc_length: 6,
+            desc_type: 48,
+            max_burst: 15,
+            attributes: 0,
+            bytes_per_interval: 0,
+        };
+        let ep = EndpointContext::new_transfer_endpoint(
+            &ep_desc(0x02, 2, 1024, 0),
+            Some(&companion),
+            UsbMode::SuperSpeed,
+            0x1000,
+            8,
+        )
+        .unwrap();
+        assert_eq!(ep.data[0] >> 16 & 0xFF, 0);
+        assert_eq!(ep.data[1] >> 3 & 0b111, EndpointType::BulkOut as u32);
+        assert_eq!(ep.data[1] >> 16, 1024);
+        assert_eq!(ep.data[1] >> 8 & 0xFF, 15);
+    }
 }
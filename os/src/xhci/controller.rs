@@ -3,6 +3,9 @@ extern crate alloc;
 use crate::error;
 use crate::error::Error;
 use crate::error::Result;
+use crate::executor::with_timeout_ms;
+use crate::executor::yield_execution;
+use crate::info;
 use crate::memory::Mmio;
 use crate::mutex::Mutex;
 use crate::usb::descriptor::ConfigDescriptor;
@@ -10,13 +13,17 @@ use crate::usb::descriptor::DescriptorIterator;
 use crate::usb::descriptor::DescriptorType;
 use crate::usb::descriptor::DeviceDescriptor;
 use crate::usb::descriptor::EndpointDescriptor;
+use crate::usb::descriptor::SuperSpeedEndpointCompanionDescriptor;
 use crate::usb::descriptor::UsbDescriptor;
 use crate::util::IntoPinnedMutableSlice;
+use crate::x86_64::interrupt::InterruptVector;
 use crate::xhci::context::DeviceContextBaseAddressArray;
 use crate::xhci::context::EndpointContext;
 use crate::xhci::context::InputContext;
 use crate::xhci::context::InputControlContext;
 use crate::xhci::context::OutputContext;
+use crate::xhci::device::DeviceLocation;
+use crate::xhci::device::UsbDeviceInfo;
 use crate::xhci::future::EventFuture;
 use crate::xhci::registers::CapabilityRegisters;
 use crate::xhci::registers::Doorbell;
@@ -25,6 +32,7 @@ use crate::xhci::registers::PortSc;
 use crate::xhci::registers::PortScIterator;
 use crate::xhci::registers::PortScWrapper;
 use crate::xhci::registers::RuntimeRegisters;
+use crate::xhci::registers::UsbMode;
 use crate::xhci::ring::CommandRing;
 use crate::xhci::ring::EventRing;
 use crate::xhci::ring::TransferRing;
@@ -33,6 +41,7 @@ use crate::xhci::trb::GenericTrbEntry;
 use crate::xhci::trb::SetupStageTrb;
 use crate::xhci::trb::StatusStageTrb;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::collections::LinkedList;
 use alloc::fmt::Debug;
 use alloc::rc::Rc;
@@ -76,17 +85,47 @@ impl From<&EndpointDescriptor> for EndpointType {
 
 type DeviceFuture = Pin<Box<dyn Future<Output = Result<()>>>>;
 
+static CONTROLLERS: Mutex<Vec<Weak<Controller>>> = Mutex::new(Vec::new());
+
+pub fn register_controller(xhc: &Rc<Controller>) {
+    CONTROLLERS.lock().push(Rc::downgrade(xhc));
+}
+
+/// Returns the controllers which are still alive, in the order of registration.
+pub fn controllers() -> Vec<Rc<Controller>> {
+    let mut controllers = CONTROLLERS.lock();
+    controllers.retain(|c| c.strong_count() > 0);
+    controllers.iter().filter_map(|c| c.upgrade()).collect()
+}
+
 /// Abstraction of xHCI's host controller interfaces
+/// The contexts and the rings of a device, which the xHC can access until
+/// the slot is disabled (see Controller::retire_device()).
+pub struct RetiredDevice {
+    pub input_context: Pin<Box<InputContext>>,
+    pub ctrl_ep_ring: Pin<Box<CommandRing>>,
+    pub ep_rings: Vec<TransferRing>,
+}
+
 pub struct Controller {
     cap_regs: Mmio<CapabilityRegisters>,
-    op_regs: Mmio<OperationalRegisters>,
-    rt_regs: Mmio<RuntimeRegisters>,
+    // The registers are written through &self (e.g. to acknowledge interrupts),
+    // so they are guarded by Mutexes.
+    op_regs: Mutex<Mmio<OperationalRegisters>>,
+    rt_regs: Mutex<Mmio<RuntimeRegisters>>,
     portsc: PortSc,
     doorbell_regs: Vec<Rc<Doorbell>>,
     command_ring: Mutex<CommandRing>,
     primary_event_ring: Mutex<EventRing>,
     device_context_base_array: Mutex<DeviceContextBaseAddressArray>,
     device_futures: Mutex<LinkedList<DeviceFuture>>,
+    // Devices with an enabled slot, and where they are attached
+    devices: Mutex<BTreeMap<u8, UsbDeviceInfo>>,
+    // Memory of the devices whose drivers have exited. The xHC may still access them
+    // until the slots are disabled.
+    retired_devices: Mutex<BTreeMap<u8, RetiredDevice>>,
+    // MSI or MSI-X for the primary interrupter. None if the event ring should be polled.
+    interrupt: Option<InterruptVector>,
 }
 impl Controller {
     pub fn new(
@@ -96,27 +135,119 @@ impl Controller {
         portsc: PortSc,
         doorbell_regs: Vec<Rc<Doorbell>>,
         device_context_base_array: Mutex<DeviceContextBaseAddressArray>,
+        interrupt: Option<InterruptVector>,
     ) -> Result<Self> {
         let mut xhc = Self {
             cap_regs,
-            op_regs,
-            rt_regs,
+            op_regs: Mutex::new(op_regs),
+            rt_regs: Mutex::new(rt_regs),
             portsc,
             doorbell_regs,
             command_ring: Mutex::new(CommandRing::default()),
             primary_event_ring: Mutex::new(EventRing::new()?),
             device_context_base_array,
             device_futures: Mutex::new(LinkedList::new()),
+            devices: Mutex::new(BTreeMap::new()),
+            retired_devices: Mutex::new(BTreeMap::new()),
+            interrupt,
         };
         xhc.init_primary_event_ring()?;
+        if xhc.interrupt.is_some() {
+            unsafe { xhc.rt_regs.lock().get_unchecked_mut() }.enable_interrupter(0)?;
+            unsafe { xhc.op_regs.lock().get_unchecked_mut() }.enable_interrupt();
+        }
         xhc.init_slots_and_contexts()?;
         xhc.init_command_ring();
-        unsafe { xhc.op_regs.get_unchecked_mut() }.start_xhc();
+        unsafe { xhc.op_regs.lock().get_unchecked_mut() }.start_xhc();
         Ok(xhc)
     }
     pub fn device_futures(&self) -> &Mutex<LinkedList<DeviceFuture>> {
         &self.device_futures
     }
+    pub fn register_device(&self, slot: u8, location: DeviceLocation) {
+        self.primary_event_ring.lock().on_slot_enabled(slot);
+        self.devices
+            .lock()
+            .insert(slot, UsbDeviceInfo::new(location));
+    }
+    /// Records the descriptors of the device on the slot, for inspecting it later.
+    pub fn set_device_descriptors(
+        &self,
+        slot: u8,
+        device_descriptor: DeviceDescriptor,
+        descriptors: Vec<UsbDescriptor>,
+        strings: BTreeMap<u8, String>,
+    ) {
+        if let Some(info) = self.devices.lock().get_mut(&slot) {
+            info.device_descriptor = Some(device_descriptor);
+            info.descriptors = descriptors;
+            info.strings = strings;
+        }
+    }
+    /// Returns the attached devices, ordered by their slot ids.
+    pub fn devices(&self) -> Vec<(u8, UsbDeviceInfo)> {
+        self.devices
+            .lock()
+            .iter()
+            .map(|(slot, info)| (*slot, info.clone()))
+            .collect()
+    }
+    /// Returns false once the device on the slot is detached.
+    pub fn is_attached(&self, slot: u8) -> bool {
+        self.devices.lock().contains_key(&slot)
+    }
+    /// Marks the devices at the location (and behind it, if it is a hub) as detached,
+    /// and cancels the EventFutures waiting for them so that their drivers exit.
+    pub fn detach_devices_at(&self, location: &DeviceLocation) {
+        let slots: Vec<u8> = self
+            .devices
+            .lock()
+            .iter()
+            .filter(|(_, info)| info.location.is_at_or_behind(location))
+            .map(|(slot, _)| *slot)
+            .collect();
+        for slot in slots {
+            self.detach_slot(slot);
+        }
+    }
+    pub fn detach_devices_on_root_port(&self, port: usize) {
+        let slots: Vec<u8> = self
+            .devices
+            .lock()
+            .iter()
+            .filter(|(_, info)| info.location.root_port() == port)
+            .map(|(slot, _)| *slot)
+            .collect();
+        for slot in slots {
+            self.detach_slot(slot);
+        }
+    }
+    fn detach_slot(&self, slot: u8) {
+        info!("Slot {slot}: detached");
+        self.devices.lock().remove(&slot);
+        self.primary_event_ring.lock().cancel_waiters_on_slot(slot);
+    }
+    /// Keeps the memory of the device on the slot until the slot is disabled.
+    pub fn retire_device(&self, slot: u8, device: RetiredDevice) {
+        self.retired_devices.lock().insert(slot, device);
+    }
+    /// Disables the slot and releases the resources for it,
+    /// so that the slot can be used for another device.
+    pub async fn disable_slot(&self, slot: u8) -> Result<()> {
+        self.devices.lock().remove(&slot);
+        self.primary_event_ring.lock().cancel_waiters_on_slot(slot);
+        // 4.6.4 Disable Slot: the xHC stops using the Device Context and the rings
+        // of the slot once the command is completed.
+        self.send_command(GenericTrbEntry::cmd_disable_slot(slot))
+            .await?
+            .completed()?;
+        self.device_context_base_array
+            .lock()
+            .clear_output_context(slot);
+        self.retired_devices.lock().remove(&slot);
+        info!("Slot {slot}: disabled");
+        Ok(())
+    }
     pub fn portsc(&self, port: usize) -> Result<Weak<PortScWrapper>> {
         self.portsc.get(port)
     }
@@ -125,15 +256,29 @@ impl Controller {
     }
     fn init_primary_event_ring(&mut self) -> Result<()> {
         let eq = &mut self.primary_event_ring;
-        unsafe { self.rt_regs.get_unchecked_mut() }.init_irs(0, &mut eq.lock())
+        unsafe { self.rt_regs.lock().get_unchecked_mut() }.init_irs(0, &mut eq.lock())
     }
     pub fn primary_event_ring(&self) -> &Mutex<EventRing> {
         &self.primary_event_ring
     }
+    /// Waits until the xHC signals an interrupt for the primary event ring.
+    /// Falls back to just yielding the execution if the interrupt is not available.
+    pub async fn wait_for_event_interrupt(&self) -> Result<()> {
+        if let Some(interrupt) = &self.interrupt {
+            // The timeout is a safety net for missed interrupts.
+            let _ = with_timeout_ms(interrupt.wait(), 10).await;
+            // SAFETY: the registers are valid as far as self is valid.
+            unsafe { self.op_regs.lock().get_unchecked_mut() }.clear_event_interrupt();
+            unsafe { self.rt_regs.lock().get_unchecked_mut() }.clear_interrupt_pending(0)?;
+        } else {
+            yield_execution().await;
+        }
+        Ok(())
+    }
     fn init_slots_and_contexts(&mut self) -> Result<()> {
         let num_slots = self.cap_regs.as_ref().num_of_device_slots();
-        unsafe { self.op_regs.get_unchecked_mut() }.set_num_device_slots(num_slots)?;
-        unsafe { self.op_regs.get_unchecked_mut() }
+        unsafe { self.op_regs.lock().get_unchecked_mut() }.set_num_device_slots(num_slots)?;
+        unsafe { self.op_regs.lock().get_unchecked_mut() }
             .set_dcbaa_ptr(&mut self.device_context_base_array.lock())
     }
     pub fn set_output_context_for_slot(&self, slot: u8, output_context: Pin<Box<OutputContext>>) {
@@ -142,7 +287,8 @@ impl Controller {
             .set_output_context(slot, output_context);
     }
     fn init_command_ring(&mut self) {
-        unsafe { self.op_regs.get_unchecked_mut() }.set_cmd_ring_ctrl(&self.command_ring.lock());
+        unsafe { self.op_regs.lock().get_unchecked_mut() }
+            .set_cmd_ring_ctrl(&self.command_ring.lock());
     }
     fn notify_xhc(&self) {
         self.doorbell_regs[0].notify(0, 0);
@@ -213,7 +359,7 @@ impl Controller {
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
         self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
+        EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, trb_ptr_waiting)
             .await?
             .completed()
     }
@@ -236,7 +382,7 @@ impl Controller {
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
         self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
+        EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, trb_ptr_waiting)
             .await?
             .completed()
     }
@@ -262,7 +408,7 @@ impl Controller {
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
         self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
+        EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, trb_ptr_waiting)
             .await?
             .completed()
     }
@@ -288,7 +434,69 @@ impl Controller {
         let trb_ptr_waiting = ctrl_ep_ring.push(DataStageTrb::new_in(buf).into())?;
         ctrl_ep_ring.push(StatusStageTrb::new_out().into())?;
         self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
+        EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, trb_ptr_waiting)
+            .await?
+            .completed()
+    }
+    pub async fn request_set_report(
+        &self,
+        slot: u8,
+        ctrl_ep_ring: &mut CommandRing,
+        interface_number: u8,
+        report_type_and_id: u16,
+        buf: Pin<&mut [u8]>,
+    ) -> Result<()> {
+        // [HID] 7.2.2 Set_Report Request
+        ctrl_ep_ring.push(
+            SetupStageTrb::new(
+                SetupStageTrb::REQ_TYPE_DIR_HOST_TO_DEVICE
+                    | SetupStageTrb::REQ_TYPE_TYPE_CLASS
+                    | SetupStageTrb::REQ_TYPE_TO_INTERFACE,
+                SetupStageTrb::REQ_SET_REPORT,
+                report_type_and_id,
+                interface_number as u16,
+                buf.len() as u16,
+            )
+            .into(),
+        )?;
+        let data_trb_ptr = ctrl_ep_ring.push(DataStageTrb::new_out(buf).into())?;
+        let status_trb_ptr = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
+        // Both of the stages report their completion
+        let data_stage_done =
+            EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, data_trb_ptr);
+        let status_stage_done =
+            EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, status_trb_ptr);
+        self.notify_ep(slot, 1)?;
+        data_stage_done.await?.completed()?;
+        status_stage_done.await?.completed()
+    }
+    /// Sends a control request which has an IN data stage.
+    pub async fn request_control_in(
+        &self,
+        slot: u8,
+        ctrl_ep_ring: &mut CommandRing,
+        setup: SetupStageTrb,
+        buf: Pin<&mut [u8]>,
+    ) -> Result<()> {
+        ctrl_ep_ring.push(setup.into())?;
+        let trb_ptr_waiting = ctrl_ep_ring.push(DataStageTrb::new_in(buf).into())?;
+        ctrl_ep_ring.push(StatusStageTrb::new_out().into())?;
+        self.notify_ep(slot, 1)?;
+        EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, trb_ptr_waiting)
+            .await?
+            .completed()
+    }
+    /// Sends a control request which has no data stage.
+    pub async fn request_control_no_data(
+        &self,
+        slot: u8,
+        ctrl_ep_ring: &mut CommandRing,
+        setup: SetupStageTrb,
+    ) -> Result<()> {
+        ctrl_ep_ring.push(setup.into())?;
+        let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
+        self.notify_ep(slot, 1)?;
+        EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, trb_ptr_waiting)
             .await?
             .completed()
     }
@@ -314,7 +522,7 @@ impl Controller {
         let trb_ptr_waiting = ctrl_ep_ring.push(DataStageTrb::new_in(buf).into())?;
         ctrl_ep_ring.push(StatusStageTrb::new_out().into())?;
         self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
+        EventFuture::new_transfer_event_on_trb(&self.primary_event_ring, slot, trb_ptr_waiting)
             .await?
             .completed()
     }
@@ -390,70 +598,39 @@ impl Controller {
     }
     pub async fn setup_endpoints(
         &self,
-        port: usize,
+        speed: UsbMode,
         slot: u8,
         input_context: &mut Pin<&mut InputContext>,
-        ep_desc_list: &Vec<EndpointDescriptor>,
+        ep_desc_list: &[(
+            EndpointDescriptor,
+            Option<SuperSpeedEndpointCompanionDescriptor>,
+        )],
     ) -> Result<[Option<TransferRing>; 32]> {
         // 4.6.6 Configure Endpoint
         // When configuring or deconfiguring a device, only after completing a successful
         // Configure Endpoint Command and a successful USB SET_CONFIGURATION
         // request may software schedule data transfers through a newly enabled endpoint
         // or Stream Transfer Ring of the Device Slot.
-        let portsc = self
-            .portsc
-            .get(port)?
-            .upgrade()
-            .ok_or("PORTSC became invalid")?;
         let mut input_ctrl_ctx = InputControlContext::default();
         input_ctrl_ctx.add_context(0)?;
         const EP_RING_NONE: Option<TransferRing> = None;
         let mut ep_rings = [EP_RING_NONE; 32];
         let mut last_dci = 1;
-        for ep_desc in ep_desc_list {
+        for (ep_desc, ss_companion) in ep_desc_list {
             match EndpointType::from(ep_desc) {
-                EndpointType::InterruptIn => {
-                    let tring = TransferRing::new(4096)?;
-                    input_ctrl_ctx.add_context(ep_desc.dci())?;
-                    input_context.set_ep_ctx(
-                        ep_desc.dci(),
-                        EndpointContext::new_interrupt_in_endpoint(
-                            portsc.max_packet_size()?,
-                            tring.ring_phys_addr(),
-                            portsc.port_speed(),
-                            ep_desc.interval,
-                            8,
-                        )?,
-                    )?;
-                    last_dci = max(last_dci, ep_desc.dci());
-                    ep_rings[ep_desc.dci()] = Some(tring);
-                }
-                EndpointType::BulkIn => {
-                    let tring = TransferRing::new(4096)?;
-                    input_ctrl_ctx.add_context(ep_desc.dci())?;
-                    input_context.set_ep_ctx(
-                        ep_desc.dci(),
-                        EndpointContext::new_bulk_in_endpoint(
-                            portsc.max_packet_size()?,
-                            tring.ring_phys_addr(),
-                            portsc.port_speed(),
-                            ep_desc.interval,
-                            8,
-                        )?,
-                    )?;
-                    last_dci = max(last_dci, ep_desc.dci());
-                    ep_rings[ep_desc.dci()] = Some(tring);
-                }
-                EndpointType::BulkOut => {
+                EndpointType::InterruptIn
+                | EndpointType::InterruptOut
+                | EndpointType::BulkIn
+                | EndpointType::BulkOut => {
                     let tring = TransferRing::new(4096)?;
                     input_ctrl_ctx.add_context(ep_desc.dci())?;
                     input_context.set_ep_ctx(
                         ep_desc.dci(),
-                        EndpointContext::new_bulk_out_endpoint(
-                            portsc.max_packet_size()?,
+                        EndpointContext::new_transfer_endpoint(
+                            ep_desc,
+                            ss_companion.as_ref(),
+                            speed,
                             tring.ring_phys_addr(),
-                            portsc.port_speed(),
-                            ep_desc.interval,
                             8,
                         )?,
                     )?;
@@ -1,20 +1,33 @@
 extern crate alloc;
 
+use crate::error::Error;
 use crate::error::Result;
+use crate::usb::descriptor::endpoints_with_companion;
+use crate::usb::descriptor::DescriptorType;
+use crate::usb::descriptor::DeviceDescriptor;
 use crate::usb::descriptor::EndpointDescriptor;
 use crate::usb::descriptor::InterfaceDescriptor;
 use crate::usb::descriptor::UsbDescriptor;
 use crate::warn;
 use crate::xhci::context::InputContext;
+use crate::xhci::context::InputControlContext;
 use crate::xhci::controller::Controller;
+use crate::xhci::controller::RetiredDevice;
 use crate::xhci::future::EventFuture;
+use crate::xhci::registers::UsbMode;
 use crate::xhci::ring::CommandRing;
 use crate::xhci::ring::TransferRing;
+use crate::xhci::trb::CompletionCode;
 use crate::xhci::trb::GenericTrbEntry;
+use crate::xhci::trb::SetupStageTrb;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::mem::ManuallyDrop;
 use core::pin::Pin;
 
 #[derive(Debug, Clone)]
@@ -53,47 +66,149 @@ pub enum UsbHidProtocol {
     ReportProtocol = 1,
 }
 
+// [usb2] Table 9-6. Standard Feature Selectors
+const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+/// What is known about an enumerated device, for inspecting it (e.g. lsusb)
+#[derive(Debug, Clone)]
+pub struct UsbDeviceInfo {
+    pub location: DeviceLocation,
+    // None until the descriptors are read
+    pub device_descriptor: Option<DeviceDescriptor>,
+    pub descriptors: Vec<UsbDescriptor>,
+    // String descriptors referenced from the descriptors above, by index
+    pub strings: BTreeMap<u8, String>,
+}
+impl UsbDeviceInfo {
+    pub fn new(location: DeviceLocation) -> Self {
+        Self {
+            location,
+            device_descriptor: None,
+            descriptors: Vec::new(),
+            strings: BTreeMap::new(),
+        }
+    }
+    /// Returns the string descriptor at the index, or an empty string if there is none.
+    pub fn string(&self, index: u8) -> &str {
+        self.strings.get(&index).map(|s| s.as_str()).unwrap_or("")
+    }
+}
+
+/// Where a device is attached in the USB topology.
+/// Used to fill the Slot Context (6.2.2) of the device.
+#[derive(Debug, Copy, Clone)]
+pub struct DeviceLocation {
+    root_port: usize,
+    // 8.9 Route String: the port number on each tier of hubs (4 bits for each tier)
+    route_string: u32,
+    tier: usize,
+    speed: UsbMode,
+    // Slot and port of the nearest HS hub, for LS/FS devices behind it (Transaction Translator)
+    tt_hub: Option<(u8, u8)>,
+}
+impl DeviceLocation {
+    const MAX_TIERS: usize = 5;
+    pub fn new_on_root_port(root_port: usize, speed: UsbMode) -> Self {
+        Self {
+            root_port,
+            route_string: 0,
+            tier: 0,
+            speed,
+            tt_hub: None,
+        }
+    }
+    /// Location of a device attached to a port of the hub at this location.
+    pub fn child(&self, hub_slot: u8, hub_port: u8, speed: UsbMode) -> Result<Self> {
+        if self.tier >= Self::MAX_TIERS {
+            return Err(Error::Failed("Too many tiers of hubs"));
+        }
+        // Port numbers above 15 are represented as 15 in the Route String
+        let route_string = self.route_string | (hub_port.min(15) as u32) << (self.tier * 4);
+        let tt_hub = match (self.speed, speed) {
+            (UsbMode::HighSpeed, UsbMode::FullSpeed | UsbMode::LowSpeed) => {
+                Some((hub_slot, hub_port))
+            }
+            _ => self.tt_hub,
+        };
+        Ok(Self {
+            root_port: self.root_port,
+            route_string,
+            tier: self.tier + 1,
+            speed,
+            tt_hub,
+        })
+    }
+    pub fn root_port(&self) -> usize {
+        self.root_port
+    }
+    pub fn route_string(&self) -> u32 {
+        self.route_string
+    }
+    pub fn speed(&self) -> UsbMode {
+        self.speed
+    }
+    pub fn tt_hub(&self) -> Option<(u8, u8)> {
+        self.tt_hub
+    }
+    pub fn tier(&self) -> usize {
+        self.tier
+    }
+    /// Returns true if this is the location itself, or behind the hub at the location.
+    pub fn is_at_or_behind(&self, location: &DeviceLocation) -> bool {
+        let mask = (1u64 << (location.tier * 4)) - 1;
+        self.root_port == location.root_port
+            && self.tier >= location.tier
+            && self.route_string as u64 & mask == location.route_string as u64
+    }
+}
+
 pub struct UsbDeviceDriverContext {
-    port: usize,
+    location: DeviceLocation,
     slot: u8,
     xhci: Rc<Controller>,
     descriptors: Vec<UsbDescriptor>,
-    _input_context: Pin<Box<InputContext>>,
-    ctrl_ep_ring: Pin<Box<CommandRing>>,
+    // Moved to the controller on drop (see Controller::retire_device())
+    input_context: ManuallyDrop<Pin<Box<InputContext>>>,
+    ctrl_ep_ring: ManuallyDrop<Pin<Box<CommandRing>>>,
     ep_desc_list: Vec<EndpointDescriptor>,
     ep_rings: [Option<TransferRing>; 32],
 }
 impl UsbDeviceDriverContext {
     pub async fn new(
-        port: usize,
+        location: DeviceLocation,
         slot: u8,
         xhci: Rc<Controller>,
         mut input_context: Pin<Box<InputContext>>,
         ctrl_ep_ring: Pin<Box<CommandRing>>,
         descriptors: Vec<UsbDescriptor>,
     ) -> Result<Self> {
-        let mut ep_desc_list = Vec::new();
-        for d in &descriptors {
-            if let UsbDescriptor::Endpoint(e) = d {
-                ep_desc_list.push(*e);
-            }
-        }
+        let ep_list = endpoints_with_companion(&descriptors);
         let ep_rings = xhci
-            .setup_endpoints(port, slot, &mut input_context.as_mut(), &ep_desc_list)
+            .setup_endpoints(
+                location.speed(),
+                slot,
+                &mut input_context.as_mut(),
+                &ep_list,
+            )
             .await?;
+        let ep_desc_list = ep_list.iter().map(|(e, _)| *e).collect();
         Ok(Self {
-            port,
+            location,
             slot,
             xhci,
             descriptors,
-            _input_context: input_context,
-            ctrl_ep_ring,
+            input_context: ManuallyDrop::new(input_context),
+            ctrl_ep_ring: ManuallyDrop::new(ctrl_ep_ring),
             ep_desc_list,
             ep_rings,
         })
     }
+    /// Port number of the root hub that the device is attached to (possibly via hubs)
     pub fn port(&self) -> usize {
-        self.port
+        self.location.root_port()
+    }
+    pub fn location(&self) -> DeviceLocation {
+        self.location
     }
     pub fn slot(&self) -> u8 {
         self.slot
@@ -104,6 +219,10 @@ impl UsbDeviceDriverContext {
     pub fn descriptors(&self) -> &Vec<UsbDescriptor> {
         &self.descriptors
     }
+    /// Returns false once the device is detached. Drivers should exit then.
+    pub fn is_attached(&self) -> bool {
+        self.xhci.is_attached(self.slot)
+    }
     pub fn ep_desc_list(&self) -> &Vec<EndpointDescriptor> {
         &self.ep_desc_list
     }
@@ -142,6 +261,150 @@ impl UsbDeviceDriverContext {
             )
             .await
     }
+    /// USB HID specific request. Sends an output report (e.g. LEDs of keyboards).
+    pub async fn set_output_report(
+        &mut self,
+        interface_desc: &InterfaceDescriptor,
+        report_id: u8,
+        data: &[u8],
+    ) -> Result<()> {
+        // [hid_1_11] 7.2.2: The high byte of wValue is the report type (2: Output)
+        let mut buf = Box::into_pin(data.to_vec().into_boxed_slice());
+        self.xhci
+            .request_set_report(
+                self.slot,
+                &mut self.ctrl_ep_ring,
+                interface_desc.interface_number(),
+                0x0200 | report_id as u16,
+                buf.as_mut(),
+            )
+            .await
+    }
+    /// USB HID specific request. Returns the Report Descriptor of the interface.
+    pub async fn request_report_descriptor(
+        &mut self,
+        interface_desc: &InterfaceDescriptor,
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        // [hid_1_11] 7.1.1 Get_Descriptor Request: sent to the interface
+        let mut buf = Box::into_pin(vec![0u8; len].into_boxed_slice());
+        self.control_in(
+            SetupStageTrb::new(
+                SetupStageTrb::REQ_TYPE_DIR_DEVICE_TO_HOST | SetupStageTrb::REQ_TYPE_TO_INTERFACE,
+                SetupStageTrb::REQ_GET_DESCRIPTOR,
+                (DescriptorType::HidReport as u16) << 8,
+                interface_desc.interface_number() as u16,
+                len as u16,
+            ),
+            buf.as_mut(),
+        )
+        .await?;
+        Ok(buf.to_vec())
+    }
+    pub async fn control_in(&mut self, setup: SetupStageTrb, buf: Pin<&mut [u8]>) -> Result<()> {
+        self.xhci
+            .request_control_in(self.slot, &mut self.ctrl_ep_ring, setup, buf)
+            .await
+    }
+    pub async fn control_no_data(&mut self, setup: SetupStageTrb) -> Result<()> {
+        self.xhci
+            .request_control_no_data(self.slot, &mut self.ctrl_ep_ring, setup)
+            .await
+    }
+    /// Tells the xHC that the device is a hub (4.5.1, 6.2.2).
+    pub async fn configure_as_hub(
+        &mut self,
+        num_ports: u8,
+        multi_tt: bool,
+        tt_think_time: u8,
+    ) -> Result<()> {
+        let mut input_ctrl_ctx = InputControlContext::default();
+        input_ctrl_ctx.add_context(0)?;
+        let mut input_context = self.input_context.as_mut();
+        input_context.set_hub(num_ports, multi_tt, tt_think_time)?;
+        input_context.set_input_ctrl_ctx(input_ctrl_ctx)?;
+        let cmd = GenericTrbEntry::cmd_configure_endpoint(self.input_context.as_ref(), self.slot);
+        self.xhci.send_command(cmd).await?.completed()
+    }
+    /// Sends `data` via the OUT endpoint and returns the Transfer Event for it.
+    /// The endpoint should not be used with fill_ring().
+    pub async fn transfer_out(
+        &mut self,
+        ep: &EndpointDescriptor,
+        data: &[u8],
+    ) -> Result<GenericTrbEntry> {
+        let ring = self
+            .ep_ring(ep.dci())?
+            .as_ref()
+            .ok_or(Error::Failed("Endpoint not created"))?;
+        let trb_ptr = ring.push_transfer(Some(data), data.len())?;
+        let event = EventFuture::new_transfer_event_on_trb(
+            self.xhci.primary_event_ring(),
+            self.slot,
+            trb_ptr,
+        );
+        self.xhci.notify_ep(self.slot, ep.dci())?;
+        event.await
+    }
+    /// Receives up to `buf.len()` bytes via the IN endpoint and returns the Transfer Event for it.
+    /// The number of bytes received is `buf.len() - event.transfer_length()`.
+    /// The endpoint should not be used with fill_ring().
+    pub async fn transfer_in(
+        &mut self,
+        ep: &EndpointDescriptor,
+        buf: &mut [u8],
+    ) -> Result<GenericTrbEntry> {
+        let ring = self
+            .ep_ring(ep.dci())?
+            .as_ref()
+            .ok_or(Error::Failed("Endpoint not created"))?;
+        let trb_ptr = ring.push_transfer(None, buf.len())?;
+        let event = EventFuture::new_transfer_event_on_trb(
+            self.xhci.primary_event_ring(),
+            self.slot,
+            trb_ptr,
+        );
+        self.xhci.notify_ep(self.slot, ep.dci())?;
+        let event = event.await?;
+        ring.read_transfer_buffer(trb_ptr, buf)?;
+        Ok(event)
+    }
+    /// Recovers the endpoint after a STALL: resets the endpoint on the xHC side (4.6.8),
+    /// skips the TRB which was stalled (4.6.10), then clears the halt on the device side.
+    pub async fn clear_halt(&mut self, ep: &EndpointDescriptor) -> Result<()> {
+        let dci = ep.dci();
+        let event = self
+            .xhci
+            .send_command(GenericTrbEntry::cmd_reset_endpoint(self.slot, dci))
+            .await?;
+        // Context State Error means that the endpoint was not halted on the xHC side
+        // (e.g. the device did not stall yet). Only the device side needs to be cleared then.
+        if event.completion_code() != CompletionCode::ContextStateError as u32 {
+            event.completed()?;
+            let (tr_dequeue_ptr, dcs) = self
+                .ep_ring(dci)?
+                .as_ref()
+                .ok_or(Error::Failed("Endpoint not created"))?
+                .next_enqueue_ptr();
+            self.xhci
+                .send_command(GenericTrbEntry::cmd_set_tr_dequeue_pointer(
+                    self.slot,
+                    dci,
+                    tr_dequeue_ptr,
+                    dcs,
+                ))
+                .await?
+                .completed()?;
+        }
+        self.control_no_data(SetupStageTrb::new(
+            SetupStageTrb::REQ_TYPE_TO_ENDPOINT,
+            SetupStageTrb::REQ_CLEAR_FEATURE,
+            FEATURE_ENDPOINT_HALT,
+            ep.endpoint_address as u16,
+            0,
+        ))
+        .await
+    }
     pub fn push_trb_to_ctrl_ep(&mut self, trb: GenericTrbEntry) -> Result<u64> {
         self.ctrl_ep_ring.push(trb)
     }
@@ -157,3 +420,25 @@ impl UsbDeviceDriverContext {
             .completed()
     }
 }
+impl Drop for UsbDeviceDriverContext {
+    fn drop(&mut self) {
+        // The xHC can still access the contexts and the rings until the slot is
+        // disabled, so keep them alive until then.
+        // SAFETY: the fields are not used anymore since self is being dropped
+        let (input_context, ctrl_ep_ring) = unsafe {
+            (
+                ManuallyDrop::take(&mut self.input_context),
+                ManuallyDrop::take(&mut self.ctrl_ep_ring),
+            )
+        };
+        let ep_rings = self.ep_rings.iter_mut().filter_map(|r| r.take()).collect();
+        self.xhci.retire_device(
+            self.slot,
+            RetiredDevice {
+                input_context,
+                ctrl_ep_ring,
+                ep_rings,
+            },
+        );
+    }
+}
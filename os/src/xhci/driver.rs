@@ -6,6 +6,7 @@ use crate::error::Error;
 use crate::error::Result;
 use crate::executor::dummy_waker;
 use crate::executor::spawn_global;
+use crate::executor::with_timeout_ms;
 use crate::executor::yield_execution;
 use crate::info;
 use crate::pci::BusDeviceFunction;
@@ -13,14 +14,17 @@ use crate::pci::PciDeviceDriver;
 use crate::pci::PciDeviceDriverInstance;
 use crate::pci::VendorDeviceId;
 use crate::usb::descriptor::UsbDescriptor;
-use crate::usb_hid_keyboard;
-use crate::usb_hid_tablet;
+use crate::usb_hid;
+use crate::usb_hub;
+use crate::usb_mass_storage;
 use crate::warn;
 use crate::xhci::context::EndpointContext;
 use crate::xhci::context::InputContext;
 use crate::xhci::context::InputControlContext;
 use crate::xhci::context::OutputContext;
+use crate::xhci::controller::register_controller;
 use crate::xhci::controller::Controller;
+use crate::xhci::device::DeviceLocation;
 use crate::xhci::device::UsbDeviceDriverContext;
 use crate::xhci::init::create_host_controller;
 use crate::xhci::registers::PortLinkState;
@@ -32,29 +36,27 @@ use crate::xhci::ring::CommandRing;
 use crate::xhci::ring::TrbRing;
 use crate::xhci::trb::GenericTrbEntry;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::rc::Rc;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::Context;
 
+// Link training normally completes within tens of milliseconds
+const USB3_LINK_TRAINING_TIMEOUT_MS: u64 = 1000;
+
 #[derive(Default)]
 pub struct XhciDriverForPci {}
 impl XhciDriverForPci {
     async fn update_max_packet_size(
         xhc: &Rc<Controller>,
-        port: usize,
+        location: DeviceLocation,
         slot: u8,
         input_context: &mut Pin<Box<InputContext>>,
         ctrl_ep_ring: &mut Pin<Box<CommandRing>>,
     ) -> Result<()> {
-        if xhc
-            .portsc(port)?
-            .upgrade()
-            .ok_or("PORTSC was invalid")?
-            .port_speed()
-            != UsbMode::FullSpeed
-        {
+        if location.speed() != UsbMode::FullSpeed {
             return Ok(());
         }
         // TODO: refactor this part out
@@ -80,12 +82,12 @@ impl XhciDriverForPci {
     }
     async fn device_ready(
         xhc: Rc<Controller>,
-        port: usize,
+        location: DeviceLocation,
         slot: u8,
         mut input_context: Pin<Box<InputContext>>,
         mut ctrl_ep_ring: Pin<Box<CommandRing>>,
     ) -> Result<Pin<Box<dyn Future<Output = Result<()>>>>> {
-        Self::update_max_packet_size(&xhc, port, slot, &mut input_context, &mut ctrl_ep_ring)
+        Self::update_max_packet_size(&xhc, location, slot, &mut input_context, &mut ctrl_ep_ring)
             .await?;
         let device_descriptor = xhc
             .request_device_descriptor(slot, &mut ctrl_ep_ring)
@@ -95,76 +97,83 @@ impl XhciDriverForPci {
             .await?;
         let device_vendor_id = device_descriptor.vendor_id;
         let device_product_id = device_descriptor.product_id;
+        let mut strings = BTreeMap::new();
         if let Ok(e) = xhc
             .request_string_descriptor_zero(slot, &mut ctrl_ep_ring)
             .await
         {
             let lang_id = e[1];
-            let vendor = if device_descriptor.manufacturer_idx != 0 {
-                Some(
-                    xhc.request_string_descriptor(
-                        slot,
-                        &mut ctrl_ep_ring,
-                        lang_id,
-                        device_descriptor.manufacturer_idx,
-                    )
-                    .await?,
-                )
-            } else {
-                None
-            };
-            let product = if device_descriptor.product_idx != 0 {
-                Some(
-                    xhc.request_string_descriptor(
-                        slot,
-                        &mut ctrl_ep_ring,
-                        lang_id,
-                        device_descriptor.product_idx,
-                    )
-                    .await?,
-                )
-            } else {
-                None
-            };
-            let serial = if device_descriptor.serial_idx != 0 {
-                Some(
-                    xhc.request_string_descriptor(
-                        slot,
-                        &mut ctrl_ep_ring,
-                        lang_id,
-                        device_descriptor.serial_idx,
-                    )
-                    .await?,
-                )
-            } else {
-                None
-            };
+            for index in [
+                device_descriptor.manufacturer_idx,
+                device_descriptor.product_idx,
+                device_descriptor.serial_idx,
+            ] {
+                if index != 0 && !strings.contains_key(&index) {
+                    let s = xhc
+                        .request_string_descriptor(slot, &mut ctrl_ep_ring, lang_id, index)
+                        .await?;
+                    strings.insert(index, s);
+                }
+            }
+            // Strings of the configs and interfaces are only informational (e.g. for lsusb)
+            for index in descriptors.iter().filter_map(|d| match d {
+                UsbDescriptor::Config(e) => Some(e.string_index()),
+                UsbDescriptor::Interface(e) => Some(e.string_index()),
+                _ => None,
+            }) {
+                if index == 0 || strings.contains_key(&index) {
+                    continue;
+                }
+                match xhc
+                    .request_string_descriptor(slot, &mut ctrl_ep_ring, lang_id, index)
+                    .await
+                {
+                    Ok(s) => {
+                        strings.insert(index, s);
+                    }
+                    Err(e) => warn!("Slot {slot}: failed to get string descriptor {index}: {e:?}"),
+                }
+            }
+            let string = |index: u8| strings.get(&index).cloned();
+            let vendor = string(device_descriptor.manufacturer_idx);
+            let product = string(device_descriptor.product_idx);
+            let serial = string(device_descriptor.serial_idx);
             info!("USB device detected: vendor/product/serial =  {vendor:?}/{product:?}/{serial:?} (vid:pid = {device_vendor_id:#06X}:{device_product_id:#06X})");
         } else {
             info!(
                 "USB device detected: vid:pid = {device_vendor_id:#06X}:{device_product_id:#06X}",
             );
         }
-        let ddc =
-            UsbDeviceDriverContext::new(port, slot, xhc, input_context, ctrl_ep_ring, descriptors)
-                .await?;
+        xhc.set_device_descriptors(slot, device_descriptor, descriptors.clone(), strings);
+        let ddc = UsbDeviceDriverContext::new(
+            location,
+            slot,
+            xhc,
+            input_context,
+            ctrl_ep_ring,
+            descriptors,
+        )
+        .await?;
         if device_vendor_id == 2965 && device_product_id == 6032 {
             ax88179::attach_usb_device(ddc).await?;
         } else if device_vendor_id == 0x0bda
             && (device_product_id == 0x8153 || device_product_id == 0x8151)
         {
             error!("rtl8153/8151 is not supported yet...");
+        } else if device_descriptor.device_class == usb_hub::USB_CLASS_HUB {
+            let f = usb_hub::attach_usb_device(ddc);
+            return Ok(Box::pin(f));
         } else if device_descriptor.device_class == 0 {
             // Device class is derived from Interface Descriptor
             for d in ddc.descriptors() {
                 if let UsbDescriptor::Interface(e) = d {
                     match e.triple() {
-                        (3, 0, 0) => {
-                            let f = usb_hid_tablet::attach_usb_device(ddc);
+                        (usb_hid::USB_CLASS_HID, _, _) => {
+                            let f = usb_hid::attach_usb_device(ddc);
                             return Ok(Box::pin(f));
                         }
-                        (3, 1, 1) => {
-                            let f = usb_hid_keyboard::attach_usb_device(ddc);
+                        usb_mass_storage::USB_MASS_STORAGE_TRIPLE => {
+                            let f = usb_mass_storage::attach_usb_device(ddc);
                             return Ok(Box::pin(f));
                         }
                         triple => warn!("Skipping unknown interface triple: {triple:?}"),
@@ -179,7 +188,7 @@ impl XhciDriverForPci {
     }
     async fn address_device(
         xhc: Rc<Controller>,
-        port: usize,
+        location: DeviceLocation,
         slot: u8,
     ) -> Result<Pin<Box<dyn Future<Output = Result<()>>>>> {
         // Setup an input context and send AddressDevice command.
@@ -192,24 +201,31 @@ impl XhciDriverForPci {
         let mut input_context = Box::pin(InputContext::default());
         input_context.as_mut().set_input_ctrl_ctx(input_ctrl_ctx)?;
         // 3. Initialize the Input Slot Context data structure (6.2.2)
-        input_context.as_mut().set_root_hub_port_number(port)?;
+        input_context
+            .as_mut()
+            .set_root_hub_port_number(location.root_port())?;
+        input_context
+            .as_mut()
+            .set_route_string(location.route_string())?;
+        if let Some((hub_slot, hub_port)) = location.tt_hub() {
+            input_context.as_mut().set_tt_hub(hub_slot, hub_port);
+        }
         input_context.as_mut().set_last_valid_dci(1)?;
         // 4. Initialize the Transfer Ring for the Default Control Endpoint
         // 5. Initialize the Input default control Endpoint 0 Context (6.2.3)
-        let portsc = xhc.portsc(port)?.upgrade().ok_or("PORTSC was invalid")?;
-        input_context.as_mut().set_port_speed(portsc.port_speed())?;
+        input_context.as_mut().set_port_speed(location.speed())?;
         let mut ctrl_ep_ring = Box::pin(CommandRing::default());
         input_context.as_mut().set_ep_ctx(
             1,
             EndpointContext::new_control_endpoint(
-                portsc.max_packet_size()?,
+                location.speed().default_max_packet_size()?,
                 ctrl_ep_ring.as_mut().ring_phys_addr(),
             )?,
         )?;
         // 8. Issue an Address Device Command for the Device Slot
         let cmd = GenericTrbEntry::cmd_address_device(input_context.as_ref(), slot);
         xhc.send_command(cmd).await?.completed()?;
-        Self::device_ready(xhc.clone(), port, slot, input_context, ctrl_ep_ring).await
+        Self::device_ready(xhc.clone(), location, slot, input_context, ctrl_ep_ring).await
     }
     async fn ensure_ring_is_working(xhc: Rc<Controller>) -> Result<()> {
         for _ in 0..TrbRing::NUM_TRB * 2 + 1 {
@@ -219,10 +235,13 @@ impl XhciDriverForPci {
         }
         Ok(())
     }
-    async fn enable_slot(
+    /// Enumerates the device at the location, which has been reset and enabled.
+    /// Returns a future that handles device disconnect when needed.
+    pub async fn enable_slot(
         xhc: Rc<Controller>,
-        port: usize,
+        location: DeviceLocation,
     ) -> Result<Pin<Box<dyn Future<Output = Result<()>>>>> {
+        let port = location.root_port();
         let portsc = xhc.portsc(port)?.upgrade().ok_or("PORTSC was invalid")?;
         if !portsc.ccs() {
             return Err(Error::FailedString(format!(
@@ -234,7 +253,20 @@ impl XhciDriverForPci {
             .send_command(GenericTrbEntry::cmd_enable_slot())
             .await?
             .slot_id();
-        Self::address_device(xhc.clone(), port, slot).await
+        xhc.register_device(slot, location);
+        match Self::address_device(xhc.clone(), location, slot).await {
+            Ok(f) => Ok(Box::pin(async move {
+                // The driver exits when the device is detached (or on errors).
+                // Release the slot then so that the device can be enumerated again.
+                let result = f.await;
+                xhc.disable_slot(slot).await?;
+                result
+            })),
+            Err(e) => {
+                xhc.disable_slot(slot).await?;
+                Err(e)
+            }
+        }
     }
     /// Returns a future that handles device disconnect when needed.
     async fn enable_port(
@@ -243,19 +275,63 @@ impl XhciDriverForPci {
     ) -> Result<Pin<Box<dyn Future<Output = Result<()>>>>> {
         // Reset port to enable the port (via Reset state)
         xhc.reset_port(port).await?;
-        loop {
+        let speed = loop {
             let portsc = xhc.portsc(port)?.upgrade().ok_or("PORTSC was invalid")?;
             if let (PortState::Enabled, PortLinkState::U0) = (portsc.state(), portsc.pls()) {
-                break;
+                break portsc.port_speed();
+            }
+            yield_execution().await;
+        };
+        Self::enable_slot(xhc.clone(), DeviceLocation::new_on_root_port(port, speed)).await
+    }
+    /// Waits for the link training of a USB3 port to complete.
+    async fn wait_for_usb3_link(xhc: Rc<Controller>, port: usize) -> Result<UsbMode> {
+        // 4.19.5: The link is trained automatically after a connection and the port becomes
+        // Enabled in U0. A failed training leaves the link in Inactive or Compliance Mode,
+        // which can be recovered by a Warm Reset.
+        let mut warm_reset_issued = false;
+        loop {
+            let portsc = xhc.portsc(port)?.upgrade().ok_or("PORTSC was invalid")?;
+            if !portsc.ccs() {
+                return Err(Error::FailedString(format!(
+                    "port {} disconnected while link training",
+                    port
+                )));
+            }
+            match (portsc.state(), portsc.pls()) {
+                (PortState::Enabled, PortLinkState::U0) => return Ok(portsc.port_speed()),
+                (_, PortLinkState::Inactive | PortLinkState::ComplianceMode) => {
+                    if warm_reset_issued {
+                        return Err(Error::FailedString(format!(
+                            "link training failed on port {port}: {portsc:?}"
+                        )));
+                    }
+                    warn!("Port {port}: Issuing a warm reset: {portsc:?}");
+                    portsc.warm_reset();
+                    warm_reset_issued = true;
+                }
+                _ => {}
             }
             yield_execution().await;
         }
-        Self::enable_slot(xhc.clone(), port).await
+    }
+    /// Returns a future that handles device disconnect when needed.
+    async fn enable_usb3_port(
+        xhc: Rc<Controller>,
+        port: usize,
+    ) -> Result<Pin<Box<dyn Future<Output = Result<()>>>>> {
+        // USB3 ports do not have the Disabled state and need no port reset to be enabled.
+        let speed = with_timeout_ms(
+            Self::wait_for_usb3_link(xhc.clone(), port),
+            USB3_LINK_TRAINING_TIMEOUT_MS,
+        )
+        .await??;
+        Self::enable_slot(xhc.clone(), DeviceLocation::new_on_root_port(port, speed)).await
     }
     async fn poll(xhc: Rc<Controller>) -> Result<()> {
         // 4.3 USB Device Initialization
         // USB3: Disconnected -> Polling -> Enabled
-        // USB2: Disconnected -> Disabled
+        // USB2: Disconnected -> Disabled -> (Reset) -> Enabled
         if let Some((port, portsc)) = xhc.portsc_iter().find_map(
             |PortScIteratorItem { port, portsc }| -> Option<(usize, Rc<PortScWrapper>)> {
                 let portsc = portsc.upgrade()?;
@@ -269,30 +345,35 @@ impl XhciDriverForPci {
         ) {
             if portsc.ccs() {
                 info!("Port {port}: Device attached: {portsc:?}: ");
-                if portsc.state() == PortState::Disabled {
-                    match Self::enable_port(xhc.clone(), port).await {
-                        Ok(f) => {
-                            xhc.device_futures().lock().push_back(f);
-                        }
-                        Err(e) => {
-                            error!(
-                                "Failed to initialize an USB2 device on port {}: {:?}",
-                                port, e
-                            );
-                        }
-                    }
-                } else if portsc.state() == PortState::Enabled {
-                    warn!("USB3 is not supported yet (Skipping)");
+                let usb_version = if portsc.is_usb3() { 3 } else { 2 };
+                let f = if portsc.is_usb3() {
+                    Self::enable_usb3_port(xhc.clone(), port).await
+                } else if portsc.state() == PortState::Disabled {
+                    Self::enable_port(xhc.clone(), port).await
                 } else {
-                    error!("Unexpected state");
+                    Err(Error::FailedString(format!("Unexpected state: {portsc:?}")))
+                };
+                match f {
+                    Ok(f) => {
+                        xhc.device_futures().lock().push_back(f);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to initialize an USB{} device on port {}: {:?}",
+                            usb_version, port, e
+                        );
+                    }
                 }
             } else {
                 info!("Port {}: Device detached: {:?}", port, portsc);
+                xhc.detach_devices_on_root_port(port);
             }
         }
         let waker = dummy_waker();
         let mut ctx = Context::from_waker(&waker);
-        let mut device_futures = xhc.device_futures().lock();
+        // Take the futures out of the lock since drivers (e.g. hubs) can add devices
+        // while being polled.
+        let mut device_futures = core::mem::take(&mut *xhc.device_futures().lock());
         let mut c = device_futures.cursor_front_mut();
         while let Some(f) = c.current() {
             let r = Future::poll(f.as_mut(), &mut ctx);
@@ -302,6 +383,9 @@ impl XhciDriverForPci {
                 c.move_next();
             }
         }
+        let mut added = xhc.device_futures().lock();
+        device_futures.append(&mut added);
+        *added = device_futures;
         Ok(())
     }
     fn spawn(bdf: BusDeviceFunction) -> Result<Self> {
@@ -309,12 +393,18 @@ impl XhciDriverForPci {
             info!("Initializing the xHC");
             let xhc = create_host_controller(bdf)?;
             let xhc = Rc::new(xhc);
+            register_controller(&xhc);
             {
                 let xhc = xhc.clone();
                 spawn_global(async move {
                     loop {
-                        xhc.primary_event_ring().lock().poll().await?;
-                        yield_execution().await;
+                        let has_next_event = xhc.primary_event_ring().lock().has_next_event();
+                        if has_next_event {
+                            xhc.primary_event_ring().lock().poll().await?;
+                            yield_execution().await;
+                        } else {
+                            xhc.wait_for_event_interrupt().await?;
+                        }
                     }
                 })
             }
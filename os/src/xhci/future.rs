@@ -1,5 +1,6 @@
 extern crate alloc;
 
+use crate::error::Error;
 use crate::error::Result;
 use crate::mutex::Mutex;
 use crate::xhci::ring::EventRing;
@@ -7,6 +8,7 @@ use crate::xhci::trb::GenericTrbEntry;
 use crate::xhci::trb::TrbType;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
+use core::cell::Cell;
 use core::future::Future;
 use core::marker::PhantomPinned;
 use core::pin::Pin;
@@ -24,6 +26,8 @@ pub struct EventWaitCond {
 pub struct EventWaitInfo {
     cond: EventWaitCond,
     trbs: Mutex<VecDeque<GenericTrbEntry>>,
+    // Set when the device is detached, to wake up the waiter with an error
+    cancelled: Cell<bool>,
 }
 impl EventWaitInfo {
     pub fn matches(&self, trb: &GenericTrbEntry) -> bool {
@@ -44,6 +48,12 @@ impl EventWaitInfo {
         }
         true
     }
+    pub fn is_on_slot(&self, slot: u8) -> bool {
+        self.cond.slot == Some(slot)
+    }
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+    }
     pub fn resolve(&self, trb: &GenericTrbEntry) -> Result<()> {
         self.trbs.under_locked(&|trbs| -> Result<()> {
             trbs.push_back(trb.clone());
@@ -67,6 +77,7 @@ impl EventFuture {
         let wait_on = EventWaitInfo {
             cond,
             trbs: Default::default(),
+            cancelled: Cell::new(false),
         };
         let wait_on = Rc::new(wait_on);
         event_ring.lock().register_waiter(&wait_on);
@@ -105,6 +116,22 @@ impl EventFuture {
             },
         )
     }
+    /// Waits for the completion of a transfer TRB, which is cancelled
+    /// when the device on the slot is detached.
+    pub fn new_transfer_event_on_trb(
+        event_ring: &Mutex<EventRing>,
+        slot: u8,
+        trb_addr: u64,
+    ) -> Self {
+        Self::new(
+            event_ring,
+            EventWaitCond {
+                trb_type: Some(TrbType::TransferEvent),
+                trb_addr: Some(trb_addr),
+                slot: Some(slot),
+            },
+        )
+    }
     pub fn new_on_trb(event_ring: &Mutex<EventRing>, trb_addr: u64) -> Self {
         Self::new(
             event_ring,
@@ -123,6 +150,8 @@ impl Future for EventFuture {
         let mut_self = unsafe { self.get_unchecked_mut() };
         if let Some(trb) = mut_self.wait_on.trbs.lock().pop_front() {
             Poll::Ready(Ok(trb))
+        } else if mut_self.wait_on.cancelled.get() {
+            Poll::Ready(Err(Error::Failed("The device has been detached")))
         } else {
             Poll::Pending
         }
@@ -8,6 +8,7 @@ use crate::mutex::Mutex;
 use crate::pci::BusDeviceFunction;
 use crate::pci::Pci;
 use crate::util::PAGE_SIZE;
+use crate::warn;
 use crate::xhci::context::DeviceContextBaseAddressArray;
 use crate::xhci::controller::Controller;
 use crate::xhci::registers::CapabilityRegisters;
@@ -53,6 +54,13 @@ pub fn create_host_controller(bdf: BusDeviceFunction) -> Result<Controller> {
     let pci = Pci::take();
     pci.disable_interrupt(bdf)?;
     pci.enable_bus_master(bdf)?;
+    let interrupt = match pci.request_message_signaled_interrupt(bdf, None) {
+        Ok(interrupt) => Some(interrupt),
+        Err(e) => {
+            warn!("xhci: MSI/MSI-X is not available, fallback to polling: {e:?}");
+            None
+        }
+    };
     let bar0 = pci.try_bar0_mem64(bdf)?;
     bar0.disable_cache();
 
@@ -89,5 +97,6 @@ pub fn create_host_controller(bdf: BusDeviceFunction) -> Result<Controller> {
         portsc,
         doorbell_regs,
         device_context_base_array,
+        interrupt,
     )
 }
@@ -17,6 +17,7 @@ use alloc::fmt::Debug;
 use alloc::format;
 use alloc::rc::Rc;
 use alloc::rc::Weak;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::mem::size_of;
 use core::mem::transmute;
@@ -41,6 +42,18 @@ impl UsbMode {
             Self::Unknown(psi) => psi,
         }
     }
+    /// Max packet size of the Default Control Endpoint before reading the Device Descriptor
+    pub fn default_max_packet_size(&self) -> Result<u16> {
+        match self {
+            UsbMode::FullSpeed | UsbMode::LowSpeed => Ok(8),
+            UsbMode::HighSpeed => Ok(64),
+            UsbMode::SuperSpeed => Ok(512),
+            speed => Err(Error::FailedString(format!(
+                "Unknown Protocol Speeed ID: {:?}",
+                speed
+            ))),
+        }
+    }
 }
 
 #[repr(u32)]
@@ -82,6 +95,8 @@ pub enum PortState {
 #[repr(C)]
 pub struct PortScWrapper {
     ptr: Mutex<*mut u32>,
+    // Major Revision of the USB protocol supported by this port (2 or 3)
+    major_revision: u8,
 }
 impl PortScWrapper {
     const PRESERVE_MASK: u32 = 0b01001111000000011111111111101001;
@@ -90,12 +105,18 @@ impl PortScWrapper {
     const BIT_PORT_RESET: u32 = 1 << 4;
     const BIT_PORT_POWER: u32 = 1 << 9;
     const BIT_CONNECT_STATUS_CHANGE: u32 = 1 << 17;
+    const BIT_WARM_PORT_RESET_CHANGE: u32 = 1 << 19;
     const BIT_PORT_RESET_CHANGE: u32 = 1 << 21;
-    fn new(ptr: *mut u32) -> Self {
+    const BIT_WARM_PORT_RESET: u32 = 1 << 31;
+    fn new(ptr: *mut u32, major_revision: u8) -> Self {
         Self {
             ptr: Mutex::new(ptr),
+            major_revision,
         }
     }
+    pub fn is_usb3(&self) -> bool {
+        self.major_revision >= 3
+    }
     pub fn value(&self) -> u32 {
         let portsc = self.ptr.lock();
         unsafe { read_volatile(*portsc) }
@@ -115,6 +136,16 @@ impl PortScWrapper {
             busy_loop_hint();
         }
     }
+    /// 4.19.5.1: Warm Reset brings a USB3 port back from the Inactive or
+    /// Compliance Mode link state, where a normal port reset has no effect.
+    pub fn warm_reset(&self) {
+        self.set_bits(Self::BIT_WARM_PORT_RESET);
+        // PR stays set while the Warm Reset is in progress.
+        while self.pr() {
+            busy_loop_hint();
+        }
+        self.set_bits(Self::BIT_WARM_PORT_RESET_CHANGE | Self::BIT_PORT_RESET_CHANGE);
+    }
     pub fn ccs(&self) -> bool {
         // CCS - Current Connect Status - ROS
         self.value() & Self::BIT_CURRENT_CONNECT_STATUS != 0
@@ -161,17 +192,6 @@ impl PortScWrapper {
             v => UsbMode::Unknown(v),
         }
     }
-    pub fn max_packet_size(&self) -> Result<u16> {
-        match self.port_speed() {
-            UsbMode::FullSpeed | UsbMode::LowSpeed => Ok(8),
-            UsbMode::HighSpeed => Ok(64),
-            UsbMode::SuperSpeed => Ok(512),
-            speed => Err(Error::FailedString(format!(
-                "Unknown Protocol Speeed ID: {:?}",
-                speed
-            ))),
-        }
-    }
     pub fn state(&self) -> PortState {
         // 4.19.1.1 USB2 Root Hub Port
         match (self.pp(), self.ccs(), self.ped(), self.pr()) {
@@ -191,9 +211,10 @@ impl Debug for PortScWrapper {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "PORTSC: {:#010X} {:?} (PP={:?}, PLS={:?}, Speed={:?})",
+            "PORTSC: {:#010X} {:?} (USB{}, PP={:?}, PLS={:?}, Speed={:?})",
             self.value(),
             self.state(),
+            self.major_revision,
             self.pp(),
             self.pls(),
             self.port_speed(),
@@ -247,16 +268,48 @@ impl PortSc {
     pub fn new(bar: &BarMem64, cap_regs: &CapabilityRegisters) -> Self {
         let base = unsafe { bar.addr().add(cap_regs.length()).add(0x400) } as *mut u32;
         let num_ports = cap_regs.num_of_ports();
+        let major_revisions = Self::major_revisions(bar, cap_regs);
         let mut entries = Vec::new();
         for port in 1..=num_ports {
             // SAFETY: This is safe since the result of ptr calculation
             // always points to a valid PORTSC entry under the condition.
             let ptr = unsafe { base.add((port - 1) * 4) };
-            entries.push(Rc::new(PortScWrapper::new(ptr)));
+            entries.push(Rc::new(PortScWrapper::new(ptr, major_revisions[port - 1])));
         }
         assert!(entries.len() == num_ports);
         Self { entries }
     }
+    // [xhci] 7.2 xHCI Supported Protocol Capability
+    // Returns the Major Revision of the protocol for each port (index: port - 1).
+    // Ports not covered by any capability are treated as USB2 ports.
+    fn major_revisions(bar: &BarMem64, cap_regs: &CapabilityRegisters) -> Vec<u8> {
+        const CAP_ID_SUPPORTED_PROTOCOL: u32 = 2;
+        let mut major_revisions = vec![2; cap_regs.num_of_ports()];
+        let mut offset = cap_regs.xecp();
+        while offset != 0 {
+            // SAFETY: xECP and the Next Capability Pointers point to
+            // the extended capabilities in the MMIO space of the xHC.
+            let cap = unsafe { bar.addr().add(offset) } as *const u32;
+            let header = unsafe { read_volatile(cap) };
+            if extract_bits(header, 0, 8) == CAP_ID_SUPPORTED_PROTOCOL {
+                let major_revision = extract_bits(header, 24, 8) as u8;
+                let ports = unsafe { read_volatile(cap.add(2)) };
+                let first_port = extract_bits(ports, 0, 8) as usize;
+                let num_ports = extract_bits(ports, 8, 8) as usize;
+                for port in first_port..first_port + num_ports {
+                    if let Some(e) = major_revisions.get_mut(port.wrapping_sub(1)) {
+                        *e = major_revision;
+                    }
+                }
+            }
+            let next = extract_bits(header, 8, 8) as usize;
+            if next == 0 {
+                break;
+            }
+            offset += next * 4;
+        }
+        major_revisions
+    }
     pub fn get(&self, port: usize) -> Result<Weak<PortScWrapper>> {
         self.entries
             .get(port.wrapping_sub(1))
@@ -306,6 +359,11 @@ impl CapabilityRegisters {
     pub fn num_of_ports(&self) -> usize {
         extract_bits(self.hcsparams1.read(), 24, 8) as usize
     }
+    // xHCI Extended Capabilities Pointer, in bytes from the base of the MMIO space.
+    // 0 if there are no extended capabilities.
+    pub fn xecp(&self) -> usize {
+        extract_bits(self.hccparams1.read(), 16, 16) as usize * 4
+    }
     pub fn num_scratch_pad_bufs(&self) -> usize {
         (extract_bits(self.hcsparams2.read(), 21, 5) << 5
             | extract_bits(self.hcsparams2.read(), 27, 5)) as usize
@@ -342,7 +400,9 @@ const _: () = assert!(size_of::<OperationalRegisters>() == 0x40);
 impl OperationalRegisters {
     const CMD_RUN_STOP: u32 = 0b0001;
     const CMD_HC_RESET: u32 = 0b0010;
+    const CMD_INTERRUPTER_ENABLE: u32 = 0b0100;
     const STATUS_HC_HALTED: u32 = 0b0001;
+    const STATUS_EVENT_INTERRUPT: u32 = 0b1000;
     fn clear_command_bits(&mut self, bits: u32) {
         unsafe {
             write_volatile(&mut self.command, self.command() & !bits);
@@ -403,6 +463,13 @@ impl OperationalRegisters {
             busy_loop_hint();
         }
     }
+    pub fn enable_interrupt(&mut self) {
+        self.set_command_bits(Self::CMD_INTERRUPTER_ENABLE);
+    }
+    pub fn clear_event_interrupt(&mut self) {
+        // EINT is RW1C. Other RW1C bits should not be touched.
+        unsafe { write_volatile(&mut self.status, Self::STATUS_EVENT_INTERRUPT) }
+    }
     pub fn start_xhc(&mut self) {
         self.set_command_bits(Self::CMD_RUN_STOP);
         while self.status() & Self::STATUS_HC_HALTED != 0 {
@@ -442,6 +509,39 @@ impl RuntimeRegisters {
         ring.set_erdp(&mut irs.erdp as *mut u64);
         Ok(())
     }
+    // [xhci] 5.5.2.1 Interrupter Management Register (IMAN)
+    const IMAN_INTERRUPT_PENDING: u32 = 0b01;
+    const IMAN_INTERRUPT_ENABLE: u32 = 0b10;
+    pub fn enable_interrupter(&mut self, index: usize) -> Result<()> {
+        let irs = self
+            .irs
+            .get_mut(index)
+            .ok_or(Error::Failed("Index out of range"))?;
+        // Interrupt Moderation Interval: 4000 * 250ns = 1ms
+        unsafe {
+            write_volatile(&mut irs.moderation, 4000);
+            write_volatile(
+                &mut irs.management,
+                Self::IMAN_INTERRUPT_ENABLE | Self::IMAN_INTERRUPT_PENDING,
+            );
+        }
+        Ok(())
+    }
+    pub fn clear_interrupt_pending(&mut self, index: usize) -> Result<()> {
+        let irs = self
+            .irs
+            .get_mut(index)
+            .ok_or(Error::Failed("Index out of range"))?;
+        // IP is RW1C
+        unsafe {
+            let management = read_volatile(&irs.management);
+            write_volatile(
+                &mut irs.management,
+                management | Self::IMAN_INTERRUPT_PENDING,
+            );
+        }
+        Ok(())
+    }
 }
 const _: () = assert!(size_of::<RuntimeRegisters>() == 0x8020);
 
@@ -12,8 +12,10 @@ use crate::xhci::future::EventWaitInfo;
 use crate::xhci::trb::GenericTrbEntry;
 use crate::xhci::trb::NormalTrb;
 use crate::xhci::trb::TrbType;
+use alloc::alloc::GlobalAlloc;
 use alloc::alloc::Layout;
 use alloc::collections::BTreeMap;
+use alloc::collections::BTreeSet;
 use alloc::collections::VecDeque;
 use alloc::fmt;
 use alloc::fmt::Debug;
@@ -242,6 +244,71 @@ impl TransferRingInner {
     pub fn ring_phys_addr(&self) -> u64 {
         self.ring.as_ref() as *const TrbRing as u64
     }
+    /// Gives a TRB to the xHC to transfer `len` bytes with the buffer of the TRB.
+    /// The buffer is filled with `data` before that (for OUT endpoints).
+    /// Returns the pointer to the TRB to wait for its completion.
+    ///
+    /// This is for endpoints which are driven on demand (e.g. bulk endpoints
+    /// of mass storage devices), so do not mix it with fill_ring() and dequeue_trb().
+    pub fn push_transfer(&mut self, data: Option<&[u8]>, len: usize) -> Result<u64> {
+        if len > Self::BUF_SIZE || data.is_some_and(|data| data.len() > len) {
+            return Err(Error::Failed("Transfer is too large for a TRB"));
+        }
+        let index = self.ring.as_ref().current_index();
+        let buf = self.buffers[index];
+        if let Some(data) = data {
+            // SAFETY: buf points to a buffer of BUF_SIZE bytes and it is not used by the xHC
+            // until the TRB is given to the xHC below.
+            unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len()) };
+        }
+        let mut trb: GenericTrbEntry = NormalTrb::new(buf, len as u16).into();
+        trb.set_cycle_state(self.cycle_state_ours);
+        let mut_ring = unsafe { self.ring.get_unchecked_mut() };
+        let trb_ptr = mut_ring.current_ptr();
+        mut_ring.write_current(trb);
+        mut_ring.advance_index(!self.cycle_state_ours)?;
+        if mut_ring.current().trb_type() == TrbType::Link as u32 {
+            // Reached to Link TRB. Let's skip it and toggle the cycle.
+            mut_ring.advance_index(!self.cycle_state_ours)?;
+            self.cycle_state_ours = !self.cycle_state_ours;
+        }
+        Ok(trb_ptr as u64)
+    }
+    /// Copies the data received with the TRB pushed by push_transfer().
+    pub fn read_transfer_buffer(&self, trb_ptr: u64, dst: &mut [u8]) -> Result<()> {
+        let index = (trb_ptr as usize)
+            .checked_sub(self.ring.as_ref().trb_ptr(0))
+            .map(|offset| offset / size_of::<GenericTrbEntry>())
+            .ok_or(Error::Failed("trb_ptr is not in the ring"))?;
+        if index >= self.buffers.len() {
+            return Err(Error::Failed("trb_ptr is not in the ring"));
+        }
+        if dst.len() > Self::BUF_SIZE {
+            return Err(Error::Failed("dst is too large"));
+        }
+        // SAFETY: the buffer has BUF_SIZE bytes and the transfer has been completed.
+        unsafe { core::ptr::copy_nonoverlapping(self.buffers[index], dst.as_mut_ptr(), dst.len()) };
+        Ok(())
+    }
+    /// Returns the TR Dequeue Pointer and the Dequeue Cycle State to restart
+    /// the endpoint from the next TRB to be pushed (for Set TR Dequeue Pointer Command).
+    pub fn next_enqueue_ptr(&self) -> (u64, bool) {
+        (
+            self.ring.as_ref().current_ptr() as u64,
+            !self.cycle_state_ours,
+        )
+    }
+}
+impl Drop for TransferRingInner {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(Self::BUF_SIZE, Self::BUF_ALIGN)
+            .expect("TransferRing buffer layout should be valid");
+        for buf in self.buffers.iter().filter(|buf| !buf.is_null()) {
+            // SAFETY: the buffers are allocated in new() with the same layout,
+            // and the xHC does not access them anymore once the ring is dropped.
+            unsafe { ALLOCATOR.dealloc(*buf, layout) };
+        }
+    }
 }
 impl Debug for TransferRingInner {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -277,6 +344,15 @@ impl TransferRing {
     pub fn ring_phys_addr(&self) -> u64 {
         self.inner.lock().ring_phys_addr()
     }
+    pub fn push_transfer(&self, data: Option<&[u8]>, len: usize) -> Result<u64> {
+        self.inner.lock().push_transfer(data, len)
+    }
+    pub fn read_transfer_buffer(&self, trb_ptr: u64, dst: &mut [u8]) -> Result<()> {
+        self.inner.lock().read_transfer_buffer(trb_ptr, dst)
+    }
+    pub fn next_enqueue_ptr(&self) -> (u64, bool) {
+        self.inner.lock().next_enqueue_ptr()
+    }
 }
 impl Debug for TransferRing {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -292,6 +368,8 @@ pub struct EventRing {
     events_per_slot: BTreeMap<u8, VecDeque<GenericTrbEntry>>,
     events_per_trb: BTreeMap<u64, GenericTrbEntry>,
     wait_list: VecDeque<Weak<EventWaitInfo>>,
+    // Slots of the detached devices, until the slots are enabled again
+    detached_slots: BTreeSet<u8>,
 }
 impl EventRing {
     pub fn new() -> Result<Self> {
@@ -304,6 +382,7 @@ impl EventRing {
             cycle_state_ours: true,
             erdp: None,
             events_per_slot: BTreeMap::new(),
+            detached_slots: BTreeSet::new(),
             events_per_trb: BTreeMap::new(),
             wait_list: Default::default(),
         })
@@ -317,7 +396,7 @@ impl EventRing {
     pub fn erst_phys_addr(&self) -> u64 {
         self.erst.as_ref() as *const EventRingSegmentTableEntry as u64
     }
-    fn has_next_event(&self) -> bool {
+    pub fn has_next_event(&self) -> bool {
         self.ring.as_ref().current().cycle_state() == self.cycle_state_ours
     }
     /// Non-blocking
@@ -392,9 +471,30 @@ impl EventRing {
         Ok(None)
     }
     pub fn register_waiter(&mut self, wait: &Rc<EventWaitInfo>) {
+        if self
+            .detached_slots
+            .iter()
+            .any(|slot| wait.is_on_slot(*slot))
+        {
+            wait.cancel();
+        }
         let wait = Rc::downgrade(wait);
         self.wait_list.push_back(wait);
     }
+    /// Wakes up the waiters for the events on the slot with an error.
+    /// Waiters registered later are cancelled as well, until the slot is enabled again.
+    pub fn cancel_waiters_on_slot(&mut self, slot: u8) {
+        self.detached_slots.insert(slot);
+        for w in self.wait_list.iter().filter_map(|w| w.upgrade()) {
+            if w.is_on_slot(slot) {
+                w.cancel();
+            }
+        }
+        self.events_per_slot.remove(&slot);
+    }
+    pub fn on_slot_enabled(&mut self, slot: u8) {
+        self.detached_slots.remove(&slot);
+    }
     pub async fn poll(&mut self) -> Result<()> {
         if let Some(e) = self.pop()? {
             let mut consumed = false;
@@ -25,9 +25,12 @@ pub enum TrbType {
     StatusStage = 4,
     Link = 6,
     EnableSlotCommand = 9,
+    DisableSlotCommand = 10,
     AddressDeviceCommand = 11,
     ConfigureEndpointCommand = 12,
     EvaluateContextCommand = 13,
+    ResetEndpointCommand = 14,
+    SetTrDequeuePointerCommand = 16,
     NoOpCommand = 23,
     TransferEvent = 32,
     CommandCompletionEvent = 33,
@@ -49,6 +52,7 @@ pub enum CompletionCode {
     StallError = 6,
     ShortPacket = 13,
     ParameterError = 17,
+    ContextStateError = 19,
     EventRingFullError = 21,
 }
 impl CompletionCode {
@@ -60,6 +64,7 @@ impl CompletionCode {
             code if code == CompletionCode::StallError as u32 => "StallError",
             code if code == CompletionCode::ShortPacket as u32 => "ShortPacket",
             code if code == CompletionCode::ParameterError as u32 => "ParameterError",
+            code if code == CompletionCode::ContextStateError as u32 => "ContextStateError",
             code if code == CompletionCode::EventRingFullError as u32 => "EventRingFullError",
             _ => "?",
         }
@@ -147,6 +152,12 @@ impl GenericTrbEntry {
         trb.set_trb_type(TrbType::EnableSlotCommand);
         trb
     }
+    pub fn cmd_disable_slot(slot_id: u8) -> Self {
+        let mut trb = Self::default();
+        trb.set_trb_type(TrbType::DisableSlotCommand);
+        trb.set_slot_id(slot_id);
+        trb
+    }
     pub fn cmd_address_device(input_context: Pin<&InputContext>, slot_id: u8) -> Self {
         let mut trb = Self::default();
         trb.set_trb_type(TrbType::AddressDeviceCommand);
@@ -171,6 +182,28 @@ impl GenericTrbEntry {
         trb.set_slot_id(slot_id);
         trb
     }
+    /// 4.6.8 Reset Endpoint: recovers a Halted endpoint (e.g. after a STALL)
+    pub fn cmd_reset_endpoint(slot_id: u8, dci: usize) -> Self {
+        let mut trb = Self::default();
+        trb.set_trb_type(TrbType::ResetEndpointCommand);
+        trb.control.write_bits(16, 5, dci as u32).unwrap();
+        trb.set_slot_id(slot_id);
+        trb
+    }
+    /// 4.6.10 Set TR Dequeue Pointer
+    pub fn cmd_set_tr_dequeue_pointer(
+        slot_id: u8,
+        dci: usize,
+        tr_dequeue_ptr: u64,
+        dequeue_cycle_state: bool,
+    ) -> Self {
+        let mut trb = Self::default();
+        trb.set_trb_type(TrbType::SetTrDequeuePointerCommand);
+        trb.data.write(tr_dequeue_ptr | dequeue_cycle_state as u64);
+        trb.control.write_bits(16, 5, dci as u32).unwrap();
+        trb.set_slot_id(slot_id);
+        trb
+    }
     pub fn trb_link(ring: &TrbRing) -> Self {
         let mut trb = GenericTrbEntry::default();
         trb.set_trb_type(TrbType::Link);
@@ -299,10 +332,14 @@ impl SetupStageTrb {
     //      _: Reserved
     pub const REQ_TYPE_TO_DEVICE: u8 = 0;
     pub const REQ_TYPE_TO_INTERFACE: u8 = 1;
-    //pub const REQ_TYPE_TO_ENDPOINT: u8 = 2;
-    //pub const REQ_TYPE_TO_OTHER: u8 = 3;
+    pub const REQ_TYPE_TO_ENDPOINT: u8 = 2;
+    pub const REQ_TYPE_TO_OTHER: u8 = 3;
 
+    pub const REQ_GET_STATUS: u8 = 0;
+    pub const REQ_CLEAR_FEATURE: u8 = 1;
+    pub const REQ_SET_FEATURE: u8 = 3;
     pub const REQ_GET_REPORT: u8 = 1;
+    pub const REQ_SET_REPORT: u8 = 9;
     pub const REQ_GET_DESCRIPTOR: u8 = 6;
     pub const REQ_SET_CONFIGURATION: u8 = 9;
     pub const REQ_SET_INTERFACE: u8 = 11;
@@ -7,7 +7,7 @@
 #![no_std]
 
 #[repr(C)]
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct MouseButtonState(u64);
 pub const MOUSE_BUTTON_L: u64 = 1 << 0;
 pub const MOUSE_BUTTON_C: u64 = 1 << 1;
@@ -48,6 +48,116 @@ impl PointerPosition {
 pub struct MouseEvent {
     pub button: MouseButtonState,
     pub position: PointerPosition,
+    /// Movement of the wheel since the last event. Positive values mean scrolling up.
+    pub wheel: i64,
 }
 
 pub type RawIpV4Addr = [u8; 4];
+
+/// Use this as `BlitRequest::window` to draw onto the screen directly.
+pub const BLIT_TARGET_SCREEN: i64 = -1;
+
+/// Parameters to copy the rect (sx, sy, w, h) of a 32bpp bitmap in the app
+/// to (dx, dy) of the screen or a window surface.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BlitRequest {
+    /// A window handle, or BLIT_TARGET_SCREEN
+    pub window: i64,
+    pub buf: u64,
+    pub width: i64,
+    pub height: i64,
+    pub pixels_per_line: i64,
+    pub sx: i64,
+    pub sy: i64,
+    pub w: i64,
+    pub h: i64,
+    pub dx: i64,
+    pub dy: i64,
+}
+
+// Geometry of the window decorations drawn by apps (see noli::window::Window).
+// The compositor uses these to handle clicks on the title bar.
+pub const WINDOW_TITLE_BAR_HEIGHT: i64 = 24;
+pub const WINDOW_CLOSE_BUTTON_SIZE: i64 = 16;
+pub const WINDOW_CLOSE_BUTTON_TOP: i64 = 4;
+/// Distance between the right edge of the close button and the window.
+pub const WINDOW_CLOSE_BUTTON_RIGHT_MARGIN: i64 = 6;
+
+// Bits of KeyModifiers, which are the same as the modifier byte
+// of the USB HID boot keyboard report.
+pub const KEY_MODIFIER_LEFT_CTRL: u32 = 1 << 0;
+pub const KEY_MODIFIER_LEFT_SHIFT: u32 = 1 << 1;
+pub const KEY_MODIFIER_LEFT_ALT: u32 = 1 << 2;
+pub const KEY_MODIFIER_LEFT_GUI: u32 = 1 << 3;
+pub const KEY_MODIFIER_RIGHT_CTRL: u32 = 1 << 4;
+pub const KEY_MODIFIER_RIGHT_SHIFT: u32 = 1 << 5;
+pub const KEY_MODIFIER_RIGHT_ALT: u32 = 1 << 6;
+pub const KEY_MODIFIER_RIGHT_GUI: u32 = 1 << 7;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct KeyModifiers(pub u32);
+impl KeyModifiers {
+    pub fn ctrl(self) -> bool {
+        self.0 & (KEY_MODIFIER_LEFT_CTRL | KEY_MODIFIER_RIGHT_CTRL) != 0
+    }
+    pub fn shift(self) -> bool {
+        self.0 & (KEY_MODIFIER_LEFT_SHIFT | KEY_MODIFIER_RIGHT_SHIFT) != 0
+    }
+    pub fn alt(self) -> bool {
+        self.0 & (KEY_MODIFIER_LEFT_ALT | KEY_MODIFIER_RIGHT_ALT) != 0
+    }
+    pub fn gui(self) -> bool {
+        self.0 & (KEY_MODIFIER_LEFT_GUI | KEY_MODIFIER_RIGHT_GUI) != 0
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct KeyboardEvent {
+    /// USB HID Usage ID of the key (Keyboard/Keypad page),
+    /// or 0 if the key is not from a keyboard (e.g. the serial console).
+    pub scancode: u32,
+    pub modifiers: KeyModifiers,
+    /// Unicode scalar value of the character for the key, or 0 if there is none.
+    pub ch: u32,
+}
+impl KeyboardEvent {
+    pub fn char(&self) -> Option<char> {
+        if self.ch == 0 {
+            None
+        } else {
+            char::from_u32(self.ch)
+        }
+    }
+}
+
+/// Input and window events delivered to each window (see noli::window::Window).
+/// Positions of mouse events are in the window coordinates.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub enum Event {
+    #[default]
+    None,
+    KeyDown(KeyboardEvent),
+    KeyUp(KeyboardEvent),
+    /// The pointer is moved on the window.
+    MouseMove(MouseEvent),
+    /// A button of the mouse is pressed or released on the window.
+    MouseButton(MouseEvent),
+    /// Positive values mean scrolling up.
+    MouseWheel(i64),
+    /// The window gets the keyboard focus.
+    WindowFocus,
+    /// The window loses the keyboard focus.
+    WindowUnfocus,
+    /// The window is moved to (x, y) on the screen.
+    WindowMove(PointerPosition),
+    /// The close button of the window is clicked.
+    WindowClose,
+    /// The timer set for the window is expired. `time_ms` is the uptime of the system.
+    Timer {
+        time_ms: u64,
+    },
+}